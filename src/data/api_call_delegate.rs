@@ -1,4 +1,4 @@
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 
 use actix_service::Service;
 use futures::TryFutureExt;
@@ -11,11 +11,11 @@ use crate::data::api_call_delegate::ApiCallError::{
 };
 
 pub struct ApiCallDelegate {
-    api_client: Arc<Mutex<Client>>,
+    api_client: Arc<Client>,
 }
 
 impl ApiCallDelegate {
-    pub fn new(api_client: Arc<Mutex<Client>>) -> ApiCallDelegate {
+    pub fn new(api_client: Arc<Client>) -> ApiCallDelegate {
         ApiCallDelegate { api_client }
     }
 
@@ -32,8 +32,6 @@ impl ApiCallDelegate {
         let body = serde_json::to_string(dto).map_err(|_| DtoToJsonStringError)?;
         let result = self
             .api_client
-            .lock()
-            .unwrap()
             .post(url)
             .body(body)
             .send()
@@ -64,8 +62,6 @@ impl ApiCallDelegate {
     {
         let result = self
             .api_client
-            .lock()
-            .unwrap()
             .get(url)
             .send()
             .await;
@@ -86,6 +82,29 @@ impl ApiCallDelegate {
             .map_err(|err| Self::map_and_log_error(err, JsonToDtoError))
     }
 
+    // github answers deletes with an empty 204, so there is no dto to read
+    pub async fn execute_delete_call(&self, url: String) -> Result<(), ApiCallError> {
+        let result = self
+            .api_client
+            .delete(url)
+            .send()
+            .await;
+
+        match &result {
+            Ok(response) => {
+                println!("{}", response.status());
+            }
+            Err(error) => {
+                println!("{}", error);
+            }
+        }
+
+        result
+            .and_then(|response| response.error_for_status())
+            .map(|_| ())
+            .map_err(|err| Self::map_and_log_error(err, SendError))
+    }
+
 
     fn map_and_log_error(err: Error, api_call_error: ApiCallError) -> ApiCallError {
         println!("{}", err);
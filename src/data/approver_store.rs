@@ -0,0 +1,120 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use ssh_key::rand_core::{OsRng, RngCore};
+
+use crate::data::approver_store::ApproverStoreError::{
+    CouldNotReadApprovers, CouldNotWriteApprovers,
+};
+use crate::data::atomic_write::atomic_write;
+
+static TOKEN_LENGTH: usize = 32;
+
+// the people allowed to approve deploys, with the sha-256 of their api tokens. The file is read
+// on every access, so approvers added from the cli apply to a running server right away
+pub struct ApproverStore {
+    file_path: String,
+    // held from reading the file until the changed one is renamed into place
+    write_lock: Mutex<()>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct ApproversFileDto {
+    // name -> hex of the token's sha-256
+    approvers: BTreeMap<String, String>,
+}
+
+impl ApproverStore {
+    pub fn new(file_path: String) -> ApproverStore {
+        ApproverStore {
+            file_path,
+            write_lock: Mutex::new(()),
+        }
+    }
+
+    // returns the approver's new token, an existing approver's old token stops working
+    pub fn add(&self, name: &str) -> Result<String, ApproverStoreError> {
+        let mut token_bytes = [0u8; TOKEN_LENGTH];
+        OsRng.fill_bytes(&mut token_bytes);
+        let token = URL_SAFE_NO_PAD.encode(token_bytes);
+
+        let _write_guard = self.write_lock.lock().unwrap();
+        let mut approvers_file = self.read_approvers_file()?;
+        approvers_file
+            .approvers
+            .insert(name.to_string(), Self::hash_token(&token));
+        self.write_approvers_file(&approvers_file)?;
+
+        Ok(token)
+    }
+
+    // returns whether there was such an approver
+    pub fn remove(&self, name: &str) -> Result<bool, ApproverStoreError> {
+        let _write_guard = self.write_lock.lock().unwrap();
+        let mut approvers_file = self.read_approvers_file()?;
+        let removed = approvers_file.approvers.remove(name).is_some();
+
+        if removed {
+            self.write_approvers_file(&approvers_file)?;
+        }
+
+        Ok(removed)
+    }
+
+    pub fn list(&self) -> Result<Vec<String>, ApproverStoreError> {
+        Ok(self.read_approvers_file()?.approvers.into_keys().collect())
+    }
+
+    // the name of the approver the token belongs to
+    pub fn authenticate(&self, token: &str) -> Result<Option<String>, ApproverStoreError> {
+        let token_hash = Self::hash_token(token);
+
+        Ok(self
+            .read_approvers_file()?
+            .approvers
+            .into_iter()
+            .find(|(_, approver_token_hash)| *approver_token_hash == token_hash)
+            .map(|(name, _)| name))
+    }
+
+    fn hash_token(token: &str) -> String {
+        Sha256::digest(token.as_bytes())
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    fn read_approvers_file(&self) -> Result<ApproversFileDto, ApproverStoreError> {
+        if !Path::new(&self.file_path).exists() {
+            return Ok(ApproversFileDto::default());
+        }
+
+        fs::read_to_string(&self.file_path)
+            .ok()
+            .and_then(|content| serde_json::from_str::<ApproversFileDto>(&content).ok())
+            .ok_or(CouldNotReadApprovers)
+    }
+
+    fn write_approvers_file(
+        &self,
+        approvers_file: &ApproversFileDto,
+    ) -> Result<(), ApproverStoreError> {
+        let content =
+            serde_json::to_string_pretty(approvers_file).map_err(|_| CouldNotWriteApprovers)?;
+
+        atomic_write(&self.file_path, content.as_bytes(), 0o600)
+            .map_err(|_| CouldNotWriteApprovers)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ApproverStoreError {
+    CouldNotReadApprovers,
+    CouldNotWriteApprovers,
+}
@@ -0,0 +1,41 @@
+use std::fs;
+use std::fs::{DirBuilder, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// tells the temp files of one process's writers apart
+static TEMP_FILE_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+// written next to the target and renamed, nothing ever reads half a file. Every write gets a
+// temp file of its own, so concurrent writers only race on the rename. Missing parent dirs are
+// created readable only by the mini-ci user
+pub fn atomic_write(path: &str, bytes: &[u8], mode: u32) -> std::io::Result<()> {
+    if let Some(parent) = Path::new(path).parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        DirBuilder::new().recursive(true).mode(0o700).create(parent)?;
+    }
+
+    let temp_path = format!(
+        "{}.{}-{}.tmp",
+        path,
+        std::process::id(),
+        TEMP_FILE_COUNT.fetch_add(1, Ordering::SeqCst)
+    );
+    let result = OpenOptions::new()
+        .create_new(true)
+        .write(true)
+        .mode(mode)
+        .open(&temp_path)
+        .and_then(|mut file| {
+            file.write_all(bytes)?;
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(&temp_path, path));
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+
+    result
+}
@@ -0,0 +1,80 @@
+use std::fs;
+use std::fs::{DirBuilder, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::data::audit_log::AuditLogError::{CouldNotReadAuditLog, CouldNotWriteAuditLog};
+
+// who lifted or changed deploy restrictions and why, one json object per line. Entries are
+// only ever appended
+pub struct AuditLog {
+    file_path: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub at: DateTime<Utc>,
+    pub actor: String,
+    pub action: String,
+    pub details: String,
+}
+
+impl AuditLog {
+    pub fn new(file_path: String) -> AuditLog {
+        AuditLog { file_path }
+    }
+
+    pub fn append(&self, actor: &str, action: &str, details: &str) -> Result<(), AuditLogError> {
+        let entry = AuditEntry {
+            at: Utc::now(),
+            actor: actor.to_string(),
+            action: action.to_string(),
+            details: details.to_string(),
+        };
+        let line = serde_json::to_string(&entry).map_err(|_| CouldNotWriteAuditLog)?;
+
+        println!("Audit: {} {}: {}", actor, action, details);
+
+        if let Some(parent) = Path::new(&self.file_path).parent() {
+            DirBuilder::new()
+                .recursive(true)
+                .mode(0o700)
+                .create(parent)
+                .map_err(|_| CouldNotWriteAuditLog)?;
+        }
+
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .mode(0o600)
+            .open(&self.file_path)
+            .and_then(|mut file| {
+                file.write_all(format!("{}\n", line).as_bytes())?;
+                file.sync_all()
+            })
+            .map_err(|_| CouldNotWriteAuditLog)
+    }
+
+    pub fn read(&self) -> Result<Vec<AuditEntry>, AuditLogError> {
+        if !Path::new(&self.file_path).exists() {
+            return Ok(vec![]);
+        }
+
+        fs::read_to_string(&self.file_path)
+            .map_err(|_| CouldNotReadAuditLog)?
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_str::<AuditEntry>(line).map_err(|_| CouldNotReadAuditLog))
+            .collect()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum AuditLogError {
+    CouldNotReadAuditLog,
+    CouldNotWriteAuditLog,
+}
@@ -0,0 +1,48 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::data::atomic_write::atomic_write;
+use crate::data::deploy_history_store::DeployHistoryStoreError::{
+    CouldNotReadDeploys, CouldNotWriteDeploys,
+};
+use crate::data::deploy_info_repository::DeployEntity;
+
+// ssh url -> branch name -> deploys, oldest first
+pub type DeployHistory = HashMap<String, HashMap<String, Vec<DeployEntity>>>;
+
+// the deploys rollbacks pick from, so they survive a restart. Read once at startup, written
+// after every successful deploy
+pub struct DeployHistoryStore {
+    file_path: String,
+}
+
+impl DeployHistoryStore {
+    pub fn new(file_path: String) -> DeployHistoryStore {
+        DeployHistoryStore { file_path }
+    }
+
+    pub fn read(&self) -> Result<DeployHistory, DeployHistoryStoreError> {
+        if !Path::new(&self.file_path).exists() {
+            return Ok(DeployHistory::new());
+        }
+
+        fs::read_to_string(&self.file_path)
+            .ok()
+            .and_then(|content| serde_json::from_str::<DeployHistory>(&content).ok())
+            .ok_or(CouldNotReadDeploys)
+    }
+
+    pub fn write(&self, deploy_history: &DeployHistory) -> Result<(), DeployHistoryStoreError> {
+        let content =
+            serde_json::to_string_pretty(deploy_history).map_err(|_| CouldNotWriteDeploys)?;
+
+        atomic_write(&self.file_path, content.as_bytes(), 0o600).map_err(|_| CouldNotWriteDeploys)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum DeployHistoryStoreError {
+    CouldNotReadDeploys,
+    CouldNotWriteDeploys,
+}
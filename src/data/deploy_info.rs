@@ -0,0 +1,671 @@
+use std::collections::{HashMap, HashSet};
+
+use chrono::{DateTime, Datelike, Duration, DurationRound, SubsecRound, Timelike, Utc};
+use chrono_tz::Tz;
+use croner::Cron;
+use regex::Regex;
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_yaml::Value;
+use strum::Display;
+
+use crate::data::deploy_info::DeployInfoError::{
+    CommandsAndJobs, CyclicJobs, DuplicateJob, InvalidDeployWindow, InvalidHealthCheck,
+    InvalidInput, InvalidSchedule, InvalidYaml, UnknownJob,
+};
+use crate::data::deploy_info::InputError::{InvalidValue, MissingInput, UnknownInput};
+
+// libgit2 uses this depth to turn a shallow clone back into a full one
+static UNSHALLOW_DEPTH: i32 = i32::MAX;
+// past this the full history is fetched instead of deepening further
+static MAX_DEEPEN_DEPTH: i32 = 4096;
+// the job a branch's plain commands run in
+static IMPLICIT_JOB_NAME: &str = "deploy";
+static WEEKDAY_NAMES: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+static MINUTES_PER_DAY: u32 = 24 * 60;
+// steps get the value of an input in this variable, followed by the input's name
+static INPUT_VARIABLE_PREFIX: &str = "MINI_CI_INPUT_";
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeployInfo {
+    #[serde(default)]
+    pub clone: CloneOptions,
+    // for the commands of every branch
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    // variable -> content of a temp file, the variable holds the file's path
+    #[serde(default)]
+    pub files: HashMap<String, String>,
+    // the directives commit messages may use, only the skip ones if unset
+    pub directives: Option<Vec<CommitDirective>>,
+    pub branches: Vec<Branch>,
+    // runs started by the clock instead of a push
+    #[serde(default)]
+    pub schedules: Vec<Schedule>,
+    // what a manual run may be given, by name
+    #[serde(default)]
+    pub inputs: HashMap<String, Input>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Input {
+    #[serde(rename = "type", default)]
+    pub input_type: InputType,
+    // the values a choice may take
+    #[serde(default)]
+    pub options: Vec<String>,
+    #[serde(default, deserialize_with = "deserialize_scalar")]
+    pub default: Option<String>,
+    // a manual run has to give it unless it has a default
+    #[serde(default)]
+    pub required: bool,
+    pub description: Option<String>,
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InputType {
+    #[default]
+    String,
+    // true or false
+    Boolean,
+    Number,
+    // one of the input's options
+    Choice,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Schedule {
+    // minute hour day-of-month month day-of-week
+    pub cron: String,
+    // IANA name of the zone the cron expression is read in, UTC if unset
+    pub timezone: Option<String>,
+    // runs the latest commit of this branch
+    pub branch: String,
+}
+
+// how much of the repo is fetched and checked out, unset fields fall back to the server's.
+// There are no partial clones (filters like blob:none), libgit2 can't read from them
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CloneOptions {
+    // commits fetched per branch, 0 fetches the full history
+    pub depth: Option<u32>,
+    // only these files and directories are checked out
+    #[serde(default)]
+    pub sparse_paths: Vec<String>,
+    // submodules are checked out recursively, with the credentials of their own repos
+    #[serde(default)]
+    pub submodules: bool,
+    // git lfs files are downloaded in place of their pointer files
+    #[serde(default)]
+    pub lfs: bool,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Branch {
+    pub name: String,
+    // scope of the secrets the branch gets, defaults to the branch's name
+    pub environment: Option<String>,
+    // seconds, for every command without its own timeout
+    pub timeout: Option<u64>,
+    #[serde(default)]
+    pub concurrency: ConcurrencyPolicy,
+    // seconds a coalesced push waits for newer pushes
+    pub debounce: Option<u64>,
+    // whether someone has to approve a run before its jobs start
+    #[serde(default)]
+    pub approval: ApprovalPolicy,
+    // seconds after which an approval nobody gave counts as rejected, never if unset
+    pub approval_expiry: Option<u64>,
+    // when the branch may deploy, the server's windows of its environment if unset
+    pub windows: Option<Vec<DeployWindow>>,
+    // what happens to runs outside the windows or during a freeze
+    #[serde(default)]
+    pub outside_window: OutsideWindowPolicy,
+    // globs of the files a push has to change for the branch to deploy
+    #[serde(default)]
+    pub paths: Vec<String>,
+    // globs of changed files that don't count
+    #[serde(default)]
+    pub paths_ignore: Vec<String>,
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    #[serde(default)]
+    pub files: HashMap<String, String>,
+    #[serde(default)]
+    pub commands: Vec<Command>,
+    // named jobs instead of commands, a job starts once all jobs it needs have succeeded
+    #[serde(default)]
+    pub jobs: Vec<Job>,
+    // probed once all jobs succeeded, the run fails if the service doesn't come up
+    pub health_check: Option<HealthCheck>,
+    // run in a checkout of the branch's last successful commit when the health check fails
+    #[serde(default)]
+    pub rollback: Vec<Command>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HealthCheck {
+    // may refer to the run's variables
+    pub url: String,
+    // 200 if unset
+    pub expected_status: Option<u16>,
+    // the response body has to match it
+    pub body_regex: Option<String>,
+    // probes after the first failed one, 5 if unset
+    pub retries: Option<u32>,
+    // seconds between probes, 5 if unset
+    pub interval: Option<u64>,
+    // seconds a probe may take, 10 if unset
+    pub timeout: Option<u64>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Job {
+    pub name: String,
+    #[serde(default)]
+    pub needs: Vec<String>,
+    // seconds, for every command without its own timeout
+    pub timeout: Option<u64>,
+    // like the branch's, but asked for once the job's needs have succeeded
+    #[serde(default)]
+    pub approval: ApprovalPolicy,
+    pub approval_expiry: Option<u64>,
+    // like the branch's, a job whose paths didn't change is skipped
+    #[serde(default)]
+    pub paths: Vec<String>,
+    #[serde(default)]
+    pub paths_ignore: Vec<String>,
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    #[serde(default)]
+    pub files: HashMap<String, String>,
+    pub commands: Vec<Command>,
+}
+
+// what a new push does to runs of the same branch that haven't finished yet
+#[derive(Default, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConcurrencyPolicy {
+    // run after them
+    #[default]
+    Queue,
+    // cancel them, running or not
+    CancelInProgress,
+    // replace the ones that haven't started, after waiting for the debounce period
+    Coalesce,
+}
+
+// days, hours and timezone as in "mon-thu 09:00-16:00 Europe/Berlin", the days are a comma
+// separated list of days and day ranges or * for every day, the timezone defaults to UTC
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct DeployWindow {
+    spec: String,
+    // monday first
+    days: [bool; 7],
+    // minutes of the day, the end is excluded
+    start: u32,
+    end: u32,
+    timezone: Tz,
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutsideWindowPolicy {
+    // the run waits until a window opens and no freeze applies
+    #[default]
+    Queue,
+    Reject,
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApprovalPolicy {
+    #[default]
+    None,
+    Required,
+}
+
+// [skip ci], [skip deploy] and [deploy only=job,service] in a push's commit messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CommitDirective {
+    SkipCi,
+    SkipDeploy,
+    DeployOnly,
+}
+
+// a command is either a plain string or a step with its own settings
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Command {
+    Plain(String),
+    Step(Step),
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Step {
+    pub run: String,
+    // seconds
+    pub timeout: Option<u64>,
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    #[serde(default)]
+    pub files: HashMap<String, String>,
+}
+
+impl DeployInfo {
+    // the job graphs are checked here, a deploy never starts with an invalid one
+    pub fn parse(yaml: &str) -> Result<DeployInfo, DeployInfoError> {
+        let deploy_info = serde_yaml::from_str::<DeployInfo>(yaml).map_err(|_| InvalidYaml)?;
+
+        for branch in &deploy_info.branches {
+            branch.validate_jobs()?;
+
+            if let Some(body_regex) = branch
+                .health_check
+                .as_ref()
+                .and_then(|health_check| health_check.body_regex.as_ref())
+            {
+                Regex::new(body_regex).map_err(|_| InvalidHealthCheck(branch.name.clone()))?;
+            }
+        }
+
+        for schedule in &deploy_info.schedules {
+            if deploy_info.get_branch(&schedule.branch).is_none()
+                || schedule.parse_cron().is_none()
+                || schedule.get_timezone().is_none()
+            {
+                return Err(InvalidSchedule(schedule.cron.clone()));
+            }
+        }
+
+        for (name, input) in &deploy_info.inputs {
+            let is_valid = !name.is_empty()
+                && !(input.input_type == InputType::Choice && input.options.is_empty())
+                && input.default.as_ref().map(|value| input.accepts(value)).unwrap_or(true);
+
+            if !is_valid {
+                return Err(InvalidInput(name.clone()));
+            }
+        }
+
+        Ok(deploy_info)
+    }
+
+    // the given values and the defaults of the inputs that weren't given
+    pub fn resolve_inputs(
+        &self,
+        values: &HashMap<String, String>,
+    ) -> Result<HashMap<String, String>, InputError> {
+        if let Some(name) = values.keys().find(|name| !self.inputs.contains_key(*name)) {
+            return Err(UnknownInput(name.clone()));
+        }
+
+        let mut resolved_values = HashMap::new();
+
+        for (name, input) in &self.inputs {
+            let value = match values.get(name).or(input.default.as_ref()) {
+                Some(value) => value,
+                None if input.required => return Err(MissingInput(name.clone())),
+                None => continue,
+            };
+
+            if !input.accepts(value) {
+                return Err(InvalidValue(name.clone()));
+            }
+
+            resolved_values.insert(name.clone(), value.clone());
+        }
+
+        Ok(resolved_values)
+    }
+
+    pub fn get_branch(&self, branch_name: &str) -> Option<&Branch> {
+        self.branches.iter().find(|branch| branch.name == branch_name)
+    }
+
+    pub fn get_directives(&self) -> Vec<CommitDirective> {
+        self.directives
+            .clone()
+            .unwrap_or_else(|| vec![CommitDirective::SkipCi, CommitDirective::SkipDeploy])
+    }
+}
+
+impl Branch {
+    pub fn get_environment(&self) -> &str {
+        self.environment.as_deref().unwrap_or(self.name.as_str())
+    }
+
+    // plain commands run as a single job
+    pub fn get_jobs(&self) -> Vec<Job> {
+        if !self.jobs.is_empty() {
+            return self.jobs.clone();
+        }
+
+        vec![Job {
+            name: IMPLICIT_JOB_NAME.to_string(),
+            commands: self.commands.clone(),
+            ..Job::default()
+        }]
+    }
+
+    pub fn validate_jobs(&self) -> Result<(), DeployInfoError> {
+        if !self.commands.is_empty() && !self.jobs.is_empty() {
+            return Err(CommandsAndJobs(self.name.clone()));
+        }
+
+        let mut job_names = HashSet::new();
+
+        for job in &self.jobs {
+            if !job_names.insert(job.name.as_str()) {
+                return Err(DuplicateJob(job.name.clone()));
+            }
+        }
+
+        if let Some(unknown_job) = self
+            .jobs
+            .iter()
+            .flat_map(|job| job.needs.iter())
+            .find(|need| !job_names.contains(need.as_str()))
+        {
+            return Err(UnknownJob(unknown_job.clone()));
+        }
+
+        // jobs are removed once everything they need is, whatever is left is in or behind a cycle
+        let mut remaining_jobs = self.jobs.iter().collect::<Vec<&Job>>();
+
+        loop {
+            let remaining_names = remaining_jobs
+                .iter()
+                .map(|job| job.name.as_str())
+                .collect::<HashSet<&str>>();
+            let remaining_count = remaining_jobs.len();
+
+            remaining_jobs.retain(|job| {
+                job.needs
+                    .iter()
+                    .any(|need| remaining_names.contains(need.as_str()))
+            });
+
+            if remaining_jobs.is_empty() {
+                return Ok(());
+            }
+
+            if remaining_jobs.len() == remaining_count {
+                let mut cyclic_job_names = remaining_jobs
+                    .iter()
+                    .map(|job| job.name.clone())
+                    .collect::<Vec<String>>();
+                cyclic_job_names.sort();
+
+                return Err(CyclicJobs(cyclic_job_names));
+            }
+        }
+    }
+}
+
+impl Input {
+    pub fn accepts(&self, value: &str) -> bool {
+        match self.input_type {
+            InputType::String => true,
+            InputType::Boolean => value == "true" || value == "false",
+            InputType::Number => value.parse::<f64>().map(f64::is_finite).unwrap_or(false),
+            InputType::Choice => self.options.iter().any(|option| option == value),
+        }
+    }
+
+    // MINI_CI_INPUT_ and the name in upper case, characters a variable can't have become _
+    pub fn get_variable_name(name: &str) -> String {
+        let name = name
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+            .collect::<String>();
+
+        format!("{}{}", INPUT_VARIABLE_PREFIX, name)
+    }
+}
+
+// yaml reads `default: true` as a bool and `default: 3` as a number, inputs keep them as text
+fn deserialize_scalar<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<Value>::deserialize(deserializer)? {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(value)) => Ok(Some(value)),
+        Some(Value::Bool(value)) => Ok(Some(value.to_string())),
+        Some(Value::Number(value)) => Ok(Some(value.to_string())),
+        Some(_) => Err(D::Error::custom("expected a string, number or bool")),
+    }
+}
+
+impl Schedule {
+    // the first time after the given one the schedule fires at, in the schedule's timezone
+    pub fn next_after(&self, time: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let timezone = self.get_timezone()?;
+
+        self.parse_cron()?
+            .find_next_occurrence(&time.trunc_subsecs(0).with_timezone(&timezone), false)
+            .ok()
+            .map(|next| next.with_timezone(&Utc))
+    }
+
+    fn parse_cron(&self) -> Option<Cron> {
+        Cron::new(self.cron.as_str()).parse().ok()
+    }
+
+    fn get_timezone(&self) -> Option<Tz> {
+        self.timezone
+            .as_deref()
+            .map(|timezone| timezone.parse::<Tz>().ok())
+            .unwrap_or(Some(Tz::UTC))
+    }
+}
+
+impl DeployWindow {
+    pub fn parse(spec: &str) -> Result<DeployWindow, DeployInfoError> {
+        let invalid = || InvalidDeployWindow(spec.to_string());
+        let parts = spec.split_whitespace().collect::<Vec<&str>>();
+
+        let (days, hours, timezone) = match parts.as_slice() {
+            [days, hours] => (*days, *hours, Tz::UTC),
+            [days, hours, timezone] => {
+                (*days, *hours, timezone.parse::<Tz>().map_err(|_| invalid())?)
+            }
+            _ => return Err(invalid()),
+        };
+        let (start, end) = hours
+            .split_once('-')
+            .and_then(|(start, end)| Some((Self::parse_time(start)?, Self::parse_time(end)?)))
+            .filter(|(start, end)| start < end)
+            .ok_or_else(invalid)?;
+
+        Ok(DeployWindow {
+            spec: spec.to_string(),
+            days: Self::parse_days(days).ok_or_else(invalid)?,
+            start,
+            end,
+            timezone,
+        })
+    }
+
+    pub fn is_open(&self, time: DateTime<Utc>) -> bool {
+        let local_time = time.with_timezone(&self.timezone);
+        let minute = local_time.hour() * 60 + local_time.minute();
+
+        self.days[local_time.weekday().num_days_from_monday() as usize]
+            && self.start <= minute
+            && minute < self.end
+    }
+
+    // the first minute from the given time on the window is open at, within a week
+    pub fn next_open(&self, time: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let mut minute = time.duration_trunc(Duration::minutes(1)).ok()?;
+
+        if minute < time {
+            minute += Duration::minutes(1);
+        }
+
+        (0..=7 * MINUTES_PER_DAY)
+            .map(|offset| minute + Duration::minutes(offset as i64))
+            .find(|minute| self.is_open(*minute))
+    }
+
+    // 09:00, 24:00 is the end of the day
+    fn parse_time(time: &str) -> Option<u32> {
+        let (hour, minute) = time.split_once(':')?;
+        let hour = hour.parse::<u32>().ok().filter(|hour| *hour <= 24)?;
+        let minutes = minute
+            .parse::<u32>()
+            .ok()
+            .filter(|minutes| minute.len() == 2 && *minutes < 60)?;
+
+        // 24:00 is the end of the day, no later time of it
+        Some(hour * 60 + minutes).filter(|minutes| *minutes <= MINUTES_PER_DAY)
+    }
+
+    // ranges may wrap around the week, fri-mon is friday to monday
+    fn parse_days(days: &str) -> Option<[bool; 7]> {
+        if days == "*" {
+            return Some([true; 7]);
+        }
+
+        let parse_day = |day: &str| {
+            WEEKDAY_NAMES
+                .iter()
+                .position(|name| name.eq_ignore_ascii_case(day))
+        };
+        let mut open_days = [false; 7];
+
+        for range in days.split(',') {
+            let (first, last) = match range.split_once('-') {
+                Some((first, last)) => (parse_day(first)?, parse_day(last)?),
+                None => (parse_day(range)?, parse_day(range)?),
+            };
+            let mut day = first;
+
+            loop {
+                open_days[day] = true;
+
+                if day == last {
+                    break;
+                }
+
+                day = (day + 1) % 7;
+            }
+        }
+
+        Some(open_days)
+    }
+}
+
+impl TryFrom<String> for DeployWindow {
+    type Error = DeployInfoError;
+
+    fn try_from(spec: String) -> Result<Self, Self::Error> {
+        DeployWindow::parse(spec.as_str())
+    }
+}
+
+impl From<DeployWindow> for String {
+    fn from(deploy_window: DeployWindow) -> Self {
+        deploy_window.spec
+    }
+}
+
+impl CloneOptions {
+    pub fn with_defaults(&self, defaults: &CloneOptions) -> CloneOptions {
+        CloneOptions {
+            depth: self.depth.or(defaults.depth),
+            sparse_paths: if self.sparse_paths.is_empty() {
+                defaults.sparse_paths.clone()
+            } else {
+                self.sparse_paths.clone()
+            },
+            submodules: self.submodules,
+            lfs: self.lfs,
+        }
+    }
+
+    // paths are relative to the repo's root
+    pub fn is_checked_out(&self, path: &str) -> bool {
+        self.sparse_paths.is_empty()
+            || self.sparse_paths.iter().any(|sparse_path| {
+                let sparse_path = sparse_path.trim_matches('/');
+
+                path == sparse_path || path.starts_with(format!("{}/", sparse_path).as_str())
+            })
+    }
+
+    // without any depth the clone keeps the history it has, a depth of 0 unshallows it
+    pub fn get_fetch_depth(&self, is_shallow: bool) -> i32 {
+        match self.depth {
+            Some(0) if is_shallow => UNSHALLOW_DEPTH,
+            Some(depth) => depth.min(UNSHALLOW_DEPTH as u32) as i32,
+            None => 0,
+        }
+    }
+
+    // deeper fetches of a shallow clone, ending with the full history
+    pub fn get_deepen_depths(&self) -> Vec<i32> {
+        let mut depths = self.get_bounded_deepen_depths();
+        depths.push(UNSHALLOW_DEPTH);
+        depths
+    }
+
+    // deeper fetches of a shallow clone that stop short of the full history, for shas that may
+    // not exist anymore
+    pub fn get_bounded_deepen_depths(&self) -> Vec<i32> {
+        let mut depths = vec![];
+        let mut depth = self.get_fetch_depth(false).max(1);
+
+        while depth < MAX_DEEPEN_DEPTH {
+            depth *= 8;
+            depths.push(depth.min(MAX_DEEPEN_DEPTH));
+        }
+
+        depths
+    }
+}
+
+impl Command {
+    pub fn to_step(&self) -> Step {
+        match self {
+            Command::Plain(run) => {
+                Step {
+                    run: run.clone(),
+                    ..Step::default()
+                }
+            }
+            Command::Step(step) => step.clone(),
+        }
+    }
+}
+
+#[derive(Display, Debug, Clone, PartialEq, Eq)]
+pub enum DeployInfoError {
+    InvalidYaml,
+    // branch name
+    CommandsAndJobs(String),
+    DuplicateJob(String),
+    // a job needs a job that doesn't exist
+    UnknownJob(String),
+    CyclicJobs(Vec<String>),
+    // the schedule's cron expression, its timezone or its branch is unknown
+    InvalidSchedule(String),
+    InvalidDeployWindow(String),
+    // branch name, its body_regex doesn't compile
+    InvalidHealthCheck(String),
+    // input name, a choice without options or a default of the wrong type
+    InvalidInput(String),
+}
+
+// why the values of a manual run don't fit its repo's inputs, with the input's name
+#[derive(Display, Debug, Clone, PartialEq, Eq)]
+pub enum InputError {
+    UnknownInput(String),
+    MissingInput(String),
+    InvalidValue(String),
+}
@@ -1,34 +1,101 @@
 use std::collections::hash_map::OccupiedError;
 use std::collections::HashMap;
 
-use cmd_lib::FunChildren;
-use git2::Repository;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
 
+use crate::data::deploy_history_store::{
+    DeployHistory, DeployHistoryStore, DeployHistoryStoreError,
+};
+use crate::data::deploy_info::DeployInfo;
+
+// older deploys of a branch are forgotten
+static MAX_DEPLOYS_PER_BRANCH: usize = 50;
+
+#[derive(Debug, Clone)]
 pub struct DeployInfoEntity {
-    pub ssh_git_url: &'static str,
-    pub command_builders: Vec<fn(String) -> std::io::Result<FunChildren>>,
+    pub ssh_git_url: String,
     pub repo_path: String,
-    pub git_repository: Repository,
+    pub deploy_info: DeployInfo,
+}
+
+// a commit a run deployed successfully
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeployEntity {
+    pub sha: String,
+    pub run_id: u64,
+    pub deployed_at: DateTime<Utc>,
 }
 
 pub struct DeployInfoRepository {
     cache: HashMap<String, DeployInfoEntity>,
+    deploys: DeployHistory,
+    deploy_history_store: DeployHistoryStore,
 }
 
 impl DeployInfoRepository {
-    pub fn new(cache: HashMap<String, DeployInfoEntity>) -> DeployInfoRepository {
-        DeployInfoRepository { cache }
+    pub fn new(
+        cache: HashMap<String, DeployInfoEntity>,
+        deploy_history_store: DeployHistoryStore,
+    ) -> DeployInfoRepository {
+        DeployInfoRepository {
+            cache,
+            deploys: DeployHistory::new(),
+            deploy_history_store,
+        }
+    }
+
+    // the deploys of earlier server runs
+    pub fn load_deploys(&mut self) -> Result<(), DeployHistoryStoreError> {
+        self.deploys = self.deploy_history_store.read()?;
+        Ok(())
     }
 
     pub fn save(
         &mut self,
         key: String,
         entity: DeployInfoEntity,
-    ) -> Result<&mut DeployInfoEntity, OccupiedError<'_, String, DeployInfoEntity>> {
-        self.cache.try_insert(key, entity)
+    ) -> Result<&mut DeployInfoEntity, Box<OccupiedError<'_, String, DeployInfoEntity>>> {
+        self.cache.try_insert(key, entity).map_err(Box::new)
     }
 
     pub fn get(&self, key: &String) -> Option<&DeployInfoEntity> {
         self.cache.get(key)
     }
+
+    pub fn get_all(&self) -> Vec<&DeployInfoEntity> {
+        self.cache.values().collect()
+    }
+
+    // the deploy is kept in memory even if the history can't be written
+    pub fn add_deploy(
+        &mut self,
+        ssh_git_url: &str,
+        branch_name: &str,
+        deploy: DeployEntity,
+    ) -> Result<(), DeployHistoryStoreError> {
+        let deploys = self
+            .deploys
+            .entry(ssh_git_url.to_string())
+            .or_default()
+            .entry(branch_name.to_string())
+            .or_default();
+
+        deploys.push(deploy);
+
+        if deploys.len() > MAX_DEPLOYS_PER_BRANCH {
+            deploys.remove(0);
+        }
+
+        self.deploy_history_store.write(&self.deploys)
+    }
+
+    // newest first
+    pub fn get_deploys(&self, ssh_git_url: &str, branch_name: &str) -> Vec<DeployEntity> {
+        self.deploys
+            .get(ssh_git_url)
+            .and_then(|branches| branches.get(branch_name))
+            .map(|deploys| deploys.iter().rev().cloned().collect())
+            .unwrap_or_default()
+    }
 }
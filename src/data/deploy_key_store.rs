@@ -0,0 +1,79 @@
+use std::fs;
+use std::fs::DirBuilder;
+use std::os::unix::fs::DirBuilderExt;
+use std::path::Path;
+
+use crate::data::atomic_write::atomic_write;
+use crate::data::deploy_key_store::DeployKeyStoreError::{
+    CouldNotCreateKeyDir, CouldNotWriteKey,
+};
+
+static PRIVATE_KEY_FILE_NAME: &str = "id_ed25519";
+static PUBLIC_KEY_FILE_NAME: &str = "id_ed25519.pub";
+
+// one keypair per repo below root_dir/host/owner/name, readable only by the mini-ci user
+pub struct DeployKeyStore {
+    root_dir: String,
+}
+
+impl DeployKeyStore {
+    pub fn new(root_dir: String) -> DeployKeyStore {
+        DeployKeyStore { root_dir }
+    }
+
+    pub fn get_private_key_path(&self, repo_relative_path: &str) -> String {
+        format!("{}/{}/{}", self.root_dir, repo_relative_path, PRIVATE_KEY_FILE_NAME)
+    }
+
+    // the stored public key in openssh format, if both halves exist
+    pub fn load_public_key(&self, repo_relative_path: &str) -> Option<String> {
+        let key_dir = format!("{}/{}", self.root_dir, repo_relative_path);
+
+        if !Path::new(&format!("{}/{}", key_dir, PRIVATE_KEY_FILE_NAME)).exists() {
+            return None;
+        }
+
+        fs::read_to_string(format!("{}/{}", key_dir, PUBLIC_KEY_FILE_NAME))
+            .ok()
+            .map(|public_key| public_key.trim().to_string())
+            .filter(|public_key| !public_key.is_empty())
+    }
+
+    // returns the path of the private key
+    pub fn save(
+        &self,
+        repo_relative_path: &str,
+        private_key: &str,
+        public_key: &str,
+    ) -> Result<String, DeployKeyStoreError> {
+        let key_dir = format!("{}/{}", self.root_dir, repo_relative_path);
+
+        DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(&key_dir)
+            .map_err(|_| CouldNotCreateKeyDir)?;
+
+        Self::write_file(&key_dir, PUBLIC_KEY_FILE_NAME, public_key, 0o644)?;
+        // the private key goes last, so a key without its public half is never picked up
+        Self::write_file(&key_dir, PRIVATE_KEY_FILE_NAME, private_key, 0o600)?;
+
+        Ok(format!("{}/{}", key_dir, PRIVATE_KEY_FILE_NAME))
+    }
+
+    fn write_file(
+        key_dir: &str,
+        file_name: &str,
+        content: &str,
+        mode: u32,
+    ) -> Result<(), DeployKeyStoreError> {
+        atomic_write(&format!("{}/{}", key_dir, file_name), content.as_bytes(), mode)
+            .map_err(|_| CouldNotWriteKey)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum DeployKeyStoreError {
+    CouldNotCreateKeyDir,
+    CouldNotWriteKey,
+}
@@ -0,0 +1,137 @@
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::data::atomic_write::atomic_write;
+use crate::data::freeze_store::FreezeStoreError::{CouldNotReadFreezes, CouldNotWriteFreezes};
+
+// change freezes set through the api. Like the approvers, the file is read on every access
+pub struct FreezeStore {
+    file_path: String,
+    // held from reading the file until the changed one is renamed into place
+    write_lock: Mutex<()>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FreezeEntity {
+    pub id: u64,
+    // host/owner/name, every repo if unset
+    pub repo: Option<String>,
+    // every environment if unset
+    pub environment: Option<String>,
+    pub starts_at: DateTime<Utc>,
+    pub ends_at: DateTime<Utc>,
+    pub reason: String,
+    // the approver who set it
+    pub created_by: String,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct FreezesFileDto {
+    next_id: u64,
+    freezes: Vec<FreezeEntity>,
+}
+
+impl FreezeStore {
+    pub fn new(file_path: String) -> FreezeStore {
+        FreezeStore {
+            file_path,
+            write_lock: Mutex::new(()),
+        }
+    }
+
+    // the freeze's id is assigned here, freezes that have ended are dropped
+    pub fn add(&self, freeze: FreezeEntity) -> Result<FreezeEntity, FreezeStoreError> {
+        let _write_guard = self.write_lock.lock().unwrap();
+        let mut freezes_file = self.read_freezes_file()?;
+        let freeze = FreezeEntity {
+            id: freezes_file.next_id.max(1),
+            ..freeze
+        };
+
+        freezes_file.next_id = freeze.id + 1;
+        freezes_file
+            .freezes
+            .retain(|freeze| freeze.ends_at > Utc::now());
+        freezes_file.freezes.push(freeze.clone());
+        self.write_freezes_file(&freezes_file)?;
+
+        Ok(freeze)
+    }
+
+    // returns the removed freeze
+    pub fn remove(&self, id: u64) -> Result<Option<FreezeEntity>, FreezeStoreError> {
+        let _write_guard = self.write_lock.lock().unwrap();
+        let mut freezes_file = self.read_freezes_file()?;
+        let index = freezes_file.freezes.iter().position(|freeze| freeze.id == id);
+        let removed = index.map(|index| freezes_file.freezes.remove(index));
+
+        if removed.is_some() {
+            self.write_freezes_file(&freezes_file)?;
+        }
+
+        Ok(removed)
+    }
+
+    // the freezes that haven't ended yet
+    pub fn list(&self) -> Result<Vec<FreezeEntity>, FreezeStoreError> {
+        let now = Utc::now();
+
+        Ok(self
+            .read_freezes_file()?
+            .freezes
+            .into_iter()
+            .filter(|freeze| freeze.ends_at > now)
+            .collect())
+    }
+
+    // the freezes of the repo's environment that apply at the time
+    pub fn find_active(
+        &self,
+        repo: &str,
+        environment: &str,
+        time: DateTime<Utc>,
+    ) -> Result<Vec<FreezeEntity>, FreezeStoreError> {
+        Ok(self
+            .read_freezes_file()?
+            .freezes
+            .into_iter()
+            .filter(|freeze| freeze.starts_at <= time && time < freeze.ends_at)
+            .filter(|freeze| freeze.repo.as_deref().map(|value| value == repo).unwrap_or(true))
+            .filter(|freeze| {
+                freeze
+                    .environment
+                    .as_deref()
+                    .map(|value| value == environment)
+                    .unwrap_or(true)
+            })
+            .collect())
+    }
+
+    fn read_freezes_file(&self) -> Result<FreezesFileDto, FreezeStoreError> {
+        if !Path::new(&self.file_path).exists() {
+            return Ok(FreezesFileDto::default());
+        }
+
+        fs::read_to_string(&self.file_path)
+            .ok()
+            .and_then(|content| serde_json::from_str::<FreezesFileDto>(&content).ok())
+            .ok_or(CouldNotReadFreezes)
+    }
+
+    fn write_freezes_file(&self, freezes_file: &FreezesFileDto) -> Result<(), FreezeStoreError> {
+        let content =
+            serde_json::to_string_pretty(freezes_file).map_err(|_| CouldNotWriteFreezes)?;
+
+        atomic_write(&self.file_path, content.as_bytes(), 0o600).map_err(|_| CouldNotWriteFreezes)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum FreezeStoreError {
+    CouldNotReadFreezes,
+    CouldNotWriteFreezes,
+}
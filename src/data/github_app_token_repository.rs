@@ -0,0 +1,107 @@
+use std::sync::Mutex;
+
+use chrono::{DateTime, Duration, Utc};
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use reqwest::Client;
+use reqwest::header::{ACCEPT, AUTHORIZATION, USER_AGENT};
+use serde::{Deserialize, Serialize};
+
+use crate::data::github_app_token_repository::GithubAppTokenError::{
+    CouldNotCreateJwt, CouldNotGetToken, InvalidPrivateKey,
+};
+
+pub struct GithubAppTokenRepository {
+    // without the default headers of the api client, they'd authorize as the user
+    api_client: Client,
+    app_id: String,
+    installation_id: String,
+    private_key: EncodingKey,
+    cached_token: Mutex<Option<InstallationTokenDto>>,
+}
+
+impl GithubAppTokenRepository {
+    pub fn new(
+        app_id: String,
+        installation_id: String,
+        private_key_pem: &[u8],
+    ) -> Result<GithubAppTokenRepository, GithubAppTokenError> {
+        let private_key =
+            EncodingKey::from_rsa_pem(private_key_pem).map_err(|_| InvalidPrivateKey)?;
+
+        Ok(GithubAppTokenRepository {
+            api_client: Client::new(),
+            app_id,
+            installation_id,
+            private_key,
+            cached_token: Mutex::new(None),
+        })
+    }
+
+    // installation tokens live for an hour, a cached one is used until shortly before it expires
+    pub async fn get_token(&self) -> Result<String, GithubAppTokenError> {
+        if let Some(token) = self
+            .cached_token
+            .lock()
+            .unwrap()
+            .as_ref()
+            .filter(|token| token.expires_at - Duration::minutes(5) > Utc::now())
+        {
+            return Ok(token.token.clone());
+        }
+
+        let url = format!(
+            "https://api.github.com/app/installations/{installation_id}/access_tokens",
+            installation_id = self.installation_id
+        );
+        let token = self
+            .api_client
+            .post(url)
+            .header(USER_AGENT, "reqwest")
+            .header(ACCEPT, "application/vnd.github.v3+json")
+            .header(AUTHORIZATION, format!("Bearer {}", self.create_jwt()?))
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|_| CouldNotGetToken)?
+            .json::<InstallationTokenDto>()
+            .await
+            .map_err(|_| CouldNotGetToken)?;
+
+        *self.cached_token.lock().unwrap() = Some(token.clone());
+
+        Ok(token.token)
+    }
+
+    // github accepts app jwts for at most ten minutes, iat is backdated against clock drift
+    fn create_jwt(&self) -> Result<String, GithubAppTokenError> {
+        let now = Utc::now();
+        let claims = JwtClaims {
+            iat: (now - Duration::seconds(60)).timestamp(),
+            exp: (now + Duration::minutes(9)).timestamp(),
+            iss: self.app_id.clone(),
+        };
+
+        jsonwebtoken::encode(&Header::new(Algorithm::RS256), &claims, &self.private_key)
+            .map_err(|_| CouldNotCreateJwt)
+    }
+}
+
+#[derive(Serialize)]
+struct JwtClaims {
+    iat: i64,
+    exp: i64,
+    iss: String,
+}
+
+#[derive(Deserialize, Clone)]
+struct InstallationTokenDto {
+    token: String,
+    expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug)]
+pub enum GithubAppTokenError {
+    InvalidPrivateKey,
+    CouldNotCreateJwt,
+    CouldNotGetToken,
+}
@@ -0,0 +1,172 @@
+use async_trait::async_trait;
+
+use crate::data::github_client::GithubClientError::{
+    CouldNotCreateDeployKey, CouldNotCreateWebhook, CouldNotDeleteDeployKey,
+    CouldNotGetDeployKeys, CouldNotGetFileHeaders, CouldNotGetRepos, CouldNotGetWebhooks,
+};
+use crate::data::github_deploy_key_repository::{
+    GithubDeployKeyCreateDto, GithubDeployKeyDto, GithubDeployKeyRepository,
+};
+use crate::data::github_repo_repository::{DtoWithHeaders, GithubRepoDto, GithubRepoRepository};
+use crate::data::github_webhook_repository::{
+    GithubWebhookCreateDto, GithubWebhookDto, GithubWebhookRepository,
+};
+
+#[async_trait]
+pub trait GithubClient: Send + Sync {
+    async fn get_user_repos(
+        &self,
+        page: u32,
+        per_page: u32,
+    ) -> Result<DtoWithHeaders<Vec<GithubRepoDto>>, GithubClientError>;
+
+    async fn file_exists(&self, url: &str) -> Result<bool, GithubClientError>;
+
+    async fn get_webhooks(
+        &self,
+        owner_name: String,
+        repo_name: String,
+    ) -> Result<Vec<GithubWebhookDto>, GithubClientError>;
+
+    async fn create_webhook(
+        &self,
+        owner_name: String,
+        repo_name: String,
+        dto: GithubWebhookCreateDto,
+    ) -> Result<GithubWebhookDto, GithubClientError>;
+
+    async fn get_deploy_keys(
+        &self,
+        owner_name: String,
+        repo_name: String,
+    ) -> Result<Vec<GithubDeployKeyDto>, GithubClientError>;
+
+    async fn create_deploy_key(
+        &self,
+        owner_name: String,
+        repo_name: String,
+        dto: GithubDeployKeyCreateDto,
+    ) -> Result<GithubDeployKeyDto, GithubClientError>;
+
+    async fn delete_deploy_key(
+        &self,
+        owner_name: String,
+        repo_name: String,
+        key_id: i64,
+    ) -> Result<(), GithubClientError>;
+}
+
+pub struct GithubApiClient {
+    github_repo_repository: GithubRepoRepository,
+    github_webhook_repository: GithubWebhookRepository,
+    github_deploy_key_repository: GithubDeployKeyRepository,
+}
+
+impl GithubApiClient {
+    pub fn new(
+        github_repo_repository: GithubRepoRepository,
+        github_webhook_repository: GithubWebhookRepository,
+        github_deploy_key_repository: GithubDeployKeyRepository,
+    ) -> GithubApiClient {
+        GithubApiClient {
+            github_repo_repository,
+            github_webhook_repository,
+            github_deploy_key_repository,
+        }
+    }
+}
+
+#[async_trait]
+impl GithubClient for GithubApiClient {
+    async fn get_user_repos(
+        &self,
+        page: u32,
+        per_page: u32,
+    ) -> Result<DtoWithHeaders<Vec<GithubRepoDto>>, GithubClientError> {
+        self.github_repo_repository
+            .get_user_repos(page, per_page, "owner", "created", "asc")
+            .await
+            .map_err(|_| CouldNotGetRepos)
+    }
+
+    async fn file_exists(&self, url: &str) -> Result<bool, GithubClientError> {
+        self.github_repo_repository
+            .get_headers(url)
+            .await
+            .map(|response| response.status().is_success())
+            .map_err(|_| CouldNotGetFileHeaders)
+    }
+
+    async fn get_webhooks(
+        &self,
+        owner_name: String,
+        repo_name: String,
+    ) -> Result<Vec<GithubWebhookDto>, GithubClientError> {
+        self.github_webhook_repository
+            .get_webhooks(owner_name, repo_name)
+            .await
+            .map(|dtos| *dtos)
+            .map_err(|_| CouldNotGetWebhooks)
+    }
+
+    async fn create_webhook(
+        &self,
+        owner_name: String,
+        repo_name: String,
+        dto: GithubWebhookCreateDto,
+    ) -> Result<GithubWebhookDto, GithubClientError> {
+        self.github_webhook_repository
+            .create_webhook(owner_name, repo_name, dto)
+            .await
+            .map(|dto| *dto)
+            .map_err(|_| CouldNotCreateWebhook)
+    }
+
+    async fn get_deploy_keys(
+        &self,
+        owner_name: String,
+        repo_name: String,
+    ) -> Result<Vec<GithubDeployKeyDto>, GithubClientError> {
+        self.github_deploy_key_repository
+            .get_deploy_keys(owner_name, repo_name)
+            .await
+            .map(|dtos| *dtos)
+            .map_err(|_| CouldNotGetDeployKeys)
+    }
+
+    async fn create_deploy_key(
+        &self,
+        owner_name: String,
+        repo_name: String,
+        dto: GithubDeployKeyCreateDto,
+    ) -> Result<GithubDeployKeyDto, GithubClientError> {
+        self.github_deploy_key_repository
+            .create_deploy_key(owner_name, repo_name, dto)
+            .await
+            .map(|dto| *dto)
+            .map_err(|_| CouldNotCreateDeployKey)
+    }
+
+    async fn delete_deploy_key(
+        &self,
+        owner_name: String,
+        repo_name: String,
+        key_id: i64,
+    ) -> Result<(), GithubClientError> {
+        self.github_deploy_key_repository
+            .delete_deploy_key(owner_name, repo_name, key_id)
+            .await
+            .map_err(|_| CouldNotDeleteDeployKey)
+    }
+}
+
+#[derive(Debug)]
+pub enum GithubClientError {
+    CouldNotGetRepos,
+    CouldNotGetFileHeaders,
+    CouldNotGetWebhooks,
+    CouldNotCreateWebhook,
+    CouldNotGetDeployKeys,
+    CouldNotCreateDeployKey,
+    CouldNotDeleteDeployKey,
+}
@@ -0,0 +1,81 @@
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::data::api_call_delegate::{ApiCallDelegate, ApiCallError};
+
+pub struct GithubDeployKeyRepository {
+    api_delegate: Arc<ApiCallDelegate>,
+}
+
+impl GithubDeployKeyRepository {
+    pub fn new(api_delegate: Arc<ApiCallDelegate>) -> GithubDeployKeyRepository {
+        GithubDeployKeyRepository { api_delegate }
+    }
+
+    pub async fn get_deploy_keys(
+        &self,
+        owner_name: String,
+        repo_name: String,
+    ) -> Result<Box<Vec<GithubDeployKeyDto>>, ApiCallError> {
+        let url = format!(
+            "https://api.github.com/repos/{owner_name}/{repo_name}/keys",
+            owner_name = owner_name,
+            repo_name = repo_name
+        );
+
+        self.api_delegate
+            .execute_get_call(url)
+            .await
+    }
+
+    pub async fn create_deploy_key(
+        &self,
+        owner_name: String,
+        repo_name: String,
+        dto: GithubDeployKeyCreateDto,
+    ) -> Result<Box<GithubDeployKeyDto>, ApiCallError> {
+        let url = format!(
+            "https://api.github.com/repos/{owner_name}/{repo_name}/keys",
+            owner_name = owner_name,
+            repo_name = repo_name
+        );
+
+        self.api_delegate
+            .execute_post_call(url, &dto)
+            .await
+    }
+
+    pub async fn delete_deploy_key(
+        &self,
+        owner_name: String,
+        repo_name: String,
+        key_id: i64,
+    ) -> Result<(), ApiCallError> {
+        let url = format!(
+            "https://api.github.com/repos/{owner_name}/{repo_name}/keys/{key_id}",
+            owner_name = owner_name,
+            repo_name = repo_name,
+            key_id = key_id
+        );
+
+        self.api_delegate
+            .execute_delete_call(url)
+            .await
+    }
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GithubDeployKeyCreateDto {
+    pub title: String,
+    pub key: String,
+    pub read_only: bool,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GithubDeployKeyDto {
+    pub id: i64,
+    pub key: String,
+    pub title: String,
+    pub read_only: bool,
+}
@@ -1,15 +1,15 @@
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 
 use serde::{Deserialize, Serialize};
 
 use crate::data::api_call_delegate::{ApiCallDelegate, ApiCallError};
 
 pub struct GithubWebhookRepository {
-    api_delegate: Arc<Mutex<ApiCallDelegate>>,
+    api_delegate: Arc<ApiCallDelegate>,
 }
 
 impl GithubWebhookRepository {
-    pub fn new(api_delegate: Arc<Mutex<ApiCallDelegate>>) -> GithubWebhookRepository {
+    pub fn new(api_delegate: Arc<ApiCallDelegate>) -> GithubWebhookRepository {
         GithubWebhookRepository { api_delegate }
     }
 
@@ -25,8 +25,6 @@ impl GithubWebhookRepository {
         );
 
         self.api_delegate
-            .lock()
-            .unwrap()
             .execute_get_call(url)
             .await
     }
@@ -45,8 +43,6 @@ impl GithubWebhookRepository {
         );
 
         self.api_delegate
-            .lock()
-            .unwrap()
             .execute_post_call(url, &dto)
             .await
     }
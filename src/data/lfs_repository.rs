@@ -0,0 +1,137 @@
+use std::collections::HashMap;
+
+use reqwest::Client;
+use reqwest::header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE};
+use serde::{Deserialize, Serialize};
+
+use crate::data::lfs_repository::LfsError::{CouldNotDownloadObject, CouldNotGetDownloadActions};
+
+static LFS_MEDIA_TYPE: &str = "application/vnd.git-lfs+json";
+
+// https://github.com/git-lfs/git-lfs/blob/main/docs/api/batch.md
+pub struct LfsRepository {
+    // lfs servers get the repo's credentials, not the api client's default headers
+    api_client: Client,
+}
+
+impl LfsRepository {
+    pub fn new() -> LfsRepository {
+        LfsRepository {
+            api_client: Client::new(),
+        }
+    }
+
+    pub async fn get_download_actions(
+        &self,
+        lfs_url: &str,
+        authorization: Option<&str>,
+        objects: Vec<LfsObjectDto>,
+    ) -> Result<Vec<LfsBatchObjectDto>, LfsError> {
+        let dto = LfsBatchRequestDto {
+            operation: "download".to_string(),
+            transfers: vec!["basic".to_string()],
+            objects,
+        };
+        let mut request = self
+            .api_client
+            .post(format!("{}/objects/batch", lfs_url.trim_end_matches('/')))
+            .header(ACCEPT, LFS_MEDIA_TYPE)
+            .header(CONTENT_TYPE, LFS_MEDIA_TYPE)
+            .json(&dto);
+
+        if let Some(authorization) = authorization {
+            request = request.header(AUTHORIZATION, authorization);
+        }
+
+        request
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|err| {
+                println!("{}", err);
+                CouldNotGetDownloadActions
+            })?
+            .json::<LfsBatchResponseDto>()
+            .await
+            .map(|dto| dto.objects)
+            .map_err(|_| CouldNotGetDownloadActions)
+    }
+
+    // download urls are usually presigned, the action brings its own headers
+    pub async fn download(&self, action: &LfsActionDto) -> Result<Vec<u8>, LfsError> {
+        let mut request = self.api_client.get(action.href.as_str());
+
+        for (name, value) in &action.header {
+            request = request.header(name.as_str(), value.as_str());
+        }
+
+        request
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|err| {
+                println!("{}", err);
+                CouldNotDownloadObject
+            })?
+            .bytes()
+            .await
+            .map(|bytes| bytes.to_vec())
+            .map_err(|_| CouldNotDownloadObject)
+    }
+}
+
+impl Default for LfsRepository {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Serialize)]
+struct LfsBatchRequestDto {
+    operation: String,
+    transfers: Vec<String>,
+    objects: Vec<LfsObjectDto>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LfsObjectDto {
+    pub oid: String,
+    pub size: u64,
+}
+
+#[derive(Deserialize)]
+struct LfsBatchResponseDto {
+    objects: Vec<LfsBatchObjectDto>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LfsBatchObjectDto {
+    pub oid: String,
+    pub size: u64,
+    pub actions: Option<LfsActionsDto>,
+    pub error: Option<LfsObjectErrorDto>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LfsActionsDto {
+    pub download: Option<LfsActionDto>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LfsActionDto {
+    pub href: String,
+    #[serde(default)]
+    pub header: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LfsObjectErrorDto {
+    pub code: i64,
+    pub message: String,
+}
+
+#[derive(Debug)]
+pub enum LfsError {
+    CouldNotGetDownloadActions,
+    CouldNotDownloadObject,
+}
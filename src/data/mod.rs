@@ -1,5 +1,17 @@
+pub mod approver_store;
+pub mod atomic_write;
+pub mod audit_log;
+pub mod deploy_history_store;
+pub mod deploy_info;
 pub mod deploy_info_repository;
+pub mod deploy_key_store;
+pub mod freeze_store;
+pub mod github_app_token_repository;
+pub mod github_client;
+pub mod github_deploy_key_repository;
 pub mod github_repo_repository;
 pub mod github_webhook_repository;
+pub mod lfs_repository;
 pub mod api_call_delegate;
-
+pub mod run_repository;
+pub mod secret_store;
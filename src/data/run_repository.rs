@@ -0,0 +1,452 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use strum::Display;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RunEntity {
+    pub id: u64,
+    pub ssh_git_url: String,
+    pub branch_name: String,
+    pub sha: String,
+    pub trigger: RunTrigger,
+    pub status: RunStatus,
+    pub superseded_by: Option<u64>,
+    // the approver who cancelled the run
+    pub cancelled_by: Option<String>,
+    // why a skipped run didn't deploy
+    pub skip_reason: Option<String>,
+    // why a held run waits, or why one outside its deploy windows was rejected
+    pub hold_reason: Option<String>,
+    // who let the run deploy outside its windows or during a freeze
+    pub window_override: Option<OverrideEntity>,
+    // of the run's branch and jobs, in the order they were asked for
+    pub approvals: Vec<ApprovalEntity>,
+    pub jobs: Vec<JobEntity>,
+    // the steps of all jobs, in the order of their jobs
+    pub steps: Vec<StepEntity>,
+    pub health_check: Option<HealthCheckEntity>,
+    // of a run whose health check failed
+    pub rollback: Option<RollbackEntity>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HealthCheckEntity {
+    pub url: String,
+    pub status: StepStatus,
+    pub attempts: u32,
+    // why the last probe failed
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RollbackEntity {
+    // the commit rolled back to
+    pub sha: String,
+    pub status: StepStatus,
+    pub steps: Vec<StepEntity>,
+}
+
+// a job's status is the one of the step it stopped at
+#[derive(Debug, Clone, Serialize)]
+pub struct JobEntity {
+    pub name: String,
+    pub needs: Vec<String>,
+    pub status: StepStatus,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ApprovalEntity {
+    // None for the branch's approval
+    pub job: Option<String>,
+    pub requested_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    // None while it's awaited
+    pub decision: Option<ApprovalDecision>,
+    pub approver: Option<String>,
+    pub decided_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OverrideEntity {
+    pub approver: String,
+    pub reason: String,
+    pub overridden_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StepEntity {
+    pub job: String,
+    pub command: String,
+    pub status: StepStatus,
+    pub exit_code: Option<i32>,
+}
+
+// what started a run
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum RunTrigger {
+    Push,
+    // the cron expression of the schedule
+    Schedule { cron: String },
+    // redeploys the commit of an earlier run
+    Rollback { approver: String, deploy_run_id: u64 },
+    // with the values of the repo's inputs
+    Manual {
+        approver: String,
+        inputs: HashMap<String, String>,
+    },
+}
+
+#[derive(Display, Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum RunStatus {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+    Superseded,
+    // none of the branch's paths changed
+    Skipped,
+    AwaitingApproval,
+    Rejected,
+    // outside the deploy windows or during a freeze
+    Held,
+}
+
+#[derive(Display, Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum StepStatus {
+    Pending,
+    Running,
+    Succeeded,
+    Failed,
+    TimedOut,
+    Cancelled,
+    Skipped,
+    // only jobs wait for approval
+    AwaitingApproval,
+    Rejected,
+}
+
+#[derive(Display, Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum ApprovalDecision {
+    Approved,
+    Rejected,
+    // nobody decided in time
+    Expired,
+}
+
+pub struct RunRepository {
+    cache: HashMap<u64, RunEntity>,
+    next_id: u64,
+}
+
+impl Default for RunRepository {
+    fn default() -> Self {
+        RunRepository::new()
+    }
+}
+
+impl RunRepository {
+    pub fn new() -> RunRepository {
+        RunRepository {
+            cache: HashMap::new(),
+            next_id: 1,
+        }
+    }
+
+    pub fn create(
+        &mut self,
+        ssh_git_url: String,
+        branch_name: String,
+        sha: String,
+        trigger: RunTrigger,
+        jobs: Vec<JobEntity>,
+        steps: Vec<StepEntity>,
+    ) -> u64 {
+        let id = self.next_id;
+
+        self.next_id += 1;
+        self.cache.insert(
+            id,
+            RunEntity {
+                id,
+                ssh_git_url,
+                branch_name,
+                sha,
+                trigger,
+                status: RunStatus::Queued,
+                superseded_by: None,
+                cancelled_by: None,
+                skip_reason: None,
+                hold_reason: None,
+                window_override: None,
+                approvals: vec![],
+                jobs,
+                steps,
+                health_check: None,
+                rollback: None,
+                created_at: Utc::now(),
+            },
+        );
+
+        id
+    }
+
+    pub fn get(&self, id: u64) -> Option<&RunEntity> {
+        self.cache.get(&id)
+    }
+
+    // ids of the branch's runs that are in one of the given states
+    pub fn find_by_branch(
+        &self,
+        ssh_git_url: &str,
+        branch_name: &str,
+        statuses: &[RunStatus],
+    ) -> Vec<u64> {
+        self.cache
+            .values()
+            .filter(|run| run.ssh_git_url == ssh_git_url && run.branch_name == branch_name)
+            .filter(|run| statuses.contains(&run.status))
+            .map(|run| run.id)
+            .collect()
+    }
+
+    // ids of the branch's unfinished runs that were started by the trigger
+    pub fn find_unfinished_by_trigger(
+        &self,
+        ssh_git_url: &str,
+        branch_name: &str,
+        trigger: &RunTrigger,
+    ) -> Vec<u64> {
+        self.cache
+            .values()
+            .filter(|run| run.ssh_git_url == ssh_git_url && run.branch_name == branch_name)
+            .filter(|run| run.trigger == *trigger && !run.status.is_finished())
+            .map(|run| run.id)
+            .collect()
+    }
+
+    // names of the repo's branches that have queued or running runs
+    pub fn find_active_branch_names(&self, ssh_git_url: &str) -> Vec<String> {
+        let mut branch_names = self
+            .cache
+            .values()
+            .filter(|run| run.ssh_git_url == ssh_git_url && !run.status.is_finished())
+            .map(|run| run.branch_name.clone())
+            .collect::<Vec<String>>();
+
+        branch_names.sort();
+        branch_names.dedup();
+        branch_names
+    }
+
+    pub fn mark_superseded(&mut self, id: u64, superseded_by: u64) {
+        if let Some(run) = self.cache.get_mut(&id) {
+            run.superseded_by = Some(superseded_by);
+        }
+    }
+
+    pub fn mark_cancelled_by(&mut self, id: u64, approver: String) {
+        if let Some(run) = self.cache.get_mut(&id) {
+            run.cancelled_by = Some(approver);
+        }
+    }
+
+    // the run and everything in it is skipped
+    pub fn mark_skipped(&mut self, id: u64, skip_reason: String) {
+        if let Some(run) = self.cache.get_mut(&id) {
+            run.status = RunStatus::Skipped;
+            run.skip_reason = Some(skip_reason);
+
+            for job in run.jobs.iter_mut() {
+                job.status = StepStatus::Skipped;
+            }
+
+            for step in run.steps.iter_mut() {
+                step.status = StepStatus::Skipped;
+            }
+        }
+    }
+
+    pub fn mark_held(&mut self, id: u64, hold_reason: String) {
+        if let Some(run) = self.cache.get_mut(&id) {
+            run.status = RunStatus::Held;
+            run.hold_reason = Some(hold_reason);
+        }
+    }
+
+    // the held run goes on
+    pub fn release_hold(&mut self, id: u64) {
+        if let Some(run) = self.cache.get_mut(&id) {
+            run.status = RunStatus::Running;
+            run.hold_reason = None;
+        }
+    }
+
+    // the run keeps its status, the caller decides what becomes of it
+    pub fn update_hold_reason(&mut self, id: u64, hold_reason: String) {
+        if let Some(run) = self.cache.get_mut(&id) {
+            run.hold_reason = Some(hold_reason);
+        }
+    }
+
+    pub fn set_override(&mut self, id: u64, window_override: OverrideEntity) {
+        if let Some(run) = self.cache.get_mut(&id) {
+            run.window_override = Some(window_override);
+        }
+    }
+
+    pub fn request_approval(
+        &mut self,
+        id: u64,
+        job_name: Option<String>,
+        expires_at: Option<DateTime<Utc>>,
+    ) {
+        if let Some(run) = self.cache.get_mut(&id) {
+            run.approvals.push(ApprovalEntity {
+                job: job_name,
+                requested_at: Utc::now(),
+                expires_at,
+                decision: None,
+                approver: None,
+                decided_at: None,
+            });
+        }
+    }
+
+    // decides the awaited approval of the branch or job
+    pub fn decide_approval(
+        &mut self,
+        id: u64,
+        job_name: Option<&str>,
+        decision: ApprovalDecision,
+        approver: Option<String>,
+    ) {
+        if let Some(approval) = self.cache.get_mut(&id).and_then(|run| {
+            run.approvals.iter_mut().find(|approval| {
+                approval.decision.is_none() && approval.job.as_deref() == job_name
+            })
+        }) {
+            approval.decision = Some(decision);
+            approval.approver = approver;
+            approval.decided_at = Some(Utc::now());
+        }
+    }
+
+    pub fn update_health_check(&mut self, id: u64, health_check: HealthCheckEntity) {
+        if let Some(run) = self.cache.get_mut(&id) {
+            run.health_check = Some(health_check);
+        }
+    }
+
+    pub fn start_rollback(&mut self, id: u64, sha: String, steps: Vec<StepEntity>) {
+        if let Some(run) = self.cache.get_mut(&id) {
+            run.rollback = Some(RollbackEntity {
+                sha,
+                status: StepStatus::Running,
+                steps,
+            });
+        }
+    }
+
+    pub fn update_rollback(&mut self, id: u64, status: StepStatus) {
+        if let Some(rollback) = self.cache.get_mut(&id).and_then(|run| run.rollback.as_mut()) {
+            rollback.status = status;
+        }
+    }
+
+    pub fn update_rollback_step(
+        &mut self,
+        id: u64,
+        index: usize,
+        status: StepStatus,
+        exit_code: Option<i32>,
+    ) {
+        if let Some(step) = self
+            .cache
+            .get_mut(&id)
+            .and_then(|run| run.rollback.as_mut())
+            .and_then(|rollback| rollback.steps.get_mut(index))
+        {
+            step.status = status;
+            step.exit_code = exit_code;
+        }
+    }
+
+    pub fn update_status(&mut self, id: u64, status: RunStatus) {
+        if let Some(run) = self.cache.get_mut(&id) {
+            run.status = status;
+        }
+    }
+
+    pub fn update_job(&mut self, id: u64, job_name: &str, status: StepStatus) {
+        if let Some(job) = self
+            .cache
+            .get_mut(&id)
+            .and_then(|run| run.jobs.iter_mut().find(|job| job.name == job_name))
+        {
+            job.status = status;
+        }
+    }
+
+    pub fn update_step(&mut self, id: u64, index: usize, status: StepStatus, exit_code: Option<i32>) {
+        if let Some(step) = self
+            .cache
+            .get_mut(&id)
+            .and_then(|run| run.steps.get_mut(index))
+        {
+            step.status = status;
+            step.exit_code = exit_code;
+        }
+    }
+}
+
+impl JobEntity {
+    pub fn new(name: String, needs: Vec<String>) -> JobEntity {
+        JobEntity {
+            name,
+            needs,
+            status: StepStatus::Pending,
+        }
+    }
+}
+
+impl StepEntity {
+    pub fn new(job: String, command: String) -> StepEntity {
+        StepEntity {
+            job,
+            command,
+            status: StepStatus::Pending,
+            exit_code: None,
+        }
+    }
+}
+
+impl RunTrigger {
+    pub fn get_type(&self) -> &str {
+        match self {
+            RunTrigger::Push => "push",
+            RunTrigger::Schedule { .. } => "schedule",
+            RunTrigger::Rollback { .. } => "rollback",
+            RunTrigger::Manual { .. } => "manual",
+        }
+    }
+}
+
+impl RunStatus {
+    pub fn is_finished(&self) -> bool {
+        !matches!(
+            self,
+            RunStatus::Queued | RunStatus::Running | RunStatus::AwaitingApproval | RunStatus::Held
+        )
+    }
+}
@@ -0,0 +1,266 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::fs::{DirBuilder, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::Path;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use chacha20poly1305::{ChaCha20Poly1305, KeyInit, Nonce};
+use chacha20poly1305::aead::{Aead, AeadCore, OsRng, Payload};
+use serde::{Deserialize, Serialize};
+
+use crate::data::atomic_write::atomic_write;
+use crate::data::secret_store::SecretStoreError::{
+    CouldNotDecryptSecret, CouldNotEncryptSecret, CouldNotReadKey, CouldNotReadSecrets,
+    CouldNotWriteKey, CouldNotWriteSecrets, InvalidKey,
+};
+
+// secrets stored under this environment are used by every environment of the repo
+pub static ALL_ENVIRONMENTS: &str = "*";
+static SECRETS_FILE_NAME: &str = "secrets.json";
+static KEY_LENGTH: usize = 32;
+static NONCE_LENGTH: usize = 12;
+
+// one file of encrypted secrets per repo below root_dir/host/owner/name. Names stay readable,
+// values are encrypted with the master key and bound to their repo, environment and name
+pub struct SecretStore {
+    root_dir: String,
+    cipher: ChaCha20Poly1305,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct SecretsFileDto {
+    // environment -> name -> base64 of nonce and ciphertext
+    environments: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+impl SecretStore {
+    pub fn new(root_dir: String, key: &[u8]) -> Result<SecretStore, SecretStoreError> {
+        let cipher = ChaCha20Poly1305::new_from_slice(key).map_err(|_| InvalidKey)?;
+
+        Ok(SecretStore { root_dir, cipher })
+    }
+
+    // the key is kept base64 encoded, a missing key file gets a new random key
+    pub fn load_or_create_key(key_path: &str) -> Result<Vec<u8>, SecretStoreError> {
+        if Path::new(key_path).exists() {
+            return fs::read_to_string(key_path)
+                .map_err(|_| CouldNotReadKey)
+                .and_then(|key| Self::decode_key(key.as_str()));
+        }
+
+        let key = ChaCha20Poly1305::generate_key(&mut OsRng).to_vec();
+
+        if let Some(parent) = Path::new(key_path).parent() {
+            DirBuilder::new()
+                .recursive(true)
+                .mode(0o700)
+                .create(parent)
+                .map_err(|_| CouldNotWriteKey)?;
+        }
+
+        OpenOptions::new()
+            .create_new(true)
+            .write(true)
+            .mode(0o600)
+            .open(key_path)
+            .and_then(|mut file| file.write_all(STANDARD.encode(&key).as_bytes()))
+            .map_err(|_| CouldNotWriteKey)?;
+
+        println!("Created a new secrets key at {}", key_path);
+        Ok(key)
+    }
+
+    pub fn decode_key(key: &str) -> Result<Vec<u8>, SecretStoreError> {
+        STANDARD
+            .decode(key.trim())
+            .ok()
+            .filter(|key| key.len() == KEY_LENGTH)
+            .ok_or(InvalidKey)
+    }
+
+    // environment None stores the secret for all of the repo's environments
+    pub fn set(
+        &self,
+        repo_relative_path: &str,
+        environment: Option<&str>,
+        name: &str,
+        value: &str,
+    ) -> Result<(), SecretStoreError> {
+        let environment = environment.unwrap_or(ALL_ENVIRONMENTS);
+        let mut secrets_file = self.read_secrets_file(repo_relative_path)?;
+        let encrypted_value = self.encrypt(repo_relative_path, environment, name, value)?;
+
+        secrets_file
+            .environments
+            .entry(environment.to_string())
+            .or_default()
+            .insert(name.to_string(), encrypted_value);
+
+        self.write_secrets_file(repo_relative_path, &secrets_file)
+    }
+
+    // returns whether there was such a secret
+    pub fn remove(
+        &self,
+        repo_relative_path: &str,
+        environment: Option<&str>,
+        name: &str,
+    ) -> Result<bool, SecretStoreError> {
+        let environment = environment.unwrap_or(ALL_ENVIRONMENTS);
+        let mut secrets_file = self.read_secrets_file(repo_relative_path)?;
+        let removed = secrets_file
+            .environments
+            .get_mut(environment)
+            .and_then(|secrets| secrets.remove(name))
+            .is_some();
+
+        secrets_file.environments.retain(|_, secrets| !secrets.is_empty());
+
+        if removed {
+            self.write_secrets_file(repo_relative_path, &secrets_file)?;
+        }
+
+        Ok(removed)
+    }
+
+    // environment and name of every secret of the repo, without their values
+    pub fn list(
+        &self,
+        repo_relative_path: &str,
+    ) -> Result<Vec<(String, String)>, SecretStoreError> {
+        Ok(self
+            .read_secrets_file(repo_relative_path)?
+            .environments
+            .into_iter()
+            .flat_map(|(environment, secrets)| {
+                secrets
+                    .into_keys()
+                    .map(move |name| (environment.clone(), name))
+            })
+            .collect())
+    }
+
+    // the decrypted secrets of the environment, its own ones win over the repo wide ones
+    pub fn get_secrets(
+        &self,
+        repo_relative_path: &str,
+        environment: &str,
+    ) -> Result<HashMap<String, String>, SecretStoreError> {
+        let secrets_file = self.read_secrets_file(repo_relative_path)?;
+        let mut secrets = HashMap::new();
+
+        for scope in [ALL_ENVIRONMENTS, environment] {
+            let scope_secrets = secrets_file.environments.get(scope).into_iter().flatten();
+
+            for (name, encrypted_value) in scope_secrets {
+                let value = self.decrypt(repo_relative_path, scope, name, encrypted_value)?;
+                secrets.insert(name.clone(), value);
+            }
+        }
+
+        Ok(secrets)
+    }
+
+    fn encrypt(
+        &self,
+        repo_relative_path: &str,
+        environment: &str,
+        name: &str,
+        value: &str,
+    ) -> Result<String, SecretStoreError> {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let associated_data = Self::get_associated_data(repo_relative_path, environment, name);
+        let ciphertext = self
+            .cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: value.as_bytes(),
+                    aad: associated_data.as_bytes(),
+                },
+            )
+            .map_err(|_| CouldNotEncryptSecret)?;
+
+        Ok(STANDARD.encode([nonce.as_slice(), ciphertext.as_slice()].concat()))
+    }
+
+    fn decrypt(
+        &self,
+        repo_relative_path: &str,
+        environment: &str,
+        name: &str,
+        encrypted_value: &str,
+    ) -> Result<String, SecretStoreError> {
+        let associated_data = Self::get_associated_data(repo_relative_path, environment, name);
+
+        STANDARD
+            .decode(encrypted_value)
+            .ok()
+            .filter(|bytes| bytes.len() > NONCE_LENGTH)
+            .and_then(|bytes| {
+                let (nonce, ciphertext) = bytes.split_at(NONCE_LENGTH);
+
+                self.cipher
+                    .decrypt(
+                        Nonce::from_slice(nonce),
+                        Payload {
+                            msg: ciphertext,
+                            aad: associated_data.as_bytes(),
+                        },
+                    )
+                    .ok()
+            })
+            .and_then(|value| String::from_utf8(value).ok())
+            .ok_or(CouldNotDecryptSecret)
+    }
+
+    // a value copied to another repo, environment or name doesn't decrypt
+    fn get_associated_data(repo_relative_path: &str, environment: &str, name: &str) -> String {
+        format!("{}\n{}\n{}", repo_relative_path, environment, name)
+    }
+
+    fn read_secrets_file(
+        &self,
+        repo_relative_path: &str,
+    ) -> Result<SecretsFileDto, SecretStoreError> {
+        let path = self.get_secrets_file_path(repo_relative_path);
+
+        if !Path::new(&path).exists() {
+            return Ok(SecretsFileDto::default());
+        }
+
+        fs::read_to_string(&path)
+            .ok()
+            .and_then(|content| serde_json::from_str::<SecretsFileDto>(&content).ok())
+            .ok_or(CouldNotReadSecrets)
+    }
+
+    fn write_secrets_file(
+        &self,
+        repo_relative_path: &str,
+        secrets_file: &SecretsFileDto,
+    ) -> Result<(), SecretStoreError> {
+        let content = serde_json::to_string_pretty(secrets_file).map_err(|_| CouldNotWriteSecrets)?;
+
+        atomic_write(&self.get_secrets_file_path(repo_relative_path), content.as_bytes(), 0o600)
+            .map_err(|_| CouldNotWriteSecrets)
+    }
+
+    fn get_secrets_file_path(&self, repo_relative_path: &str) -> String {
+        format!("{}/{}/{}", self.root_dir, repo_relative_path, SECRETS_FILE_NAME)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SecretStoreError {
+    InvalidKey,
+    CouldNotReadKey,
+    CouldNotWriteKey,
+    CouldNotReadSecrets,
+    CouldNotWriteSecrets,
+    CouldNotEncryptSecret,
+    CouldNotDecryptSecret,
+}
@@ -0,0 +1,16 @@
+use crate::domain::deploy_service::DeployService;
+use crate::domain::deploy_tracker::DeployTracker;
+
+pub struct AppState {
+    pub deploy_service: DeployService,
+    pub deploy_tracker: DeployTracker,
+}
+
+impl AppState {
+    pub fn new(deploy_service: DeployService, deploy_tracker: DeployTracker) -> AppState {
+        AppState {
+            deploy_service,
+            deploy_tracker,
+        }
+    }
+}
@@ -1,2 +1,2 @@
-pub mod singletons;
+pub mod app_state;
 pub mod start_up_args;
@@ -1,11 +1,212 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
+
+use crate::domain::git_credentials::GitAuthMethod;
+use crate::domain::host_key_verifier::HostKeyPolicy;
 
 #[derive(Parser, Debug)]
 #[clap(long_about = None)]
 pub struct StartupArgs {
+    #[clap(subcommand)]
+    pub(crate) command: Option<StartupCommand>,
+
+    /// How git authenticates: ssh-key, ssh-key-memory, ssh-agent, https-token or github-app
+    #[clap(long, default_value = "ssh-key")]
+    pub(crate) git_auth: GitAuthMethod,
+
+    /// Auth method of a single repo as host/owner/name=method, can be given several times
     #[clap(long)]
+    pub(crate) repo_git_auth: Vec<String>,
+
+    /// Passphrase of the ssh key, for ssh-key and ssh-key-memory
+    #[clap(long, default_value = "")]
     pub(crate) ssh_passphrase: String,
 
+    /// Key file for ssh-key, ssh-key-memory reads the key from MINI_CI_SSH_PRIVATE_KEY instead
+    #[clap(long)]
+    pub(crate) ssh_key_path: Option<String>,
+
+    /// known_hosts file ssh host keys are checked against [default: ~/.ssh/known_hosts]
+    #[clap(long)]
+    pub(crate) known_hosts_path: Option<String>,
+
+    /// What happens with hosts missing from known_hosts: strict or trust-on-first-use
+    #[clap(long, default_value = "trust-on-first-use")]
+    pub(crate) host_key_policy: HostKeyPolicy,
+
+    /// Pins a host's key as host=SHA256:..., can be given several times. github.com is pinned
+    /// to its published keys by default
+    #[clap(long)]
+    pub(crate) host_key_fingerprint: Vec<String>,
+
+    #[clap(long)]
+    pub(crate) github_app_id: Option<String>,
+
+    #[clap(long)]
+    pub(crate) github_app_installation_id: Option<String>,
+
+    /// PEM file with the github app's private key
+    #[clap(long)]
+    pub(crate) github_app_private_key_path: Option<String>,
+
+    /// Generates a read-only deploy key per repo and uses it instead of --git-auth. Repos given
+    /// with --repo-git-auth keep their credentials
+    #[clap(long)]
+    pub(crate) deploy_keys: bool,
+
+    /// Where the generated deploy keys are kept [default: <workspace-root>/.deploy-keys]
+    #[clap(long)]
+    pub(crate) deploy_keys_path: Option<String>,
+
+    /// Commits fetched per branch, unset or 0 fetches the full history. docker-deploy.yml can set
+    /// its own under clone.depth
+    #[clap(long)]
+    pub(crate) clone_depth: Option<u32>,
+
+    /// Path checked out of every repo, can be given several times. All paths are checked out
+    /// without one
+    #[clap(long)]
+    pub(crate) sparse_path: Vec<String>,
+
+    /// Where the encrypted secrets are kept [default: <workspace-root>/.secrets]
     #[clap(long)]
-    pub(crate) ssh_key_path: String,
-}
\ No newline at end of file
+    pub(crate) secrets_path: Option<String>,
+
+    /// File with the base64 key the secrets are encrypted with, a new key is created if it's
+    /// missing. MINI_CI_SECRETS_KEY takes precedence [default: <secrets-path>/master.key]
+    #[clap(long)]
+    pub(crate) secrets_key_path: Option<String>,
+
+    /// File with the approvers of runs and the hashes of their tokens
+    /// [default: <workspace-root>/.approvers.json]
+    #[clap(long)]
+    pub(crate) approvers_path: Option<String>,
+
+    /// When branches of an environment may deploy, as environment=days hours [timezone], e.g.
+    /// prod=mon-thu 09:00-16:00 Europe/Berlin. Can be given several times, windows in
+    /// docker-deploy.yml take precedence
+    #[clap(long)]
+    pub(crate) deploy_window: Vec<String>,
+
+    /// File with the change freezes set through the api [default: <workspace-root>/.freezes.json]
+    #[clap(long)]
+    pub(crate) freezes_path: Option<String>,
+
+    /// File with the deploys rollbacks pick from [default: <workspace-root>/.deploys.json]
+    #[clap(long)]
+    pub(crate) deploys_path: Option<String>,
+
+    /// File freezes and overrides of deploy windows are logged to
+    /// [default: <workspace-root>/audit.log]
+    #[clap(long)]
+    pub(crate) audit_log_path: Option<String>,
+
+    /// Directory the repos are cloned into, clones in it are reused after a restart
+    #[clap(long, default_value = "/var/lib/mini-ci")]
+    pub(crate) workspace_root: String,
+
+    #[clap(long, default_value = "0.0.0.0:8083")]
+    pub(crate) bind_address: String,
+
+    /// How long to wait for running deploys after SIGTERM/SIGINT before exiting anyway
+    #[clap(long, default_value = "300")]
+    pub(crate) shutdown_timeout_secs: u64,
+
+    /// Deploys of different branches running at the same time, deploys of one branch always queue
+    #[clap(long, default_value = "2")]
+    pub(crate) max_concurrent_deploys: usize,
+
+    /// Blocking git operations (clone, checkout, ...) running at the same time
+    #[clap(long, default_value = "4")]
+    pub(crate) max_concurrent_git_tasks: usize,
+
+    /// Timeout for deploy commands without a timeout in docker-deploy.yml
+    #[clap(long, default_value = "3600")]
+    pub(crate) default_step_timeout_secs: u64,
+
+    /// Time between SIGTERM and SIGKILL when a command is stopped
+    #[clap(long, default_value = "10")]
+    pub(crate) kill_grace_period_secs: u64,
+
+    /// How long a branch's worktree is kept after its last deploy
+    #[clap(long, default_value = "604800")]
+    pub(crate) worktree_retention_secs: u64,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum StartupCommand {
+    /// Manages the secrets docker-deploy.yml refers to as ${{ secrets.NAME }}
+    #[clap(subcommand)]
+    Secrets(SecretsCommand),
+    /// Manages who may approve runs of branches and jobs with approval: required
+    #[clap(subcommand)]
+    Approvers(ApproversCommand),
+    /// Approves or rejects runs of a running server, with the token in MINI_CI_APPROVER_TOKEN
+    #[clap(subcommand)]
+    Runs(RunsCommand),
+    /// Starts a run of a branch on a running server, with the token in MINI_CI_APPROVER_TOKEN
+    Deploy {
+        repo: String,
+        branch: String,
+        /// Full sha of the commit to run [default: the branch's latest one]
+        #[clap(long)]
+        sha: Option<String>,
+        /// Value of an input of docker-deploy.yml as name=value, can be given several times
+        #[clap(long)]
+        input: Vec<String>,
+        #[clap(long, default_value = "http://127.0.0.1:8083")]
+        server_url: String,
+    },
+}
+
+// repos are given as host/owner/name or as their url
+#[derive(Subcommand, Debug)]
+pub enum SecretsCommand {
+    /// Stores a secret, its value is read from stdin
+    Set {
+        repo: String,
+        name: String,
+        /// Only branches deploying to this environment get the secret [default: all]
+        #[clap(long)]
+        environment: Option<String>,
+    },
+    /// Lists the environments and names of a repo's secrets
+    List { repo: String },
+    /// Removes a secret
+    Remove {
+        repo: String,
+        name: String,
+        #[clap(long)]
+        environment: Option<String>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ApproversCommand {
+    /// Adds an approver and prints their token, an existing approver gets a new one
+    Add { name: String },
+    /// Lists the approvers
+    List,
+    /// Removes an approver, their token stops working
+    Remove { name: String },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum RunsCommand {
+    /// Approves the awaited approvals of a run
+    Approve {
+        run_id: u64,
+        /// Only the approval of this job [default: all awaited ones]
+        #[clap(long)]
+        job: Option<String>,
+        #[clap(long, default_value = "http://127.0.0.1:8083")]
+        server_url: String,
+    },
+    /// Rejects the awaited approvals of a run, the jobs behind them are skipped
+    Reject {
+        run_id: u64,
+        #[clap(long)]
+        job: Option<String>,
+        #[clap(long, default_value = "http://127.0.0.1:8083")]
+        server_url: String,
+    },
+}
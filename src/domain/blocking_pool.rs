@@ -0,0 +1,36 @@
+use std::sync::Arc;
+
+use tokio::sync::Semaphore;
+
+use crate::domain::blocking_pool::BlockingPoolError::{PoolClosed, TaskPanicked};
+
+// git2 calls block, so they run here instead of on the async workers
+pub struct BlockingPool {
+    permits: Arc<Semaphore>,
+}
+
+impl BlockingPool {
+    pub fn new(size: usize) -> BlockingPool {
+        BlockingPool {
+            permits: Arc::new(Semaphore::new(size)),
+        }
+    }
+
+    pub async fn run<F, T>(&self, task: F) -> Result<T, BlockingPoolError>
+        where
+            F: FnOnce() -> T + Send + 'static,
+            T: Send + 'static,
+    {
+        let _permit = self.permits.acquire().await.map_err(|_| PoolClosed)?;
+
+        tokio::task::spawn_blocking(task)
+            .await
+            .map_err(|_| TaskPanicked)
+    }
+}
+
+#[derive(Debug)]
+pub enum BlockingPoolError {
+    PoolClosed,
+    TaskPanicked,
+}
@@ -1,20 +1,25 @@
 use std::fs;
 use std::path::Path;
+use std::sync::Arc;
 
-use git2::{Cred, FetchOptions, RemoteCallbacks, Repository};
-use git2::build::RepoBuilder;
-use lazy_static::lazy_static;
-use regex::Regex;
+use git2::{BranchType, CertificateCheckStatus, FetchOptions, RemoteCallbacks, Repository};
+use git2::build::{CheckoutBuilder, RepoBuilder};
 
+use crate::data::deploy_info::CloneOptions;
 use crate::domain::clone_repo_task::CloneRepoTaskError::{
-    CouldNotCloneRepo, CouldNotDeleteExistingRepoDir, CouldNotExtractRepoName,
+    CouldNotCloneRepo, CouldNotDeleteExistingRepoDir, CouldNotFetchRepo, CouldNotParseRepoUrl,
+    CouldNotUpdateRepo,
 };
+use crate::domain::git_credentials::GitCredentials;
+use crate::domain::host_key_verifier::HostKeyVerifier;
+use crate::domain::repo_url::RepoUrl;
 
-lazy_static! {
-    static ref REPO_NAME_REGEX: Regex = Regex::new(r".*/(.*(\.))").unwrap();
-}
+// the ssh username lookup takes one attempt of its own
+static MAX_CREDENTIALS_ATTEMPTS: u32 = 4;
 
-pub struct CloneRepoTask {}
+pub struct CloneRepoTask {
+    host_key_verifier: Arc<HostKeyVerifier>,
+}
 
 pub struct CloneRepoTaskResult {
     pub repo_path: String,
@@ -22,94 +27,167 @@ pub struct CloneRepoTaskResult {
 }
 
 impl CloneRepoTask {
-    pub fn new() -> CloneRepoTask {
-        return CloneRepoTask {};
+    pub fn new(host_key_verifier: Arc<HostKeyVerifier>) -> CloneRepoTask {
+        return CloneRepoTask { host_key_verifier };
     }
 
+    // reuses a valid clone in into_dir_path and brings it up to date, clones the repo otherwise
     pub fn execute(
         &self,
         url: String,
-        into_dir_path: &'static str,
-        ssh_passphrase: &String,
-        ssh_key_path: &String,
+        into_dir_path: &str,
+        credentials: &GitCredentials,
+        clone_options: &CloneOptions,
     ) -> Result<CloneRepoTaskResult, CloneRepoTaskError> {
         return self
-            .extract_repo_name(url.as_str())
-            .and_then(|data_holder_one| self.delete_repo_dir(into_dir_path, data_holder_one))
+            .parse_repo_url(url.as_str(), credentials)
+            .and_then(|data_holder_one| self.open_existing_repo(into_dir_path, data_holder_one))
             .and_then(|data_holder_two| {
-                self.clone_repo(url.as_str(), data_holder_two, ssh_passphrase, ssh_key_path)
+                match data_holder_two.existing_repository {
+                    Some(_) => self.update_repo(data_holder_two, credentials, clone_options),
+                    None => self.clone_repo(data_holder_two, credentials, clone_options),
+                }
             });
     }
 
-    fn extract_repo_name(&self, url: &str) -> Result<TempDataHolderOne, CloneRepoTaskError> {
-        REPO_NAME_REGEX
-            .captures(url)
-            .ok_or(CouldNotExtractRepoName)
-            .map(|captures| {
-                captures
-                    .get(1)
-                    .map_or(url, |m| m.as_str())
-                    .to_string()
-                    .replace(".", "")
+    fn parse_repo_url(
+        &self,
+        url: &str,
+        credentials: &GitCredentials,
+    ) -> Result<TempDataHolderOne, CloneRepoTaskError> {
+        RepoUrl::parse(url)
+            .map(|repo_url| {
+                TempDataHolderOne {
+                    remote_url: credentials.get_remote_url(&repo_url, url),
+                    repo_url,
+                }
             })
-            .map(|repo_name| TempDataHolderOne { repo_name })
+            .map_err(|_| CouldNotParseRepoUrl)
     }
 
-    fn delete_repo_dir(
+    // a clone that can't be opened, has no valid HEAD or points to another remote gets deleted
+    fn open_existing_repo(
         &self,
-        into_dir_path: &'static str,
+        into_dir_path: &str,
         first: TempDataHolderOne,
     ) -> Result<TempDataHolderTwo, CloneRepoTaskError> {
-        let repo_name = first.repo_name;
-        let formatted_repo_path = format!("{0}/{1}", into_dir_path, repo_name);
+        let relative_repo_path = first.repo_url.relative_path();
+        let formatted_repo_path = format!("{0}/{1}", into_dir_path, relative_repo_path);
         let repo_path = Path::new(formatted_repo_path.as_str()).to_owned();
-        let second = TempDataHolderTwo {
-            formatted_repo_path,
-        };
-
-        if repo_path.exists() {
-            fs::remove_dir_all(repo_path.as_path())
-                .map_err(|_| CouldNotDeleteExistingRepoDir)
-                .map(|_| second)
-        } else {
-            Ok(second)
+
+        if !repo_path.exists() {
+            return Ok(TempDataHolderTwo {
+                formatted_repo_path,
+                remote_url: first.remote_url,
+                existing_repository: None,
+            });
+        }
+
+        let existing_repository = Repository::open(repo_path.as_path())
+            .ok()
+            .filter(|repository| Self::is_valid_clone(repository, &first.repo_url));
+
+        if existing_repository.is_none() {
+            println!("Recloning invalid repo at {}", formatted_repo_path);
+            fs::remove_dir_all(repo_path.as_path()).map_err(|_| CouldNotDeleteExistingRepoDir)?;
         }
+
+        Ok(TempDataHolderTwo {
+            formatted_repo_path,
+            remote_url: first.remote_url,
+            existing_repository,
+        })
+    }
+
+    // the remote may be the ssh or https url of the same repo, depending on the credentials
+    fn is_valid_clone(repository: &Repository, repo_url: &RepoUrl) -> bool {
+        let has_same_remote = repository
+            .find_remote("origin")
+            .ok()
+            .and_then(|remote| remote.url().and_then(|url| RepoUrl::parse(url).ok()))
+            .filter(|remote_repo_url| remote_repo_url == repo_url)
+            .is_some();
+        let has_valid_head = repository
+            .head()
+            .and_then(|head| head.peel_to_commit())
+            .is_ok();
+
+        has_same_remote && has_valid_head
+    }
+
+    // fetches and force-resets the checked out default branch to origin, which also undoes any
+    // changes made to the working dir
+    fn update_repo(
+        &self,
+        second: TempDataHolderTwo,
+        credentials: &GitCredentials,
+        clone_options: &CloneOptions,
+    ) -> Result<CloneRepoTaskResult, CloneRepoTaskError> {
+        let repository = second.existing_repository.ok_or(CouldNotUpdateRepo)?;
+        let depth = clone_options.get_fetch_depth(repository.is_shallow());
+
+        repository
+            .remote_set_url("origin", second.remote_url.as_str())
+            .map_err(|_| CouldNotUpdateRepo)?;
+        self.fetch(&repository, credentials, depth).map_err(|err| {
+            println!("Could not fetch {}: {}", second.remote_url, err.message());
+            CouldNotFetchRepo
+        })?;
+
+        Self::reset_to_origin(&repository, clone_options)?;
+
+        Ok(CloneRepoTaskResult {
+            repo_path: second.formatted_repo_path,
+            git_repository: repository,
+        })
+    }
+
+    fn reset_to_origin(
+        repository: &Repository,
+        clone_options: &CloneOptions,
+    ) -> Result<(), CloneRepoTaskError> {
+        let head = repository.head().map_err(|_| CouldNotUpdateRepo)?;
+        let reference_name = head.name().ok_or(CouldNotUpdateRepo)?.to_string();
+        let branch_name = head.shorthand().ok_or(CouldNotUpdateRepo)?.to_string();
+        let commit = repository
+            .find_branch(format!("origin/{}", branch_name).as_str(), BranchType::Remote)
+            .and_then(|branch| branch.get().peel_to_commit())
+            .map_err(|_| CouldNotUpdateRepo)?;
+
+        // git refuses to force-update the checked out branch, so the reference is set directly
+        repository
+            .reference(reference_name.as_str(), commit.id(), true, "mini-ci: reset to origin")
+            .and_then(|_| {
+                repository.checkout_head(Some(&mut Self::create_checkout_builder(clone_options)))
+            })
+            .map_err(|_| CouldNotUpdateRepo)
     }
 
     fn clone_repo(
         &self,
-        url: &str,
         second: TempDataHolderTwo,
-        ssh_passphrase: &String,
-        ssh_key_path: &String,
+        credentials: &GitCredentials,
+        clone_options: &CloneOptions,
     ) -> Result<CloneRepoTaskResult, CloneRepoTaskError> {
         let repo_path = Path::new(second.formatted_repo_path.as_str());
-        let ssh_key_path = Path::new(ssh_key_path.as_str());
-        let ssh_passphrase = if !ssh_passphrase.trim().is_empty() {
-            Some(ssh_passphrase.as_str())
-        } else {
-            None
-        };
-        let mut callback = RemoteCallbacks::new();
-
-        callback.credentials(|_url, username_from_url, _allowed_types| {
-            Cred::ssh_key(
-                username_from_url.unwrap(),
-                None,
-                ssh_key_path,
-                ssh_passphrase,
-            )
-        });
+        let mut fo = self.create_fetch_options(credentials, second.remote_url.as_str());
+        fo.depth(clone_options.get_fetch_depth(false));
 
-        let mut fo = FetchOptions::new();
-        fo.remote_callbacks(callback);
+        if let Some(parent) = repo_path.parent() {
+            fs::create_dir_all(parent).map_err(|_| CouldNotCloneRepo)?;
+        }
 
         let mut builder = RepoBuilder::new();
-        builder.fetch_options(fo);
+        builder
+            .fetch_options(fo)
+            .with_checkout(Self::create_checkout_builder(clone_options));
 
         builder
-            .clone(url, repo_path)
-            .map_err(|_| CouldNotCloneRepo)
+            .clone(second.remote_url.as_str(), repo_path)
+            .map_err(|err| {
+                println!("Could not clone {}: {}", second.remote_url, err.message());
+                CouldNotCloneRepo
+            })
             .map(|repo| {
                 CloneRepoTaskResult {
                     repo_path: second.formatted_repo_path,
@@ -117,18 +195,107 @@ impl CloneRepoTask {
                 }
             })
     }
+
+    // a depth of 0 keeps the history the clone already has
+    pub fn fetch(
+        &self,
+        repository: &Repository,
+        credentials: &GitCredentials,
+        depth: i32,
+    ) -> Result<(), git2::Error> {
+        repository.find_remote("origin").and_then(|mut remote| {
+            let mut fo = self.create_fetch_options(credentials, remote.url().unwrap_or_default());
+            fo.depth(depth);
+
+            remote.fetch(
+                &["+refs/heads/*:refs/remotes/origin/*"],
+                Some(&mut fo),
+                None,
+            )
+        })
+    }
+
+    // a forced checkout, limited to the sparse paths if there are any
+    pub fn create_checkout_builder(clone_options: &CloneOptions) -> CheckoutBuilder<'static> {
+        let mut checkout_builder = CheckoutBuilder::new();
+        checkout_builder.force();
+
+        for sparse_path in &clone_options.sparse_paths {
+            checkout_builder.path(sparse_path.trim_matches('/'));
+        }
+
+        checkout_builder
+    }
+
+    // the remote url tells the port the host key is looked up with
+    pub fn create_fetch_options<'a>(
+        &'a self,
+        credentials: &'a GitCredentials,
+        remote_url: &str,
+    ) -> FetchOptions<'a> {
+        let mut attempts = 0;
+        let port = RepoUrl::get_ssh_port(remote_url);
+        let mut callback = RemoteCallbacks::new();
+
+        // libgit2 asks again as long as credentials are handed out, even if they get rejected
+        callback.credentials(move |_url, username_from_url, allowed_types| {
+            attempts += 1;
+
+            if attempts > MAX_CREDENTIALS_ATTEMPTS {
+                return Err(git2::Error::from_str("authentication failed"));
+            }
+
+            credentials.create_cred(username_from_url, allowed_types)
+        });
+
+        // x509 certs of https remotes are left to libgit2's own checks
+        callback.certificate_check(move |cert, hostname| {
+            let hostkey = match cert.as_hostkey() {
+                Some(hostkey) => hostkey,
+                None => return Ok(CertificateCheckStatus::CertificatePassthrough),
+            };
+            let (key, key_type) = hostkey
+                .hostkey()
+                .zip(hostkey.hostkey_type())
+                .ok_or_else(|| git2::Error::from_str("the remote sent no ssh host key"))?;
+
+            self.host_key_verifier
+                .verify(hostname, port, key_type.name(), key)
+                .map(|_| CertificateCheckStatus::CertificateOk)
+                .map_err(|err| {
+                    let message = format!(
+                        "host key verification failed for {} ({} {}): {}",
+                        hostname,
+                        key_type.name(),
+                        HostKeyVerifier::get_fingerprint(key),
+                        err
+                    );
+                    println!("{}", message);
+                    git2::Error::from_str(message.as_str())
+                })
+        });
+
+        let mut fo = FetchOptions::new();
+        fo.remote_callbacks(callback);
+        fo
+    }
 }
 
 struct TempDataHolderOne {
-    repo_name: String,
+    repo_url: RepoUrl,
+    remote_url: String,
 }
 
 struct TempDataHolderTwo {
     formatted_repo_path: String,
+    remote_url: String,
+    existing_repository: Option<Repository>,
 }
 
 pub enum CloneRepoTaskError {
-    CouldNotExtractRepoName,
+    CouldNotParseRepoUrl,
     CouldNotDeleteExistingRepoDir,
     CouldNotCloneRepo,
+    CouldNotFetchRepo,
+    CouldNotUpdateRepo,
 }
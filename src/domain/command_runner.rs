@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+use std::process::Stdio;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::{Child, Command};
+use tokio_util::sync::CancellationToken;
+
+use crate::domain::command_runner::CommandRunnerError::{
+    Cancelled, CommandFailed, CouldNotRunCommand, CouldNotSpawnCommand, TimedOut,
+};
+use crate::domain::secret_masker::{MaskingStream, SecretMasker};
+
+// mini-ci's own keys and tokens, commands get the rest of its environment
+static SERVER_VARIABLE_PREFIX: &str = "MINI_CI_";
+static SERVER_VARIABLES: [&str; 1] = ["GITHUB_TOKEN"];
+
+#[async_trait]
+pub trait CommandRunner: Send + Sync {
+    async fn run(
+        &self,
+        command: &str,
+        working_dir: &str,
+        env: &HashMap<String, String>,
+        secret_masker: &SecretMasker,
+        timeout: Duration,
+        cancellation_token: &CancellationToken,
+    ) -> Result<(), CommandRunnerError>;
+}
+
+pub struct TokioCommandRunner {
+    kill_grace_period: Duration,
+}
+
+impl TokioCommandRunner {
+    pub fn new(kill_grace_period: Duration) -> TokioCommandRunner {
+        TokioCommandRunner { kill_grace_period }
+    }
+
+    // the env is added to mini-ci's own environment, without its keys and tokens
+    fn spawn(
+        command: &str,
+        working_dir: &str,
+        env: &HashMap<String, String>,
+    ) -> std::io::Result<Child> {
+        let mut process = Command::new("bash");
+
+        process
+            .arg("-c")
+            .arg(command)
+            .current_dir(working_dir)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+
+        for (name, _) in std::env::vars_os() {
+            if Self::is_server_variable(name.to_string_lossy().as_ref()) {
+                process.env_remove(name);
+            }
+        }
+
+        // the step's own MINI_CI_* variables are set again after the server's are gone
+        process.envs(env);
+
+        // own process group, so everything the command starts can be killed together
+        unsafe {
+            process.pre_exec(|| {
+                if libc::setpgid(0, 0) == 0 {
+                    Ok(())
+                } else {
+                    Err(std::io::Error::last_os_error())
+                }
+            });
+        }
+
+        process.spawn()
+    }
+
+    fn is_server_variable(name: &str) -> bool {
+        name.starts_with(SERVER_VARIABLE_PREFIX) || SERVER_VARIABLES.contains(&name)
+    }
+
+    async fn terminate_process_group(&self, child: &mut Child) {
+        let process_group_id = match child.id() {
+            Some(id) => id as libc::pid_t,
+            None => return,
+        };
+
+        unsafe {
+            libc::killpg(process_group_id, libc::SIGTERM);
+        }
+
+        if tokio::time::timeout(self.kill_grace_period, child.wait())
+            .await
+            .is_err()
+        {
+            unsafe {
+                libc::killpg(process_group_id, libc::SIGKILL);
+            }
+            let _ = child.wait().await;
+        }
+    }
+
+    // the output is masked before it's printed, anything else reading it only sees the mask
+    async fn print_lines<R: AsyncRead + Unpin>(reader: R, mut masking_stream: MaskingStream) {
+        let mut lines = BufReader::new(reader).lines();
+
+        while let Ok(Some(line)) = lines.next_line().await {
+            for masked_line in masking_stream.push_line(line.as_str()) {
+                println!("{}", masked_line);
+            }
+        }
+
+        for masked_line in masking_stream.finish() {
+            println!("{}", masked_line);
+        }
+    }
+}
+
+#[async_trait]
+impl CommandRunner for TokioCommandRunner {
+    async fn run(
+        &self,
+        command: &str,
+        working_dir: &str,
+        env: &HashMap<String, String>,
+        secret_masker: &SecretMasker,
+        timeout: Duration,
+        cancellation_token: &CancellationToken,
+    ) -> Result<(), CommandRunnerError> {
+        let mut child =
+            Self::spawn(command, working_dir, env).map_err(|_| CouldNotSpawnCommand)?;
+        // the id is gone once the child is reaped, its process group may live on
+        let process_group_id = child.id().ok_or(CouldNotSpawnCommand)? as libc::pid_t;
+
+        let stdout = child.stdout.take().ok_or(CouldNotSpawnCommand)?;
+        let stderr = child.stderr.take().ok_or(CouldNotSpawnCommand)?;
+        let stdout_masking_stream = secret_masker.create_stream();
+        let stderr_masking_stream = secret_masker.create_stream();
+        let mut printing = tokio::spawn(async move {
+            tokio::join!(
+                Self::print_lines(stdout, stdout_masking_stream),
+                Self::print_lines(stderr, stderr_masking_stream)
+            );
+        });
+
+        let finished = tokio::select! {
+            status = child.wait() => Ok(status),
+            _ = tokio::time::sleep(timeout) => Err(TimedOut),
+            _ = cancellation_token.cancelled() => Err(Cancelled),
+        };
+
+        let result = match finished {
+            Ok(status) => {
+                match status.map_err(|_| CouldNotRunCommand)? {
+                    status if status.success() => Ok(()),
+                    status => Err(CommandFailed(status.code())),
+                }
+            }
+            Err(err) => {
+                self.terminate_process_group(&mut child).await;
+                Err(err)
+            }
+        };
+
+        // processes the command left running in the background may hold its output open
+        if tokio::time::timeout(self.kill_grace_period, &mut printing)
+            .await
+            .is_err()
+        {
+            unsafe {
+                libc::killpg(process_group_id, libc::SIGKILL);
+            }
+            printing.abort();
+        }
+
+        result
+    }
+}
+
+#[derive(Debug)]
+pub enum CommandRunnerError {
+    CouldNotSpawnCommand,
+    CouldNotRunCommand,
+    CommandFailed(Option<i32>),
+    TimedOut,
+    Cancelled,
+}
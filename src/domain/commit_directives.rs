@@ -0,0 +1,176 @@
+use crate::data::deploy_info::CommitDirective;
+
+static SKIP_CI: &str = "skip ci";
+static SKIP_DEPLOY: &str = "skip deploy";
+// compose subcommands that take services, given the ones of [deploy only=...]
+static COMPOSE_SERVICE_COMMANDS: [&str; 7] =
+    ["up", "pull", "build", "create", "start", "stop", "restart"];
+// options of those subcommands that take a separate value
+static COMPOSE_VALUE_OPTIONS: [&str; 8] = [
+    "-t",
+    "--timeout",
+    "--pull",
+    "--wait-timeout",
+    "--progress",
+    "--exit-code-from",
+    "--attach",
+    "--no-attach",
+];
+// where a command of a command line ends
+static COMMAND_SEPARATORS: [char; 6] = [';', '&', '|', '(', ')', '\n'];
+
+// the bracketed directives of a push's commit messages, the ones the repo doesn't allow are
+// left alone. Every message of the push counts, not only the head commit's
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct CommitDirectives {
+    // the directive that skips the push, as it was written
+    pub skip: Option<String>,
+    // names of jobs or compose services, empty runs everything
+    pub deploy_only: Vec<String>,
+}
+
+impl CommitDirectives {
+    pub fn parse(messages: &[&str], allowed_directives: &[CommitDirective]) -> CommitDirectives {
+        let mut commit_directives = CommitDirectives::default();
+
+        for directive in messages.iter().flat_map(|message| Self::find_directives(message)) {
+            let words = directive
+                .split_whitespace()
+                .collect::<Vec<&str>>()
+                .join(" ")
+                .to_lowercase();
+            let skip_directive = if words == SKIP_CI {
+                Some(CommitDirective::SkipCi)
+            } else if words == SKIP_DEPLOY {
+                Some(CommitDirective::SkipDeploy)
+            } else {
+                None
+            };
+
+            match skip_directive {
+                Some(skip_directive) if allowed_directives.contains(&skip_directive) => {
+                    if commit_directives.skip.is_none() {
+                        commit_directives.skip = Some(format!("[{}]", words));
+                    }
+                }
+                Some(_) => {}
+                None if allowed_directives.contains(&CommitDirective::DeployOnly) => {
+                    for name in Self::parse_deploy_only(directive).into_iter().flatten() {
+                        if !commit_directives.deploy_only.contains(&name) {
+                            commit_directives.deploy_only.push(name);
+                        }
+                    }
+                }
+                None => {}
+            }
+        }
+
+        commit_directives
+    }
+
+    // compose commands that take services get the variable's services appended, unless they
+    // name their services or refer to the variable themselves. Commands are told apart by ; & | ( ) and newlines
+    pub fn restrict_compose_services(command_line: &str, services_variable: &str) -> String {
+        let mut restricted = String::with_capacity(command_line.len());
+        let mut rest = command_line;
+
+        loop {
+            let end = rest.find(COMMAND_SEPARATORS).unwrap_or(rest.len());
+            let command = &rest[..end];
+
+            if Self::takes_services(command) && !command.contains(services_variable) {
+                let trimmed = command.trim_end();
+                restricted.push_str(trimmed);
+                restricted.push_str(&format!(" ${{{}}}", services_variable));
+                restricted.push_str(&command[trimmed.len()..]);
+            } else {
+                restricted.push_str(command);
+            }
+
+            match rest[end..].chars().next() {
+                Some(separator) => {
+                    restricted.push(separator);
+                    rest = &rest[end + separator.len_utf8()..];
+                }
+                None => return restricted,
+            }
+        }
+    }
+
+    // docker-compose or docker compose with one of the subcommands after it, naming no services
+    fn takes_services(command: &str) -> bool {
+        let words = command.split_whitespace().collect::<Vec<&str>>();
+        let compose_end = words.iter().enumerate().find_map(|(index, word)| match *word {
+            "docker-compose" => Some(index + 1),
+            "docker" if words.get(index + 1) == Some(&"compose") => Some(index + 2),
+            _ => None,
+        });
+        let subcommand = compose_end.and_then(|compose_end| {
+            words[compose_end..]
+                .iter()
+                .position(|word| COMPOSE_SERVICE_COMMANDS.contains(word))
+                .map(|position| compose_end + position)
+        });
+
+        match subcommand {
+            Some(subcommand) => {
+                !Self::names_services(words[subcommand], &words[subcommand + 1..])
+            }
+            None => false,
+        }
+    }
+
+    // any argument that is no option or option value, like api in up -d api
+    fn names_services(subcommand: &str, arguments: &[&str]) -> bool {
+        arguments.iter().enumerate().any(|(index, argument)| {
+            // build --pull is a plain flag, up --pull takes a policy
+            let option_value = index > 0
+                && COMPOSE_VALUE_OPTIONS.contains(&arguments[index - 1])
+                && !(subcommand == "build" && arguments[index - 1] == "--pull");
+            !argument.starts_with('-') && !argument.contains('=') && !option_value
+        })
+    }
+
+    // the text between [ and ], of the innermost brackets
+    fn find_directives(message: &str) -> Vec<&str> {
+        let mut directives = vec![];
+        let mut rest = message;
+
+        while let Some(start) = rest.find('[') {
+            let after_start = &rest[start + 1..];
+
+            match after_start.find(']') {
+                Some(end) => {
+                    directives.push(after_start[..end].rsplit('[').next().unwrap_or(""));
+                    rest = &after_start[end + 1..];
+                }
+                None => break,
+            }
+        }
+
+        directives
+    }
+
+    // deploy only=api,web with any case and spacing
+    fn parse_deploy_only(directive: &str) -> Option<Vec<String>> {
+        let rest = Self::strip_word(directive.trim_start(), "deploy")?;
+        let rest = Self::strip_word(rest.trim_start(), "only")?;
+        let names = rest.trim_start().strip_prefix('=')?;
+
+        Some(
+            names
+                .split(',')
+                .map(|name| name.trim())
+                .filter(|name| !name.is_empty())
+                .map(|name| name.to_string())
+                .collect(),
+        )
+    }
+
+    fn strip_word<'a>(value: &'a str, word: &str) -> Option<&'a str> {
+        value
+            .get(..word.len())
+            .filter(|start| start.eq_ignore_ascii_case(word))
+            .map(|_| &value[word.len()..])
+    }
+}
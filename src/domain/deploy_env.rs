@@ -0,0 +1,262 @@
+use std::collections::HashMap;
+
+use crate::entrypoint::github_push_event_dto::GithubPushEventDto;
+
+static SECRETS_PREFIX: &str = "secrets.";
+// commands get the secrets they refer to as variables with this prefix
+static SECRET_VARIABLE_PREFIX: &str = "MINI_CI_SECRET_";
+
+// the environment of a run's commands: the push's MINI_CI_* variables, overridden by the env
+// maps of docker-deploy.yml from file to branch to step
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct DeployEnv {
+    variables: HashMap<String, String>,
+    // variable -> content, written to temp files while a step runs
+    files: HashMap<String, String>,
+    secrets: HashMap<String, String>,
+}
+
+// the env and files of one level of docker-deploy.yml
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct EnvLayer {
+    pub env: HashMap<String, String>,
+    pub files: HashMap<String, String>,
+}
+
+impl DeployEnv {
+    pub fn new(variables: HashMap<String, String>, secrets: HashMap<String, String>) -> DeployEnv {
+        DeployEnv {
+            variables,
+            files: HashMap::new(),
+            secrets,
+        }
+    }
+
+    pub fn from_push_event(
+        dto: &GithubPushEventDto,
+        branch_name: &str,
+        run_id: u64,
+        secrets: HashMap<String, String>,
+    ) -> DeployEnv {
+        let variables = HashMap::from([
+            ("MINI_CI_REPO".to_string(), dto.repository.full_name.clone()),
+            ("MINI_CI_BRANCH".to_string(), branch_name.to_string()),
+            ("MINI_CI_SHA".to_string(), dto.after.clone()),
+            ("MINI_CI_BEFORE_SHA".to_string(), dto.before.clone()),
+            ("MINI_CI_PUSHER".to_string(), dto.pusher.name.clone()),
+            ("MINI_CI_COMMIT_MESSAGE".to_string(), dto.head_commit.message.clone()),
+            ("MINI_CI_RUN_ID".to_string(), run_id.to_string()),
+        ]);
+
+        DeployEnv::new(variables, secrets)
+    }
+
+    // values may refer to the variables of the outer levels, not to their own siblings
+    pub fn with(&self, env_layer: &EnvLayer) -> DeployEnv {
+        let mut deploy_env = self.clone();
+
+        for (name, value) in &env_layer.env {
+            deploy_env.files.remove(name);
+            deploy_env.variables.insert(name.clone(), self.interpolate(value));
+        }
+
+        for (name, content) in &env_layer.files {
+            deploy_env.variables.remove(name);
+            deploy_env.files.insert(name.clone(), self.interpolate(content));
+        }
+
+        deploy_env
+    }
+
+    // replaces ${NAME} with the variable's value and ${{ secrets.NAME }} with the secret's,
+    // unknown names are left for the shell. only for values no shell parses, see
+    // interpolate_command
+    pub fn interpolate(&self, value: &str) -> String {
+        let mut interpolated = String::with_capacity(value.len());
+        let mut rest = value;
+
+        while let Some(start) = rest.find("${") {
+            interpolated.push_str(&rest[..start]);
+            rest = &rest[start..];
+
+            let replacement = match Self::parse_secret_reference(rest) {
+                Some((name, length)) => self.secrets.get(name).map(|value| (value, length)),
+                None => {
+                    Self::parse_variable_reference(rest)
+                        .and_then(|(name, length)| {
+                            self.variables.get(name).map(|value| (value, length))
+                        })
+                }
+            };
+
+            match replacement {
+                Some((value, length)) => {
+                    interpolated.push_str(value);
+                    rest = &rest[length..];
+                }
+                None => {
+                    interpolated.push_str("${");
+                    rest = &rest[2..];
+                }
+            }
+        }
+
+        interpolated.push_str(rest);
+        interpolated
+    }
+
+    // a known ${NAME} or ${{ secrets.NAME }} becomes a quoted expansion, so bash reads the value
+    // from the step's env instead of parsing it as part of the command, and secrets never show up
+    // in its arguments. quotes the command already has are kept track of, unknown names are left
+    // for the shell
+    pub fn interpolate_command(&self, command: &str) -> String {
+        let mut interpolated = String::with_capacity(command.len());
+        // the quote the scanned part of the command is in
+        let mut quote = None;
+        let mut index = 0;
+
+        while let Some(char) = command[index..].chars().next() {
+            let rest = &command[index..];
+
+            if rest.starts_with("${") {
+                if let Some((secret_name, length)) = Self::parse_secret_reference(rest) {
+                    if self.secrets.contains_key(secret_name) {
+                        let name = Self::get_secret_variable_name(secret_name);
+                        interpolated.push_str(&Self::quote_expansion(&name, quote));
+                        index += length;
+                        continue;
+                    }
+                } else if let Some((name, length)) = Self::parse_variable_reference(rest)
+                    .filter(|(name, _)| self.variables.contains_key(*name))
+                {
+                    interpolated.push_str(&Self::quote_expansion(name, quote));
+                    index += length;
+                    continue;
+                }
+            }
+
+            let length = match (char, quote) {
+                // the escaped char can't open or close a quote
+                ('\\', None | Some('"')) => {
+                    1 + rest[1..].chars().next().map_or(0, |escaped| escaped.len_utf8())
+                }
+                ('\'' | '"', None) => {
+                    quote = Some(char);
+                    1
+                }
+                (char, Some(open)) if char == open => {
+                    quote = None;
+                    1
+                }
+                _ => char.len_utf8(),
+            };
+            interpolated.push_str(&rest[..length]);
+            index += length;
+        }
+
+        interpolated
+    }
+
+    // names of the secrets referenced as ${{ secrets.NAME }}
+    pub fn find_secret_names(value: &str) -> Vec<String> {
+        let mut names = vec![];
+        let mut rest = value;
+
+        while let Some(start) = rest.find("${{") {
+            rest = &rest[start..];
+
+            match Self::parse_secret_reference(rest) {
+                Some((name, length)) => {
+                    names.push(name.to_string());
+                    rest = &rest[length..];
+                }
+                None => rest = &rest[3..],
+            }
+        }
+
+        names
+    }
+
+    pub fn get_variables(&self) -> &HashMap<String, String> {
+        &self.variables
+    }
+
+    pub fn has_secret(&self, secret_name: &str) -> bool {
+        self.secrets.contains_key(secret_name)
+    }
+
+    // the variables of a command, with the secrets interpolate_command refers to
+    pub fn get_command_variables(&self) -> HashMap<String, String> {
+        let mut variables = self.variables.clone();
+
+        for (name, value) in &self.secrets {
+            variables.insert(Self::get_secret_variable_name(name), value.clone());
+        }
+
+        variables
+    }
+
+    pub fn get_secret_variable_name(secret_name: &str) -> String {
+        format!("{}{}", SECRET_VARIABLE_PREFIX, secret_name)
+    }
+
+    pub fn get_files(&self) -> &HashMap<String, String> {
+        &self.files
+    }
+
+    pub fn is_valid_name(name: &str) -> bool {
+        let mut chars = name.chars();
+
+        chars
+            .next()
+            .filter(|first| first.is_ascii_alphabetic() || *first == '_')
+            .is_some()
+            && chars.all(|char| char.is_ascii_alphanumeric() || char == '_')
+    }
+
+    // single quotes are closed around the expansion, bash doesn't expand anything in them
+    fn quote_expansion(name: &str, quote: Option<char>) -> String {
+        match quote {
+            None => format!("\"${{{}}}\"", name),
+            Some('"') => format!("${{{}}}", name),
+            Some(_) => format!("'\"${{{}}}\"'", name),
+        }
+    }
+
+    // value starts with ${, returns the name and the length of the reference
+    fn parse_variable_reference(value: &str) -> Option<(&str, usize)> {
+        value[2..]
+            .find('}')
+            .map(|end| &value[2..2 + end])
+            .filter(|name| Self::is_valid_name(name))
+            .map(|name| (name, name.len() + 3))
+    }
+
+    // value starts with ${{, whitespace around the name is optional
+    fn parse_secret_reference(value: &str) -> Option<(&str, usize)> {
+        let end = value.strip_prefix("${{")?.find("}}")? + 3;
+
+        value[3..end]
+            .trim()
+            .strip_prefix(SECRETS_PREFIX)
+            .filter(|name| Self::is_valid_name(name))
+            .map(|name| (name, end + 2))
+    }
+}
+
+impl EnvLayer {
+    pub fn new(env: &HashMap<String, String>, files: &HashMap<String, String>) -> EnvLayer {
+        EnvLayer {
+            env: env.clone(),
+            files: files.clone(),
+        }
+    }
+
+    pub fn find_secret_names(&self) -> Vec<String> {
+        self.env
+            .values()
+            .chain(self.files.values())
+            .flat_map(|value| DeployEnv::find_secret_names(value))
+            .collect()
+    }
+}
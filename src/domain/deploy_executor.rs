@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::runtime::Handle;
+use tokio::sync::{oneshot, Semaphore};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+// lets a running deploy give its place in the concurrency limit to others while it waits
+#[derive(Clone)]
+pub struct DeploySlot {
+    concurrency_limit: Arc<Semaphore>,
+}
+
+pub struct DeployExecutor {
+    concurrency_limit: Arc<Semaphore>,
+    // completion signal of the last deploy submitted per queue, the next one waits for it
+    queue_tails: Arc<Mutex<HashMap<String, oneshot::Receiver<()>>>>,
+    // deploys must outlive the http workers that submit them
+    runtime: Handle,
+    // parent of every deploy's token
+    shutdown_token: CancellationToken,
+}
+
+impl DeployExecutor {
+    pub fn new(max_concurrent_deploys: usize, runtime: Handle) -> DeployExecutor {
+        DeployExecutor {
+            concurrency_limit: Arc::new(Semaphore::new(max_concurrent_deploys)),
+            queue_tails: Arc::new(Mutex::new(HashMap::new())),
+            runtime,
+            shutdown_token: CancellationToken::new(),
+        }
+    }
+
+    pub fn create_cancellation_token(&self) -> CancellationToken {
+        self.shutdown_token.child_token()
+    }
+
+    pub fn create_slot(&self) -> DeploySlot {
+        DeploySlot {
+            concurrency_limit: self.concurrency_limit.clone(),
+        }
+    }
+
+    // deploys with the same queue key run in submission order, at most max_concurrent_deploys
+    // at once. A start delay is waited out before the deploy takes its place in the queue.
+    pub fn submit<F>(
+        &self,
+        queue_key: &str,
+        start_delay: Option<Duration>,
+        cancellation_token: CancellationToken,
+        deploy: F,
+    ) -> JoinHandle<()>
+        where
+            F: Future<Output=()> + Send + 'static,
+    {
+        let (done_sender, done_receiver) = oneshot::channel::<()>();
+        let queue_tails = self.queue_tails.clone();
+        let queue_key = queue_key.to_string();
+        let concurrency_limit = self.concurrency_limit.clone();
+        let (previous_done, pending_done_receiver) = match start_delay {
+            None => (Self::enqueue(&queue_tails, &queue_key, done_receiver), None),
+            Some(_) => (None, Some(done_receiver)),
+        };
+
+        self.runtime.spawn(async move {
+            let mut previous_done = previous_done;
+
+            if let Some(start_delay) = start_delay {
+                tokio::select! {
+                    _ = tokio::time::sleep(start_delay) => {}
+                    _ = cancellation_token.cancelled() => {}
+                }
+            }
+
+            if let Some(done_receiver) = pending_done_receiver {
+                previous_done = Self::enqueue(&queue_tails, &queue_key, done_receiver);
+            }
+
+            if let Some(previous_done) = previous_done {
+                // an error only means the previous deploy was dropped, which is fine too
+                let _ = previous_done.await;
+            }
+
+            match concurrency_limit.acquire().await {
+                Ok(_permit) => deploy.await,
+                Err(_) => println!("Deploy executor closed, dropping deploy"),
+            }
+
+            let _ = done_sender.send(());
+        })
+    }
+
+    // stops running commands and lets queued deploys fall through
+    pub fn cancel_all(&self) {
+        self.shutdown_token.cancel();
+    }
+
+    fn enqueue(
+        queue_tails: &Mutex<HashMap<String, oneshot::Receiver<()>>>,
+        queue_key: &str,
+        done_receiver: oneshot::Receiver<()>,
+    ) -> Option<oneshot::Receiver<()>> {
+        queue_tails
+            .lock()
+            .unwrap()
+            .insert(queue_key.to_string(), done_receiver)
+    }
+}
+
+impl DeploySlot {
+    // only for a deploy holding its permit with nothing else of it running. The permit is lent
+    // out meanwhile and a new one is waited for afterwards, so the deploy can't exceed the limit
+    pub async fn release_while<F>(&self, future: F) -> F::Output
+        where
+            F: Future,
+    {
+        self.concurrency_limit.add_permits(1);
+        let output = future.await;
+
+        if let Ok(permit) = self.concurrency_limit.acquire().await {
+            permit.forget();
+        }
+
+        output
+    }
+}
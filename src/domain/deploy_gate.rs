@@ -0,0 +1,74 @@
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+
+use crate::data::deploy_info::DeployWindow;
+use crate::data::freeze_store::FreezeStore;
+
+// whether a branch may deploy at a time: inside one of its windows, if it has any, and not
+// during a freeze of its repo or environment
+pub struct DeployGate {
+    // host/owner/name
+    repo: String,
+    environment: String,
+    windows: Vec<DeployWindow>,
+    freeze_store: Arc<FreezeStore>,
+}
+
+// why a branch may not deploy
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClosedGate {
+    pub reason: String,
+    // when it may open next, None if only a change of the freezes can open it
+    pub opens_at: Option<DateTime<Utc>>,
+}
+
+impl DeployGate {
+    pub fn new(
+        repo: String,
+        environment: String,
+        windows: Vec<DeployWindow>,
+        freeze_store: Arc<FreezeStore>,
+    ) -> DeployGate {
+        DeployGate {
+            repo,
+            environment,
+            windows,
+            freeze_store,
+        }
+    }
+
+    // None if the branch may deploy. Freezes that can't be read keep it closed
+    pub fn check(&self, time: DateTime<Utc>) -> Option<ClosedGate> {
+        let freezes = match self.freeze_store.find_active(&self.repo, &self.environment, time) {
+            Ok(freezes) => freezes,
+            Err(err) => {
+                println!("Could not read the freezes: {:?}", err);
+                return Some(ClosedGate {
+                    reason: "the freezes could not be read".to_string(),
+                    opens_at: None,
+                });
+            }
+        };
+
+        if let Some(freeze) = freezes.iter().max_by_key(|freeze| freeze.ends_at) {
+            return Some(ClosedGate {
+                reason: format!("frozen until {}: {}", freeze.ends_at, freeze.reason),
+                opens_at: Some(freeze.ends_at),
+            });
+        }
+
+        if self.windows.is_empty() || self.windows.iter().any(|window| window.is_open(time)) {
+            return None;
+        }
+
+        Some(ClosedGate {
+            reason: format!("outside the deploy windows of {}", self.environment),
+            opens_at: self
+                .windows
+                .iter()
+                .filter_map(|window| window.next_open(time))
+                .min(),
+        })
+    }
+}
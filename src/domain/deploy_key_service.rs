@@ -0,0 +1,160 @@
+use std::sync::Arc;
+
+use ssh_key::{Algorithm, LineEnding, PrivateKey};
+use ssh_key::rand_core::OsRng;
+
+use crate::data::deploy_key_store::DeployKeyStore;
+use crate::data::github_client::GithubClient;
+use crate::data::github_deploy_key_repository::{GithubDeployKeyCreateDto, GithubDeployKeyDto};
+use crate::domain::deploy_key_service::DeployKeyServiceError::{
+    CouldNotCreateDeployKey, CouldNotDeleteDeployKey, CouldNotGenerateKey, CouldNotGetDeployKeys,
+    CouldNotParseRepoUrl, CouldNotSaveKey,
+};
+use crate::domain::git_credentials::{GitAuth, GitCredentials};
+use crate::domain::repo_url::RepoUrl;
+
+// keys with this title belong to mini-ci, older ones are removed once a new key is registered
+static DEPLOY_KEY_TITLE: &str = "mini-ci";
+
+pub struct DeployKeyService {
+    github_client: Arc<dyn GithubClient>,
+    deploy_key_store: DeployKeyStore,
+    git_auth: Arc<GitAuth>,
+}
+
+impl DeployKeyService {
+    pub fn new(
+        github_client: Arc<dyn GithubClient>,
+        deploy_key_store: DeployKeyStore,
+        git_auth: Arc<GitAuth>,
+    ) -> DeployKeyService {
+        DeployKeyService {
+            github_client,
+            deploy_key_store,
+            git_auth,
+        }
+    }
+
+    // makes sure the repo has a registered read-only deploy key and uses it for clone and fetch
+    // repos with explicitly configured credentials are left alone
+    pub async fn provision(
+        &self,
+        owner_name: &str,
+        repo_name: &str,
+        ssh_git_url: &str,
+    ) -> Result<(), DeployKeyServiceError> {
+        let repo_url = RepoUrl::parse(ssh_git_url).map_err(|_| CouldNotParseRepoUrl)?;
+        let repo_relative_path = repo_url.relative_path();
+        let private_key_path = self.deploy_key_store.get_private_key_path(&repo_relative_path);
+
+        if self.has_other_credentials(&repo_url, &private_key_path) {
+            println!("{} has its own git credentials, no deploy key is used", repo_relative_path);
+            return Ok(());
+        }
+
+        let registered_keys = self
+            .github_client
+            .get_deploy_keys(owner_name.to_string(), repo_name.to_string())
+            .await
+            .map_err(|_| CouldNotGetDeployKeys)?;
+
+        let public_key = match self
+            .deploy_key_store
+            .load_public_key(&repo_relative_path)
+            .filter(|public_key| Self::is_registered(&registered_keys, public_key))
+        {
+            Some(public_key) => public_key,
+            None => {
+                let public_key = self.generate_key(&repo_relative_path)?;
+
+                self.github_client
+                    .create_deploy_key(
+                        owner_name.to_string(),
+                        repo_name.to_string(),
+                        GithubDeployKeyCreateDto {
+                            title: DEPLOY_KEY_TITLE.to_string(),
+                            key: public_key.clone(),
+                            read_only: true,
+                        },
+                    )
+                    .await
+                    .map_err(|_| CouldNotCreateDeployKey)?;
+
+                println!("Registered a new deploy key for {}", repo_relative_path);
+                public_key
+            }
+        };
+
+        let stale_keys = registered_keys.iter().filter(|key| {
+            key.title == DEPLOY_KEY_TITLE
+                && Self::get_key_data(&key.key) != Self::get_key_data(&public_key)
+        });
+
+        for stale_key in stale_keys {
+            self.github_client
+                .delete_deploy_key(owner_name.to_string(), repo_name.to_string(), stale_key.id)
+                .await
+                .map_err(|_| CouldNotDeleteDeployKey)?;
+        }
+
+        self.git_auth.set_repo_credentials(
+            &repo_url,
+            GitCredentials::SshKey {
+                key_path: private_key_path,
+                passphrase: None,
+            },
+        );
+
+        Ok(())
+    }
+
+    fn has_other_credentials(&self, repo_url: &RepoUrl, private_key_path: &str) -> bool {
+        match self.git_auth.get_repo_credentials(repo_url) {
+            Some(GitCredentials::SshKey { key_path, .. }) => key_path != private_key_path,
+            Some(_) => true,
+            None => false,
+        }
+    }
+
+    // returns the public key in openssh format
+    fn generate_key(&self, repo_relative_path: &str) -> Result<String, DeployKeyServiceError> {
+        let mut private_key =
+            PrivateKey::random(&mut OsRng, Algorithm::Ed25519).map_err(|_| CouldNotGenerateKey)?;
+        private_key.set_comment(format!("{}@{}", DEPLOY_KEY_TITLE, repo_relative_path));
+
+        let private_openssh = private_key
+            .to_openssh(LineEnding::LF)
+            .map_err(|_| CouldNotGenerateKey)?;
+        let public_openssh = private_key
+            .public_key()
+            .to_openssh()
+            .map_err(|_| CouldNotGenerateKey)?;
+
+        self.deploy_key_store
+            .save(repo_relative_path, private_openssh.as_str(), public_openssh.as_str())
+            .map_err(|_| CouldNotSaveKey)?;
+
+        Ok(public_openssh)
+    }
+
+    fn is_registered(registered_keys: &[GithubDeployKeyDto], public_key: &str) -> bool {
+        registered_keys
+            .iter()
+            .any(|key| Self::get_key_data(&key.key) == Self::get_key_data(public_key))
+    }
+
+    // github drops the comment of registered keys, only type and key are compared
+    fn get_key_data(public_key: &str) -> Vec<&str> {
+        public_key.split_whitespace().take(2).collect()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum DeployKeyServiceError {
+    CouldNotParseRepoUrl,
+    CouldNotGetDeployKeys,
+    CouldNotGenerateKey,
+    CouldNotSaveKey,
+    CouldNotCreateDeployKey,
+    CouldNotDeleteDeployKey,
+}
@@ -1,114 +1,2199 @@
-use std::io::{BufRead, BufReader};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::fs::{DirBuilder, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
-use std::thread;
-use std::thread::JoinHandle;
+use std::time::Duration;
 
-use cmd_lib::FunChildren;
-use git2::{Branch, BranchType, ObjectType};
-use git2::build::CheckoutBuilder;
+use futures::stream::FuturesUnordered;
+use futures::StreamExt;
 use strum::Display;
+use chrono::Utc;
+use tokio::sync::{oneshot, Notify};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
 
-use crate::data::deploy_info_repository::{DeployInfoEntity, DeployInfoRepository};
+use crate::data::approver_store::ApproverStore;
+use crate::data::audit_log::AuditLog;
+use crate::data::deploy_info::{
+    ApprovalPolicy, Branch, CloneOptions, ConcurrencyPolicy, DeployInfo, DeployWindow, Input,
+    InputError, OutsideWindowPolicy, Schedule,
+};
+use crate::data::deploy_info_repository::{DeployEntity, DeployInfoEntity, DeployInfoRepository};
+use crate::data::freeze_store::{FreezeEntity, FreezeStore};
+use crate::data::run_repository::{
+    ApprovalDecision, HealthCheckEntity, JobEntity, OverrideEntity, RunEntity, RunRepository,
+    RunStatus, RunTrigger, StepEntity, StepStatus,
+};
+use crate::data::secret_store::SecretStore;
+use crate::domain::blocking_pool::BlockingPool;
+use crate::domain::command_runner::{CommandRunner, CommandRunnerError};
+use crate::domain::commit_directives::CommitDirectives;
+use crate::domain::deploy_env::{DeployEnv, EnvLayer};
+use crate::domain::deploy_executor::{DeployExecutor, DeploySlot};
+use crate::domain::deploy_gate::DeployGate;
 use crate::domain::deploy_service::DeployServiceError::{
-    CouldNotCheckoutBranch, CouldNotGetBranch, CouldNotGetRepoInfo,
+    CommitNotOnBranch, CouldNotCheckoutBranch, CouldNotGetApprovers, CouldNotGetBranch,
+    CouldNotGetBranchSha, CouldNotGetFreezes, CouldNotGetRepoInfo, CouldNotGetSecrets,
+    CouldNotReadDeployConfig, CouldNotWriteAuditLog, DeployNotFound, FreezeNotFound, InvalidFreeze,
+    InvalidInputs, InvalidSha, MissingSecrets, NoApprovalAwaited, NoCommandsForBranch,
+    NotAnApprover, RunAlreadyFinished, RunNotFound, RunNotHeld, ScheduledRunUnfinished,
 };
-use crate::entrypoint::github_push_event_dto::GithubPushEventDto;
+use crate::domain::git_operations::GitOperations;
+use crate::domain::health_checker::{HealthCheckError, HealthChecker, HealthProbe};
+use crate::domain::init_service::DOCKER_DEPLOY_FILENAME;
+use crate::domain::path_filter::PathFilter;
+use crate::domain::repo_url::RepoUrl;
+use crate::domain::secret_masker::SecretMasker;
+use crate::entrypoint::github_push_event_dto::{GithubPushEventDto, Repository};
+
+static DEFAULT_DEBOUNCE_SECS: u64 = 10;
+// the compose services named by [deploy only=...], space separated. Compose commands get them
+// appended, see CommitDirectives::restrict_compose_services
+static SERVICES_VARIABLE: &str = "MINI_CI_SERVICES";
+// push, schedule, rollback or manual
+static TRIGGER_VARIABLE: &str = "MINI_CI_TRIGGER";
+// the job of the steps of a rollback
+static ROLLBACK_JOB_NAME: &str = "rollback";
+static UNCHANGED_PATHS_REASON: &str = "no paths of the branch changed";
+// a held run looks at its windows and the freezes at least this often, the freezes file may
+// change behind the server's back
+static MAX_HOLD_RECHECK_INTERVAL: Duration = Duration::from_secs(60);
 
 pub struct DeployService {
     deploy_info_repo: Arc<Mutex<DeployInfoRepository>>,
+    run_repo: Arc<Mutex<RunRepository>>,
+    git_operations: Arc<dyn GitOperations>,
+    command_runner: Arc<dyn CommandRunner>,
+    blocking_pool: Arc<BlockingPool>,
+    deploy_executor: DeployExecutor,
+    health_checker: Arc<HealthChecker>,
+    cancellation_tokens: Arc<Mutex<HashMap<u64, CancellationToken>>>,
+    pending_approvals: Arc<Mutex<HashMap<u64, Vec<PendingApproval>>>>,
+    // wakes held runs when freezes or overrides change
+    hold_changed: Arc<Notify>,
+    config: DeployServiceConfig,
+}
+
+pub struct DeployServiceConfig {
+    // for steps without a timeout of their own or of their branch
+    pub default_step_timeout: Duration,
+    // how long a branch's worktree is kept after its last run
+    pub worktree_retention: Duration,
+    // for repos whose docker-deploy.yml doesn't set them
+    pub default_clone_options: CloneOptions,
+    // secrets referenced by docker-deploy.yml
+    pub secret_store: Arc<SecretStore>,
+    // who may approve runs, set freezes and override them
+    pub approver_store: Arc<ApproverStore>,
+    pub freeze_store: Arc<FreezeStore>,
+    pub audit_log: Arc<AuditLog>,
+    // environment -> windows, for branches without windows of their own
+    pub deploy_windows: HashMap<String, Vec<DeployWindow>>,
+}
+
+pub struct StartedDeploy {
+    pub run_id: u64,
+    // None if the run was skipped right away
+    pub join_handle: Option<JoinHandle<()>>,
+}
+
+// which deploy of a branch a rollback goes back to
+pub enum RollbackTarget {
+    // the latest one of another commit than the current deploy's
+    Previous,
+    Run(u64),
+    // may be abbreviated
+    Sha(String),
+}
+
+// a run someone asked for, the repo's inputs are given by name
+pub struct ManualDeploy {
+    pub branch_name: String,
+    // the branch's latest commit if unset
+    pub sha: Option<String>,
+    pub inputs: HashMap<String, String>,
+}
+
+// a manual run that was authorized and whose inputs fit the repo's
+pub struct PlannedManualDeploy {
+    approver: String,
+    branch_name: String,
+    sha: String,
+    // with the defaults of the inputs that weren't given
+    inputs: HashMap<String, String>,
+    deploy_info: DeployInfoEntity,
+}
+
+// a rollback that was authorized and whose deploy config was read
+pub struct PlannedRollback {
+    approver: String,
+    branch_name: String,
+    // with the deploy config of the deploy's commit
+    deploy_info: DeployInfoEntity,
+    deploy: DeployEntity,
 }
 
 impl DeployService {
-    pub fn new(deploy_info_repo: Arc<Mutex<DeployInfoRepository>>) -> DeployService {
-        return DeployService { deploy_info_repo };
+    pub fn new(
+        deploy_info_repo: Arc<Mutex<DeployInfoRepository>>,
+        run_repo: Arc<Mutex<RunRepository>>,
+        git_operations: Arc<dyn GitOperations>,
+        command_runner: Arc<dyn CommandRunner>,
+        blocking_pool: Arc<BlockingPool>,
+        deploy_executor: DeployExecutor,
+        config: DeployServiceConfig,
+    ) -> DeployService {
+        DeployService {
+            deploy_info_repo,
+            run_repo,
+            git_operations,
+            command_runner,
+            blocking_pool,
+            deploy_executor,
+            health_checker: Arc::new(HealthChecker::new()),
+            cancellation_tokens: Arc::new(Mutex::new(HashMap::new())),
+            pending_approvals: Arc::new(Mutex::new(HashMap::new())),
+            hold_changed: Arc::new(Notify::new()),
+            config,
+        }
+    }
+
+    pub fn execute(&self, dto: GithubPushEventDto) -> Result<StartedDeploy, DeployServiceError> {
+        let deploy_info = self.get_deploy_info(&dto)?;
+
+        self.start_run(dto, deploy_info, RunTrigger::Push)
+    }
+
+    // runs the commit like a push of it would, without path filters and commit directives.
+    // Skipped while a run the schedule started earlier hasn't finished
+    pub fn execute_schedule(
+        &self,
+        ssh_git_url: &str,
+        schedule: &Schedule,
+        sha: String,
+    ) -> Result<StartedDeploy, DeployServiceError> {
+        let trigger = RunTrigger::Schedule {
+            cron: schedule.cron.clone(),
+        };
+
+        if !self
+            .run_repo
+            .lock()
+            .unwrap()
+            .find_unfinished_by_trigger(ssh_git_url, schedule.branch.as_str(), &trigger)
+            .is_empty()
+        {
+            return Err(ScheduledRunUnfinished);
+        }
+
+        let dto = Self::create_push_event(ssh_git_url, schedule.branch.as_str(), sha);
+        let deploy_info = self.get_deploy_info(&dto)?;
+
+        self.start_run(dto, deploy_info, trigger)
+    }
+
+    // checks the inputs and finds the commit to run, the repo is given as host/owner/name
+    pub async fn plan_manual_deploy(
+        &self,
+        token: &str,
+        repo: &str,
+        deploy: ManualDeploy,
+    ) -> Result<PlannedManualDeploy, DeployServiceError> {
+        let approver = self.authenticate(token)?;
+        let deploy_info = self.find_deploy_info(repo)?;
+
+        deploy_info
+            .deploy_info
+            .get_branch(deploy.branch_name.as_str())
+            .ok_or(NoCommandsForBranch)?;
+
+        let inputs = deploy_info
+            .deploy_info
+            .resolve_inputs(&deploy.inputs)
+            .map_err(InvalidInputs)?;
+        let sha = match deploy.sha {
+            Some(sha) => {
+                self.check_on_branch(&deploy_info, deploy.branch_name.as_str(), sha.as_str())
+                    .await?;
+                sha.to_ascii_lowercase()
+            }
+            None => {
+                self.get_branch_sha(deploy_info.ssh_git_url.as_str(), deploy.branch_name.as_str())
+                    .await?
+            }
+        };
+
+        Ok(PlannedManualDeploy {
+            approver,
+            branch_name: deploy.branch_name,
+            sha,
+            inputs,
+            deploy_info,
+        })
+    }
+
+    // runs the commit like a push of it would, without path filters and commit directives. The
+    // run is audit-logged before it starts
+    pub fn execute_manual_deploy(
+        &self,
+        deploy: PlannedManualDeploy,
+    ) -> Result<StartedDeploy, DeployServiceError> {
+        let ssh_git_url = deploy.deploy_info.ssh_git_url.clone();
+        let mut inputs = deploy
+            .inputs
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<String>>();
+        inputs.sort();
+        let details = format!(
+            "{} {} at {} ({})",
+            ssh_git_url,
+            deploy.branch_name,
+            deploy.sha,
+            inputs.join(", ")
+        );
+        self.append_audit_log(&deploy.approver, "deploy", &details)?;
+
+        let dto = Self::create_push_event(
+            ssh_git_url.as_str(),
+            deploy.branch_name.as_str(),
+            deploy.sha,
+        );
+        let trigger = RunTrigger::Manual {
+            approver: deploy.approver,
+            inputs: deploy.inputs,
+        };
+
+        self.start_run(dto, deploy.deploy_info, trigger)
+    }
+
+    // finds the deploy to go back to and reads the deploy config of its commit, the repo is
+    // given as host/owner/name
+    pub async fn plan_rollback(
+        &self,
+        token: &str,
+        repo: &str,
+        branch_name: &str,
+        target: RollbackTarget,
+    ) -> Result<PlannedRollback, DeployServiceError> {
+        let approver = self.authenticate(token)?;
+        let deploy_info = self.find_deploy_info(repo)?;
+        let deploys = self
+            .deploy_info_repo
+            .lock()
+            .unwrap()
+            .get_deploys(deploy_info.ssh_git_url.as_str(), branch_name);
+
+        let deploy = match target {
+            RollbackTarget::Previous => {
+                let current_sha = deploys.first().map(|deploy| deploy.sha.clone());
+                deploys
+                    .into_iter()
+                    .find(|deploy| Some(&deploy.sha) != current_sha.as_ref())
+            }
+            RollbackTarget::Run(run_id) => {
+                deploys.into_iter().find(|deploy| deploy.run_id == run_id)
+            }
+            RollbackTarget::Sha(sha) => {
+                deploys
+                    .into_iter()
+                    .find(|deploy| !sha.is_empty() && deploy.sha.starts_with(sha.as_str()))
+            }
+        }
+        .ok_or(DeployNotFound)?;
+
+        let git_operations = self.git_operations.clone();
+        let repo_path = deploy_info.repo_path.clone();
+        let sha = deploy.sha.clone();
+        let clone_options = deploy_info
+            .deploy_info
+            .clone
+            .with_defaults(&self.config.default_clone_options);
+
+        let yaml_text = self
+            .blocking_pool
+            .run(move || {
+                git_operations.read_file(
+                    repo_path.as_str(),
+                    sha.as_str(),
+                    DOCKER_DEPLOY_FILENAME,
+                    &clone_options,
+                )
+            })
+            .await
+            .map_err(|_| CouldNotReadDeployConfig)?
+            .map_err(|_| CouldNotReadDeployConfig)?;
+        let commit_deploy_info = DeployInfo::parse(&yaml_text).map_err(|err| {
+            println!("Invalid {} of {}: {:?}", DOCKER_DEPLOY_FILENAME, deploy.sha, err);
+            CouldNotReadDeployConfig
+        })?;
+
+        Ok(PlannedRollback {
+            approver,
+            branch_name: branch_name.to_string(),
+            deploy_info: DeployInfoEntity {
+                deploy_info: commit_deploy_info,
+                ..deploy_info
+            },
+            deploy,
+        })
     }
 
-    pub fn execute(&self, dto: GithubPushEventDto) -> Result<JoinHandle<()>, DeployServiceError> {
-        return self
+    // redeploys the commit with its own deploy config, the rollback is audit-logged before it
+    // starts
+    pub fn execute_rollback(
+        &self,
+        rollback: PlannedRollback,
+    ) -> Result<StartedDeploy, DeployServiceError> {
+        let ssh_git_url = rollback.deploy_info.ssh_git_url.clone();
+        let details = format!(
+            "{} {} to {} of run {}",
+            ssh_git_url, rollback.branch_name, rollback.deploy.sha, rollback.deploy.run_id
+        );
+        self.append_audit_log(&rollback.approver, "rollback", &details)?;
+
+        let dto = Self::create_push_event(
+            ssh_git_url.as_str(),
+            rollback.branch_name.as_str(),
+            rollback.deploy.sha,
+        );
+        let trigger = RunTrigger::Rollback {
+            approver: rollback.approver,
+            deploy_run_id: rollback.deploy.run_id,
+        };
+
+        self.start_run(dto, rollback.deploy_info, trigger)
+    }
+
+    // the commits the branch deployed, newest first
+    pub fn get_deploys(
+        &self,
+        token: &str,
+        repo: &str,
+        branch_name: &str,
+    ) -> Result<Vec<DeployEntity>, DeployServiceError> {
+        self.authenticate(token)?;
+        let deploy_info = self.find_deploy_info(repo)?;
+
+        Ok(self
             .deploy_info_repo
+            .lock()
+            .unwrap()
+            .get_deploys(deploy_info.ssh_git_url.as_str(), branch_name))
+    }
+
+    // the schedules of every repo, with the ssh url of their repo
+    pub fn get_schedules(&self) -> Vec<(String, Schedule)> {
+        self.deploy_info_repo
+            .lock()
+            .unwrap()
+            .get_all()
+            .into_iter()
+            .flat_map(|entity| {
+                entity
+                    .deploy_info
+                    .schedules
+                    .iter()
+                    .map(|schedule| (entity.ssh_git_url.clone(), schedule.clone()))
+            })
+            .collect()
+    }
+
+    // the sha has to be a full one, of a commit the branch has
+    async fn check_on_branch(
+        &self,
+        deploy_info: &DeployInfoEntity,
+        branch_name: &str,
+        sha: &str,
+    ) -> Result<(), DeployServiceError> {
+        if sha.len() != 40 || !sha.chars().all(|char| char.is_ascii_hexdigit()) {
+            return Err(InvalidSha);
+        }
+
+        let git_operations = self.git_operations.clone();
+        let repo_path = deploy_info.repo_path.clone();
+        let branch_name = branch_name.to_string();
+        let sha = sha.to_ascii_lowercase();
+        let clone_options = deploy_info
+            .deploy_info
+            .clone
+            .with_defaults(&self.config.default_clone_options);
+
+        let is_on_branch = self
+            .blocking_pool
+            .run(move || {
+                git_operations.is_on_branch(
+                    repo_path.as_str(),
+                    branch_name.as_str(),
+                    sha.as_str(),
+                    &clone_options,
+                )
+            })
+            .await
+            .map_err(|_| CouldNotGetBranch)?
+            .map_err(|_| CouldNotGetBranch)?;
+
+        is_on_branch.then_some(()).ok_or(CommitNotOnBranch)
+    }
+
+    // fetches the repo and returns the latest commit of the branch
+    pub async fn get_branch_sha(
+        &self,
+        ssh_git_url: &str,
+        branch_name: &str,
+    ) -> Result<String, DeployServiceError> {
+        let deploy_info = self
+            .deploy_info_repo
+            .lock()
+            .unwrap()
+            .get(&ssh_git_url.to_string())
+            .cloned()
+            .ok_or(CouldNotGetRepoInfo)?;
+        let git_operations = self.git_operations.clone();
+        let branch_name = branch_name.to_string();
+        let clone_options = deploy_info
+            .deploy_info
+            .clone
+            .with_defaults(&self.config.default_clone_options);
+
+        self.blocking_pool
+            .run(move || {
+                git_operations.get_branch_sha(
+                    deploy_info.repo_path.as_str(),
+                    branch_name.as_str(),
+                    &clone_options,
+                )
+            })
+            .await
+            .map_err(|_| CouldNotGetBranchSha)?
+            .map_err(|_| CouldNotGetBranchSha)
+    }
+
+    fn start_run(
+        &self,
+        dto: GithubPushEventDto,
+        deploy_info: DeployInfoEntity,
+        trigger: RunTrigger,
+    ) -> Result<StartedDeploy, DeployServiceError> {
+        let ssh_git_url = deploy_info.ssh_git_url.clone();
+        let temp_data_holder = self.get_branch(dto, deploy_info, trigger)?;
+
+        // a skipped push neither waits for nor supersedes other runs
+        if let Some(skip_reason) = temp_data_holder.skip_reason.clone() {
+            let secret_masker =
+                SecretMasker::new(temp_data_holder.secrets.values().cloned().collect());
+            let run_id = self.create_run(ssh_git_url, &temp_data_holder, &secret_masker);
+
+            println!("Skipped {}: {}", temp_data_holder.branch_name, skip_reason);
+            self.run_repo.lock().unwrap().mark_skipped(run_id, skip_reason);
+
+            return Ok(StartedDeploy {
+                run_id,
+                join_handle: None,
+            });
+        }
+
+        let concurrency = temp_data_holder.concurrency;
+        let start_delay = match concurrency {
+            ConcurrencyPolicy::Coalesce => Some(temp_data_holder.debounce),
+            _ => None,
+        };
+        let cancellation_token = self.deploy_executor.create_cancellation_token();
+        let deploy_job = self.create_deploy_job(
+            ssh_git_url.clone(),
+            temp_data_holder,
+            cancellation_token.clone(),
+        );
+        let run_id = deploy_job.run_id;
+        // every branch deploys in its own worktree, so only runs of the same branch have to wait
+        let queue_key = format!("{}#{}", ssh_git_url, deploy_job.branch_name);
+
+        self.supersede_runs(&deploy_job, concurrency);
+
+        let join_handle = self.deploy_executor.submit(
+            queue_key.as_str(),
+            start_delay,
+            cancellation_token.clone(),
+            deploy_job.execute(cancellation_token),
+        );
+
+        Ok(StartedDeploy {
+            run_id,
+            join_handle: Some(join_handle),
+        })
+    }
+
+    // the approver the token belongs to is recorded as the one who cancelled the run
+    pub fn cancel(&self, run_id: u64, token: &str) -> Result<RunEntity, DeployServiceError> {
+        let approver = self.authenticate(token)?;
+        let mut run_repo = self.run_repo.lock().unwrap();
+        let run = run_repo.get(run_id).ok_or(RunNotFound)?;
+
+        if run.status.is_finished() {
+            return Err(RunAlreadyFinished);
+        }
+
+        let details = format!("run {} of {} {}", run_id, run.ssh_git_url, run.branch_name);
+        self.append_audit_log(&approver, "cancel", &details)?;
+        run_repo.mark_cancelled_by(run_id, approver);
+
+        if let Some(cancellation_token) = self.cancellation_tokens.lock().unwrap().get(&run_id) {
+            cancellation_token.cancel();
+        }
+
+        run_repo.get(run_id).cloned().ok_or(RunNotFound)
+    }
+
+    // decides the awaited approvals of the run, only the job's if one is given
+    pub fn decide_approval(
+        &self,
+        run_id: u64,
+        token: &str,
+        job_name: Option<&str>,
+        decision: ApprovalDecision,
+    ) -> Result<RunEntity, DeployServiceError> {
+        let approver = self.authenticate(token)?;
+
+        let mut pending_approvals = self.pending_approvals.lock().unwrap();
+        let mut run_repo = self.run_repo.lock().unwrap();
+        let run = run_repo.get(run_id).ok_or(RunNotFound)?;
+
+        if run.status.is_finished() {
+            return Err(RunAlreadyFinished);
+        }
+
+        let run_approvals = pending_approvals.entry(run_id).or_default();
+        let (decided_approvals, awaited_approvals) = std::mem::take(run_approvals)
+            .into_iter()
+            .partition::<Vec<PendingApproval>, _>(|pending_approval| {
+                job_name.is_none() || pending_approval.job_name.as_deref() == job_name
+            });
+        *run_approvals = awaited_approvals;
+
+        if decided_approvals.is_empty() {
+            return Err(NoApprovalAwaited);
+        }
+
+        for pending_approval in decided_approvals {
+            println!(
+                "{} {} run {} of {}",
+                approver,
+                decision,
+                run_id,
+                pending_approval.job_name.as_deref().unwrap_or("the branch")
+            );
+            run_repo.decide_approval(
+                run_id,
+                pending_approval.job_name.as_deref(),
+                decision,
+                Some(approver.clone()),
+            );
+            // the run picks the decision up, a dropped receiver means it's gone already
+            let _ = pending_approval.sender.send(decision);
+        }
+
+        run_repo.get(run_id).cloned().ok_or(RunNotFound)
+    }
+
+    // lets a held run deploy outside its windows or during a freeze, the override is
+    // audit-logged before it applies
+    pub fn override_hold(
+        &self,
+        run_id: u64,
+        token: &str,
+        reason: &str,
+    ) -> Result<RunEntity, DeployServiceError> {
+        let approver = self.authenticate(token)?;
+        let mut run_repo = self.run_repo.lock().unwrap();
+        let run = run_repo.get(run_id).ok_or(RunNotFound)?;
+
+        if run.status != RunStatus::Held {
+            return Err(RunNotHeld);
+        }
+
+        let details = format!(
+            "run {} of {} {} ({}): {}",
+            run_id,
+            run.ssh_git_url,
+            run.branch_name,
+            run.hold_reason.as_deref().unwrap_or_default(),
+            reason
+        );
+        self.append_audit_log(&approver, "override", &details)?;
+        run_repo.set_override(
+            run_id,
+            OverrideEntity {
+                approver,
+                reason: reason.to_string(),
+                overridden_at: Utc::now(),
+            },
+        );
+        drop(run_repo);
+        self.hold_changed.notify_waiters();
+
+        self.run_repo.lock().unwrap().get(run_id).cloned().ok_or(RunNotFound)
+    }
+
+    // the repo may be given as host/owner/name or as its url, the approver is recorded as the
+    // freeze's creator
+    pub fn create_freeze(
+        &self,
+        token: &str,
+        freeze: FreezeEntity,
+    ) -> Result<FreezeEntity, DeployServiceError> {
+        let approver = self.authenticate(token)?;
+        let repo = match &freeze.repo {
+            Some(repo) => Some(Self::parse_repo(repo).ok_or(InvalidFreeze)?),
+            None => None,
+        };
+
+        if freeze.ends_at <= freeze.starts_at || freeze.ends_at <= Utc::now() {
+            return Err(InvalidFreeze);
+        }
+
+        let details = format!(
+            "{} in {} from {} until {}: {}",
+            repo.as_deref().unwrap_or("every repo"),
+            freeze.environment.as_deref().unwrap_or("every environment"),
+            freeze.starts_at,
+            freeze.ends_at,
+            freeze.reason
+        );
+        self.append_audit_log(&approver, "freeze", &details)?;
+
+        self.config
+            .freeze_store
+            .add(FreezeEntity {
+                repo,
+                created_by: approver,
+                ..freeze
+            })
+            .map_err(|err| {
+                println!("Could not store the freeze: {:?}", err);
+                CouldNotGetFreezes
+            })
+    }
+
+    pub fn list_freezes(&self, token: &str) -> Result<Vec<FreezeEntity>, DeployServiceError> {
+        self.authenticate(token)?;
+
+        self.config.freeze_store.list().map_err(|err| {
+            println!("Could not read the freezes: {:?}", err);
+            CouldNotGetFreezes
+        })
+    }
+
+    // held runs the freeze kept back go on right away if nothing else holds them
+    pub fn remove_freeze(&self, token: &str, id: u64) -> Result<FreezeEntity, DeployServiceError> {
+        let approver = self.authenticate(token)?;
+        let freeze = self
+            .config
+            .freeze_store
+            .list()
+            .map_err(|_| CouldNotGetFreezes)?
+            .into_iter()
+            .find(|freeze| freeze.id == id)
+            .ok_or(FreezeNotFound)?;
+
+        self.append_audit_log(&approver, "unfreeze", &format!("{}: {}", id, freeze.reason))?;
+        let removed = self
+            .config
+            .freeze_store
+            .remove(id)
+            .map_err(|_| CouldNotGetFreezes)?
+            .ok_or(FreezeNotFound)?;
+        self.hold_changed.notify_waiters();
+
+        Ok(removed)
+    }
+
+    pub fn cancel_all(&self) {
+        self.deploy_executor.cancel_all();
+    }
+
+    // the name of the approver the token belongs to
+    fn authenticate(&self, token: &str) -> Result<String, DeployServiceError> {
+        self.config
+            .approver_store
+            .authenticate(token)
+            .map_err(|err| {
+                println!("Could not read the approvers: {:?}", err);
+                CouldNotGetApprovers
+            })?
+            .ok_or(NotAnApprover)
+    }
+
+    fn append_audit_log(
+        &self,
+        approver: &str,
+        action: &str,
+        details: &str,
+    ) -> Result<(), DeployServiceError> {
+        self.config
+            .audit_log
+            .append(approver, action, details)
+            .map_err(|err| {
+                println!("Could not write the audit log: {:?}", err);
+                CouldNotWriteAuditLog
+            })
+    }
+
+    // host/owner/name of the repo given by its url or by host/owner/name
+    fn parse_repo(repo: &str) -> Option<String> {
+        RepoUrl::parse(repo)
+            .or_else(|_| RepoUrl::parse(format!("https://{}", repo.trim_matches('/')).as_str()))
+            .map(|repo_url| repo_url.relative_path())
+            .ok()
+    }
+
+    fn find_deploy_info(&self, repo: &str) -> Result<DeployInfoEntity, DeployServiceError> {
+        let repo = Self::parse_repo(repo).ok_or(CouldNotGetRepoInfo)?;
+
+        self.deploy_info_repo
+            .lock()
+            .unwrap()
+            .get_all()
+            .into_iter()
+            .find(|entity| {
+                RepoUrl::parse(entity.ssh_git_url.as_str())
+                    .map(|repo_url| repo_url.relative_path() == repo)
+                    .unwrap_or(false)
+            })
+            .cloned()
+            .ok_or(CouldNotGetRepoInfo)
+    }
+
+    // what a push of the sha to the branch would look like
+    fn create_push_event(ssh_git_url: &str, branch_name: &str, sha: String) -> GithubPushEventDto {
+        let full_name = RepoUrl::parse(ssh_git_url)
+            .map(|repo_url| format!("{}/{}", repo_url.owner, repo_url.name))
+            .unwrap_or_default();
+
+        GithubPushEventDto {
+            ref_field: format!("refs/heads/{}", branch_name),
+            before: sha.clone(),
+            after: sha,
+            repository: Repository {
+                ssh_url: ssh_git_url.to_string(),
+                full_name,
+                ..Repository::default()
+            },
+            ..GithubPushEventDto::default()
+        }
+    }
+
+    fn get_deploy_info(&self, dto: &GithubPushEventDto) -> Result<DeployInfoEntity, DeployServiceError> {
+        self.deploy_info_repo
             .lock()
             .unwrap()
             .get(&dto.repository.ssh_url)
+            .cloned()
             .ok_or(CouldNotGetRepoInfo)
-            .and_then(|deploy_info| Self::get_branch(dto, deploy_info))
-            .and_then(|temp_data_holder| Self::checkout_branch(temp_data_holder))
-            .map(|repo_info| Self::execute_deploy_commands(repo_info));
     }
 
     fn get_branch(
+        &self,
         dto: GithubPushEventDto,
-        deploy_info: &DeployInfoEntity,
+        deploy_info: DeployInfoEntity,
+        trigger: RunTrigger,
     ) -> Result<TempDataHolderOne, DeployServiceError> {
-        let refs = dto.ref_field;
-
-        refs.rfind("/")
-            .ok_or(CouldNotGetBranch)
-            .and_then(|position| {
-                let branch_name = &refs[position..];
-                let formatted_branch_name = format!("origin/{}", branch_name);
-
-                deploy_info
-                    .git_repository
-                    .find_branch(formatted_branch_name.as_str(), BranchType::Remote)
-                    .map(|branch| {
-                        TempDataHolderOne {
-                            branch,
-                            deploy_info,
-                            refs,
-                        }
+        let refs = dto.ref_field.as_str();
+
+        let branch_name = refs
+            .strip_prefix("refs/heads/")
+            .ok_or(CouldNotGetBranch)?
+            .to_string();
+
+        let branch = deploy_info
+            .deploy_info
+            .get_branch(branch_name.as_str())
+            .ok_or(NoCommandsForBranch)?;
+        let debounce = Duration::from_secs(branch.debounce.unwrap_or(DEFAULT_DEBOUNCE_SECS));
+        // only a push changes paths and has commit messages
+        let is_push = trigger == RunTrigger::Push;
+        let create_path_filter = |paths: &[String], paths_ignore: &[String]| {
+            if is_push {
+                PathFilter::new(paths, paths_ignore)
+            } else {
+                PathFilter::new(&[], &[])
+            }
+        };
+
+        let get_timeout = |timeout: Option<u64>| {
+            timeout
+                .map(Duration::from_secs)
+                .unwrap_or(self.config.default_step_timeout)
+        };
+
+        let mut first_step_index = 0;
+        let mut jobs = branch
+            .get_jobs()
+            .into_iter()
+            .map(|job| {
+                // a step's own timeout wins over its job's, then its branch's, then the default
+                let steps = job
+                    .commands
+                    .iter()
+                    .map(|command| command.to_step())
+                    .map(|step| PlannedStep {
+                        timeout: get_timeout(step.timeout.or(job.timeout).or(branch.timeout)),
+                        command: step.run,
+                        env_layer: EnvLayer::new(&step.env, &step.files),
                     })
-                    .map_err(|_| CouldNotGetBranch)
+                    .collect::<Vec<PlannedStep>>();
+                let planned_job = PlannedJob {
+                    name: job.name,
+                    needs: job.needs,
+                    path_filter: create_path_filter(&job.paths, &job.paths_ignore),
+                    selected: true,
+                    approval: PlannedApproval::new(job.approval, job.approval_expiry),
+                    env_layer: EnvLayer::new(&job.env, &job.files),
+                    first_step_index,
+                    steps,
+                };
+
+                first_step_index += planned_job.steps.len();
+                planned_job
             })
+            .collect::<Vec<PlannedJob>>();
+        // a rollback isn't rolled back itself, it would only go back to the commit it replaced
+        let rollback_steps = match trigger {
+            RunTrigger::Rollback { .. } => vec![],
+            _ => PlannedStep::plan_rollback(branch, self.config.default_step_timeout),
+        };
+        let commit_messages = [dto.head_commit.message.as_str()]
+            .into_iter()
+            .chain(dto.commits.iter().map(|commit| commit.message.as_str()))
+            .collect::<Vec<&str>>();
+        let commit_directives = if is_push {
+            CommitDirectives::parse(&commit_messages, &deploy_info.deploy_info.get_directives())
+        } else {
+            CommitDirectives::default()
+        };
+        let services = commit_directives
+            .deploy_only
+            .iter()
+            .filter(|name| !jobs.iter().any(|job| job.name == **name))
+            .cloned()
+            .collect::<Vec<String>>();
+        Self::select_jobs(&mut jobs, &commit_directives.deploy_only);
+        let env_layers = vec![
+            EnvLayer::new(&deploy_info.deploy_info.env, &deploy_info.deploy_info.files),
+            EnvLayer::new(&branch.env, &branch.files),
+        ];
+        let secrets = self.get_secrets(
+            deploy_info.ssh_git_url.as_str(),
+            branch.get_environment(),
+            &env_layers,
+            &jobs,
+            &rollback_steps,
+        )?;
+
+        let windows = branch.windows.clone().unwrap_or_else(|| {
+            self.config
+                .deploy_windows
+                .get(branch.get_environment())
+                .cloned()
+                .unwrap_or_default()
+        });
+        let gate = DeployGate::new(
+            RepoUrl::parse(deploy_info.ssh_git_url.as_str())
+                .map(|repo_url| repo_url.relative_path())
+                .unwrap_or_default(),
+            branch.get_environment().to_string(),
+            windows,
+            self.config.freeze_store.clone(),
+        );
+
+        Ok(TempDataHolderOne {
+            branch_name,
+            gate,
+            outside_window: branch.outside_window,
+            sha: dto.after.clone(),
+            path_filter: create_path_filter(&branch.paths, &branch.paths_ignore),
+            pushed_files: Self::get_pushed_files(&dto),
+            approval: PlannedApproval::new(branch.approval, branch.approval_expiry),
+            repo_path: deploy_info.repo_path,
+            clone_options: deploy_info
+                .deploy_info
+                .clone
+                .with_defaults(&self.config.default_clone_options),
+            concurrency: branch.concurrency,
+            debounce,
+            skip_reason: commit_directives
+                .skip
+                .map(|skip| format!("{} in a commit message", skip)),
+            services,
+            env_layers,
+            secrets,
+            jobs,
+            health_probe: branch.health_check.as_ref().and_then(HealthProbe::new),
+            rollback_steps,
+            trigger,
+            push_event: dto,
+        })
     }
 
-    fn checkout_branch(first: TempDataHolderOne) -> Result<&DeployInfoEntity, DeployServiceError> {
-        let deploy_info = first.deploy_info;
-        let git_repository = &deploy_info.git_repository;
-        let branch = first.branch;
-        let refs = first.refs;
+    // only the named jobs and the ones they need run, all of them if no job was named
+    fn select_jobs(jobs: &mut [PlannedJob], deploy_only: &[String]) {
+        let mut selected_names = deploy_only
+            .iter()
+            .filter(|name| jobs.iter().any(|job| job.name == **name))
+            .cloned()
+            .collect::<HashSet<String>>();
 
+        if selected_names.is_empty() {
+            return;
+        }
 
-        branch
-            .get()
-            .peel_to_tree()
-            .and_then(|tree| deploy_info.git_repository.find_tree(tree.id()))
-            .and_then(|tree| git_repository.find_object(tree.id(), Option::Some(ObjectType::Tree)))
-            .and_then(|git_object| {
-                git_repository.checkout_tree(&git_object, Some(CheckoutBuilder::default().force()))
-            })
-            .and_then(|_| git_repository.set_head(refs.as_str()))
-            .map_err(|_| CouldNotCheckoutBranch)
-            .map(|_| deploy_info)
+        let mut unvisited_names = selected_names.iter().cloned().collect::<Vec<String>>();
+
+        while let Some(name) = unvisited_names.pop() {
+            let needs = jobs
+                .iter()
+                .filter(|job| job.name == name)
+                .flat_map(|job| job.needs.iter());
+
+            for need in needs {
+                if selected_names.insert(need.clone()) {
+                    unvisited_names.push(need.clone());
+                }
+            }
+        }
+
+        for job in jobs.iter_mut() {
+            job.selected = selected_names.contains(&job.name);
+        }
+    }
+
+    // the files the payload lists as changed, github truncates long lists of commits
+    fn get_pushed_files(dto: &GithubPushEventDto) -> Vec<String> {
+        let mut pushed_files = dto
+            .commits
+            .iter()
+            .flat_map(|commit| {
+                commit
+                    .added
+                    .iter()
+                    .chain(commit.removed.iter())
+                    .filter_map(|file| file.as_str().map(|file| file.to_string()))
+                    .chain(commit.modified.iter().cloned())
+                    .collect::<Vec<String>>()
+            })
+            .collect::<Vec<String>>();
+        pushed_files.sort();
+        pushed_files.dedup();
+
+        pushed_files
+    }
+
+    // only the secrets the branch refers to are decrypted, all of them have to exist
+    fn get_secrets(
+        &self,
+        ssh_git_url: &str,
+        environment: &str,
+        env_layers: &[EnvLayer],
+        jobs: &[PlannedJob],
+        rollback_steps: &[PlannedStep],
+    ) -> Result<HashMap<String, String>, DeployServiceError> {
+        let steps = jobs
+            .iter()
+            .flat_map(|job| job.steps.iter())
+            .chain(rollback_steps.iter());
+        let secret_names = env_layers
+            .iter()
+            .chain(jobs.iter().map(|job| &job.env_layer))
+            .chain(steps.clone().map(|step| &step.env_layer))
+            .flat_map(|env_layer| env_layer.find_secret_names())
+            .chain(steps.flat_map(|step| DeployEnv::find_secret_names(&step.command)))
+            .collect::<HashSet<String>>();
+
+        if secret_names.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        let repo_relative_path = RepoUrl::parse(ssh_git_url)
+            .map(|repo_url| repo_url.relative_path())
+            .map_err(|_| CouldNotGetSecrets)?;
+        let mut secrets = self
+            .config
+            .secret_store
+            .get_secrets(&repo_relative_path, environment)
+            .map_err(|err| {
+                println!("Could not read the secrets of {}: {:?}", repo_relative_path, err);
+                CouldNotGetSecrets
+            })?;
+        let mut missing_names = secret_names
+            .iter()
+            .filter(|name| !secrets.contains_key(*name))
+            .cloned()
+            .collect::<Vec<String>>();
+
+        if !missing_names.is_empty() {
+            missing_names.sort();
+            println!(
+                "Missing secrets of {} in {}: {}",
+                repo_relative_path,
+                environment,
+                missing_names.join(", ")
+            );
+            return Err(MissingSecrets);
+        }
+
+        secrets.retain(|name, _| secret_names.contains(name));
+        Ok(secrets)
+    }
+
+    fn create_deploy_job(
+        &self,
+        ssh_git_url: String,
+        first: TempDataHolderOne,
+        cancellation_token: CancellationToken,
+    ) -> DeployJob {
+        let secret_masker = SecretMasker::new(first.secrets.values().cloned().collect());
+        let run_id = self.create_run(ssh_git_url.clone(), &first, &secret_masker);
+
+        let push_env = DeployEnv::from_push_event(
+            &first.push_event,
+            &first.branch_name,
+            run_id,
+            first.secrets,
+        );
+        let mut run_env =
+            HashMap::from([(TRIGGER_VARIABLE.to_string(), first.trigger.get_type().to_string())]);
+
+        if !first.services.is_empty() {
+            run_env.insert(SERVICES_VARIABLE.to_string(), first.services.join(" "));
+        }
+
+        if let RunTrigger::Manual { inputs, .. } = &first.trigger {
+            run_env.extend(
+                inputs
+                    .iter()
+                    .map(|(name, value)| (Input::get_variable_name(name), value.clone())),
+            );
+        }
+
+        let env = [EnvLayer::new(&run_env, &HashMap::new())]
+            .iter()
+            .chain(first.env_layers.iter())
+            .fold(push_env, |env, env_layer| env.with(env_layer));
+
+        self.cancellation_tokens
+            .lock()
+            .unwrap()
+            .insert(run_id, cancellation_token);
+
+        DeployJob {
+            run_id,
+            ssh_git_url,
+            branch_name: first.branch_name,
+            before_sha: first.push_event.before.clone(),
+            sha: first.sha,
+            path_filter: first.path_filter,
+            pushed_files: first.pushed_files,
+            approval: first.approval,
+            gate: first.gate,
+            outside_window: first.outside_window,
+            repo_path: first.repo_path,
+            clone_options: first.clone_options,
+            env,
+            secret_masker,
+            jobs: first.jobs,
+            health_probe: first.health_probe,
+            rollback_steps: first.rollback_steps,
+            default_step_timeout: self.config.default_step_timeout,
+            worktree_retention: self.config.worktree_retention,
+            run_repo: self.run_repo.clone(),
+            git_operations: self.git_operations.clone(),
+            command_runner: self.command_runner.clone(),
+            blocking_pool: self.blocking_pool.clone(),
+            health_checker: self.health_checker.clone(),
+            cancellation_tokens: self.cancellation_tokens.clone(),
+            pending_approvals: self.pending_approvals.clone(),
+            hold_changed: self.hold_changed.clone(),
+            deploy_slot: self.deploy_executor.create_slot(),
+            deploy_info_repo: self.deploy_info_repo.clone(),
+        }
+    }
+
+    // nothing about a run is kept or shown unmasked
+    fn create_run(
+        &self,
+        ssh_git_url: String,
+        first: &TempDataHolderOne,
+        secret_masker: &SecretMasker,
+    ) -> u64 {
+        let jobs = first
+            .jobs
+            .iter()
+            .map(|job| JobEntity::new(job.name.clone(), job.needs.clone()))
+            .collect();
+        let steps = first
+            .jobs
+            .iter()
+            .flat_map(|job| {
+                job.steps.iter().map(|step| {
+                    StepEntity::new(job.name.clone(), secret_masker.mask(step.command.as_str()))
+                })
+            })
+            .collect();
+        self.run_repo.lock().unwrap().create(
+            ssh_git_url,
+            first.branch_name.clone(),
+            first.sha.clone(),
+            first.trigger.clone(),
+            jobs,
+            steps,
+        )
+    }
+
+    // the new job's run replaces unfinished runs of its branch, depending on the policy
+    fn supersede_runs(&self, deploy_job: &DeployJob, concurrency: ConcurrencyPolicy) {
+        let statuses = match concurrency {
+            ConcurrencyPolicy::Queue => return,
+            ConcurrencyPolicy::CancelInProgress => {
+                vec![
+                    RunStatus::Queued,
+                    RunStatus::Running,
+                    RunStatus::AwaitingApproval,
+                    RunStatus::Held,
+                ]
+            }
+            ConcurrencyPolicy::Coalesce => vec![RunStatus::Queued],
+        };
+        let mut run_repo = self.run_repo.lock().unwrap();
+        let cancellation_tokens = self.cancellation_tokens.lock().unwrap();
+
+        let superseded_run_ids = run_repo
+            .find_by_branch(
+                deploy_job.ssh_git_url.as_str(),
+                deploy_job.branch_name.as_str(),
+                statuses.as_slice(),
+            )
+            .into_iter()
+            .filter(|run_id| *run_id != deploy_job.run_id)
+            .collect::<Vec<u64>>();
+
+        for run_id in superseded_run_ids {
+            run_repo.mark_superseded(run_id, deploy_job.run_id);
+
+            if let Some(cancellation_token) = cancellation_tokens.get(&run_id) {
+                cancellation_token.cancel();
+            }
+        }
+    }
+}
+
+struct DeployJob {
+    run_id: u64,
+    ssh_git_url: String,
+    branch_name: String,
+    before_sha: String,
+    sha: String,
+    path_filter: PathFilter,
+    // the fallback when the clone can't diff the push
+    pushed_files: Vec<String>,
+    approval: Option<PlannedApproval>,
+    gate: DeployGate,
+    outside_window: OutsideWindowPolicy,
+    repo_path: String,
+    clone_options: CloneOptions,
+    env: DeployEnv,
+    secret_masker: SecretMasker,
+    jobs: Vec<PlannedJob>,
+    health_probe: Option<HealthProbe>,
+    // the pushed commit's, they decide whether a failed run rolls back and which secrets are
+    // decrypted. The steps that run are the ones of the commit rolled back to
+    rollback_steps: Vec<PlannedStep>,
+    default_step_timeout: Duration,
+    worktree_retention: Duration,
+    run_repo: Arc<Mutex<RunRepository>>,
+    git_operations: Arc<dyn GitOperations>,
+    command_runner: Arc<dyn CommandRunner>,
+    blocking_pool: Arc<BlockingPool>,
+    health_checker: Arc<HealthChecker>,
+    cancellation_tokens: Arc<Mutex<HashMap<u64, CancellationToken>>>,
+    pending_approvals: Arc<Mutex<HashMap<u64, Vec<PendingApproval>>>>,
+    hold_changed: Arc<Notify>,
+    deploy_slot: DeploySlot,
+    deploy_info_repo: Arc<Mutex<DeployInfoRepository>>,
+}
+
+impl DeployJob {
+    async fn execute(self, cancellation_token: CancellationToken) {
+        let run_status = if !self.start(&cancellation_token) {
+            self.skip_jobs();
+            RunStatus::Cancelled
+        } else {
+            match self.prepare_worktree(self.sha.as_str()).await {
+                Ok(worktree_path) => {
+                    let changed_files = self.get_changed_files().await;
+
+                    if !self.path_filter.matches(&changed_files) {
+                        println!("No paths of {} changed, skipping the run", self.branch_name);
+                        self.run_repo
+                            .lock()
+                            .unwrap()
+                            .mark_skipped(self.run_id, UNCHANGED_PATHS_REASON.to_string());
+                        RunStatus::Skipped
+                    } else {
+                        // nothing else of the run happens while it waits, others can deploy
+                        let approval_status = match &self.approval {
+                            Some(approval) => {
+                                self.deploy_slot
+                                    .release_while(self.await_approval(
+                                        None,
+                                        approval,
+                                        &cancellation_token,
+                                    ))
+                                    .await
+                            }
+                            None => StepStatus::Succeeded,
+                        };
+                        // the gate is passed last, right before the jobs deploy
+                        let approval_status = match approval_status {
+                            StepStatus::Succeeded if self.gate.check(Utc::now()).is_some() => {
+                                self.deploy_slot
+                                    .release_while(self.wait_for_gate(&cancellation_token))
+                                    .await
+                            }
+                            approval_status => approval_status,
+                        };
+
+                        match approval_status {
+                            StepStatus::Succeeded => {
+                                let run_status = self
+                                    .execute_jobs(
+                                        &worktree_path,
+                                        &changed_files,
+                                        &cancellation_token,
+                                    )
+                                    .await;
+
+                                match &self.health_probe {
+                                    Some(probe) if run_status == RunStatus::Succeeded => {
+                                        self.verify_deploy(probe, &cancellation_token).await
+                                    }
+                                    _ => run_status,
+                                }
+                            }
+                            StepStatus::Cancelled => {
+                                self.skip_jobs();
+                                RunStatus::Cancelled
+                            }
+                            _ => {
+                                self.skip_jobs();
+                                RunStatus::Rejected
+                            }
+                        }
+                    }
+                }
+                Err(err) => {
+                    println!("{}: {}", err, self.branch_name);
+                    self.skip_jobs();
+                    RunStatus::Failed
+                }
+            }
+        };
+
+        self.finish(run_status);
+        self.remove_stale_worktrees().await;
     }
 
-    fn execute_deploy_commands(deploy_info: &DeployInfoEntity) -> JoinHandle<()> {
-        let path = deploy_info.repo_path.clone();
-        let command_builders = Arc::new(Mutex::new(deploy_info.command_builders.clone()));
+    // checked under the run repo lock, so a run can't be superseded once it's running
+    fn start(&self, cancellation_token: &CancellationToken) -> bool {
+        let mut run_repo = self.run_repo.lock().unwrap();
+
+        if cancellation_token.is_cancelled() {
+            return false;
+        }
+
+        run_repo.update_status(self.run_id, RunStatus::Running);
+        true
+    }
+
+    // every job whose needs have all succeeded runs, in parallel with the other ones.
+    // Jobs that need a job that didn't succeed are skipped
+    async fn execute_jobs(
+        &self,
+        worktree_path: &str,
+        changed_files: &[String],
+        cancellation_token: &CancellationToken,
+    ) -> RunStatus {
+        let mut job_statuses = HashMap::<&str, StepStatus>::new();
+        let mut running_jobs = FuturesUnordered::new();
+
+        loop {
+            let mut changed = true;
+
+            // a skipped job can settle the needs of other jobs, so until nothing changes
+            while changed {
+                changed = false;
+
+                for job in &self.jobs {
+                    if job_statuses.contains_key(job.name.as_str()) {
+                        continue;
+                    }
+
+                    let need_statuses = job
+                        .needs
+                        .iter()
+                        .map(|need| job_statuses.get(need.as_str()).copied())
+                        .collect::<Option<Vec<StepStatus>>>();
+
+                    let need_statuses = match need_statuses {
+                        Some(need_statuses) => need_statuses,
+                        None => continue,
+                    };
+
+                    if need_statuses.contains(&StepStatus::Running) {
+                        continue;
+                    }
+
+                    if !need_statuses.iter().all(|status| *status == StepStatus::Succeeded) {
+                        job_statuses.insert(job.name.as_str(), StepStatus::Skipped);
+                        self.skip_job(job);
+                    } else if !job.selected || !job.path_filter.matches(changed_files) {
+                        // left out or unchanged, it doesn't hold back the jobs that need it
+                        job_statuses.insert(job.name.as_str(), StepStatus::Succeeded);
+                        self.skip_job(job);
+                    } else {
+                        job_statuses.insert(job.name.as_str(), StepStatus::Running);
+                        running_jobs.push(async move {
+                            let job_status =
+                                self.execute_job(job, worktree_path, cancellation_token).await;
+                            (job.name.as_str(), job_status)
+                        });
+                    }
+
+                    changed = true;
+                }
+            }
+
+            match running_jobs.next().await {
+                Some((job_name, job_status)) => {
+                    job_statuses.insert(job_name, job_status);
+                }
+                None => break,
+            }
+        }
+
+        let job_statuses = job_statuses.into_values().collect::<Vec<StepStatus>>();
+
+        if job_statuses.contains(&StepStatus::Cancelled) {
+            RunStatus::Cancelled
+        } else if job_statuses.iter().any(|status| {
+            matches!(status, StepStatus::Failed | StepStatus::TimedOut)
+        }) {
+            RunStatus::Failed
+        } else if job_statuses.contains(&StepStatus::Rejected) {
+            RunStatus::Rejected
+        } else {
+            RunStatus::Succeeded
+        }
+    }
 
-        thread::spawn(move || {
-            for command_builder in command_builders.lock().unwrap().iter() {
-                let builder_result: std::io::Result<FunChildren> = command_builder(path.clone());
+    // waits for the approval of the branch or job. Returns Succeeded if it was approved,
+    // Rejected if it was rejected or expired and Cancelled if the run was
+    async fn await_approval(
+        &self,
+        job_name: Option<&str>,
+        approval: &PlannedApproval,
+        cancellation_token: &CancellationToken,
+    ) -> StepStatus {
+        let (sender, mut receiver) = oneshot::channel::<ApprovalDecision>();
+        let expires_at = approval
+            .expiry
+            .and_then(|expiry| chrono::Duration::from_std(expiry).ok())
+            .map(|expiry| Utc::now() + expiry);
 
-                builder_result.unwrap().wait_with_pipe(&mut |pipe| {
-                    BufReader::new(pipe)
-                        .lines()
-                        .filter_map(|line| line.ok())
-                        .for_each(|line| println!("{}", line));
+        {
+            let mut pending_approvals = self.pending_approvals.lock().unwrap();
+            let mut run_repo = self.run_repo.lock().unwrap();
+
+            pending_approvals
+                .entry(self.run_id)
+                .or_default()
+                .push(PendingApproval {
+                    job_name: job_name.map(|job_name| job_name.to_string()),
+                    sender,
                 });
+            run_repo.request_approval(
+                self.run_id,
+                job_name.map(|job_name| job_name.to_string()),
+                expires_at,
+            );
+            run_repo.update_status(self.run_id, RunStatus::AwaitingApproval);
+        }
+
+        println!(
+            "Run {} of {} awaits approval{}",
+            self.run_id,
+            self.branch_name,
+            job_name.map(|job_name| format!(" of {}", job_name)).unwrap_or_default()
+        );
+
+        let expiry = async {
+            match approval.expiry {
+                Some(expiry) => tokio::time::sleep(expiry).await,
+                None => std::future::pending().await,
             }
-        })
+        };
+        let received_decision = tokio::select! {
+            decision = &mut receiver => decision.ok(),
+            _ = expiry => None,
+            _ = cancellation_token.cancelled() => None,
+        };
+
+        let mut pending_approvals = self.pending_approvals.lock().unwrap();
+        let mut run_repo = self.run_repo.lock().unwrap();
+        let run_approvals = pending_approvals.entry(self.run_id).or_default();
+        let own_index = run_approvals
+            .iter()
+            .position(|pending_approval| pending_approval.job_name.as_deref() == job_name);
+
+        // a decision may have come in after the expiry or cancellation, it still counts
+        let decision = match own_index {
+            Some(own_index) => {
+                run_approvals.remove(own_index);
+                let decision =
+                    (!cancellation_token.is_cancelled()).then_some(ApprovalDecision::Expired);
+
+                if let Some(decision) = decision {
+                    run_repo.decide_approval(self.run_id, job_name, decision, None);
+                }
+
+                decision
+            }
+            None => received_decision.or_else(|| receiver.try_recv().ok()),
+        };
+
+        if run_approvals.is_empty() {
+            pending_approvals.remove(&self.run_id);
+            run_repo.update_status(self.run_id, RunStatus::Running);
+        }
+
+        match decision {
+            Some(ApprovalDecision::Approved) => StepStatus::Succeeded,
+            Some(_) => StepStatus::Rejected,
+            None => StepStatus::Cancelled,
+        }
+    }
+
+    // holds the run until its gate opens or someone overrides the hold. Returns Succeeded once
+    // it may deploy, Rejected if its branch rejects runs outside the gate and Cancelled if the
+    // run was
+    async fn wait_for_gate(&self, cancellation_token: &CancellationToken) -> StepStatus {
+        let mut held = false;
+
+        loop {
+            // enabled before checking, a change while checking still wakes the run
+            let hold_changed = self.hold_changed.notified();
+            tokio::pin!(hold_changed);
+            hold_changed.as_mut().enable();
+
+            let overridden = self
+                .run_repo
+                .lock()
+                .unwrap()
+                .get(self.run_id)
+                .map(|run| run.window_override.is_some())
+                .unwrap_or(false);
+            let closed_gate = match self.gate.check(Utc::now()) {
+                Some(closed_gate) if !overridden => closed_gate,
+                _ => break,
+            };
+
+            if self.outside_window == OutsideWindowPolicy::Reject {
+                println!(
+                    "Rejected run {} of {}: {}",
+                    self.run_id, self.branch_name, closed_gate.reason
+                );
+                self.run_repo
+                    .lock()
+                    .unwrap()
+                    .update_hold_reason(self.run_id, closed_gate.reason);
+                return StepStatus::Rejected;
+            }
+
+            if !held {
+                println!(
+                    "Holding run {} of {}: {}",
+                    self.run_id, self.branch_name, closed_gate.reason
+                );
+                held = true;
+            }
+
+            let recheck_interval = closed_gate
+                .opens_at
+                .and_then(|opens_at| (opens_at - Utc::now()).to_std().ok())
+                .unwrap_or(MAX_HOLD_RECHECK_INTERVAL)
+                .min(MAX_HOLD_RECHECK_INTERVAL);
+            self.run_repo
+                .lock()
+                .unwrap()
+                .mark_held(self.run_id, closed_gate.reason);
+
+            tokio::select! {
+                _ = tokio::time::sleep(recheck_interval) => {}
+                _ = hold_changed => {}
+                _ = cancellation_token.cancelled() => return StepStatus::Cancelled,
+            }
+        }
+
+        if held {
+            self.run_repo.lock().unwrap().release_hold(self.run_id);
+        }
+
+        StepStatus::Succeeded
     }
+
+    // probes the deployed service, a run whose service doesn't come up fails and is rolled back
+    async fn verify_deploy(
+        &self,
+        probe: &HealthProbe,
+        cancellation_token: &CancellationToken,
+    ) -> RunStatus {
+        let probe = HealthProbe {
+            url: self.env.interpolate(probe.url.as_str()),
+            ..probe.clone()
+        };
+        let mut health_check = HealthCheckEntity {
+            url: self.secret_masker.mask(probe.url.as_str()),
+            status: StepStatus::Running,
+            attempts: 0,
+            last_error: None,
+        };
+        self.update_health_check(&health_check);
+
+        let run_status = match self.health_checker.check(&probe, cancellation_token).await {
+            Ok(attempts) => {
+                health_check.status = StepStatus::Succeeded;
+                health_check.attempts = attempts;
+                RunStatus::Succeeded
+            }
+            Err(HealthCheckError::Unhealthy { attempts, reason }) => {
+                println!(
+                    "Health check of run {} of {} failed after {} attempts: {}",
+                    self.run_id, self.branch_name, attempts, reason
+                );
+                health_check.status = StepStatus::Failed;
+                health_check.attempts = attempts;
+                health_check.last_error = Some(reason);
+                RunStatus::Failed
+            }
+            Err(HealthCheckError::Cancelled) => {
+                health_check.status = StepStatus::Cancelled;
+                RunStatus::Cancelled
+            }
+        };
+        self.update_health_check(&health_check);
+
+        if run_status == RunStatus::Failed {
+            self.roll_back(cancellation_token).await;
+        }
+
+        run_status
+    }
+
+    // runs the rollback commands of the branch's last deployed commit, in a checkout of it
+    async fn roll_back(&self, cancellation_token: &CancellationToken) {
+        if self.rollback_steps.is_empty() {
+            return;
+        }
+
+        let sha = self
+            .deploy_info_repo
+            .lock()
+            .unwrap()
+            .get_deploys(self.ssh_git_url.as_str(), self.branch_name.as_str())
+            .first()
+            .map(|deploy| deploy.sha.clone());
+        let sha = match sha {
+            Some(sha) => sha,
+            None => {
+                println!("No successful run of {} to roll back to", self.branch_name);
+                return;
+            }
+        };
+
+        println!("Rolling back {} to {}", self.branch_name, sha);
+
+        let rollback_steps = match self.read_rollback_steps(sha.as_str()).await {
+            Ok(rollback_steps) => rollback_steps,
+            Err(err) => {
+                println!("{}: {} at {}", err, self.branch_name, sha);
+                let mut run_repo = self.run_repo.lock().unwrap();
+                run_repo.start_rollback(self.run_id, sha, vec![]);
+                run_repo.update_rollback(self.run_id, StepStatus::Failed);
+                return;
+            }
+        };
+        let steps = rollback_steps
+            .iter()
+            .map(|step| {
+                StepEntity::new(
+                    ROLLBACK_JOB_NAME.to_string(),
+                    self.secret_masker.mask(step.command.as_str()),
+                )
+            })
+            .collect();
+        self.run_repo
+            .lock()
+            .unwrap()
+            .start_rollback(self.run_id, sha.clone(), steps);
+
+        let worktree_path = match self.prepare_worktree(sha.as_str()).await {
+            Ok(worktree_path) => worktree_path,
+            Err(err) => {
+                println!("{}: {}", err, self.branch_name);
+                let mut run_repo = self.run_repo.lock().unwrap();
+
+                for index in 0..rollback_steps.len() {
+                    run_repo.update_rollback_step(self.run_id, index, StepStatus::Skipped, None);
+                }
+
+                run_repo.update_rollback(self.run_id, StepStatus::Failed);
+                return;
+            }
+        };
+
+        let rollback_env = HashMap::from([("MINI_CI_SHA".to_string(), sha)]);
+        let env = self.env.with(&EnvLayer::new(&rollback_env, &HashMap::new()));
+        let mut rollback_status = StepStatus::Succeeded;
+
+        for (index, step) in rollback_steps.iter().enumerate() {
+            if rollback_status != StepStatus::Succeeded {
+                self.update_rollback_step(index, StepStatus::Skipped, None);
+                continue;
+            }
+
+            self.update_rollback_step(index, StepStatus::Running, None);
+
+            let (step_status, exit_code) = self
+                .run_step(
+                    format!("rollback-{}", index).as_str(),
+                    step,
+                    &env.with(&step.env_layer),
+                    worktree_path.as_str(),
+                    cancellation_token,
+                )
+                .await;
+
+            rollback_status = step_status;
+            self.update_rollback_step(index, step_status, exit_code);
+        }
+
+        self.run_repo
+            .lock()
+            .unwrap()
+            .update_rollback(self.run_id, rollback_status);
+    }
+
+    // the rollback steps of the commit's own docker-deploy.yml. The run only has the secrets the
+    // pushed commit's config refers to, steps that need others can't run
+    async fn read_rollback_steps(&self, sha: &str) -> Result<Vec<PlannedStep>, DeployServiceError> {
+        let git_operations = self.git_operations.clone();
+        let repo_path = self.repo_path.clone();
+        let sha = sha.to_string();
+        let clone_options = self.clone_options.clone();
+
+        let yaml_text = self
+            .blocking_pool
+            .run(move || {
+                git_operations.read_file(
+                    repo_path.as_str(),
+                    sha.as_str(),
+                    DOCKER_DEPLOY_FILENAME,
+                    &clone_options,
+                )
+            })
+            .await
+            .map_err(|_| CouldNotReadDeployConfig)?
+            .map_err(|_| CouldNotReadDeployConfig)?;
+        let deploy_info = DeployInfo::parse(&yaml_text).map_err(|err| {
+            println!("Invalid {}: {:?}", DOCKER_DEPLOY_FILENAME, err);
+            CouldNotReadDeployConfig
+        })?;
+        let rollback_steps = deploy_info
+            .get_branch(self.branch_name.as_str())
+            .map(|branch| PlannedStep::plan_rollback(branch, self.default_step_timeout))
+            .unwrap_or_default();
+
+        if rollback_steps.is_empty() {
+            return Err(NoCommandsForBranch);
+        }
+
+        let missing_secret = rollback_steps
+            .iter()
+            .flat_map(|step| {
+                step.env_layer
+                    .find_secret_names()
+                    .into_iter()
+                    .chain(DeployEnv::find_secret_names(&step.command))
+            })
+            .find(|name| !self.env.has_secret(name));
+
+        match missing_secret {
+            Some(name) => {
+                println!("Missing secret {} of the rollback of {}", name, self.branch_name);
+                Err(MissingSecrets)
+            }
+            None => Ok(rollback_steps),
+        }
+    }
+
+    // the diff between the push's commits, or the payload's files if the clone can't tell
+    async fn get_changed_files(&self) -> Vec<String> {
+        if self.path_filter.is_empty() && self.jobs.iter().all(|job| job.path_filter.is_empty()) {
+            return vec![];
+        }
+
+        let git_operations = self.git_operations.clone();
+        let repo_path = self.repo_path.clone();
+        let before_sha = self.before_sha.clone();
+        let sha = self.sha.clone();
+        let clone_options = self.clone_options.clone();
+
+        let result = self
+            .blocking_pool
+            .run(move || {
+                git_operations.get_changed_files(
+                    repo_path.as_str(),
+                    before_sha.as_str(),
+                    sha.as_str(),
+                    &clone_options,
+                )
+            })
+            .await;
+
+        match result {
+            Ok(Ok(changed_files)) => changed_files,
+            _ => {
+                println!(
+                    "Could not diff {}..{}, using the files of the pushed commits",
+                    self.before_sha, self.sha
+                );
+                self.pushed_files.clone()
+            }
+        }
+    }
+
+    // returns the status of the step the job stopped at
+    async fn execute_job(
+        &self,
+        job: &PlannedJob,
+        worktree_path: &str,
+        cancellation_token: &CancellationToken,
+    ) -> StepStatus {
+        if let Some(approval) = &job.approval {
+            self.update_job(job, StepStatus::AwaitingApproval);
+
+            let approval_status = self
+                .await_approval(Some(job.name.as_str()), approval, cancellation_token)
+                .await;
+
+            if approval_status != StepStatus::Succeeded {
+                self.skip_job(job);
+                self.update_job(job, approval_status);
+                return approval_status;
+            }
+        }
+
+        let mut job_status = StepStatus::Succeeded;
+        let job_env = self.env.with(&job.env_layer);
+
+        self.update_job(job, StepStatus::Running);
+
+        for (step_offset, step) in job.steps.iter().enumerate() {
+            let index = job.first_step_index + step_offset;
+
+            if job_status != StepStatus::Succeeded {
+                self.update_step(index, StepStatus::Skipped, None);
+                continue;
+            }
+
+            if cancellation_token.is_cancelled() {
+                self.update_step(index, StepStatus::Cancelled, None);
+                job_status = StepStatus::Cancelled;
+                continue;
+            }
+
+            self.update_step(index, StepStatus::Running, None);
+
+            let (step_status, exit_code) = self
+                .run_step(
+                    format!("step-{}", index).as_str(),
+                    step,
+                    &job_env.with(&step.env_layer),
+                    worktree_path,
+                    cancellation_token,
+                )
+                .await;
+
+            job_status = step_status;
+            self.update_step(index, step_status, exit_code);
+        }
+
+        self.update_job(job, job_status);
+        job_status
+    }
+
+    // files_key tells the files of the run's steps apart
+    async fn run_step(
+        &self,
+        files_key: &str,
+        step: &PlannedStep,
+        env: &DeployEnv,
+        worktree_path: &str,
+        cancellation_token: &CancellationToken,
+    ) -> (StepStatus, Option<i32>) {
+        // the run keeps the command as configured, only the executed one is interpolated.
+        // variables and secrets are expanded by bash, their values are never part of the command
+        let mut command = env.interpolate_command(step.command.as_str());
+
+        if env.get_variables().contains_key(SERVICES_VARIABLE) {
+            command = CommitDirectives::restrict_compose_services(&command, SERVICES_VARIABLE);
+        }
+
+        let result = match self.write_files(files_key, env) {
+            Ok(variables) => {
+                self.command_runner
+                    .run(
+                        command.as_str(),
+                        worktree_path,
+                        &variables,
+                        &self.secret_masker,
+                        step.timeout,
+                        cancellation_token,
+                    )
+                    .await
+            }
+            Err(err) => {
+                println!(
+                    "Could not write the files of {}: {}",
+                    self.secret_masker.mask(step.command.as_str()),
+                    err
+                );
+                Err(CommandRunnerError::CouldNotSpawnCommand)
+            }
+        };
+        self.remove_files(files_key);
+
+        let (step_status, exit_code) = match result {
+            Ok(_) => (StepStatus::Succeeded, Some(0)),
+            Err(CommandRunnerError::CommandFailed(exit_code)) => (StepStatus::Failed, exit_code),
+            Err(CommandRunnerError::TimedOut) => (StepStatus::TimedOut, None),
+            Err(CommandRunnerError::Cancelled) => (StepStatus::Cancelled, None),
+            Err(_) => (StepStatus::Failed, None),
+        };
+
+        if step_status != StepStatus::Succeeded {
+            println!("{}: {}", step_status, self.secret_masker.mask(step.command.as_str()));
+        }
+
+        (step_status, exit_code)
+    }
+
+    // files only exist while their step runs and are readable by the mini-ci user alone,
+    // returns the step's variables with the files' paths
+    fn write_files(
+        &self,
+        files_key: &str,
+        env: &DeployEnv,
+    ) -> std::io::Result<HashMap<String, String>> {
+        let mut variables = env.get_command_variables();
+
+        if env.get_files().is_empty() {
+            return Ok(variables);
+        }
+
+        let files_dir_path = self.get_files_dir_path(files_key);
+        DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(&files_dir_path)?;
+
+        for (name, content) in env.get_files() {
+            let file_path = files_dir_path.join(name);
+
+            OpenOptions::new()
+                .create(true)
+                .truncate(true)
+                .write(true)
+                .mode(0o600)
+                .open(&file_path)?
+                .write_all(content.as_bytes())?;
+            variables.insert(name.clone(), file_path.to_string_lossy().to_string());
+        }
+
+        Ok(variables)
+    }
+
+    fn remove_files(&self, files_key: &str) {
+        let files_dir_path = self.get_files_dir_path(files_key);
+
+        if files_dir_path.exists() {
+            let _ = fs::remove_dir_all(files_dir_path);
+        }
+    }
+
+    fn get_files_dir_path(&self, files_key: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("mini-ci-run-{}-{}", self.run_id, files_key))
+    }
+
+    fn finish(&self, run_status: RunStatus) {
+        self.cancellation_tokens.lock().unwrap().remove(&self.run_id);
+
+        let mut run_repo = self.run_repo.lock().unwrap();
+        let superseded = run_repo
+            .get(self.run_id)
+            .and_then(|run| run.superseded_by)
+            .is_some();
+
+        let run_status = match run_status {
+            RunStatus::Cancelled if superseded => RunStatus::Superseded,
+            run_status => run_status,
+        };
+
+        run_repo.update_status(self.run_id, run_status);
+        drop(run_repo);
+
+        if run_status == RunStatus::Succeeded {
+            let result = self.deploy_info_repo.lock().unwrap().add_deploy(
+                self.ssh_git_url.as_str(),
+                self.branch_name.as_str(),
+                DeployEntity {
+                    sha: self.sha.clone(),
+                    run_id: self.run_id,
+                    deployed_at: Utc::now(),
+                },
+            );
+
+            if let Err(err) = result {
+                println!("Could not save the deploy of run {}: {:?}", self.run_id, err);
+            }
+        }
+    }
+
+    // returns the path of the worktree the steps run in, checked out at the sha
+    async fn prepare_worktree(&self, sha: &str) -> Result<String, DeployServiceError> {
+        let git_operations = self.git_operations.clone();
+        let repo_path = self.repo_path.clone();
+        let branch_name = self.branch_name.clone();
+        let sha = sha.to_string();
+        let clone_options = self.clone_options.clone();
+
+        self.blocking_pool
+            .run(move || {
+                git_operations.prepare_worktree(
+                    repo_path.as_str(),
+                    branch_name.as_str(),
+                    sha.as_str(),
+                    &clone_options,
+                )
+            })
+            .await
+            .map_err(|_| CouldNotCheckoutBranch)?
+            .map_err(|_| CouldNotCheckoutBranch)
+    }
+
+    // worktrees of branches with queued or running runs are kept regardless of their age
+    async fn remove_stale_worktrees(&self) {
+        let git_operations = self.git_operations.clone();
+        let repo_path = self.repo_path.clone();
+        let max_idle = self.worktree_retention;
+        let kept_branch_names = self
+            .run_repo
+            .lock()
+            .unwrap()
+            .find_active_branch_names(self.ssh_git_url.as_str());
+
+        let result = self
+            .blocking_pool
+            .run(move || {
+                git_operations.remove_stale_worktrees(
+                    repo_path.as_str(),
+                    max_idle,
+                    kept_branch_names.as_slice(),
+                )
+            })
+            .await;
+
+        match result {
+            Ok(Ok(removed_paths)) => {
+                for removed_path in removed_paths {
+                    println!("Removed stale worktree {}", removed_path);
+                }
+            }
+            Ok(Err(err)) => println!("Could not remove stale worktrees: {:?}", err),
+            Err(err) => println!("Could not remove stale worktrees: {:?}", err),
+        }
+    }
+
+    fn skip_jobs(&self) {
+        for job in &self.jobs {
+            self.skip_job(job);
+        }
+    }
+
+    fn skip_job(&self, job: &PlannedJob) {
+        self.update_job(job, StepStatus::Skipped);
+
+        for index in job.first_step_index..job.first_step_index + job.steps.len() {
+            self.update_step(index, StepStatus::Skipped, None);
+        }
+    }
+
+    fn update_job(&self, job: &PlannedJob, status: StepStatus) {
+        self.run_repo
+            .lock()
+            .unwrap()
+            .update_job(self.run_id, job.name.as_str(), status);
+    }
+
+    fn update_step(&self, index: usize, status: StepStatus, exit_code: Option<i32>) {
+        self.run_repo
+            .lock()
+            .unwrap()
+            .update_step(self.run_id, index, status, exit_code);
+    }
+
+    fn update_rollback_step(&self, index: usize, status: StepStatus, exit_code: Option<i32>) {
+        self.run_repo
+            .lock()
+            .unwrap()
+            .update_rollback_step(self.run_id, index, status, exit_code);
+    }
+
+    fn update_health_check(&self, health_check: &HealthCheckEntity) {
+        self.run_repo
+            .lock()
+            .unwrap()
+            .update_health_check(self.run_id, health_check.clone());
+    }
+}
+
+struct TempDataHolderOne {
+    branch_name: String,
+    gate: DeployGate,
+    outside_window: OutsideWindowPolicy,
+    sha: String,
+    path_filter: PathFilter,
+    pushed_files: Vec<String>,
+    approval: Option<PlannedApproval>,
+    repo_path: String,
+    clone_options: CloneOptions,
+    concurrency: ConcurrencyPolicy,
+    debounce: Duration,
+    skip_reason: Option<String>,
+    services: Vec<String>,
+    env_layers: Vec<EnvLayer>,
+    secrets: HashMap<String, String>,
+    jobs: Vec<PlannedJob>,
+    health_probe: Option<HealthProbe>,
+    rollback_steps: Vec<PlannedStep>,
+    trigger: RunTrigger,
+    push_event: GithubPushEventDto,
+}
+
+struct PlannedJob {
+    name: String,
+    needs: Vec<String>,
+    path_filter: PathFilter,
+    // false if [deploy only=...] left the job out
+    selected: bool,
+    approval: Option<PlannedApproval>,
+    env_layer: EnvLayer,
+    // of the job's first step among the steps of the run
+    first_step_index: usize,
+    steps: Vec<PlannedStep>,
+}
+
+struct PlannedApproval {
+    // None waits as long as the run isn't cancelled
+    expiry: Option<Duration>,
+}
+
+// an approval someone still has to decide
+struct PendingApproval {
+    // None for the branch's approval
+    job_name: Option<String>,
+    sender: oneshot::Sender<ApprovalDecision>,
+}
+
+impl PlannedApproval {
+    fn new(policy: ApprovalPolicy, expiry_secs: Option<u64>) -> Option<PlannedApproval> {
+        match policy {
+            ApprovalPolicy::None => None,
+            ApprovalPolicy::Required => {
+                Some(PlannedApproval {
+                    expiry: expiry_secs.map(Duration::from_secs),
+                })
+            }
+        }
+    }
+}
+
+struct PlannedStep {
+    command: String,
+    timeout: Duration,
+    env_layer: EnvLayer,
 }
 
-struct TempDataHolderOne<'a> {
-    branch: Branch<'a>,
-    deploy_info: &'a DeployInfoEntity,
-    refs: String,
+impl PlannedStep {
+    // a step's own timeout wins over its branch's, then the default
+    fn plan_rollback(branch: &Branch, default_step_timeout: Duration) -> Vec<PlannedStep> {
+        branch
+            .rollback
+            .iter()
+            .map(|command| command.to_step())
+            .map(|step| PlannedStep {
+                timeout: step
+                    .timeout
+                    .or(branch.timeout)
+                    .map(Duration::from_secs)
+                    .unwrap_or(default_step_timeout),
+                command: step.run,
+                env_layer: EnvLayer::new(&step.env, &step.files),
+            })
+            .collect()
+    }
 }
 
 #[derive(Display, Debug)]
 pub enum DeployServiceError {
     CouldNotGetBranch,
     CouldNotGetRepoInfo,
+    NoCommandsForBranch,
+    CouldNotGetSecrets,
+    MissingSecrets,
     CouldNotCheckoutBranch,
+    RunNotFound,
+    RunAlreadyFinished,
+    CouldNotGetApprovers,
+    NotAnApprover,
+    NoApprovalAwaited,
+    CouldNotGetBranchSha,
+    // the schedule's previous run is still queued or running
+    ScheduledRunUnfinished,
+    RunNotHeld,
+    CouldNotGetFreezes,
+    FreezeNotFound,
+    // it has to end after it starts and after now
+    InvalidFreeze,
+    CouldNotWriteAuditLog,
+    // the branch didn't deploy the rollback's target
+    DeployNotFound,
+    CouldNotReadDeployConfig,
+    // the values of a manual run don't fit the repo's inputs
+    InvalidInputs(InputError),
+    // a manual run's sha isn't a full one
+    InvalidSha,
+    // the branch's tip isn't the commit or one of its descendants
+    CommitNotOnBranch,
 }
@@ -0,0 +1,88 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tokio::task::JoinHandle;
+
+use crate::domain::deploy_service::{DeployServiceError, StartedDeploy};
+use crate::domain::deploy_tracker::DeployTrackerError::{CouldNotStartDeploy, NotAcceptingDeploys};
+
+static POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+pub struct DeployTracker {
+    accepting: AtomicBool,
+    running: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl Default for DeployTracker {
+    fn default() -> Self {
+        DeployTracker::new()
+    }
+}
+
+impl DeployTracker {
+    pub fn new() -> DeployTracker {
+        DeployTracker {
+            accepting: AtomicBool::new(true),
+            running: Mutex::new(vec![]),
+        }
+    }
+
+    // the lock is held while starting, so a deploy can't slip in after stop_accepting
+    // returns the run id of the started deploy
+    pub fn track<F>(&self, start_deploy: F) -> Result<u64, DeployTrackerError>
+        where
+            F: FnOnce() -> Result<StartedDeploy, DeployServiceError>,
+    {
+        let mut running = self.running.lock().unwrap();
+
+        if !self.accepting.load(Ordering::SeqCst) {
+            return Err(NotAcceptingDeploys);
+        }
+
+        running.retain(|handle| !handle.is_finished());
+
+        start_deploy()
+            .map_err(CouldNotStartDeploy)
+            .map(|started_deploy| {
+                running.extend(started_deploy.join_handle);
+                started_deploy.run_id
+            })
+    }
+
+    pub fn stop_accepting(&self) {
+        let _running = self.running.lock().unwrap();
+
+        self.accepting.store(false, Ordering::SeqCst);
+    }
+
+    // returns false if deploys were still running when the timeout elapsed
+    pub async fn wait_for_running(&self, timeout: Duration) -> bool {
+        let started_at = Instant::now();
+
+        loop {
+            let running_count = {
+                let mut running = self.running.lock().unwrap();
+                running.retain(|handle| !handle.is_finished());
+                running.len()
+            };
+
+            if running_count == 0 {
+                return true;
+            }
+
+            if started_at.elapsed() >= timeout {
+                println!("{} deploy(s) still running after {:?}", running_count, timeout);
+                return false;
+            }
+
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum DeployTrackerError {
+    NotAcceptingDeploys,
+    CouldNotStartDeploy(DeployServiceError),
+}
@@ -0,0 +1,185 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+use git2::{Cred, CredentialType, Error};
+use strum::{Display, EnumString};
+use tokio::runtime::Handle;
+
+use crate::data::github_app_token_repository::GithubAppTokenRepository;
+use crate::domain::repo_url::RepoUrl;
+
+static DEFAULT_SSH_USERNAME: &str = "git";
+// github takes any username together with a token, this is the documented one
+static HTTPS_TOKEN_USERNAME: &str = "x-access-token";
+
+#[derive(Display, EnumString, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[strum(serialize_all = "kebab-case")]
+pub enum GitAuthMethod {
+    SshKey,
+    SshKeyMemory,
+    SshAgent,
+    HttpsToken,
+    GithubApp,
+}
+
+#[derive(Clone)]
+pub enum GitCredentials {
+    SshKey {
+        key_path: String,
+        passphrase: Option<String>,
+    },
+    SshKeyMemory {
+        private_key: String,
+        passphrase: Option<String>,
+    },
+    SshAgent,
+    HttpsToken {
+        token: String,
+    },
+    GithubApp(Arc<GithubAppTokenRepository>),
+}
+
+impl GitCredentials {
+    pub fn get_method(&self) -> GitAuthMethod {
+        match self {
+            GitCredentials::SshKey { .. } => GitAuthMethod::SshKey,
+            GitCredentials::SshKeyMemory { .. } => GitAuthMethod::SshKeyMemory,
+            GitCredentials::SshAgent => GitAuthMethod::SshAgent,
+            GitCredentials::HttpsToken { .. } => GitAuthMethod::HttpsToken,
+            GitCredentials::GithubApp(_) => GitAuthMethod::GithubApp,
+        }
+    }
+
+    fn is_https(&self) -> bool {
+        matches!(self, GitCredentials::HttpsToken { .. } | GitCredentials::GithubApp(_))
+    }
+
+    // tokens only work over https and keys only over ssh, so the url is switched if needed
+    pub fn get_remote_url(&self, repo_url: &RepoUrl, url: &str) -> String {
+        let is_https_url = url.starts_with("https://") || url.starts_with("http://");
+
+        if url.starts_with("file://") || self.is_https() == is_https_url {
+            url.to_string()
+        } else if self.is_https() {
+            format!("https://{}/{}/{}.git", repo_url.host, repo_url.owner, repo_url.name)
+        } else {
+            format!("git@{}:{}/{}.git", repo_url.host, repo_url.owner, repo_url.name)
+        }
+    }
+
+    // called from libgit2's credentials callback, only hands out what the transport allows
+    pub fn create_cred(
+        &self,
+        username_from_url: Option<&str>,
+        allowed_types: CredentialType,
+    ) -> Result<Cred, Error> {
+        let username = username_from_url.unwrap_or(DEFAULT_SSH_USERNAME);
+
+        // ssh asks for the username on its own first if the url doesn't contain one
+        if !self.is_https() && allowed_types == CredentialType::USERNAME {
+            return Cred::username(username);
+        }
+
+        match self {
+            GitCredentials::SshKey {
+                key_path,
+                passphrase,
+            } if allowed_types.contains(CredentialType::SSH_KEY) => {
+                Cred::ssh_key(username, None, Path::new(key_path), passphrase.as_deref())
+            }
+            GitCredentials::SshKeyMemory {
+                private_key,
+                passphrase,
+            } if allowed_types.contains(CredentialType::SSH_MEMORY) => {
+                Cred::ssh_key_from_memory(username, None, private_key, passphrase.as_deref())
+            }
+            GitCredentials::SshAgent if allowed_types.contains(CredentialType::SSH_KEY) => {
+                Cred::ssh_key_from_agent(username)
+            }
+            GitCredentials::HttpsToken { token }
+            if allowed_types.contains(CredentialType::USER_PASS_PLAINTEXT) => {
+                Cred::userpass_plaintext(HTTPS_TOKEN_USERNAME, token)
+            }
+            GitCredentials::GithubApp(token_repository)
+            if allowed_types.contains(CredentialType::USER_PASS_PLAINTEXT) => {
+                Self::get_github_app_token(token_repository)
+                    .and_then(|token| Cred::userpass_plaintext(HTTPS_TOKEN_USERNAME, &token))
+            }
+            _ => {
+                Err(Error::from_str(
+                    format!(
+                        "{} credentials can't be used, the remote allows {:?}",
+                        self.get_method(),
+                        allowed_types
+                    )
+                        .as_str(),
+                ))
+            }
+        }
+    }
+
+    // what https remotes and lfs servers are authorized with, ssh credentials have no token
+    pub fn get_https_token(&self) -> Result<Option<String>, Error> {
+        match self {
+            GitCredentials::HttpsToken { token } => Ok(Some(token.clone())),
+            GitCredentials::GithubApp(token_repository) => {
+                Self::get_github_app_token(token_repository).map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    // git operations run on the blocking pool, which may block on the runtime
+    fn get_github_app_token(token_repository: &GithubAppTokenRepository) -> Result<String, Error> {
+        Handle::try_current()
+            .map_err(|_| Error::from_str("no runtime to get the github app token on"))
+            .and_then(|runtime| {
+                runtime
+                    .block_on(token_repository.get_token())
+                    .map_err(|err| Error::from_str(format!("{:?}", err).as_str()))
+            })
+    }
+}
+
+// credentials per repo, falling back to the default ones
+pub struct GitAuth {
+    default_credentials: GitCredentials,
+    // keyed by host/owner/name, so the ssh and https url of a repo share their credentials
+    // provisioned deploy keys are added while mini-ci is already running
+    repo_credentials: RwLock<HashMap<String, GitCredentials>>,
+}
+
+impl GitAuth {
+    pub fn new(
+        default_credentials: GitCredentials,
+        repo_credentials: HashMap<String, GitCredentials>,
+    ) -> GitAuth {
+        GitAuth {
+            default_credentials,
+            repo_credentials: RwLock::new(repo_credentials),
+        }
+    }
+
+    pub fn get_credentials(&self, url: &str) -> GitCredentials {
+        RepoUrl::parse(url)
+            .ok()
+            .and_then(|repo_url| self.get_repo_credentials(&repo_url))
+            .unwrap_or_else(|| self.default_credentials.clone())
+    }
+
+    pub fn get_repo_credentials(&self, repo_url: &RepoUrl) -> Option<GitCredentials> {
+        self.repo_credentials
+            .read()
+            .unwrap()
+            .get(&repo_url.relative_path())
+            .cloned()
+    }
+
+    pub fn set_repo_credentials(&self, repo_url: &RepoUrl, credentials: GitCredentials) {
+        self.repo_credentials
+            .write()
+            .unwrap()
+            .insert(repo_url.relative_path(), credentials);
+    }
+}
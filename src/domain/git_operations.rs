@@ -0,0 +1,673 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use git2::{
+    BranchType, Commit, ObjectType, Oid, Repository, Signature, SubmoduleUpdateOptions,
+    WorktreeAddOptions, WorktreePruneOptions,
+};
+
+use crate::data::deploy_info::CloneOptions;
+use crate::domain::clone_repo_task::CloneRepoTask;
+use crate::domain::git_credentials::GitAuth;
+use crate::domain::git_operations::GitOperationsError::{
+    CouldNotCheckoutBranch, CouldNotCheckoutLfsFiles, CouldNotCloneRepo, CouldNotCreateWorktree,
+    CouldNotFetchRepo, CouldNotGetBranch, CouldNotGetChangedFiles, CouldNotGetFileId,
+    CouldNotOpenRepo, CouldNotReadFile, CouldNotRemoveWorktree, CouldNotUpdateSubmodules,
+};
+use crate::domain::lfs_task::LfsTask;
+use crate::domain::repo_url::RepoUrl;
+
+static LAST_USED_FILENAME: &str = "last-used";
+static SPARSE_PATHS_FILENAME: &str = "sparse-paths";
+
+pub trait GitOperations: Send + Sync {
+    // clones the repo or updates an existing clone, returns the path of the clone
+    fn clone_repo(
+        &self,
+        url: &str,
+        into_dir_path: &str,
+        clone_options: &CloneOptions,
+    ) -> Result<String, GitOperationsError>;
+
+    fn get_file_id(
+        &self,
+        repo_path: &str,
+        rev: &str,
+        file_name: &str,
+    ) -> Result<String, GitOperationsError>;
+
+    // fetches the clone and checks the sha (or the branch's tip if it's empty) out into the
+    // branch's own worktree, returns the worktree's path
+    // a shallow clone is deepened until it contains the sha
+    fn prepare_worktree(
+        &self,
+        repo_path: &str,
+        branch_name: &str,
+        sha: &str,
+        clone_options: &CloneOptions,
+    ) -> Result<String, GitOperationsError>;
+
+    // removes worktrees that weren't used for max_idle, except the ones of the kept branches
+    // returns the paths of the removed worktrees
+    fn remove_stale_worktrees(
+        &self,
+        repo_path: &str,
+        max_idle: Duration,
+        kept_branch_names: &[String],
+    ) -> Result<Vec<String>, GitOperationsError>;
+
+    // paths of the files that differ between the two commits, both paths of renamed ones
+    // expects the clone to be fetched already, a shallow one is deepened until it has from_sha
+    fn get_changed_files(
+        &self,
+        repo_path: &str,
+        from_sha: &str,
+        to_sha: &str,
+        clone_options: &CloneOptions,
+    ) -> Result<Vec<String>, GitOperationsError>;
+
+    // fetches the clone and returns the sha of the branch's tip
+    fn get_branch_sha(
+        &self,
+        repo_path: &str,
+        branch_name: &str,
+        clone_options: &CloneOptions,
+    ) -> Result<String, GitOperationsError>;
+
+    // the content of the file in the commit, a shallow clone is deepened until it has the sha
+    fn read_file(
+        &self,
+        repo_path: &str,
+        sha: &str,
+        file_name: &str,
+        clone_options: &CloneOptions,
+    ) -> Result<String, GitOperationsError>;
+
+    // fetches the clone and tells whether the commit is the branch's tip or one of its ancestors,
+    // a shallow clone is deepened a bounded number of times to find the sha. An unknown commit,
+    // or one older than the deepest fetch, is on no branch
+    fn is_on_branch(
+        &self,
+        repo_path: &str,
+        branch_name: &str,
+        sha: &str,
+        clone_options: &CloneOptions,
+    ) -> Result<bool, GitOperationsError>;
+}
+
+pub struct Git2Operations {
+    clone_repo_task: CloneRepoTask,
+    git_auth: Arc<GitAuth>,
+    lfs_task: LfsTask,
+    // git2 operations on one clone must not run at the same time
+    repo_locks: Mutex<HashMap<String, Arc<Mutex<()>>>>,
+}
+
+impl Git2Operations {
+    pub fn new(
+        clone_repo_task: CloneRepoTask,
+        git_auth: Arc<GitAuth>,
+        lfs_task: LfsTask,
+    ) -> Git2Operations {
+        Git2Operations {
+            clone_repo_task,
+            git_auth,
+            lfs_task,
+            repo_locks: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn get_repo_lock(&self, repo_path: &str) -> Arc<Mutex<()>> {
+        self.repo_locks
+            .lock()
+            .unwrap()
+            .entry(repo_path.to_string())
+            .or_insert_with(|| Arc::new(Mutex::new(())))
+            .clone()
+    }
+
+    fn fetch(&self, repository: &Repository, depth: i32) -> Result<(), GitOperationsError> {
+        let remote = repository.find_remote("origin").map_err(|_| CouldNotFetchRepo)?;
+        let credentials = self
+            .git_auth
+            .get_credentials(remote.url().ok_or(CouldNotFetchRepo)?);
+
+        self.clone_repo_task
+            .fetch(repository, &credentials, depth)
+            .map_err(|err| {
+                println!("Could not fetch {}: {}", remote.url().unwrap_or(""), err.message());
+                CouldNotFetchRepo
+            })
+    }
+
+    // submodules of submodules are updated as well, each with the credentials of its own repo
+    fn update_submodules(
+        &self,
+        repository: &mut Repository,
+        clone_options: &CloneOptions,
+    ) -> Result<(), GitOperationsError> {
+        let submodule_names = repository
+            .submodules()
+            .map_err(|_| CouldNotUpdateSubmodules)?
+            .iter()
+            .filter(|submodule| {
+                clone_options.is_checked_out(submodule.path().to_str().unwrap_or(""))
+            })
+            .filter_map(|submodule| submodule.name().map(|name| name.to_string()))
+            .collect::<Vec<String>>();
+
+        for submodule_name in submodule_names {
+            // init resolves relative urls against origin
+            repository
+                .find_submodule(&submodule_name)
+                .and_then(|mut submodule| submodule.init(true))
+                .map_err(|_| CouldNotUpdateSubmodules)?;
+
+            let url = repository
+                .find_submodule(&submodule_name)
+                .ok()
+                .and_then(|submodule| submodule.url().map(|url| url.to_string()))
+                .ok_or(CouldNotUpdateSubmodules)?;
+            let credentials = self.git_auth.get_credentials(&url);
+            let remote_url = RepoUrl::parse(&url)
+                .map(|repo_url| credentials.get_remote_url(&repo_url, &url))
+                .unwrap_or(url.clone());
+
+            if remote_url != url {
+                repository
+                    .submodule_set_url(&submodule_name, &remote_url)
+                    .map_err(|_| CouldNotUpdateSubmodules)?;
+            }
+
+            let mut submodule = repository
+                .find_submodule(&submodule_name)
+                .map_err(|_| CouldNotUpdateSubmodules)?;
+            let mut options = SubmoduleUpdateOptions::new();
+            options
+                .fetch(self.clone_repo_task.create_fetch_options(&credentials, &remote_url))
+                .checkout(CloneRepoTask::create_checkout_builder(&CloneOptions::default()));
+
+            submodule
+                .sync()
+                .and_then(|_| submodule.update(true, Some(&mut options)))
+                .map_err(|err| {
+                    println!("Could not update submodule {}: {}", submodule_name, err.message());
+                    CouldNotUpdateSubmodules
+                })?;
+
+            let mut submodule_repository =
+                submodule.open().map_err(|_| CouldNotUpdateSubmodules)?;
+            self.update_submodules(&mut submodule_repository, &CloneOptions::default())?;
+        }
+
+        Ok(())
+    }
+
+    fn checkout_lfs_files(
+        &self,
+        repository: &Repository,
+        commit: &Commit,
+        clone_options: &CloneOptions,
+    ) -> Result<(), GitOperationsError> {
+        let remote = repository.find_remote("origin").map_err(|_| CouldNotCheckoutLfsFiles)?;
+        let remote_url = remote.url().ok_or(CouldNotCheckoutLfsFiles)?;
+        let credentials = self.git_auth.get_credentials(remote_url);
+
+        self.lfs_task
+            .execute(repository, commit, remote_url, &credentials, clone_options)
+            .map(|count| {
+                if count > 0 {
+                    println!("Checked out {} lfs files", count);
+                }
+            })
+            .map_err(|err| {
+                println!("Could not check out lfs files: {:?}", err);
+                CouldNotCheckoutLfsFiles
+            })
+    }
+
+    // a sha older than the shallow history is only there after deepening to one of the depths
+    fn deepen_until_found(
+        &self,
+        repository: &Repository,
+        sha: &str,
+        depths: Vec<i32>,
+    ) -> Result<(), GitOperationsError> {
+        let oid = Oid::from_str(sha).map_err(|_| CouldNotGetBranch)?;
+
+        for depth in depths {
+            if !repository.is_shallow() || repository.find_commit(oid).is_ok() {
+                break;
+            }
+
+            println!("Deepening the clone to {} commits to find {}", depth, sha);
+            self.fetch(repository, depth)?;
+        }
+
+        Ok(())
+    }
+
+    fn find_commit<'a>(
+        repository: &'a Repository,
+        branch_name: &str,
+        sha: &str,
+    ) -> Result<Commit<'a>, GitOperationsError> {
+        if !sha.is_empty() {
+            return Oid::from_str(sha)
+                .and_then(|oid| repository.find_commit(oid))
+                .map_err(|_| CouldNotGetBranch);
+        }
+
+        let formatted_branch_name = format!("origin/{}", branch_name);
+
+        repository
+            .find_branch(formatted_branch_name.as_str(), BranchType::Remote)
+            .and_then(|branch| branch.get().peel_to_commit())
+            .map_err(|_| CouldNotGetBranch)
+    }
+
+    // reuses the branch's worktree if it's still intact, recreates it otherwise
+    // a worktree checked out with other sparse paths is recreated, it'd keep the files of the
+    // paths that were dropped
+    fn open_worktree(
+        repository: &Repository,
+        worktree_name: &str,
+        worktree_path: &Path,
+        commit: &Commit,
+        sparse_paths: &[String],
+    ) -> Result<Repository, GitOperationsError> {
+        let sparse_paths_file_path = worktree_path
+            .parent()
+            .ok_or(CouldNotCreateWorktree)?
+            .join(SPARSE_PATHS_FILENAME);
+        let formatted_sparse_paths = sparse_paths.join("\n");
+
+        if let Ok(worktree) = repository.find_worktree(worktree_name) {
+            let has_same_sparse_paths = fs::read_to_string(&sparse_paths_file_path)
+                .unwrap_or_default()
+                == formatted_sparse_paths;
+
+            if worktree.validate().is_ok() && has_same_sparse_paths {
+                return Repository::open_from_worktree(&worktree).map_err(|_| CouldNotOpenRepo);
+            }
+
+            worktree
+                .prune(Some(WorktreePruneOptions::new().valid(true).working_tree(true)))
+                .map_err(|_| CouldNotCreateWorktree)?;
+        }
+
+        if worktree_path.exists() {
+            fs::remove_dir_all(worktree_path).map_err(|_| CouldNotCreateWorktree)?;
+        }
+
+        if let Some(parent) = worktree_path.parent() {
+            fs::create_dir_all(parent).map_err(|_| CouldNotCreateWorktree)?;
+        }
+
+        // adding a worktree checks out everything, a sparse one starts from an empty commit
+        // and gets its paths checked out afterwards
+        let start_commit = if sparse_paths.is_empty() {
+            commit.clone()
+        } else {
+            Self::create_empty_commit(repository)?
+        };
+        let branch = repository
+            .branch(worktree_name, &start_commit, true)
+            .map_err(|_| CouldNotCreateWorktree)?;
+        let mut options = WorktreeAddOptions::new();
+        options.reference(Some(branch.get()));
+
+        let worktree_repository = repository
+            .worktree(worktree_name, worktree_path, Some(&options))
+            .and_then(|worktree| Repository::open_from_worktree(&worktree))
+            .map_err(|_| CouldNotCreateWorktree)?;
+
+        fs::write(&sparse_paths_file_path, formatted_sparse_paths)
+            .map_err(|_| CouldNotCreateWorktree)?;
+
+        Ok(worktree_repository)
+    }
+
+    fn create_empty_commit(repository: &Repository) -> Result<Commit<'_>, GitOperationsError> {
+        let signature =
+            Signature::now("mini-ci", "mini-ci@localhost").map_err(|_| CouldNotCreateWorktree)?;
+
+        repository
+            .treebuilder(None)
+            .and_then(|tree_builder| tree_builder.write())
+            .and_then(|tree_id| repository.find_tree(tree_id))
+            .and_then(|tree| {
+                repository.commit(None, &signature, &signature, "mini-ci: sparse", &tree, &[])
+            })
+            .and_then(|commit_id| repository.find_commit(commit_id))
+            .map_err(|_| CouldNotCreateWorktree)
+    }
+
+    fn remove_worktree(
+        repository: &Repository,
+        worktree_name: &str,
+        worktree_dir_path: &Path,
+    ) -> Result<(), GitOperationsError> {
+        repository
+            .find_worktree(worktree_name)
+            .and_then(|worktree| {
+                worktree.prune(Some(WorktreePruneOptions::new().valid(true).working_tree(true)))
+            })
+            .map_err(|_| CouldNotRemoveWorktree)?;
+
+        if let Ok(mut branch) = repository.find_branch(worktree_name, BranchType::Local) {
+            branch.delete().map_err(|_| CouldNotRemoveWorktree)?;
+        }
+
+        if worktree_dir_path.exists() {
+            fs::remove_dir_all(worktree_dir_path).map_err(|_| CouldNotRemoveWorktree)?;
+        }
+
+        Ok(())
+    }
+
+    fn get_worktrees_dir_path(repo_path: &str) -> String {
+        format!("{}.worktrees", repo_path.trim_end_matches('/'))
+    }
+
+    // branch names can contain slashes and collide once sanitized, the hash keeps them apart
+    fn get_worktree_name(branch_name: &str) -> String {
+        let sanitized_branch_name = branch_name
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                    c
+                } else {
+                    '-'
+                }
+            })
+            .collect::<String>();
+        let hash = Oid::hash_object(ObjectType::Blob, branch_name.as_bytes())
+            .map(|oid| oid.to_string())
+            .unwrap_or_default();
+
+        format!("mini-ci-{}-{}", sanitized_branch_name, &hash[..hash.len().min(8)])
+    }
+
+    fn is_idle(worktree_dir_path: &Path, max_idle: Duration) -> bool {
+        fs::metadata(worktree_dir_path.join(LAST_USED_FILENAME))
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| modified.elapsed().ok())
+            .map(|idle| idle >= max_idle)
+            .unwrap_or(true)
+    }
+}
+
+impl GitOperations for Git2Operations {
+    fn clone_repo(
+        &self,
+        url: &str,
+        into_dir_path: &str,
+        clone_options: &CloneOptions,
+    ) -> Result<String, GitOperationsError> {
+        self.clone_repo_task
+            .execute(
+                url.to_string(),
+                into_dir_path,
+                &self.git_auth.get_credentials(url),
+                clone_options,
+            )
+            .map(|task_result| task_result.repo_path)
+            .map_err(|_| CouldNotCloneRepo)
+    }
+
+    fn get_file_id(
+        &self,
+        repo_path: &str,
+        rev: &str,
+        file_name: &str,
+    ) -> Result<String, GitOperationsError> {
+        let repository = Repository::open(repo_path).map_err(|_| CouldNotOpenRepo)?;
+        let object = repository.revparse_single(rev).map_err(|_| CouldNotGetFileId)?;
+        let commit = object.as_commit().ok_or(CouldNotGetFileId)?;
+        let tree = commit.tree().map_err(|_| CouldNotGetFileId)?;
+
+        tree.iter()
+            .find(|entry| entry.name().unwrap_or("") == file_name)
+            .map(|entry| entry.id().to_string())
+            .ok_or(CouldNotGetFileId)
+    }
+
+    fn prepare_worktree(
+        &self,
+        repo_path: &str,
+        branch_name: &str,
+        sha: &str,
+        clone_options: &CloneOptions,
+    ) -> Result<String, GitOperationsError> {
+        let repo_lock = self.get_repo_lock(repo_path);
+        let _guard = repo_lock.lock().unwrap();
+
+        let repository = Repository::open(repo_path).map_err(|_| CouldNotOpenRepo)?;
+        self.fetch(&repository, clone_options.get_fetch_depth(repository.is_shallow()))?;
+
+        if !sha.is_empty() {
+            self.deepen_until_found(&repository, sha, clone_options.get_deepen_depths())?;
+        }
+
+        let commit = Self::find_commit(&repository, branch_name, sha)?;
+
+        // the checkout keeps the clone's dir name, so docker compose project names don't change
+        let worktree_name = Self::get_worktree_name(branch_name);
+        let worktrees_dir_path = Self::get_worktrees_dir_path(repo_path);
+        let worktree_dir_path = Path::new(&worktrees_dir_path).join(&worktree_name);
+        let repo_dir_name = Path::new(repo_path).file_name().ok_or(CouldNotOpenRepo)?;
+        let worktree_path = worktree_dir_path.join(repo_dir_name);
+
+        let mut worktree_repository = Self::open_worktree(
+            &repository,
+            &worktree_name,
+            &worktree_path,
+            &commit,
+            &clone_options.sparse_paths,
+        )?;
+        // objects can't be shared between repository handles
+        let worktree_commit = worktree_repository
+            .find_commit(commit.id())
+            .map_err(|_| CouldNotCheckoutBranch)?;
+
+        worktree_repository
+            .checkout_tree(
+                worktree_commit.as_object(),
+                Some(&mut CloneRepoTask::create_checkout_builder(clone_options)),
+            )
+            .and_then(|_| worktree_repository.set_head_detached(worktree_commit.id()))
+            .map_err(|_| CouldNotCheckoutBranch)?;
+
+        if clone_options.lfs {
+            self.checkout_lfs_files(&worktree_repository, &worktree_commit, clone_options)?;
+        }
+
+        drop(worktree_commit);
+
+        if clone_options.submodules {
+            self.update_submodules(&mut worktree_repository, clone_options)?;
+        }
+
+        fs::write(worktree_dir_path.join(LAST_USED_FILENAME), commit.id().to_string())
+            .map_err(|_| CouldNotCheckoutBranch)?;
+
+        worktree_path
+            .to_str()
+            .map(|path| path.to_string())
+            .ok_or(CouldNotCheckoutBranch)
+    }
+
+    fn remove_stale_worktrees(
+        &self,
+        repo_path: &str,
+        max_idle: Duration,
+        kept_branch_names: &[String],
+    ) -> Result<Vec<String>, GitOperationsError> {
+        let repo_lock = self.get_repo_lock(repo_path);
+        let _guard = repo_lock.lock().unwrap();
+
+        let repository = Repository::open(repo_path).map_err(|_| CouldNotOpenRepo)?;
+        let worktrees_dir_path = Self::get_worktrees_dir_path(repo_path);
+        let kept_worktree_names = kept_branch_names
+            .iter()
+            .map(|branch_name| Self::get_worktree_name(branch_name))
+            .collect::<Vec<String>>();
+        let worktree_names = repository
+            .worktrees()
+            .map_err(|_| CouldNotOpenRepo)?
+            .iter()
+            .flatten()
+            .map(|worktree_name| worktree_name.to_string())
+            .collect::<Vec<String>>();
+        let mut removed_paths = vec![];
+
+        for worktree_name in worktree_names {
+            let worktree_dir_path = Path::new(&worktrees_dir_path).join(&worktree_name);
+
+            // only worktrees made by prepare_worktree are touched
+            if !worktree_dir_path.exists()
+                || kept_worktree_names.contains(&worktree_name)
+                || !Self::is_idle(&worktree_dir_path, max_idle)
+            {
+                continue;
+            }
+
+            Self::remove_worktree(&repository, &worktree_name, &worktree_dir_path)?;
+            removed_paths.push(worktree_dir_path.to_string_lossy().to_string());
+        }
+
+        Ok(removed_paths)
+    }
+
+    fn get_changed_files(
+        &self,
+        repo_path: &str,
+        from_sha: &str,
+        to_sha: &str,
+        clone_options: &CloneOptions,
+    ) -> Result<Vec<String>, GitOperationsError> {
+        let repo_lock = self.get_repo_lock(repo_path);
+        let _guard = repo_lock.lock().unwrap();
+
+        // a push that created the branch has no commit before it, deepening wouldn't find one
+        if Oid::from_str(from_sha).map(|oid| oid.is_zero()).unwrap_or(true) {
+            return Err(CouldNotGetChangedFiles);
+        }
+
+        let repository = Repository::open(repo_path).map_err(|_| CouldNotOpenRepo)?;
+        // a force push leaves a before sha the remote doesn't have anymore, looking for it in the
+        // full history would unshallow the clone for nothing
+        self.deepen_until_found(&repository, from_sha, clone_options.get_bounded_deepen_depths())
+            .map_err(|_| CouldNotGetChangedFiles)?;
+
+        let from_tree = Self::find_commit(&repository, "", from_sha)
+            .ok()
+            .and_then(|commit| commit.tree().ok())
+            .ok_or(CouldNotGetChangedFiles)?;
+        let to_tree = Self::find_commit(&repository, "", to_sha)
+            .ok()
+            .and_then(|commit| commit.tree().ok())
+            .ok_or(CouldNotGetChangedFiles)?;
+        let diff = repository
+            .diff_tree_to_tree(Some(&from_tree), Some(&to_tree), None)
+            .map_err(|_| CouldNotGetChangedFiles)?;
+
+        let mut changed_files = diff
+            .deltas()
+            .flat_map(|delta| [delta.old_file().path(), delta.new_file().path()])
+            .flatten()
+            .map(|path| path.to_string_lossy().to_string())
+            .collect::<Vec<String>>();
+        changed_files.sort();
+        changed_files.dedup();
+
+        Ok(changed_files)
+    }
+
+    fn get_branch_sha(
+        &self,
+        repo_path: &str,
+        branch_name: &str,
+        clone_options: &CloneOptions,
+    ) -> Result<String, GitOperationsError> {
+        let repo_lock = self.get_repo_lock(repo_path);
+        let _guard = repo_lock.lock().unwrap();
+
+        let repository = Repository::open(repo_path).map_err(|_| CouldNotOpenRepo)?;
+        self.fetch(&repository, clone_options.get_fetch_depth(repository.is_shallow()))?;
+
+        Self::find_commit(&repository, branch_name, "").map(|commit| commit.id().to_string())
+    }
+
+    fn read_file(
+        &self,
+        repo_path: &str,
+        sha: &str,
+        file_name: &str,
+        clone_options: &CloneOptions,
+    ) -> Result<String, GitOperationsError> {
+        let repo_lock = self.get_repo_lock(repo_path);
+        let _guard = repo_lock.lock().unwrap();
+
+        let repository = Repository::open(repo_path).map_err(|_| CouldNotOpenRepo)?;
+        self.deepen_until_found(&repository, sha, clone_options.get_deepen_depths())?;
+
+        let commit = Self::find_commit(&repository, "", sha)?;
+        let blob = commit
+            .tree()
+            .and_then(|tree| tree.get_path(Path::new(file_name)))
+            .and_then(|entry| entry.to_object(&repository))
+            .and_then(|object| object.peel_to_blob())
+            .map_err(|_| CouldNotReadFile)?;
+
+        String::from_utf8(blob.content().to_vec()).map_err(|_| CouldNotReadFile)
+    }
+
+    fn is_on_branch(
+        &self,
+        repo_path: &str,
+        branch_name: &str,
+        sha: &str,
+        clone_options: &CloneOptions,
+    ) -> Result<bool, GitOperationsError> {
+        let repo_lock = self.get_repo_lock(repo_path);
+        let _guard = repo_lock.lock().unwrap();
+
+        let repository = Repository::open(repo_path).map_err(|_| CouldNotOpenRepo)?;
+        self.fetch(&repository, clone_options.get_fetch_depth(repository.is_shallow()))?;
+        // any 40 hex chars get here, a sha the remote doesn't have would unshallow the clone
+        self.deepen_until_found(&repository, sha, clone_options.get_bounded_deepen_depths())?;
+
+        let tip_oid = Self::find_commit(&repository, branch_name, "")?.id();
+        let oid = match Self::find_commit(&repository, "", sha) {
+            Ok(commit) => commit.id(),
+            Err(_) => return Ok(false),
+        };
+
+        Ok(tip_oid == oid
+            || repository
+                .graph_descendant_of(tip_oid, oid)
+                .map_err(|_| CouldNotGetBranch)?)
+    }
+}
+
+#[derive(Debug)]
+pub enum GitOperationsError {
+    CouldNotCloneRepo,
+    CouldNotOpenRepo,
+    CouldNotGetFileId,
+    CouldNotGetBranch,
+    CouldNotCheckoutBranch,
+    CouldNotFetchRepo,
+    CouldNotCreateWorktree,
+    CouldNotRemoveWorktree,
+    CouldNotUpdateSubmodules,
+    CouldNotGetChangedFiles,
+    CouldNotCheckoutLfsFiles,
+    CouldNotReadFile,
+}
@@ -0,0 +1,137 @@
+use std::time::Duration;
+
+use regex::Regex;
+use reqwest::Client;
+use tokio_util::sync::CancellationToken;
+
+use crate::data::deploy_info::HealthCheck;
+use crate::domain::health_checker::HealthCheckError::{Cancelled, Unhealthy};
+
+static DEFAULT_EXPECTED_STATUS: u16 = 200;
+static DEFAULT_RETRIES: u32 = 5;
+static DEFAULT_INTERVAL_SECS: u64 = 5;
+static DEFAULT_TIMEOUT_SECS: u64 = 10;
+
+// probes a deployed service until it answers as expected or the retries run out
+#[derive(Default)]
+pub struct HealthChecker {
+    client: Client,
+}
+
+// a branch's health check with its defaults applied
+#[derive(Debug, Clone)]
+pub struct HealthProbe {
+    // may refer to the run's variables
+    pub url: String,
+    pub expected_status: u16,
+    pub body_regex: Option<Regex>,
+    pub retries: u32,
+    pub interval: Duration,
+    pub timeout: Duration,
+}
+
+impl HealthProbe {
+    // None if the body regex doesn't compile
+    pub fn new(health_check: &HealthCheck) -> Option<HealthProbe> {
+        let body_regex = match &health_check.body_regex {
+            Some(body_regex) => Some(Regex::new(body_regex).ok()?),
+            None => None,
+        };
+
+        Some(HealthProbe {
+            url: health_check.url.clone(),
+            expected_status: health_check.expected_status.unwrap_or(DEFAULT_EXPECTED_STATUS),
+            body_regex,
+            retries: health_check.retries.unwrap_or(DEFAULT_RETRIES),
+            interval: Duration::from_secs(health_check.interval.unwrap_or(DEFAULT_INTERVAL_SECS)),
+            timeout: Duration::from_secs(health_check.timeout.unwrap_or(DEFAULT_TIMEOUT_SECS)),
+        })
+    }
+}
+
+impl HealthChecker {
+    pub fn new() -> HealthChecker {
+        HealthChecker {
+            client: Client::new(),
+        }
+    }
+
+    // returns how many probes it took, the url has to be interpolated already
+    pub async fn check(
+        &self,
+        probe: &HealthProbe,
+        cancellation_token: &CancellationToken,
+    ) -> Result<u32, HealthCheckError> {
+        let mut attempts = 0;
+
+        loop {
+            attempts += 1;
+
+            let result = tokio::select! {
+                result = self.probe(probe) => result,
+                _ = cancellation_token.cancelled() => return Err(Cancelled),
+            };
+
+            let reason = match result {
+                Ok(()) => return Ok(attempts),
+                Err(reason) => reason,
+            };
+
+            if attempts > probe.retries {
+                return Err(Unhealthy { attempts, reason });
+            }
+
+            tokio::select! {
+                _ = tokio::time::sleep(probe.interval) => {}
+                _ = cancellation_token.cancelled() => return Err(Cancelled),
+            }
+        }
+    }
+
+    // the reason if the service didn't answer as expected
+    async fn probe(&self, probe: &HealthProbe) -> Result<(), String> {
+        let response = self
+            .client
+            .get(probe.url.as_str())
+            .timeout(probe.timeout)
+            .send()
+            .await
+            .map_err(|err| {
+                if err.is_timeout() {
+                    format!("no response within {}s", probe.timeout.as_secs())
+                } else {
+                    "could not connect".to_string()
+                }
+            })?;
+        let status = response.status().as_u16();
+
+        if status != probe.expected_status {
+            return Err(format!("status {}, expected {}", status, probe.expected_status));
+        }
+
+        let body_regex = match &probe.body_regex {
+            Some(body_regex) => body_regex,
+            None => return Ok(()),
+        };
+        let body = response
+            .text()
+            .await
+            .map_err(|_| "could not read the body".to_string())?;
+
+        if !body_regex.is_match(&body) {
+            return Err(format!("the body doesn't match {}", body_regex));
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum HealthCheckError {
+    Unhealthy {
+        attempts: u32,
+        // why the last probe failed
+        reason: String,
+    },
+    Cancelled,
+}
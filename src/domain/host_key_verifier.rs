@@ -0,0 +1,244 @@
+use std::collections::HashMap;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+
+use base64::Engine;
+use base64::engine::general_purpose::{STANDARD, STANDARD_NO_PAD};
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use strum::{Display, EnumString};
+
+use crate::domain::host_key_verifier::HostKeyError::{
+    CouldNotReadKnownHosts, CouldNotWriteKnownHosts, HostKeyMismatch, UnknownHost,
+};
+
+// https://docs.github.com/en/authentication/keeping-your-account-and-data-secure/githubs-ssh-key-fingerprints
+static GITHUB_HOST: &str = "github.com";
+static GITHUB_FINGERPRINTS: [&str; 3] = [
+    "SHA256:uNiVztksCsDhcc0u9e8BujQXVUpKZIDTMczCvj3tD2s",
+    "SHA256:p2QAMXNIC1TJYWeIOttrVc98/R1BUFWu3/LiyKgUfQM",
+    "SHA256:+DiY3wvvV6TuJJhbpZisF/zLDA0zPMSvHdkr4UvCOqU",
+];
+static HASHED_HOST_PREFIX: &str = "|1|";
+static REVOKED_MARKER: &str = "@revoked";
+static DEFAULT_SSH_PORT: u16 = 22;
+
+// key type, key
+type KnownKey = (String, Vec<u8>);
+
+#[derive(Display, EnumString, Debug, Clone, Copy, PartialEq, Eq)]
+#[strum(serialize_all = "kebab-case")]
+pub enum HostKeyPolicy {
+    // unknown hosts are rejected
+    Strict,
+    // unknown hosts are added to known_hosts, changed keys are still rejected
+    TrustOnFirstUse,
+}
+
+pub struct HostKeyVerifier {
+    known_hosts_path: String,
+    policy: HostKeyPolicy,
+    // host -> SHA256 fingerprints, pinned hosts never look at known_hosts
+    pinned_fingerprints: HashMap<String, Vec<String>>,
+    known_hosts_lock: Mutex<()>,
+}
+
+impl HostKeyVerifier {
+    // github.com is pinned to its published keys unless other fingerprints are given for it
+    pub fn new(
+        known_hosts_path: String,
+        policy: HostKeyPolicy,
+        pinned_fingerprints: HashMap<String, Vec<String>>,
+    ) -> HostKeyVerifier {
+        let mut pinned_fingerprints = pinned_fingerprints;
+
+        pinned_fingerprints
+            .entry(GITHUB_HOST.to_string())
+            .or_insert_with(|| GITHUB_FINGERPRINTS.iter().map(|f| f.to_string()).collect());
+
+        HostKeyVerifier {
+            known_hosts_path,
+            policy,
+            pinned_fingerprints,
+            known_hosts_lock: Mutex::new(()),
+        }
+    }
+
+    // same format as ssh-keygen -l: SHA256:<unpadded base64>
+    pub fn get_fingerprint(key: &[u8]) -> String {
+        format!("SHA256:{}", STANDARD_NO_PAD.encode(Sha256::digest(key)))
+    }
+
+    // a revoked key is a mismatch, even if the host has no other keys
+    pub fn verify(
+        &self,
+        host: &str,
+        port: u16,
+        key_type: &str,
+        key: &[u8],
+    ) -> Result<(), HostKeyError> {
+        let fingerprint = Self::get_fingerprint(key);
+
+        if let Some(pinned_fingerprints) = self.pinned_fingerprints.get(host) {
+            return if pinned_fingerprints.contains(&fingerprint) {
+                Ok(())
+            } else {
+                Err(HostKeyMismatch)
+            };
+        }
+
+        let _guard = self.known_hosts_lock.lock().unwrap();
+        let (known_keys, revoked_keys) = self.read_known_keys(host, port)?;
+        let is_listed = |keys: &Vec<KnownKey>| {
+            keys.iter()
+                .any(|(known_key_type, known_key)| known_key_type == key_type && known_key == key)
+        };
+
+        if is_listed(&revoked_keys) {
+            Err(HostKeyMismatch)
+        } else if is_listed(&known_keys) {
+            Ok(())
+        } else if !known_keys.is_empty() {
+            Err(HostKeyMismatch)
+        } else if self.policy == HostKeyPolicy::TrustOnFirstUse {
+            println!("Trusting new host key of {} ({})", host, fingerprint);
+            self.append_known_host(host, port, key_type, key)
+        } else {
+            Err(UnknownHost)
+        }
+    }
+
+    // (key type, key) of the known and of the @revoked entries matching the host, other markers
+    // like @cert-authority are skipped
+    fn read_known_keys(
+        &self,
+        host: &str,
+        port: u16,
+    ) -> Result<(Vec<KnownKey>, Vec<KnownKey>), HostKeyError> {
+        if !Path::new(&self.known_hosts_path).exists() {
+            return Ok((vec![], vec![]));
+        }
+
+        let known_hosts =
+            fs::read_to_string(&self.known_hosts_path).map_err(|_| CouldNotReadKnownHosts)?;
+        let mut known_keys = vec![];
+        let mut revoked_keys = vec![];
+
+        for line in known_hosts.lines().map(|line| line.trim()) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut fields = line.split_whitespace().peekable();
+            let is_revoked = match fields.peek() {
+                Some(&marker) if marker == REVOKED_MARKER => true,
+                Some(marker) if marker.starts_with('@') => continue,
+                _ => false,
+            };
+
+            if is_revoked {
+                fields.next();
+            }
+
+            let entry = fields.next().zip(fields.next()).zip(fields.next());
+            let ((hosts, key_type), key) = match entry {
+                Some(entry) => entry,
+                None => continue,
+            };
+            let key = match STANDARD.decode(key) {
+                Ok(key) => key,
+                Err(_) => continue,
+            };
+
+            if !hosts.split(',').any(|pattern| Self::matches_host(pattern, host, port)) {
+                continue;
+            }
+
+            if is_revoked {
+                revoked_keys.push((key_type.to_string(), key));
+            } else {
+                known_keys.push((key_type.to_string(), key));
+            }
+        }
+
+        Ok((known_keys, revoked_keys))
+    }
+
+    // plain names, [name]:port entries and names hashed by HashKnownHosts. Plain names are
+    // port 22 only, like in ssh
+    fn matches_host(pattern: &str, host: &str, port: u16) -> bool {
+        if let Some(hashed) = pattern.strip_prefix(HASHED_HOST_PREFIX) {
+            return (port == DEFAULT_SSH_PORT && Self::matches_hashed_host(hashed, host))
+                || Self::matches_hashed_host(hashed, format!("[{}]:{}", host, port).as_str());
+        }
+
+        let (name, pattern_port) = match pattern.strip_prefix('[') {
+            Some(pattern) => match pattern.split_once("]:") {
+                Some((name, pattern_port)) => (name, pattern_port.parse::<u16>().ok()),
+                None => return false,
+            },
+            None => (pattern, Some(DEFAULT_SSH_PORT)),
+        };
+
+        pattern_port == Some(port) && name.eq_ignore_ascii_case(host)
+    }
+
+    fn matches_hashed_host(hashed: &str, host: &str) -> bool {
+        let (salt, hash) = match hashed.split_once('|') {
+            Some(salt_and_hash) => salt_and_hash,
+            None => return false,
+        };
+
+        match (STANDARD.decode(salt), STANDARD.decode(hash)) {
+            (Ok(salt), Ok(hash)) => {
+                Hmac::<Sha1>::new_from_slice(salt.as_slice())
+                    .map(|mut mac| {
+                        mac.update(host.as_bytes());
+                        mac.verify_slice(hash.as_slice()).is_ok()
+                    })
+                    .unwrap_or(false)
+            }
+            _ => false,
+        }
+    }
+
+    // other ports than 22 are written as [host]:port
+    fn append_known_host(
+        &self,
+        host: &str,
+        port: u16,
+        key_type: &str,
+        key: &[u8],
+    ) -> Result<(), HostKeyError> {
+        let host = if port == DEFAULT_SSH_PORT {
+            host.to_string()
+        } else {
+            format!("[{}]:{}", host, port)
+        };
+
+        if let Some(parent) = Path::new(&self.known_hosts_path).parent() {
+            fs::create_dir_all(parent).map_err(|_| CouldNotWriteKnownHosts)?;
+        }
+
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.known_hosts_path)
+            .and_then(|mut file| {
+                writeln!(file, "{} {} {}", host, key_type, STANDARD.encode(key))
+            })
+            .map_err(|_| CouldNotWriteKnownHosts)
+    }
+}
+
+#[derive(Display, Debug, PartialEq, Eq)]
+pub enum HostKeyError {
+    UnknownHost,
+    HostKeyMismatch,
+    CouldNotReadKnownHosts,
+    CouldNotWriteKnownHosts,
+}
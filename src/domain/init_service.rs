@@ -1,56 +1,66 @@
 use std::fs;
 use std::sync::{Arc, Mutex};
 
-use actix_service::Service;
 use futures::{FutureExt, stream, StreamExt, TryFutureExt, TryStreamExt};
 use futures::stream::FuturesOrdered;
-use git2::{Object, Repository};
-use serde::{Deserialize, Serialize};
 
-use crate::data::deploy_info_repository::DeployInfoRepository;
+use crate::data::deploy_info::{CloneOptions, DeployInfo};
+use crate::data::deploy_info_repository::{DeployInfoEntity, DeployInfoRepository};
+use crate::data::github_client::GithubClient;
 use crate::data::github_repo_repository::{DtoWithHeaders, GithubRepoDto};
 use crate::data::github_webhook_repository::{
-    GithhubWebhookConfigDto, GithubWebhookCreateDto, GithubWebhookDto, GithubWebhookRepository,
+    GithhubWebhookConfigDto, GithubWebhookCreateDto, GithubWebhookDto,
 };
-use crate::di::start_up_args::StartupArgs;
-use crate::domain::clone_repo_task::{CloneRepoTask, CloneRepoTaskError, CloneRepoTaskResult};
+use crate::domain::blocking_pool::BlockingPool;
+use crate::domain::deploy_key_service::DeployKeyService;
+use crate::domain::git_operations::GitOperations;
 use crate::domain::init_service::InitServiceError::{
-    CouldNotConvertLinkHeaderValue, CouldNotCreateWebhook, CouldNotGetGitFileId, CouldNotGetRepos,
-    CouldNotReadYamlFile, NoReposFound,
+    CouldNotCloneRepo, CouldNotConvertLinkHeaderValue, CouldNotCreateWebhook, CouldNotGetGitFileId,
+    CouldNotGetRepos, CouldNotLoadDeploys, CouldNotProvisionDeployKey, CouldNotReadYamlFile,
+    CouldNotSaveDeployInfo, NoReposFound,
 };
-use crate::GithubRepoRepository;
 use crate::header::HeaderMap;
 
 static REPOS_PER_PAGE: u32 = 100;
-static DOCKER_DEPLOY_FILENAME: &str = "docker-deploy.yml";
-static GITHUB_CLONE_PATH: &str = "/tmp";
+pub static DOCKER_DEPLOY_FILENAME: &str = "docker-deploy.yml";
 
 pub struct InitService {
-    pub github_repo_repository: GithubRepoRepository,
-    pub github_webhook_repository: GithubWebhookRepository,
+    pub github_client: Arc<dyn GithubClient>,
+    pub git_operations: Arc<dyn GitOperations>,
     pub deploy_info_repo: Arc<Mutex<DeployInfoRepository>>,
-    pub clone_repo_task: CloneRepoTask,
-    pub args: StartupArgs,
+    pub blocking_pool: Arc<BlockingPool>,
+    // clones are kept here between restarts
+    pub workspace_root: String,
+    // set when every repo gets its own deploy key instead of the shared credentials
+    pub deploy_key_service: Option<Arc<DeployKeyService>>,
+    // for repos whose docker-deploy.yml doesn't set them
+    pub default_clone_options: CloneOptions,
 }
 
 impl InitService {
     pub fn new(
-        github_repo_repository: GithubRepoRepository,
-        github_webhook_repository: GithubWebhookRepository,
+        github_client: Arc<dyn GithubClient>,
+        git_operations: Arc<dyn GitOperations>,
         deploy_info_repo: Arc<Mutex<DeployInfoRepository>>,
-        clone_repo_task: CloneRepoTask,
-        args: StartupArgs,
+        blocking_pool: Arc<BlockingPool>,
+        workspace_root: String,
+        deploy_key_service: Option<Arc<DeployKeyService>>,
+        default_clone_options: CloneOptions,
     ) -> InitService {
         InitService {
-            github_repo_repository,
-            github_webhook_repository,
+            github_client,
+            git_operations,
             deploy_info_repo,
-            clone_repo_task,
-            args,
+            blocking_pool,
+            workspace_root,
+            deploy_key_service,
+            default_clone_options,
         }
     }
 
     pub async fn execute(&mut self) -> Result<(), InitServiceError> {
+        self.load_deploys()?;
+
         let github_repos = self.get_all_repos_for_user().await?;
         let sanitized_github_repos = self.remove_archived_and_disabled_repos(github_repos);
 
@@ -62,15 +72,19 @@ impl InitService {
             .filter_repos_by_deploy_file(sanitized_github_repos)
             .await;
 
-        let temp_data_one_holders = self.clone_repos(github_repos_with_deploy_file)?;
+        self.provision_deploy_keys(&github_repos_with_deploy_file).await?;
+
+        let temp_data_one_holders = self.clone_repos(github_repos_with_deploy_file).await?;
 
         let temp_data_two_holders = self.get_deploy_info(temp_data_one_holders)?;
 
-        let temp_data_three_holders = self.get_deploy_file_git_id(temp_data_two_holders)?;
+        self.apply_repo_clone_options(&temp_data_two_holders).await?;
+
+        let temp_data_three_holders = self.get_deploy_file_git_id(temp_data_two_holders).await?;
 
         let temp_data_four_holders = self.create_github_webhooks(temp_data_three_holders).await?;
 
-        Ok(())
+        self.save_deploy_infos(temp_data_four_holders)
     }
 
     // TODO: parallel (?)
@@ -102,8 +116,8 @@ impl InitService {
         page: u32,
         per_page: u32,
     ) -> Result<DtoWithHeaders<Vec<GithubRepoDto>>, InitServiceError> {
-        self.github_repo_repository
-            .get_user_repos(page, per_page, "owner", "created", "asc")
+        self.github_client
+            .get_user_repos(page, per_page)
             .await
             .map_err(|_| CouldNotGetRepos)
     }
@@ -143,66 +157,105 @@ impl InitService {
                 file_name = DOCKER_DEPLOY_FILENAME,
             );
 
-            match self.github_repo_repository.get_headers(url.as_str()).await {
-                Ok(response) => {
-                    if response.status().is_success() {
-                        filtered_repos.push(repo)
-                    }
-                }
-                Err(_error) => {}
-            };
+            if let Ok(true) = self.github_client.file_exists(url.as_str()).await {
+                filtered_repos.push(repo)
+            }
         }
 
         filtered_repos
     }
 
-    /*    fn get_deploy_infos() -> Vec<DeployInfo> {
-        /* let contents = fs::read_to_string("docker-deploy.yml")
-            .map_err(|_| CouldNotReadYamlFile)DE
-
-            .and_then(|yaml_text| {
-                serde_yaml::from_str::<DeployInfo>(&yaml_text).map_err(|_| CouldNotReadYamlFile)
-            });
+    // keys have to be registered before the first clone, which already uses them
+    async fn provision_deploy_keys(&self, repos: &[GithubRepoDto]) -> Result<(), InitServiceError> {
+        let deploy_key_service = match &self.deploy_key_service {
+            Some(deploy_key_service) => deploy_key_service,
+            None => return Ok(()),
+        };
 
-        spawn_with_output!(bash -c "docker ps");*/
+        for repo in repos {
+            deploy_key_service
+                .provision(&repo.owner.login, &repo.name, &repo.ssh_url)
+                .await
+                .map_err(|err| {
+                    println!("Could not provision a deploy key for {}: {:?}", repo.full_name, err);
+                    CouldNotProvisionDeployKey
+                })?;
+        }
 
-        vec![DeployInfo {
-            ssh_git_url: "git@github.com:romqu/schimmelhof-api.git",
-            command_builders: vec![
-                |path: String| spawn_with_output!(docker-compose -f ${path}/docker-compose.yml build --build-arg ENVPROFILE=dev),
-                |path: String| spawn_with_output!(docker-compose -f ${path}/docker-compose.yml up --force-recreate --no-deps -d api),
-            ],
-        }]
-    }*/
+        Ok(())
+    }
 
-    // TODO: parallel (?)
-    fn clone_repos(
+    async fn clone_repos(
         &self,
         repos: Vec<GithubRepoDto>,
     ) -> Result<Vec<TempDataHolderOne>, InitServiceError> {
-        repos
-            .into_iter()
+        let clone_options = self.get_clone_options(&CloneOptions::default());
+
+        stream::iter(repos)
             .map(|repo| {
-                self.clone_repo(repo.ssh_url.clone())
-                    .map(|task_result| {
+                self.clone_repo(repo.ssh_url.clone(), clone_options.clone()).map(|result| {
+                    result.map(|repo_path| {
                         TempDataHolderOne {
-                            repo_path: task_result.repo_path,
-                            git_repository: task_result.git_repository,
+                            repo_path,
                             github_repo: repo,
                         }
                     })
-                    .map_err(|_| InitServiceError::CouldNotCloneRepo)
+                })
             })
-            .collect()
+            .collect::<FuturesOrdered<_>>()
+            .await
+            .try_collect::<Vec<TempDataHolderOne>>()
+            .await
+    }
+
+    async fn clone_repo(
+        &self,
+        ssh_git_url: String,
+        clone_options: CloneOptions,
+    ) -> Result<String, InitServiceError> {
+        let git_operations = self.git_operations.clone();
+        let workspace_root = self.workspace_root.clone();
+
+        self.blocking_pool
+            .run(move || {
+                git_operations.clone_repo(
+                    ssh_git_url.as_str(),
+                    workspace_root.as_str(),
+                    &clone_options,
+                )
+            })
+            .await
+            .map_err(|_| CouldNotCloneRepo)?
+            .map_err(|_| CouldNotCloneRepo)
+    }
+
+    // the deploy file is read from the clone, so an own depth or sparse paths only take effect
+    // with this second, cheap update
+    async fn apply_repo_clone_options(
+        &self,
+        temps: &[TempDataHolderTwo],
+    ) -> Result<(), InitServiceError> {
+        for temp in temps {
+            if temp.deploy_info.clone == CloneOptions::default() {
+                continue;
+            }
+
+            let clone_options = self.get_clone_options(&temp.deploy_info.clone);
+            self.clone_repo(temp.github_repo.ssh_url.clone(), clone_options).await?;
+        }
+
+        Ok(())
     }
 
-    fn clone_repo(&self, ssh_git_url: String) -> Result<CloneRepoTaskResult, CloneRepoTaskError> {
-        self.clone_repo_task.execute(
-            ssh_git_url,
-            GITHUB_CLONE_PATH,
-            &self.args.ssh_passphrase,
-            &self.args.ssh_key_path,
-        )
+    // the main clone always needs the deploy file, even if it's not in the sparse paths
+    fn get_clone_options(&self, repo_clone_options: &CloneOptions) -> CloneOptions {
+        let mut clone_options = repo_clone_options.with_defaults(&self.default_clone_options);
+
+        if !clone_options.sparse_paths.is_empty() {
+            clone_options.sparse_paths.push(DOCKER_DEPLOY_FILENAME.to_string());
+        }
+
+        clone_options
     }
 
     fn get_deploy_info(
@@ -218,7 +271,6 @@ impl InitService {
                         TempDataHolderTwo {
                             github_repo: data_holder.github_repo,
                             repo_path: data_holder.repo_path,
-                            git_repository: data_holder.git_repository,
                             deploy_info,
                         }
                     })
@@ -230,45 +282,50 @@ impl InitService {
         &self,
         file_path: String,
     ) -> Result<DeployInfo, InitServiceError> {
-        fs::read_to_string(file_path)
+        fs::read_to_string(&file_path)
             .map_err(|_| CouldNotReadYamlFile)
             .and_then(|yaml_text| {
-                serde_yaml::from_str::<DeployInfo>(&yaml_text).map_err(|_| CouldNotReadYamlFile)
+                DeployInfo::parse(&yaml_text).map_err(|err| {
+                    println!("Invalid {}: {:?}", file_path, err);
+                    CouldNotReadYamlFile
+                })
             })
     }
 
-    fn get_deploy_file_git_id(
+    async fn get_deploy_file_git_id(
         &self,
         temps: Vec<TempDataHolderTwo>,
     ) -> Result<Vec<TempDataHolderThree>, InitServiceError> {
-        temps
-            .into_iter()
+        stream::iter(temps)
             .map(|temp| {
-                temp.git_repository
-                    .revparse_single(temp.github_repo.default_branch.as_str())
-                    .map_err(|_| CouldNotGetGitFileId)
-                    .and_then(|object| self.get_file_id(object))
-                    .map(|file_id| {
-                        TempDataHolderThree {
-                            github_repo: temp.github_repo,
-                            repo_path: temp.repo_path,
-                            git_repository: temp.git_repository,
-                            deploy_info: temp.deploy_info,
-                            deploy_file_git_id: file_id.to_string(),
-                        }
+                self.get_file_id(temp.repo_path.clone(), temp.github_repo.default_branch.clone())
+                    .map(|result| {
+                        result.map(|file_id| {
+                            TempDataHolderThree {
+                                github_repo: temp.github_repo,
+                                repo_path: temp.repo_path,
+                                deploy_info: temp.deploy_info,
+                                deploy_file_git_id: file_id,
+                            }
+                        })
                     })
             })
-            .collect()
+            .collect::<FuturesOrdered<_>>()
+            .await
+            .try_collect::<Vec<TempDataHolderThree>>()
+            .await
     }
 
-    fn get_file_id(&self, object: Object) -> Result<String, InitServiceError> {
-        let commit = object.as_commit().ok_or(CouldNotGetGitFileId)?;
-        let tree = commit.tree().map_err(|_| CouldNotGetGitFileId)?;
+    async fn get_file_id(&self, repo_path: String, rev: String) -> Result<String, InitServiceError> {
+        let git_operations = self.git_operations.clone();
 
-        tree.iter()
-            .find(|entry| entry.name().unwrap_or("") == DOCKER_DEPLOY_FILENAME)
-            .map(|entry| entry.id().to_string())
-            .ok_or(CouldNotGetGitFileId)
+        self.blocking_pool
+            .run(move || {
+                git_operations.get_file_id(repo_path.as_str(), rev.as_str(), DOCKER_DEPLOY_FILENAME)
+            })
+            .await
+            .map_err(|_| CouldNotGetGitFileId)?
+            .map_err(|_| CouldNotGetGitFileId)
     }
 
     // TODO: create only if webhook doesn't already exists
@@ -294,17 +351,16 @@ impl InitService {
                     config,
                 };
 
-                self.github_webhook_repository
+                self.github_client
                     .create_webhook(owner_name.to_string(), repo_name.to_string(), dto)
                     .map(|result| {
                         result.map(|dto| {
                             TempDataHolderFour {
                                 github_repo: holder.github_repo,
                                 repo_path: holder.repo_path,
-                                git_repository: holder.git_repository,
                                 deploy_info: holder.deploy_info,
                                 deploy_file_git_id: holder.deploy_file_git_id,
-                                github_webhook_dto: *dto,
+                                github_webhook_dto: dto,
                             }
                         })
                     })
@@ -316,47 +372,55 @@ impl InitService {
             .await
     }
 
-    /*    fn save_deploy_infos(
+    // the deploy history rollbacks pick from, kept across restarts
+    fn load_deploys(&self) -> Result<(), InitServiceError> {
+        self.deploy_info_repo
+            .lock()
+            .unwrap()
+            .load_deploys()
+            .map_err(|err| {
+                println!("Could not read the deploy history: {:?}", err);
+                CouldNotLoadDeploys
+            })
+    }
+
+    fn save_deploy_infos(
         &mut self,
-        data_vec: Vec<TempDataHolderOne>,
+        data_holders: Vec<TempDataHolderFour>,
     ) -> Result<(), InitServiceError> {
-        let deploy_infos = data_vec.into_iter().map(|data| {
-            DeployInfoEntity {
-                ssh_git_url: data.ssh_git_url,
-                command_builders: data.command_builders,
-                repo_path: data.repo_path,
-                git_repository: data.git_repository,
-            }
-        });
+        let mut deploy_info_repo = self.deploy_info_repo.lock().unwrap();
+
+        for holder in data_holders {
+            let ssh_git_url = holder.github_repo.ssh_url;
+            let entity = DeployInfoEntity {
+                ssh_git_url: ssh_git_url.clone(),
+                repo_path: holder.repo_path,
+                deploy_info: holder.deploy_info,
+            };
 
-        for deploy_info in deploy_infos {
-            self.deploy_info_repo
-                .lock()
-                .unwrap()
-                .save(deploy_info.ssh_git_url.to_string(), deploy_info);
+            deploy_info_repo
+                .save(ssh_git_url, entity)
+                .map_err(|_| CouldNotSaveDeployInfo)?;
         }
 
-        Ok({})
-    }*/
+        Ok(())
+    }
 }
 
 pub struct TempDataHolderOne {
     pub github_repo: GithubRepoDto,
     pub repo_path: String,
-    pub git_repository: Repository,
 }
 
 pub struct TempDataHolderTwo {
     pub github_repo: GithubRepoDto,
     pub repo_path: String,
-    pub git_repository: Repository,
     pub deploy_info: DeployInfo,
 }
 
 pub struct TempDataHolderThree {
     pub github_repo: GithubRepoDto,
     pub repo_path: String,
-    pub git_repository: Repository,
     pub deploy_info: DeployInfo,
     pub deploy_file_git_id: String,
 }
@@ -364,23 +428,11 @@ pub struct TempDataHolderThree {
 pub struct TempDataHolderFour {
     pub github_repo: GithubRepoDto,
     pub repo_path: String,
-    pub git_repository: Repository,
     pub deploy_info: DeployInfo,
     pub deploy_file_git_id: String,
     pub github_webhook_dto: GithubWebhookDto,
 }
 
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-pub struct DeployInfo {
-    pub branches: Vec<Branch>,
-}
-
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-pub struct Branch {
-    pub name: String,
-    pub commands: Vec<String>,
-}
-
 #[derive(Debug)]
 pub enum InitServiceError {
     CouldNotGetRepos,
@@ -388,7 +440,10 @@ pub enum InitServiceError {
     CouldNotReadYamlFile,
     CouldNotParseYamlFile,
     CouldNotCloneRepo,
+    CouldNotProvisionDeployKey,
     CouldNotConvertLinkHeaderValue,
     CouldNotGetGitFileId,
     CouldNotCreateWebhook,
+    CouldNotSaveDeployInfo,
+    CouldNotLoadDeploys,
 }
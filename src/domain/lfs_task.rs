@@ -0,0 +1,285 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use git2::{Commit, ObjectType, Repository, TreeWalkMode, TreeWalkResult};
+use sha2::{Digest, Sha256};
+use tokio::runtime::Handle;
+
+use crate::data::deploy_info::CloneOptions;
+use crate::data::lfs_repository::{LfsBatchObjectDto, LfsObjectDto, LfsRepository};
+use crate::domain::git_credentials::GitCredentials;
+use crate::domain::lfs_task::LfsTaskError::{
+    CouldNotCopyObject, CouldNotDownloadObject, CouldNotGetLfsUrl, CouldNotReadTree,
+    CouldNotStoreObject, InvalidObject, NoRuntime,
+};
+use crate::domain::repo_url::RepoUrl;
+
+static POINTER_VERSION_LINE: &str = "version https://git-lfs.github.com/spec/v1";
+// pointer files are about 130 bytes, git-lfs itself ignores anything bigger than this
+static MAX_POINTER_SIZE: usize = 1024;
+static LFS_CONFIG_FILENAME: &str = ".lfsconfig";
+static HTTPS_TOKEN_USERNAME: &str = "x-access-token";
+
+// replaces the pointer files of a checkout with their git lfs objects
+pub struct LfsTask {
+    lfs_repository: LfsRepository,
+    // ssh credentials can't authorize against the lfs api, github accepts the api token instead
+    fallback_token: Option<String>,
+}
+
+impl LfsTask {
+    pub fn new(lfs_repository: LfsRepository, fallback_token: Option<String>) -> LfsTask {
+        LfsTask {
+            lfs_repository,
+            fallback_token,
+        }
+    }
+
+    // objects are cached below the clone's .git/lfs like git-lfs does, so a forced checkout
+    // that brings the pointers back doesn't download them again
+    // returns the number of files replaced
+    pub fn execute(
+        &self,
+        repository: &Repository,
+        commit: &Commit,
+        remote_url: &str,
+        credentials: &GitCredentials,
+        clone_options: &CloneOptions,
+    ) -> Result<usize, LfsTaskError> {
+        let workdir = repository.workdir().ok_or(CouldNotReadTree)?;
+        let pointers = Self::find_pointers(repository, commit, clone_options)?;
+
+        if pointers.is_empty() {
+            return Ok(0);
+        }
+
+        let objects_dir_path = repository.commondir().join("lfs").join("objects");
+        let missing_objects = pointers
+            .values()
+            .filter(|object| !Self::get_object_path(&objects_dir_path, &object.oid).exists())
+            .map(|object| (object.oid.clone(), object.clone()))
+            .collect::<HashMap<String, LfsObjectDto>>()
+            .into_values()
+            .collect::<Vec<LfsObjectDto>>();
+
+        if !missing_objects.is_empty() {
+            let lfs_url = Self::get_lfs_url(repository, commit, remote_url)?;
+            self.download_objects(&lfs_url, credentials, missing_objects, &objects_dir_path)?;
+        }
+
+        for (path, object) in &pointers {
+            fs::copy(Self::get_object_path(&objects_dir_path, &object.oid), workdir.join(path))
+                .map_err(|_| CouldNotCopyObject)?;
+        }
+
+        Ok(pointers.len())
+    }
+
+    // path -> object of every pointer file in the checked out paths
+    fn find_pointers(
+        repository: &Repository,
+        commit: &Commit,
+        clone_options: &CloneOptions,
+    ) -> Result<HashMap<String, LfsObjectDto>, LfsTaskError> {
+        let tree = commit.tree().map_err(|_| CouldNotReadTree)?;
+        let mut pointers = HashMap::new();
+
+        tree.walk(TreeWalkMode::PreOrder, |root, entry| {
+            if entry.kind() != Some(ObjectType::Blob) {
+                return TreeWalkResult::Ok;
+            }
+
+            let path = format!("{}{}", root, entry.name().unwrap_or(""));
+
+            if !clone_options.is_checked_out(&path) {
+                return TreeWalkResult::Ok;
+            }
+
+            if let Some(object) = repository
+                .find_blob(entry.id())
+                .ok()
+                .filter(|blob| blob.size() <= MAX_POINTER_SIZE)
+                .and_then(|blob| Self::parse_pointer(blob.content()))
+            {
+                pointers.insert(path, object);
+            }
+
+            TreeWalkResult::Ok
+        })
+        .map_err(|_| CouldNotReadTree)?;
+
+        Ok(pointers)
+    }
+
+    pub fn parse_pointer(content: &[u8]) -> Option<LfsObjectDto> {
+        let content = std::str::from_utf8(content).ok()?;
+        let mut lines = content.lines();
+
+        if lines.next()? != POINTER_VERSION_LINE {
+            return None;
+        }
+
+        let mut oid = None;
+        let mut size = None;
+
+        for line in lines {
+            match line.split_once(' ') {
+                Some(("oid", value)) => oid = value.strip_prefix("sha256:"),
+                Some(("size", value)) => size = value.parse::<u64>().ok(),
+                _ => {}
+            }
+        }
+
+        oid.filter(|oid| oid.len() == 64 && oid.chars().all(|c| c.is_ascii_hexdigit()))
+            .zip(size)
+            .map(|(oid, size)| LfsObjectDto { oid: oid.to_string(), size })
+    }
+
+    // lfs.url of the repo's .lfsconfig, the remote's default lfs endpoint otherwise
+    fn get_lfs_url(
+        repository: &Repository,
+        commit: &Commit,
+        remote_url: &str,
+    ) -> Result<String, LfsTaskError> {
+        let configured_url = commit
+            .tree()
+            .ok()
+            .and_then(|tree| tree.get_name(LFS_CONFIG_FILENAME).map(|entry| entry.id()))
+            .and_then(|id| repository.find_blob(id).ok())
+            .and_then(|blob| Self::parse_lfs_config_url(blob.content()));
+
+        if let Some(configured_url) = configured_url {
+            return Ok(configured_url);
+        }
+
+        RepoUrl::parse(remote_url)
+            .ok()
+            .filter(|_| !remote_url.starts_with("file://"))
+            .map(|repo_url| {
+                format!(
+                    "https://{}/{}/{}.git/info/lfs",
+                    repo_url.host, repo_url.owner, repo_url.name
+                )
+            })
+            .ok_or(CouldNotGetLfsUrl)
+    }
+
+    fn parse_lfs_config_url(content: &[u8]) -> Option<String> {
+        let mut is_lfs_section = false;
+
+        for line in std::str::from_utf8(content).ok()?.lines().map(|line| line.trim()) {
+            if line.starts_with('[') {
+                is_lfs_section = line == "[lfs]";
+            } else if let Some((key, value)) = line.split_once('=') {
+                if is_lfs_section && key.trim() == "url" {
+                    return Some(value.trim().trim_matches('"').to_string());
+                }
+            }
+        }
+
+        None
+    }
+
+    // the sync git operations run on the blocking pool, which may block on the runtime
+    fn download_objects(
+        &self,
+        lfs_url: &str,
+        credentials: &GitCredentials,
+        objects: Vec<LfsObjectDto>,
+        objects_dir_path: &Path,
+    ) -> Result<(), LfsTaskError> {
+        let runtime = Handle::try_current().map_err(|_| NoRuntime)?;
+        let token = credentials
+            .get_https_token()
+            .map_err(|_| CouldNotDownloadObject)?
+            .or_else(|| self.fallback_token.clone());
+        let authorization = token.map(|token| {
+            format!("Basic {}", STANDARD.encode(format!("{}:{}", HTTPS_TOKEN_USERNAME, token)))
+        });
+
+        let batch_objects = runtime
+            .block_on(self.lfs_repository.get_download_actions(
+                lfs_url,
+                authorization.as_deref(),
+                objects,
+            ))
+            .map_err(|_| CouldNotDownloadObject)?;
+
+        for batch_object in batch_objects {
+            let content = self.download_object(&runtime, &batch_object)?;
+            Self::store_object(objects_dir_path, &batch_object.oid, content.as_slice())?;
+        }
+
+        Ok(())
+    }
+
+    fn download_object(
+        &self,
+        runtime: &Handle,
+        batch_object: &LfsBatchObjectDto,
+    ) -> Result<Vec<u8>, LfsTaskError> {
+        if let Some(error) = &batch_object.error {
+            println!(
+                "Could not download lfs object {}: {} {}",
+                batch_object.oid, error.code, error.message
+            );
+            return Err(CouldNotDownloadObject);
+        }
+
+        let action = batch_object
+            .actions
+            .as_ref()
+            .and_then(|actions| actions.download.as_ref())
+            .ok_or(CouldNotDownloadObject)?;
+        let content = runtime
+            .block_on(self.lfs_repository.download(action))
+            .map_err(|_| CouldNotDownloadObject)?;
+
+        // the oid is the content's sha256, anything else isn't the object the pointer meant
+        if content.len() as u64 != batch_object.size
+            || Self::to_hex(Sha256::digest(content.as_slice()).as_slice()) != batch_object.oid
+        {
+            println!("Downloaded lfs object {} doesn't match its oid", batch_object.oid);
+            return Err(InvalidObject);
+        }
+
+        Ok(content)
+    }
+
+    // written next to the target and renamed, a partial download is never picked up
+    fn store_object(objects_dir_path: &Path, oid: &str, content: &[u8]) -> Result<(), LfsTaskError> {
+        let object_path = Self::get_object_path(objects_dir_path, oid);
+        let temp_path = object_path.with_extension("tmp");
+
+        object_path
+            .parent()
+            .map(fs::create_dir_all)
+            .unwrap_or(Ok(()))
+            .and_then(|_| fs::write(&temp_path, content))
+            .and_then(|_| fs::rename(&temp_path, &object_path))
+            .map_err(|_| CouldNotStoreObject)
+    }
+
+    // same layout as git-lfs: objects/ab/cd/abcd...
+    fn get_object_path(objects_dir_path: &Path, oid: &str) -> PathBuf {
+        objects_dir_path.join(&oid[0..2]).join(&oid[2..4]).join(oid)
+    }
+
+    fn to_hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum LfsTaskError {
+    NoRuntime,
+    CouldNotReadTree,
+    CouldNotGetLfsUrl,
+    CouldNotDownloadObject,
+    InvalidObject,
+    CouldNotStoreObject,
+    CouldNotCopyObject,
+}
@@ -1,4 +1,19 @@
+pub mod blocking_pool;
 pub mod clone_repo_task;
+pub mod command_runner;
+pub mod commit_directives;
+pub mod deploy_env;
+pub mod deploy_executor;
+pub mod deploy_gate;
+pub mod deploy_key_service;
 pub mod deploy_service;
+pub mod deploy_tracker;
+pub mod git_credentials;
+pub mod git_operations;
+pub mod health_checker;
+pub mod host_key_verifier;
 pub mod init_service;
-
+pub mod lfs_task;
+pub mod path_filter;
+pub mod repo_url;
+pub mod secret_masker;
@@ -0,0 +1,73 @@
+// decides from a push's changed files whether a branch or job runs. * and ? stay within a
+// path segment, ** spans segments and **/ also matches no directory at all
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct PathFilter {
+    paths: Vec<String>,
+    paths_ignore: Vec<String>,
+}
+
+impl PathFilter {
+    pub fn new(paths: &[String], paths_ignore: &[String]) -> PathFilter {
+        PathFilter {
+            paths: paths.to_vec(),
+            paths_ignore: paths_ignore.to_vec(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty() && self.paths_ignore.is_empty()
+    }
+
+    // true if a changed file matches paths (or there are none) without matching paths_ignore
+    pub fn matches(&self, changed_files: &[String]) -> bool {
+        if self.is_empty() {
+            return true;
+        }
+
+        changed_files.iter().any(|changed_file| {
+            (self.paths.is_empty() || Self::matches_any(&self.paths, changed_file))
+                && !Self::matches_any(&self.paths_ignore, changed_file)
+        })
+    }
+
+    pub fn glob_matches(pattern: &str, path: &str) -> bool {
+        Self::glob_matches_bytes(pattern.as_bytes(), path.as_bytes())
+    }
+
+    fn matches_any(patterns: &[String], path: &str) -> bool {
+        patterns
+            .iter()
+            .any(|pattern| Self::glob_matches(pattern.as_str(), path))
+    }
+
+    fn glob_matches_bytes(pattern: &[u8], path: &[u8]) -> bool {
+        match pattern {
+            [] => path.is_empty(),
+            [b'*', b'*', b'/', rest @ ..] => {
+                // no directory, or any number of them
+                Self::glob_matches_bytes(rest, path)
+                    || (0..path.len())
+                        .filter(|index| path[*index] == b'/')
+                        .any(|index| Self::glob_matches_bytes(rest, &path[index + 1..]))
+            }
+            [b'*', b'*', rest @ ..] => {
+                (0..=path.len()).any(|index| Self::glob_matches_bytes(rest, &path[index..]))
+            }
+            [b'*', rest @ ..] => {
+                let segment_length = path
+                    .iter()
+                    .position(|byte| *byte == b'/')
+                    .unwrap_or(path.len());
+
+                (0..=segment_length).any(|index| Self::glob_matches_bytes(rest, &path[index..]))
+            }
+            [b'?', rest @ ..] => {
+                matches!(path.first(), Some(byte) if *byte != b'/')
+                    && Self::glob_matches_bytes(rest, &path[1..])
+            }
+            [byte, rest @ ..] => {
+                path.first() == Some(byte) && Self::glob_matches_bytes(rest, &path[1..])
+            }
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use crate::domain::repo_url::RepoUrlError::{
+    InvalidPathSegment, MissingHost, MissingName, MissingOwner, UnsupportedScheme,
+};
+
+static LOCAL_HOST: &str = "local";
+static DEFAULT_SSH_PORT: u16 = 22;
+
+// host/owner/name of a git remote, owner can span several segments (gitlab subgroups)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RepoUrl {
+    pub host: String,
+    pub owner: String,
+    pub name: String,
+}
+
+impl RepoUrl {
+    // understands scp-like ssh (git@host:owner/name.git), ssh://, git://, http(s):// and file://
+    pub fn parse(url: &str) -> Result<RepoUrl, RepoUrlError> {
+        let url = url.trim();
+
+        match url.split_once("://") {
+            Some(("file", path)) => Self::from_parts(LOCAL_HOST, path),
+            Some(("ssh" | "git" | "http" | "https" | "git+ssh", rest)) => {
+                let (authority, path) = rest.split_once('/').ok_or(MissingName)?;
+                Self::from_parts(Self::strip_user_and_port(authority), path)
+            }
+            Some(_) => Err(UnsupportedScheme),
+            None => {
+                let (authority, path) = url.split_once(':').ok_or(UnsupportedScheme)?;
+
+                // a slash before the colon means a local path, not host:path
+                if authority.contains('/') {
+                    return Err(UnsupportedScheme);
+                }
+
+                Self::from_parts(Self::strip_user_and_port(authority), path)
+            }
+        }
+    }
+
+    // only ssh:// urls can name another port, scp-like ones always use 22
+    pub fn get_ssh_port(url: &str) -> u16 {
+        url.trim()
+            .strip_prefix("ssh://")
+            .or_else(|| url.trim().strip_prefix("git+ssh://"))
+            .and_then(|rest| rest.split('/').next())
+            .map(|authority| authority.rsplit_once('@').map_or(authority, |(_, host)| host))
+            .and_then(|host| host.split_once(':'))
+            .and_then(|(_, port)| port.parse::<u16>().ok())
+            .unwrap_or(DEFAULT_SSH_PORT)
+    }
+
+    // where the repo lives below a workspace root
+    pub fn relative_path(&self) -> String {
+        format!("{}/{}/{}", self.host, self.owner, self.name)
+    }
+
+    fn strip_user_and_port(authority: &str) -> &str {
+        let host = authority
+            .rsplit_once('@')
+            .map(|(_, host)| host)
+            .unwrap_or(authority);
+
+        host.split_once(':').map(|(host, _)| host).unwrap_or(host)
+    }
+
+    fn from_parts(host: &str, path: &str) -> Result<RepoUrl, RepoUrlError> {
+        if host.is_empty() {
+            return Err(MissingHost);
+        }
+
+        let path = path.trim_matches('/');
+        let path = path.strip_suffix(".git").unwrap_or(path);
+        let segments = path.split('/').collect::<Vec<&str>>();
+
+        if segments
+            .iter()
+            .any(|segment| segment.is_empty() || *segment == "." || *segment == "..")
+        {
+            return Err(InvalidPathSegment);
+        }
+
+        let (name, owner_segments) = segments.split_last().ok_or(MissingName)?;
+
+        if owner_segments.is_empty() {
+            return Err(MissingOwner);
+        }
+
+        Ok(RepoUrl {
+            host: host.to_lowercase(),
+            owner: owner_segments.join("/"),
+            name: name.to_string(),
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RepoUrlError {
+    UnsupportedScheme,
+    MissingHost,
+    MissingOwner,
+    MissingName,
+    InvalidPathSegment,
+}
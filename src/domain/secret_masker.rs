@@ -0,0 +1,142 @@
+use base64::Engine;
+use base64::engine::general_purpose::{STANDARD, STANDARD_NO_PAD, URL_SAFE_NO_PAD};
+
+static MASK: &str = "***";
+// shorter encodings would mask too much of the output to be useful, a value itself is always
+// masked
+static MIN_PATTERN_LENGTH: usize = 4;
+
+// replaces secret values, their base64 and url encodings with ***
+#[derive(Default, Debug, Clone)]
+pub struct SecretMasker {
+    // longest first, so a value is masked before any shorter pattern inside it
+    patterns: Vec<String>,
+    // lines a stream holds back, so a value spanning several lines is seen at once
+    max_newlines: usize,
+}
+
+// masks output as it's read line by line
+pub struct MaskingStream {
+    secret_masker: SecretMasker,
+    pending: String,
+}
+
+impl SecretMasker {
+    pub fn new(secrets: Vec<String>) -> SecretMasker {
+        let mut patterns = secrets
+            .iter()
+            .filter(|secret| !secret.is_empty())
+            .flat_map(|secret| {
+                Self::get_encoded_patterns(secret)
+                    .into_iter()
+                    .filter(|pattern| pattern.len() >= MIN_PATTERN_LENGTH)
+                    .chain([secret.clone()])
+            })
+            .collect::<Vec<String>>();
+
+        patterns.sort_by(|first, second| second.len().cmp(&first.len()).then(first.cmp(second)));
+        patterns.dedup();
+
+        let max_newlines = patterns
+            .iter()
+            .map(|pattern| pattern.matches('\n').count())
+            .max()
+            .unwrap_or(0);
+
+        SecretMasker {
+            patterns,
+            max_newlines,
+        }
+    }
+
+    pub fn mask(&self, text: &str) -> String {
+        self.patterns
+            .iter()
+            .fold(text.to_string(), |text, pattern| text.replace(pattern.as_str(), MASK))
+    }
+
+    pub fn create_stream(&self) -> MaskingStream {
+        MaskingStream {
+            secret_masker: self.clone(),
+            pending: String::new(),
+        }
+    }
+
+    fn get_encoded_patterns(secret: &str) -> Vec<String> {
+        let mut patterns = vec![
+            STANDARD.encode(secret),
+            Self::url_encode(secret, "%20"),
+            Self::url_encode(secret, "+"),
+        ];
+
+        patterns.extend(Self::get_base64_patterns(secret.as_bytes()));
+        patterns
+    }
+
+    // the value's base64 at each of the 3 offsets it can have inside a longer encoded text,
+    // like user:password in a basic auth header. Characters that also depend on the unknown
+    // bytes around the value are left out
+    fn get_base64_patterns(value: &[u8]) -> Vec<String> {
+        let mut patterns = vec![];
+
+        for engine in [STANDARD_NO_PAD, URL_SAFE_NO_PAD] {
+            for offset in 0..3 {
+                let bytes = [vec![0; offset].as_slice(), value].concat();
+                let encoded = engine.encode(bytes.as_slice());
+                let start = [0, 2, 3][offset];
+                let end = match bytes.len() % 3 {
+                    0 => encoded.len(),
+                    _ => encoded.len() - 1,
+                };
+
+                if start < end {
+                    patterns.push(encoded[start..end].to_string());
+                }
+            }
+        }
+
+        patterns
+    }
+
+    // percent-encodes everything but the unreserved characters of rfc 3986
+    fn url_encode(value: &str, space: &str) -> String {
+        value
+            .bytes()
+            .map(|byte| {
+                match byte {
+                    b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                        (byte as char).to_string()
+                    }
+                    b' ' => space.to_string(),
+                    _ => format!("%{:02X}", byte),
+                }
+            })
+            .collect()
+    }
+}
+
+impl MaskingStream {
+    // returns the lines that can't be part of a value anymore, masked
+    pub fn push_line(&mut self, line: &str) -> Vec<String> {
+        self.pending.push_str(line);
+        self.pending.push('\n');
+        self.pending = self.secret_masker.mask(self.pending.as_str());
+
+        let mut lines = vec![];
+
+        while self.pending.matches('\n').count() > self.secret_masker.max_newlines {
+            let (line, rest) = self.pending.split_once('\n').unwrap_or((&self.pending, ""));
+            lines.push(line.to_string());
+            self.pending = rest.to_string();
+        }
+
+        lines
+    }
+
+    // the lines still held back once the output has ended
+    pub fn finish(&mut self) -> Vec<String> {
+        let pending = std::mem::take(&mut self.pending);
+
+        pending.lines().map(|line| line.to_string()).collect()
+    }
+}
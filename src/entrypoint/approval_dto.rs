@@ -0,0 +1,7 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ApprovalDto {
+    // only the approval of this job, all awaited ones without it
+    pub job: Option<String>,
+}
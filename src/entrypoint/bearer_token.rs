@@ -0,0 +1,11 @@
+use actix_web::HttpRequest;
+use actix_web::http::header;
+
+// the token of an Authorization: Bearer header
+pub fn get_bearer_token(request: &HttpRequest) -> Option<&str> {
+    request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+}
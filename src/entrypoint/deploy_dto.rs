@@ -0,0 +1,13 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeployDto {
+    pub branch: String,
+    // the full sha of a commit of the branch, its latest one if unset
+    pub sha: Option<String>,
+    // values of the inputs docker-deploy.yml declares, strings, numbers or bools
+    #[serde(default)]
+    pub inputs: HashMap<String, serde_json::Value>,
+}
@@ -0,0 +1,14 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FreezeDto {
+    // host/owner/name or the repo's url, every repo if unset
+    pub repo: Option<String>,
+    // every environment if unset
+    pub environment: Option<String>,
+    // now if unset
+    pub starts_at: Option<DateTime<Utc>>,
+    pub ends_at: DateTime<Utc>,
+    pub reason: String,
+}
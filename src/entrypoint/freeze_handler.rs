@@ -0,0 +1,79 @@
+use actix_web::{HttpRequest, HttpResponse};
+use actix_web::web::{Data, Json, Path};
+use chrono::Utc;
+
+use crate::data::freeze_store::FreezeEntity;
+use crate::di::app_state::AppState;
+use crate::domain::deploy_service::DeployServiceError;
+use crate::entrypoint::bearer_token::get_bearer_token;
+use crate::entrypoint::freeze_dto::FreezeDto;
+
+// only approvers may see and change freezes
+pub async fn handle_get_freezes(request: HttpRequest, app_state: Data<AppState>) -> HttpResponse {
+    let token = match get_bearer_token(&request) {
+        Some(token) => token,
+        None => return HttpResponse::Unauthorized().finish(),
+    };
+
+    match app_state.deploy_service.list_freezes(token) {
+        Ok(freezes) => HttpResponse::Ok().json(freezes),
+        Err(err) => to_error_response(err),
+    }
+}
+
+pub async fn handle_post_freeze(
+    request: HttpRequest,
+    json: Json<FreezeDto>,
+    app_state: Data<AppState>,
+) -> HttpResponse {
+    let token = match get_bearer_token(&request) {
+        Some(token) => token,
+        None => return HttpResponse::Unauthorized().finish(),
+    };
+    let dto = json.into_inner();
+    let freeze = FreezeEntity {
+        id: 0,
+        repo: dto.repo,
+        environment: dto.environment,
+        starts_at: dto.starts_at.unwrap_or_else(Utc::now),
+        ends_at: dto.ends_at,
+        reason: dto.reason,
+        created_by: String::new(),
+    };
+
+    match app_state.deploy_service.create_freeze(token, freeze) {
+        Ok(freeze) => HttpResponse::Created().json(freeze),
+        Err(err) => to_error_response(err),
+    }
+}
+
+pub async fn handle_delete_freeze(
+    path: Path<u64>,
+    request: HttpRequest,
+    app_state: Data<AppState>,
+) -> HttpResponse {
+    let token = match get_bearer_token(&request) {
+        Some(token) => token,
+        None => return HttpResponse::Unauthorized().finish(),
+    };
+
+    match app_state
+        .deploy_service
+        .remove_freeze(token, path.into_inner())
+    {
+        Ok(freeze) => HttpResponse::Ok().json(freeze),
+        Err(err) => to_error_response(err),
+    }
+}
+
+fn to_error_response(err: DeployServiceError) -> HttpResponse {
+    match err {
+        DeployServiceError::NotAnApprover => HttpResponse::Unauthorized().finish(),
+        DeployServiceError::FreezeNotFound => HttpResponse::NotFound().finish(),
+        DeployServiceError::InvalidFreeze => HttpResponse::BadRequest().finish(),
+        err => {
+            println!("{}", err);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
@@ -1,2 +1,16 @@
+pub mod approval_dto;
+pub mod bearer_token;
+pub mod deploy_dto;
+pub mod freeze_dto;
+pub mod freeze_handler;
 pub mod github_push_event_dto;
+pub mod override_dto;
+pub mod post_cancel_run_handler;
+pub mod post_deploy_handler;
 pub mod post_github_push_event_handler;
+pub mod post_run_approval_handler;
+pub mod post_run_override_handler;
+pub mod rollback_dto;
+pub mod rollback_handler;
+pub mod run_created_dto;
+pub mod schedule_trigger;
@@ -0,0 +1,7 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OverrideDto {
+    // why the run has to deploy now, it goes to the audit log
+    pub reason: String,
+}
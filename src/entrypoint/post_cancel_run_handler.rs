@@ -0,0 +1,29 @@
+use actix_web::{HttpRequest, HttpResponse};
+use actix_web::web::{Data, Path};
+
+use crate::di::app_state::AppState;
+use crate::domain::deploy_service::DeployServiceError;
+use crate::entrypoint::bearer_token::get_bearer_token;
+
+// only approvers may cancel runs
+pub async fn handle_post_cancel_run(
+    path: Path<u64>,
+    request: HttpRequest,
+    app_state: Data<AppState>,
+) -> HttpResponse {
+    let token = match get_bearer_token(&request) {
+        Some(token) => token,
+        None => return HttpResponse::Unauthorized().finish(),
+    };
+
+    match app_state.deploy_service.cancel(path.into_inner(), token) {
+        Ok(run) => HttpResponse::Accepted().json(run),
+        Err(DeployServiceError::NotAnApprover) => HttpResponse::Unauthorized().finish(),
+        Err(DeployServiceError::RunNotFound) => HttpResponse::NotFound().finish(),
+        Err(DeployServiceError::RunAlreadyFinished) => HttpResponse::Conflict().finish(),
+        Err(err) => {
+            println!("{}", err);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+
+use actix_web::{HttpRequest, HttpResponse};
+use actix_web::web::{Data, Json, Path};
+
+use crate::data::deploy_info::InputError;
+use crate::di::app_state::AppState;
+use crate::domain::deploy_service::{DeployServiceError, ManualDeploy};
+use crate::domain::deploy_tracker::DeployTrackerError;
+use crate::entrypoint::bearer_token::get_bearer_token;
+use crate::entrypoint::deploy_dto::DeployDto;
+use crate::entrypoint::run_created_dto::RunCreatedDto;
+
+// the repo is host/owner/name, only approvers may start runs. Inputs that don't fit are
+// answered with 400 and which input it is, so is a sha that isn't a full one. A commit the branch
+// doesn't have is 404
+pub async fn handle_post_deploy(
+    path: Path<String>,
+    request: HttpRequest,
+    json: Json<DeployDto>,
+    app_state: Data<AppState>,
+) -> HttpResponse {
+    let token = match get_bearer_token(&request) {
+        Some(token) => token,
+        None => return HttpResponse::Unauthorized().finish(),
+    };
+    let dto = json.into_inner();
+    let mut inputs = HashMap::new();
+
+    for (name, value) in dto.inputs {
+        let value = match value {
+            serde_json::Value::String(value) => value,
+            serde_json::Value::Bool(value) => value.to_string(),
+            serde_json::Value::Number(value) => value.to_string(),
+            _ => {
+                return HttpResponse::BadRequest()
+                    .body(format!("input {} has to be a string, number or bool", name))
+            }
+        };
+        inputs.insert(name, value);
+    }
+
+    let deploy = ManualDeploy {
+        branch_name: dto.branch,
+        sha: dto.sha,
+        inputs,
+    };
+    let deploy = match app_state
+        .deploy_service
+        .plan_manual_deploy(token, path.into_inner().as_str(), deploy)
+        .await
+    {
+        Ok(deploy) => deploy,
+        Err(err) => return to_error_response(err),
+    };
+
+    match app_state
+        .deploy_tracker
+        .track(|| app_state.deploy_service.execute_manual_deploy(deploy))
+    {
+        Ok(run_id) => HttpResponse::Ok().json(RunCreatedDto { run_id }),
+        Err(DeployTrackerError::NotAcceptingDeploys) => HttpResponse::ServiceUnavailable().finish(),
+        Err(DeployTrackerError::CouldNotStartDeploy(err)) => to_error_response(err),
+    }
+}
+
+fn to_error_response(err: DeployServiceError) -> HttpResponse {
+    match err {
+        DeployServiceError::NotAnApprover => HttpResponse::Unauthorized().finish(),
+        DeployServiceError::CouldNotGetRepoInfo
+        | DeployServiceError::NoCommandsForBranch
+        | DeployServiceError::CommitNotOnBranch => HttpResponse::NotFound().finish(),
+        DeployServiceError::InvalidSha => {
+            HttpResponse::BadRequest().body("sha has to be a full 40 character commit sha")
+        }
+        DeployServiceError::InvalidInputs(err) => {
+            let message = match err {
+                InputError::UnknownInput(name) => format!("unknown input {}", name),
+                InputError::MissingInput(name) => format!("input {} is required", name),
+                InputError::InvalidValue(name) => format!("invalid value of input {}", name),
+            };
+            HttpResponse::BadRequest().body(message)
+        }
+        DeployServiceError::MissingSecrets => HttpResponse::Conflict().finish(),
+        err => {
+            println!("{}", err);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
@@ -1,18 +1,25 @@
 use actix_web::HttpResponse;
-use actix_web::web::Json;
+use actix_web::web::{Data, Json};
 
-use crate::di::singletons::DEPLOY_SERVICE_CELL;
+use crate::di::app_state::AppState;
+use crate::domain::deploy_tracker::DeployTrackerError;
 use crate::entrypoint::github_push_event_dto::GithubPushEventDto;
+use crate::entrypoint::run_created_dto::RunCreatedDto;
 
-pub async fn handle_post_github_push_event(json: Json<GithubPushEventDto>) -> HttpResponse {
-    match DEPLOY_SERVICE_CELL
-        .get()
-        .unwrap()
-        .execute(json.into_inner())
+pub async fn handle_post_github_push_event(
+    json: Json<GithubPushEventDto>,
+    app_state: Data<AppState>,
+) -> HttpResponse {
+    let dto = json.into_inner();
+
+    match app_state
+        .deploy_tracker
+        .track(|| app_state.deploy_service.execute(dto))
     {
-        Ok(_) => HttpResponse::Ok().finish(),
-        Err(err) => {
-            // println!("{}", err);
+        Ok(run_id) => HttpResponse::Ok().json(RunCreatedDto { run_id }),
+        Err(DeployTrackerError::NotAcceptingDeploys) => HttpResponse::ServiceUnavailable().finish(),
+        Err(DeployTrackerError::CouldNotStartDeploy(err)) => {
+            println!("{}", err);
             HttpResponse::BadRequest().finish()
         }
     }
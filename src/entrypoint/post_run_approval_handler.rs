@@ -0,0 +1,58 @@
+use actix_web::{HttpRequest, HttpResponse};
+use actix_web::web::{Data, Json, Path};
+
+use crate::data::run_repository::ApprovalDecision;
+use crate::di::app_state::AppState;
+use crate::domain::deploy_service::DeployServiceError;
+use crate::entrypoint::approval_dto::ApprovalDto;
+use crate::entrypoint::bearer_token::get_bearer_token;
+
+pub async fn handle_post_approve_run(
+    path: Path<u64>,
+    request: HttpRequest,
+    json: Option<Json<ApprovalDto>>,
+    app_state: Data<AppState>,
+) -> HttpResponse {
+    decide_approval(path, request, json, app_state, ApprovalDecision::Approved)
+}
+
+pub async fn handle_post_reject_run(
+    path: Path<u64>,
+    request: HttpRequest,
+    json: Option<Json<ApprovalDto>>,
+    app_state: Data<AppState>,
+) -> HttpResponse {
+    decide_approval(path, request, json, app_state, ApprovalDecision::Rejected)
+}
+
+// the approver is the one the bearer token belongs to
+fn decide_approval(
+    path: Path<u64>,
+    request: HttpRequest,
+    json: Option<Json<ApprovalDto>>,
+    app_state: Data<AppState>,
+    decision: ApprovalDecision,
+) -> HttpResponse {
+    let token = match get_bearer_token(&request) {
+        Some(token) => token,
+        None => return HttpResponse::Unauthorized().finish(),
+    };
+    let job_name = json.and_then(|json| json.into_inner().job);
+
+    match app_state.deploy_service.decide_approval(
+        path.into_inner(),
+        token,
+        job_name.as_deref(),
+        decision,
+    ) {
+        Ok(run) => HttpResponse::Ok().json(run),
+        Err(DeployServiceError::NotAnApprover) => HttpResponse::Unauthorized().finish(),
+        Err(DeployServiceError::RunNotFound) => HttpResponse::NotFound().finish(),
+        Err(DeployServiceError::RunAlreadyFinished) => HttpResponse::Conflict().finish(),
+        Err(DeployServiceError::NoApprovalAwaited) => HttpResponse::Conflict().finish(),
+        Err(err) => {
+            println!("{}", err);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
@@ -0,0 +1,39 @@
+use actix_web::{HttpRequest, HttpResponse};
+use actix_web::web::{Data, Json, Path};
+
+use crate::di::app_state::AppState;
+use crate::domain::deploy_service::DeployServiceError;
+use crate::entrypoint::bearer_token::get_bearer_token;
+use crate::entrypoint::override_dto::OverrideDto;
+
+// the approver is the one the bearer token belongs to
+pub async fn handle_post_override_run(
+    path: Path<u64>,
+    request: HttpRequest,
+    json: Json<OverrideDto>,
+    app_state: Data<AppState>,
+) -> HttpResponse {
+    let token = match get_bearer_token(&request) {
+        Some(token) => token,
+        None => return HttpResponse::Unauthorized().finish(),
+    };
+    let reason = json.into_inner().reason;
+
+    if reason.trim().is_empty() {
+        return HttpResponse::BadRequest().finish();
+    }
+
+    match app_state
+        .deploy_service
+        .override_hold(path.into_inner(), token, reason.as_str())
+    {
+        Ok(run) => HttpResponse::Ok().json(run),
+        Err(DeployServiceError::NotAnApprover) => HttpResponse::Unauthorized().finish(),
+        Err(DeployServiceError::RunNotFound) => HttpResponse::NotFound().finish(),
+        Err(DeployServiceError::RunNotHeld) => HttpResponse::Conflict().finish(),
+        Err(err) => {
+            println!("{}", err);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
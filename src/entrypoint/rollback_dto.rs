@@ -0,0 +1,10 @@
+use serde::{Deserialize, Serialize};
+
+// without a run or sha the branch goes back to the commit it deployed before the current one
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RollbackDto {
+    // a run that deployed the branch
+    pub run_id: Option<u64>,
+    // a commit the branch deployed, may be abbreviated
+    pub sha: Option<String>,
+}
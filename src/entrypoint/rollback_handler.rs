@@ -0,0 +1,93 @@
+use actix_web::{HttpRequest, HttpResponse};
+use actix_web::web::{Bytes, Data, Path};
+
+use crate::di::app_state::AppState;
+use crate::domain::deploy_service::{DeployServiceError, RollbackTarget};
+use crate::domain::deploy_tracker::DeployTrackerError;
+use crate::entrypoint::bearer_token::get_bearer_token;
+use crate::entrypoint::rollback_dto::RollbackDto;
+use crate::entrypoint::run_created_dto::RunCreatedDto;
+
+// the repo is host/owner/name, only approvers may roll back and see what a branch deployed
+pub async fn handle_get_deploys(
+    path: Path<(String, String)>,
+    request: HttpRequest,
+    app_state: Data<AppState>,
+) -> HttpResponse {
+    let token = match get_bearer_token(&request) {
+        Some(token) => token,
+        None => return HttpResponse::Unauthorized().finish(),
+    };
+    let (repo, branch_name) = path.into_inner();
+
+    match app_state
+        .deploy_service
+        .get_deploys(token, repo.as_str(), branch_name.as_str())
+    {
+        Ok(deploys) => HttpResponse::Ok().json(deploys),
+        Err(err) => to_error_response(err),
+    }
+}
+
+// the body is optional, an empty one rolls back to the previous deploy
+pub async fn handle_post_rollback(
+    path: Path<(String, String)>,
+    request: HttpRequest,
+    body: Bytes,
+    app_state: Data<AppState>,
+) -> HttpResponse {
+    let token = match get_bearer_token(&request) {
+        Some(token) => token,
+        None => return HttpResponse::Unauthorized().finish(),
+    };
+    let dto = if body.is_empty() {
+        RollbackDto::default()
+    } else {
+        match serde_json::from_slice::<RollbackDto>(&body) {
+            Ok(dto) => dto,
+            Err(_) => return HttpResponse::BadRequest().finish(),
+        }
+    };
+    let target = match (dto.run_id, dto.sha) {
+        (None, None) => RollbackTarget::Previous,
+        (Some(run_id), None) => RollbackTarget::Run(run_id),
+        (None, Some(sha)) => RollbackTarget::Sha(sha),
+        (Some(_), Some(_)) => return HttpResponse::BadRequest().finish(),
+    };
+    let (repo, branch_name) = path.into_inner();
+
+    let rollback = match app_state
+        .deploy_service
+        .plan_rollback(token, repo.as_str(), branch_name.as_str(), target)
+        .await
+    {
+        Ok(rollback) => rollback,
+        Err(err) => return to_error_response(err),
+    };
+
+    match app_state
+        .deploy_tracker
+        .track(|| app_state.deploy_service.execute_rollback(rollback))
+    {
+        Ok(run_id) => HttpResponse::Ok().json(RunCreatedDto { run_id }),
+        Err(DeployTrackerError::NotAcceptingDeploys) => HttpResponse::ServiceUnavailable().finish(),
+        Err(DeployTrackerError::CouldNotStartDeploy(err)) => to_error_response(err),
+    }
+}
+
+fn to_error_response(err: DeployServiceError) -> HttpResponse {
+    match err {
+        DeployServiceError::NotAnApprover => HttpResponse::Unauthorized().finish(),
+        DeployServiceError::CouldNotGetRepoInfo | DeployServiceError::DeployNotFound => {
+            HttpResponse::NotFound().finish()
+        }
+        // the commit's deploy config has no such branch or lacks its secrets
+        DeployServiceError::NoCommandsForBranch | DeployServiceError::MissingSecrets => {
+            HttpResponse::Conflict().finish()
+        }
+        err => {
+            println!("{}", err);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
@@ -0,0 +1,6 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RunCreatedDto {
+    pub run_id: u64,
+}
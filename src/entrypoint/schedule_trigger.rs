@@ -0,0 +1,82 @@
+use std::time::Duration;
+
+use actix_web::web::Data;
+use chrono::{DateTime, Utc};
+
+use crate::di::app_state::AppState;
+use crate::domain::deploy_tracker::DeployTrackerError;
+
+// cron expressions have no seconds, a tick within each minute is enough
+static TICK_INTERVAL: Duration = Duration::from_secs(15);
+
+// starts the runs of the repos' schedules until deploys aren't accepted anymore. Schedules come
+// from the docker-deploy.yml read at startup, changing them takes a restart
+pub async fn run_schedule_trigger(app_state: Data<AppState>) {
+    let mut checked_until = Utc::now();
+
+    loop {
+        tokio::time::sleep(TICK_INTERVAL).await;
+
+        let now = Utc::now();
+
+        if trigger_due_schedules(&app_state, checked_until, now).await.is_none() {
+            return;
+        }
+
+        checked_until = now;
+    }
+}
+
+// starts the schedules that fire after `after` and up to `until`, a schedule that should have
+// fired several times in between fires once. Returns the ids of the started runs, None once
+// deploys aren't accepted anymore
+pub async fn trigger_due_schedules(
+    app_state: &AppState,
+    after: DateTime<Utc>,
+    until: DateTime<Utc>,
+) -> Option<Vec<u64>> {
+    let mut run_ids = vec![];
+
+    for (ssh_git_url, schedule) in app_state.deploy_service.get_schedules() {
+        let is_due = schedule
+            .next_after(after)
+            .map(|next| next <= until)
+            .unwrap_or(false);
+
+        if !is_due {
+            continue;
+        }
+
+        let sha = match app_state
+            .deploy_service
+            .get_branch_sha(ssh_git_url.as_str(), schedule.branch.as_str())
+            .await
+        {
+            Ok(sha) => sha,
+            Err(err) => {
+                println!("{} of {}: {}", err, schedule.branch, ssh_git_url);
+                continue;
+            }
+        };
+
+        match app_state.deploy_tracker.track(|| {
+            app_state
+                .deploy_service
+                .execute_schedule(ssh_git_url.as_str(), &schedule, sha)
+        }) {
+            Ok(run_id) => {
+                println!(
+                    "Schedule {} of {} started run {}",
+                    schedule.cron, schedule.branch, run_id
+                );
+                run_ids.push(run_id);
+            }
+            Err(DeployTrackerError::NotAcceptingDeploys) => return None,
+            Err(DeployTrackerError::CouldNotStartDeploy(err)) => {
+                println!("Schedule {} of {}: {}", schedule.cron, schedule.branch, err);
+            }
+        }
+    }
+
+    Some(run_ids)
+}
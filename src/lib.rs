@@ -1,64 +1,712 @@
-#![feature(once_cell)]
 #![feature(map_try_insert)]
 
 use std::collections::HashMap;
 use std::fmt::Display;
+use std::fs;
+use std::io::Read;
+use std::str::FromStr;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use actix_service::Service;
+use actix_web::{App, HttpServer, web};
+use actix_web::dev::Server;
 use clap::Parser;
 use reqwest::{Client, header};
 use reqwest::header::HeaderValue;
+use tokio::signal::unix::{signal, SignalKind};
 
 use crate::data::api_call_delegate::ApiCallDelegate;
+use crate::data::approver_store::ApproverStore;
+use crate::data::audit_log::AuditLog;
+use crate::data::deploy_history_store::DeployHistoryStore;
+use crate::data::deploy_info::{CloneOptions, DeployWindow};
 use crate::data::deploy_info_repository::DeployInfoRepository;
+use crate::data::deploy_key_store::DeployKeyStore;
+use crate::data::freeze_store::FreezeStore;
+use crate::data::github_app_token_repository::GithubAppTokenRepository;
+use crate::data::github_client::GithubApiClient;
+use crate::data::github_deploy_key_repository::GithubDeployKeyRepository;
 use crate::data::github_repo_repository::GithubRepoRepository;
 use crate::data::github_webhook_repository::GithubWebhookRepository;
-use crate::di::singletons::DEPLOY_SERVICE_CELL;
-use crate::di::start_up_args::StartupArgs;
+use crate::data::lfs_repository::LfsRepository;
+use crate::data::run_repository::{ApprovalDecision, RunRepository};
+use crate::data::secret_store::SecretStore;
+use crate::di::app_state::AppState;
+use crate::di::start_up_args::{
+    ApproversCommand, RunsCommand, SecretsCommand, StartupArgs, StartupCommand,
+};
 use crate::domain::clone_repo_task::CloneRepoTask;
-use crate::domain::deploy_service::DeployService;
+use crate::domain::blocking_pool::BlockingPool;
+use crate::domain::command_runner::TokioCommandRunner;
+use crate::domain::deploy_env::DeployEnv;
+use crate::domain::deploy_executor::DeployExecutor;
+use crate::domain::deploy_key_service::DeployKeyService;
+use crate::domain::deploy_service::{DeployService, DeployServiceConfig};
+use crate::domain::deploy_tracker::DeployTracker;
+use crate::domain::git_credentials::{GitAuth, GitAuthMethod, GitCredentials};
+use crate::domain::git_operations::Git2Operations;
+use crate::domain::host_key_verifier::HostKeyVerifier;
 use crate::domain::init_service::InitService;
-use crate::InitError::{CouldNotInitApp, CouldNotInitDependencies};
+use crate::domain::lfs_task::LfsTask;
+use crate::domain::repo_url::RepoUrl;
+use crate::entrypoint::approval_dto::ApprovalDto;
+use crate::entrypoint::deploy_dto::DeployDto;
+use crate::entrypoint::freeze_handler::{
+    handle_delete_freeze, handle_get_freezes, handle_post_freeze,
+};
+use crate::entrypoint::post_cancel_run_handler::handle_post_cancel_run;
+use crate::entrypoint::post_deploy_handler::handle_post_deploy;
+use crate::entrypoint::post_github_push_event_handler::handle_post_github_push_event;
+use crate::entrypoint::post_run_approval_handler::{
+    handle_post_approve_run, handle_post_reject_run,
+};
+use crate::entrypoint::post_run_override_handler::handle_post_override_run;
+use crate::entrypoint::rollback_handler::{handle_get_deploys, handle_post_rollback};
+use crate::entrypoint::run_created_dto::RunCreatedDto;
+use crate::entrypoint::schedule_trigger::run_schedule_trigger;
+use crate::InitError::{
+    CouldNotInitApp, CouldNotInitDependencies, CouldNotRunCommand, CouldNotStartApp,
+};
+
+static SSH_PRIVATE_KEY_ENV: &str = "MINI_CI_SSH_PRIVATE_KEY";
+static SECRETS_KEY_ENV: &str = "MINI_CI_SECRETS_KEY";
+static APPROVER_TOKEN_ENV: &str = "MINI_CI_APPROVER_TOKEN";
 
 pub mod data;
 pub mod di;
 pub mod domain;
 pub mod entrypoint;
 
-pub async fn init_app() -> Result<(), InitError> {
-    let mut init_service = init_dependencies()?;
+pub async fn run_app() -> Result<(), InitError> {
+    let args: StartupArgs = StartupArgs::parse();
+
+    if let Some(command) = &args.command {
+        return run_command(&args, command).await;
+    }
+
+    let bind_address = args.bind_address.clone();
+    let shutdown_timeout = Duration::from_secs(args.shutdown_timeout_secs);
+    let kill_grace_period = Duration::from_secs(args.kill_grace_period_secs);
+    let (mut init_service, app_state) = init_dependencies(args)?;
+
+    init_service.execute().await.map_err(|_| CouldNotInitApp)?;
+
+    let app_state = web::Data::new(app_state);
+    let server =
+        start_app(bind_address.as_str(), app_state.clone()).map_err(|_| CouldNotStartApp)?;
+    let server_handle = server.handle();
+    let server_task = tokio::spawn(server);
+    let schedule_task = tokio::spawn(run_schedule_trigger(app_state.clone()));
 
-    init_service.execute().await.map_err(|_| CouldNotInitApp)
+    wait_for_shutdown_signal().await?;
+
+    println!("Shutting down, no new deploys will be accepted");
+    app_state.deploy_tracker.stop_accepting();
+    schedule_task.abort();
+    server_handle.stop(true).await;
+
+    if !app_state
+        .deploy_tracker
+        .wait_for_running(shutdown_timeout)
+        .await
+    {
+        println!("Cancelling deploys that are still running");
+        app_state.deploy_service.cancel_all();
+        app_state
+            .deploy_tracker
+            .wait_for_running(kill_grace_period * 2)
+            .await;
+    }
+
+    server_task
+        .await
+        .map_err(|_| CouldNotStartApp)?
+        .map_err(|_| CouldNotStartApp)
 }
 
-fn init_dependencies() -> Result<InitService, InitError> {
-    let args: StartupArgs = StartupArgs::parse();
+pub fn start_app(bind_address: &str, app_state: web::Data<AppState>) -> std::io::Result<Server> {
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(app_state.clone())
+            .route(
+                "/api/v1/events/push",
+                web::post().to(handle_post_github_push_event),
+            )
+            .route(
+                "/api/v1/runs/{id}/cancel",
+                web::post().to(handle_post_cancel_run),
+            )
+            .route(
+                "/api/v1/runs/{id}/approve",
+                web::post().to(handle_post_approve_run),
+            )
+            .route(
+                "/api/v1/runs/{id}/reject",
+                web::post().to(handle_post_reject_run),
+            )
+            .route(
+                "/api/v1/runs/{id}/override",
+                web::post().to(handle_post_override_run),
+            )
+            .route("/api/v1/freezes", web::get().to(handle_get_freezes))
+            .route("/api/v1/freezes", web::post().to(handle_post_freeze))
+            .route("/api/v1/freezes/{id}", web::delete().to(handle_delete_freeze))
+            // repos are host/owner/name and branch names may contain slashes as well
+            .route("/api/v1/repos/{repo:.+}/deploy", web::post().to(handle_post_deploy))
+            .route(
+                "/api/v1/repos/{repo:.+}/branches/{branch:.+}/deploys",
+                web::get().to(handle_get_deploys),
+            )
+            .route(
+                "/api/v1/repos/{repo:.+}/branches/{branch:.+}/rollback",
+                web::post().to(handle_post_rollback),
+            )
+    })
+        .disable_signals()
+        .bind(bind_address)?
+        .run();
+
+    Ok(server)
+}
+
+async fn run_command(args: &StartupArgs, command: &StartupCommand) -> Result<(), InitError> {
+    match command {
+        StartupCommand::Secrets(secrets_command) => run_secrets_command(args, secrets_command),
+        StartupCommand::Approvers(approvers_command) => {
+            run_approvers_command(args, approvers_command)
+        }
+        StartupCommand::Runs(runs_command) => run_runs_command(runs_command).await,
+        StartupCommand::Deploy {
+            repo,
+            branch,
+            sha,
+            input,
+            server_url,
+        } => run_deploy_command(repo, branch, sha, input, server_url).await,
+    }
+}
+
+fn run_secrets_command(args: &StartupArgs, command: &SecretsCommand) -> Result<(), InitError> {
+    let secret_store = init_secret_store(args)?;
+
+    match command {
+        SecretsCommand::Set {
+            repo,
+            name,
+            environment,
+        } => {
+            if !DeployEnv::is_valid_name(name) {
+                println!("Secret names may only contain letters, digits and _: {}", name);
+                return Err(CouldNotRunCommand);
+            }
+
+            let repo_path = get_repo_path(repo)?;
+            let mut value = String::new();
+            std::io::stdin()
+                .read_to_string(&mut value)
+                .map_err(|_| CouldNotRunCommand)?;
+            // echo and here-strings end the value with a newline
+            let value = value.strip_suffix('\n').unwrap_or(value.as_str());
+
+            secret_store
+                .set(&repo_path, environment.as_deref(), name, value)
+                .map_err(|err| {
+                    println!("Could not store {}: {:?}", name, err);
+                    CouldNotRunCommand
+                })?;
+            println!("Stored {} of {}", name, repo_path);
+        }
+        SecretsCommand::List { repo } => {
+            let repo_path = get_repo_path(repo)?;
+            let secrets = secret_store.list(&repo_path).map_err(|err| {
+                println!("Could not read the secrets of {}: {:?}", repo_path, err);
+                CouldNotRunCommand
+            })?;
+
+            for (environment, name) in secrets {
+                println!("{}\t{}", environment, name);
+            }
+        }
+        SecretsCommand::Remove {
+            repo,
+            name,
+            environment,
+        } => {
+            let repo_path = get_repo_path(repo)?;
+            let removed = secret_store
+                .remove(&repo_path, environment.as_deref(), name)
+                .map_err(|err| {
+                    println!("Could not remove {}: {:?}", name, err);
+                    CouldNotRunCommand
+                })?;
+
+            if !removed {
+                println!("{} has no secret {}", repo_path, name);
+                return Err(CouldNotRunCommand);
+            }
+
+            println!("Removed {} of {}", name, repo_path);
+        }
+    }
+
+    Ok(())
+}
+
+fn run_approvers_command(args: &StartupArgs, command: &ApproversCommand) -> Result<(), InitError> {
+    let approver_store = init_approver_store(args);
+    let print_err = |err| {
+        println!("Could not update the approvers: {:?}", err);
+        CouldNotRunCommand
+    };
+
+    match command {
+        ApproversCommand::Add { name } => {
+            let token = approver_store.add(name).map_err(print_err)?;
+
+            println!("Added {}, their token is shown only once:", name);
+            println!("{}", token);
+        }
+        ApproversCommand::List => {
+            for name in approver_store.list().map_err(print_err)? {
+                println!("{}", name);
+            }
+        }
+        ApproversCommand::Remove { name } => {
+            if !approver_store.remove(name).map_err(print_err)? {
+                println!("{} is no approver", name);
+                return Err(CouldNotRunCommand);
+            }
+
+            println!("Removed {}", name);
+        }
+    }
+
+    Ok(())
+}
+
+async fn run_runs_command(command: &RunsCommand) -> Result<(), InitError> {
+    let (run_id, job, server_url, decision) = match command {
+        RunsCommand::Approve {
+            run_id,
+            job,
+            server_url,
+        } => (run_id, job, server_url, ApprovalDecision::Approved),
+        RunsCommand::Reject {
+            run_id,
+            job,
+            server_url,
+        } => (run_id, job, server_url, ApprovalDecision::Rejected),
+    };
+    let action = match decision {
+        ApprovalDecision::Approved => "approve",
+        _ => "reject",
+    };
+    let token = std::env::var(APPROVER_TOKEN_ENV).map_err(|_| {
+        println!("{} has to hold your approver token", APPROVER_TOKEN_ENV);
+        CouldNotRunCommand
+    })?;
+    let url = format!(
+        "{}/api/v1/runs/{}/{}",
+        server_url.trim_end_matches('/'),
+        run_id,
+        action
+    );
+
+    let response = Client::new()
+        .post(url.as_str())
+        .bearer_auth(token)
+        .json(&ApprovalDto { job: job.clone() })
+        .send()
+        .await
+        .map_err(|err| {
+            println!("Could not reach {}: {}", server_url, err);
+            CouldNotRunCommand
+        })?;
+
+    match response.status().as_u16() {
+        200 => println!("Run {}: {}", run_id, decision),
+        401 => println!("The token in {} belongs to no approver", APPROVER_TOKEN_ENV),
+        404 => println!("There's no run {}", run_id),
+        409 => println!("Run {} awaits no approval", run_id),
+        status => println!("Could not {} run {}: {}", action, run_id, status),
+    }
+
+    response
+        .status()
+        .is_success()
+        .then_some(())
+        .ok_or(CouldNotRunCommand)
+}
+
+async fn run_deploy_command(
+    repo: &str,
+    branch: &str,
+    sha: &Option<String>,
+    inputs: &[String],
+    server_url: &str,
+) -> Result<(), InitError> {
+    let repo_path = get_repo_path(repo)?;
+    let inputs = inputs
+        .iter()
+        .map(|input| {
+            input
+                .split_once('=')
+                .map(|(name, value)| (name.to_string(), serde_json::Value::from(value)))
+                .ok_or_else(|| {
+                    println!("Inputs are given as name=value: {}", input);
+                    CouldNotRunCommand
+                })
+        })
+        .collect::<Result<HashMap<String, serde_json::Value>, InitError>>()?;
+    let token = std::env::var(APPROVER_TOKEN_ENV).map_err(|_| {
+        println!("{} has to hold your approver token", APPROVER_TOKEN_ENV);
+        CouldNotRunCommand
+    })?;
+    let url = format!(
+        "{}/api/v1/repos/{}/deploy",
+        server_url.trim_end_matches('/'),
+        repo_path
+    );
+
+    let response = Client::new()
+        .post(url.as_str())
+        .bearer_auth(token)
+        .json(&DeployDto {
+            branch: branch.to_string(),
+            sha: sha.clone(),
+            inputs,
+        })
+        .send()
+        .await
+        .map_err(|err| {
+            println!("Could not reach {}: {}", server_url, err);
+            CouldNotRunCommand
+        })?;
+    let status = response.status();
+
+    match status.as_u16() {
+        200 => {
+            match response.json::<RunCreatedDto>().await {
+                Ok(run_created) => println!("Started run {}", run_created.run_id),
+                Err(_) => println!("Started a run of {}", branch),
+            }
+        }
+        400 => {
+            let reason = response.text().await.unwrap_or_default();
+            println!("Could not deploy {}: {}", branch, reason);
+        }
+        401 => println!("The token in {} belongs to no approver", APPROVER_TOKEN_ENV),
+        404 => println!("{} has no branch {} to deploy", repo_path, branch),
+        status => println!("Could not deploy {}: {}", branch, status),
+    }
+
+    status.is_success().then_some(()).ok_or(CouldNotRunCommand)
+}
+
+// host/owner/name of a repo given as host/owner/name or as its url
+fn get_repo_path(repo: &str) -> Result<String, InitError> {
+    RepoUrl::parse(repo)
+        .or_else(|_| RepoUrl::parse(format!("https://{}", repo.trim_matches('/')).as_str()))
+        .map(|repo_url| repo_url.relative_path())
+        .map_err(|_| {
+            println!("Not a repo, expected host/owner/name or a url: {}", repo);
+            CouldNotRunCommand
+        })
+}
+
+async fn wait_for_shutdown_signal() -> Result<(), InitError> {
+    let mut terminate = signal(SignalKind::terminate()).map_err(|_| CouldNotStartApp)?;
+    let mut interrupt = signal(SignalKind::interrupt()).map_err(|_| CouldNotStartApp)?;
+
+    tokio::select! {
+        _ = terminate.recv() => {}
+        _ = interrupt.recv() => {}
+    }
+
+    Ok(())
+}
+
+fn init_dependencies(args: StartupArgs) -> Result<(InitService, AppState), InitError> {
     let github_token = env!("GITHUB_TOKEN");
+    let git_auth = Arc::new(init_git_auth(&args, github_token)?);
+    let host_key_verifier = init_host_key_verifier(&args)?;
+    let secret_store = Arc::new(init_secret_store(&args)?);
+    let approver_store = Arc::new(init_approver_store(&args));
+    let deploy_windows = init_deploy_windows(&args)?;
+    let freeze_store = Arc::new(FreezeStore::new(
+        args.freezes_path
+            .clone()
+            .unwrap_or_else(|| format!("{}/.freezes.json", args.workspace_root)),
+    ));
+    let audit_log = Arc::new(AuditLog::new(
+        args.audit_log_path
+            .clone()
+            .unwrap_or_else(|| format!("{}/audit.log", args.workspace_root)),
+    ));
+    let default_clone_options = CloneOptions {
+        depth: args.clone_depth,
+        sparse_paths: args.sparse_path.clone(),
+        ..CloneOptions::default()
+    };
 
-    init_github_api_client(github_token.to_string()).and_then(|api_client| {
-        let api_client = Arc::new(Mutex::new(api_client));
-        let api_call_delegate = Arc::new(Mutex::new(ApiCallDelegate::new(api_client.clone())));
-        let deploy_info_repository =
-            Arc::new(Mutex::new(DeployInfoRepository::new(HashMap::new())));
+    init_github_api_client(github_token.to_string()).map(|api_client| {
+        let api_client = Arc::new(api_client);
+        let api_call_delegate = Arc::new(ApiCallDelegate::new(api_client.clone()));
+        let deploy_history_store = DeployHistoryStore::new(
+            args.deploys_path
+                .clone()
+                .unwrap_or_else(|| format!("{}/.deploys.json", args.workspace_root)),
+        );
+        let deploy_info_repository = Arc::new(Mutex::new(DeployInfoRepository::new(
+            HashMap::new(),
+            deploy_history_store,
+        )));
         let github_repo_repository = GithubRepoRepository::new(api_client.clone());
         let github_webhook_repository = GithubWebhookRepository::new(api_call_delegate.clone());
-        let clone_repo_task = CloneRepoTask::new();
-        let init_service = InitService::new(
+        let github_deploy_key_repository =
+            GithubDeployKeyRepository::new(api_call_delegate.clone());
+        let github_client = Arc::new(GithubApiClient::new(
             github_repo_repository,
             github_webhook_repository,
+            github_deploy_key_repository,
+        ));
+        let deploy_key_service = args.deploy_keys.then(|| {
+            let deploy_keys_path = args
+                .deploy_keys_path
+                .clone()
+                .unwrap_or_else(|| format!("{}/.deploy-keys", args.workspace_root));
+
+            Arc::new(DeployKeyService::new(
+                github_client.clone(),
+                DeployKeyStore::new(deploy_keys_path),
+                git_auth.clone(),
+            ))
+        });
+        let git_operations = Arc::new(Git2Operations::new(
+            CloneRepoTask::new(Arc::new(host_key_verifier)),
+            git_auth,
+            LfsTask::new(LfsRepository::new(), Some(github_token.to_string())),
+        ));
+        let run_repository = Arc::new(Mutex::new(RunRepository::new()));
+        let command_runner = Arc::new(TokioCommandRunner::new(Duration::from_secs(
+            args.kill_grace_period_secs,
+        )));
+        let blocking_pool = Arc::new(BlockingPool::new(args.max_concurrent_git_tasks));
+        let deploy_executor =
+            DeployExecutor::new(args.max_concurrent_deploys, tokio::runtime::Handle::current());
+        let init_service = InitService::new(
+            github_client,
+            git_operations.clone(),
             deploy_info_repository.clone(),
-            clone_repo_task,
-            args,
+            blocking_pool.clone(),
+            args.workspace_root,
+            deploy_key_service,
+            default_clone_options.clone(),
+        );
+        let deploy_service = DeployService::new(
+            deploy_info_repository,
+            run_repository,
+            git_operations,
+            command_runner,
+            blocking_pool,
+            deploy_executor,
+            DeployServiceConfig {
+                default_step_timeout: Duration::from_secs(args.default_step_timeout_secs),
+                worktree_retention: Duration::from_secs(args.worktree_retention_secs),
+                default_clone_options,
+                secret_store,
+                approver_store,
+                freeze_store,
+                audit_log,
+                deploy_windows,
+            },
         );
 
-        DEPLOY_SERVICE_CELL
-            .set(DeployService::new(deploy_info_repository.clone()))
-            .map_err(|_| CouldNotInitDependencies)
-            .map(|_| init_service)
+        (init_service, AppState::new(deploy_service, DeployTracker::new()))
     })
 }
 
+// repos using the same auth method share its credentials
+fn init_git_auth(args: &StartupArgs, github_token: &str) -> Result<GitAuth, InitError> {
+    let mut credentials_by_method: HashMap<GitAuthMethod, GitCredentials> = HashMap::new();
+    let mut get_credentials = |method: GitAuthMethod| -> Result<GitCredentials, InitError> {
+        if let Some(credentials) = credentials_by_method.get(&method) {
+            return Ok(credentials.clone());
+        }
+
+        let credentials = create_git_credentials(method, args, github_token)?;
+        credentials_by_method.insert(method, credentials.clone());
+        Ok(credentials)
+    };
+
+    let default_credentials = get_credentials(args.git_auth)?;
+    let mut repo_credentials = HashMap::new();
+
+    for repo_git_auth in &args.repo_git_auth {
+        let (repo, method) = repo_git_auth.rsplit_once('=').ok_or_else(|| {
+            println!("--repo-git-auth must look like host/owner/name=method: {}", repo_git_auth);
+            CouldNotInitDependencies
+        })?;
+        let method = GitAuthMethod::from_str(method).map_err(|_| {
+            println!("Unknown git auth method: {}", method);
+            CouldNotInitDependencies
+        })?;
+        // a full url works as well as host/owner/name
+        let repo_key = RepoUrl::parse(repo)
+            .map(|repo_url| repo_url.relative_path())
+            .unwrap_or_else(|_| repo.trim_matches('/').to_lowercase());
+
+        repo_credentials.insert(repo_key, get_credentials(method)?);
+    }
+
+    Ok(GitAuth::new(default_credentials, repo_credentials))
+}
+
+// MINI_CI_SECRETS_KEY keeps the key off the disk the secrets are on
+fn init_secret_store(args: &StartupArgs) -> Result<SecretStore, InitError> {
+    let secrets_path = args
+        .secrets_path
+        .clone()
+        .unwrap_or_else(|| format!("{}/.secrets", args.workspace_root));
+    let key_path = args
+        .secrets_key_path
+        .clone()
+        .unwrap_or_else(|| format!("{}/master.key", secrets_path));
+    let key = match std::env::var(SECRETS_KEY_ENV) {
+        Ok(key) => SecretStore::decode_key(key.as_str()),
+        Err(_) => SecretStore::load_or_create_key(key_path.as_str()),
+    }
+    .map_err(|err| {
+        println!("Could not load the secrets key: {:?}", err);
+        CouldNotInitDependencies
+    })?;
+
+    SecretStore::new(secrets_path, key.as_slice()).map_err(|_| CouldNotInitDependencies)
+}
+
+fn init_approver_store(args: &StartupArgs) -> ApproverStore {
+    ApproverStore::new(
+        args.approvers_path
+            .clone()
+            .unwrap_or_else(|| format!("{}/.approvers.json", args.workspace_root)),
+    )
+}
+
+fn init_deploy_windows(
+    args: &StartupArgs,
+) -> Result<HashMap<String, Vec<DeployWindow>>, InitError> {
+    let mut deploy_windows: HashMap<String, Vec<DeployWindow>> = HashMap::new();
+
+    for deploy_window in &args.deploy_window {
+        let (environment, window) = deploy_window
+            .split_once('=')
+            .and_then(|(environment, spec)| Some((environment, DeployWindow::parse(spec).ok()?)))
+            .ok_or_else(|| {
+                println!(
+                    "--deploy-window must look like environment=mon-fri 09:00-17:00 [timezone]: {}",
+                    deploy_window
+                );
+                CouldNotInitDependencies
+            })?;
+
+        deploy_windows
+            .entry(environment.to_string())
+            .or_default()
+            .push(window);
+    }
+
+    Ok(deploy_windows)
+}
+
+fn init_host_key_verifier(args: &StartupArgs) -> Result<HostKeyVerifier, InitError> {
+    let known_hosts_path = args
+        .known_hosts_path
+        .clone()
+        .or_else(|| {
+            std::env::var("HOME")
+                .ok()
+                .map(|home| format!("{}/.ssh/known_hosts", home))
+        })
+        .ok_or_else(|| {
+            println!("--known-hosts-path is needed without a HOME");
+            CouldNotInitDependencies
+        })?;
+    let mut pinned_fingerprints: HashMap<String, Vec<String>> = HashMap::new();
+
+    for host_key_fingerprint in &args.host_key_fingerprint {
+        let (host, fingerprint) = host_key_fingerprint
+            .split_once('=')
+            .filter(|(_, fingerprint)| fingerprint.starts_with("SHA256:"))
+            .ok_or_else(|| {
+                println!(
+                    "--host-key-fingerprint must look like host=SHA256:...: {}",
+                    host_key_fingerprint
+                );
+                CouldNotInitDependencies
+            })?;
+
+        pinned_fingerprints
+            .entry(host.to_lowercase())
+            .or_default()
+            .push(fingerprint.to_string());
+    }
+
+    Ok(HostKeyVerifier::new(
+        known_hosts_path,
+        args.host_key_policy,
+        pinned_fingerprints,
+    ))
+}
+
+fn create_git_credentials(
+    method: GitAuthMethod,
+    args: &StartupArgs,
+    github_token: &str,
+) -> Result<GitCredentials, InitError> {
+    let passphrase = Some(args.ssh_passphrase.clone()).filter(|passphrase| !passphrase.is_empty());
+    let missing = |what: &str| {
+        println!("{} auth needs {}", method, what);
+        CouldNotInitDependencies
+    };
+
+    match method {
+        GitAuthMethod::SshKey => {
+            args.ssh_key_path
+                .clone()
+                .map(|key_path| GitCredentials::SshKey { key_path, passphrase })
+                .ok_or_else(|| missing("--ssh-key-path"))
+        }
+        GitAuthMethod::SshKeyMemory => {
+            std::env::var(SSH_PRIVATE_KEY_ENV)
+                .map(|private_key| GitCredentials::SshKeyMemory { private_key, passphrase })
+                .map_err(|_| missing(SSH_PRIVATE_KEY_ENV))
+        }
+        GitAuthMethod::SshAgent => Ok(GitCredentials::SshAgent),
+        GitAuthMethod::HttpsToken => {
+            Ok(GitCredentials::HttpsToken {
+                token: github_token.to_string(),
+            })
+        }
+        GitAuthMethod::GithubApp => {
+            let app_id = args.github_app_id.clone().ok_or_else(|| missing("--github-app-id"))?;
+            let installation_id = args
+                .github_app_installation_id
+                .clone()
+                .ok_or_else(|| missing("--github-app-installation-id"))?;
+            let private_key = args
+                .github_app_private_key_path
+                .as_ref()
+                .and_then(|path| fs::read(path).ok())
+                .ok_or_else(|| missing("a readable --github-app-private-key-path"))?;
+
+            GithubAppTokenRepository::new(app_id, installation_id, private_key.as_slice())
+                .map(|token_repository| GitCredentials::GithubApp(Arc::new(token_repository)))
+                .map_err(|_| missing("a valid RSA private key"))
+        }
+    }
+}
+
 fn init_github_api_client(github_token: String) -> Result<Client, InitError> {
     HeaderValue::from_str(("Bearer ".to_owned().clone() + github_token.as_str()).as_str())
         .map_err(|_| CouldNotInitDependencies)
@@ -84,4 +732,5 @@ pub enum InitError {
     CouldNotInitDependencies,
     CouldNotInitApp,
     CouldNotStartApp,
+    CouldNotRunCommand,
 }
@@ -1,36 +1,3971 @@
+use std::collections::HashMap;
+use std::os::unix::fs::PermissionsExt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
 use actix_web::{App, test, web};
-use futures::executor::block_on;
+use actix_web::http::StatusCode;
+use async_trait::async_trait;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use chrono::{DateTime, Datelike, Utc};
+use git2::CredentialType;
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
+
+use untitled::data::approver_store::ApproverStore;
+use untitled::data::audit_log::AuditLog;
+use untitled::data::deploy_info::{
+    ApprovalPolicy, Branch, CloneOptions, Command, CommitDirective,
+    ConcurrencyPolicy, DeployInfo, DeployInfoError, DeployWindow, HealthCheck, Input, InputError,
+    OutsideWindowPolicy, Schedule, Step,
+};
+use untitled::data::deploy_history_store::DeployHistoryStore;
+use untitled::data::deploy_info_repository::{
+    DeployEntity, DeployInfoEntity, DeployInfoRepository,
+};
+use untitled::data::deploy_key_store::DeployKeyStore;
+use untitled::data::freeze_store::{FreezeEntity, FreezeStore};
+use untitled::data::github_client::{GithubClient, GithubClientError};
+use untitled::data::github_deploy_key_repository::{GithubDeployKeyCreateDto, GithubDeployKeyDto};
+use untitled::data::github_repo_repository::{DtoWithHeaders, GithubRepoDto};
+use untitled::data::github_webhook_repository::{GithubWebhookCreateDto, GithubWebhookDto};
+use untitled::data::lfs_repository::LfsRepository;
+use untitled::data::run_repository::{
+    ApprovalDecision, RunRepository, RunStatus, RunTrigger, StepStatus,
+};
+use untitled::data::secret_store::{SecretStore, SecretStoreError};
+use untitled::di::app_state::AppState;
+use untitled::domain::blocking_pool::BlockingPool;
+use untitled::domain::clone_repo_task::CloneRepoTask;
+use untitled::domain::command_runner::{CommandRunner, CommandRunnerError, TokioCommandRunner};
+use untitled::domain::commit_directives::CommitDirectives;
+use untitled::domain::deploy_env::DeployEnv;
+use untitled::domain::deploy_executor::DeployExecutor;
+use untitled::domain::deploy_key_service::DeployKeyService;
+use untitled::domain::deploy_service::{DeployService, DeployServiceConfig};
+use untitled::domain::deploy_tracker::DeployTracker;
+use untitled::domain::git_credentials::{GitAuth, GitAuthMethod, GitCredentials};
+use untitled::domain::git_operations::{Git2Operations, GitOperations, GitOperationsError};
+use untitled::domain::health_checker::{HealthCheckError, HealthChecker, HealthProbe};
+use untitled::domain::host_key_verifier::{HostKeyError, HostKeyPolicy, HostKeyVerifier};
+use untitled::domain::lfs_task::LfsTask;
+use untitled::domain::path_filter::PathFilter;
+use untitled::domain::repo_url::{RepoUrl, RepoUrlError};
+use untitled::domain::secret_masker::SecretMasker;
+use untitled::entrypoint::github_push_event_dto::{
+    Commit, GithubPushEventDto, HeadCommit, Pusher, Repository,
+};
+use untitled::entrypoint::approval_dto::ApprovalDto;
+use untitled::entrypoint::deploy_dto::DeployDto;
+use untitled::entrypoint::freeze_dto::FreezeDto;
+use untitled::entrypoint::freeze_handler::{
+    handle_delete_freeze, handle_get_freezes, handle_post_freeze,
+};
+use untitled::entrypoint::override_dto::OverrideDto;
+use untitled::entrypoint::post_cancel_run_handler::handle_post_cancel_run;
+use untitled::entrypoint::post_deploy_handler::handle_post_deploy;
+use untitled::entrypoint::post_github_push_event_handler::handle_post_github_push_event;
+use untitled::entrypoint::post_run_approval_handler::{
+    handle_post_approve_run, handle_post_reject_run,
+};
+use untitled::entrypoint::post_run_override_handler::handle_post_override_run;
+use untitled::entrypoint::rollback_dto::RollbackDto;
+use untitled::entrypoint::rollback_handler::{handle_get_deploys, handle_post_rollback};
+use untitled::entrypoint::schedule_trigger::trigger_due_schedules;
+
+static PUSH_EVENT_PATH: &str = "/api/v1/events/push";
+static CANCEL_RUN_PATH: &str = "/api/v1/runs/{id}/cancel";
+static APPROVE_RUN_PATH: &str = "/api/v1/runs/{id}/approve";
+static REJECT_RUN_PATH: &str = "/api/v1/runs/{id}/reject";
+static OVERRIDE_RUN_PATH: &str = "/api/v1/runs/{id}/override";
+static FREEZES_PATH: &str = "/api/v1/freezes";
+static FREEZE_PATH: &str = "/api/v1/freezes/{id}";
+static DEPLOYS_PATH: &str = "/api/v1/repos/{repo:.+}/branches/{branch:.+}/deploys";
+static ROLLBACK_PATH: &str = "/api/v1/repos/{repo:.+}/branches/{branch:.+}/rollback";
+static DEPLOY_PATH: &str = "/api/v1/repos/{repo:.+}/deploy";
+// every app state gets stores of its own, freezes would hold the runs of other tests
+static STORE_COUNT: AtomicUsize = AtomicUsize::new(0);
+static SSH_URL: &str = "git@github.com:romqu/schimmelhof-api.git";
+static REPO_PATH: &str = "/tmp/schimmelhof-api";
+static DEV_WORKTREE_PATH: &str = "/tmp/schimmelhof-api.worktrees/dev";
+// the tip FakeGitOperations reports for every branch
+static BRANCH_TIP_SHA: &str = "3333333";
+static JOBS_YAML: &str = r#"
+directives: [skip_ci, deploy_only]
+branches:
+  - name: dev
+    jobs:
+      - name: build
+        commands:
+          - make build
+      - name: test
+        needs: [build]
+        commands:
+          - make test
+          - make integration-test
+      - name: lint
+        commands:
+          - make lint
+      - name: deploy
+        needs: [test, lint]
+        env:
+          TARGET: production
+        commands:
+          - make deploy
+"#;
+
+static SCALE_ARGUMENT: &str = "\"${MINI_CI_INPUT_SERVICE}\"=\"${MINI_CI_INPUT_REPLICAS}\"";
+
+static INPUTS_YAML: &str = r#"
+branches:
+  - name: dev
+    commands:
+      - docker-compose up -d --scale ${MINI_CI_INPUT_SERVICE}=${MINI_CI_INPUT_REPLICAS}
+inputs:
+  service:
+    type: choice
+    options: [api, web]
+    default: api
+  replicas:
+    type: number
+    required: true
+  verbose:
+    type: boolean
+    default: false
+"#;
+
+#[actix_web::main]
+async fn main() {
+    test_push_runs_branch_commands().await;
+    test_pushes_to_same_repo_run_one_after_another().await;
+    test_push_for_unknown_repo_is_rejected().await;
+    test_push_after_shutdown_is_refused().await;
+    test_timed_out_step_is_marked_and_stops_the_run().await;
+    test_command_runner_kills_process_group_on_timeout().await;
+    test_command_runner_kills_background_processes_holding_its_output().await;
+    test_cancel_in_progress_supersedes_running_deploy().await;
+    test_coalesce_runs_only_newest_push().await;
+    test_cancel_run_endpoint_cancels_running_deploy().await;
+    test_pushes_to_different_branches_run_in_parallel().await;
+    test_jobs_run_in_parallel_once_their_needs_succeeded().await;
+    test_failed_job_skips_the_jobs_that_need_it().await;
+    test_job_graphs_are_validated_when_parsed();
+    test_path_filters_match_changed_files();
+    test_runs_and_jobs_without_changed_paths_are_skipped().await;
+    test_commit_directives_are_parsed_when_allowed();
+    test_skip_directive_skips_the_run_and_records_why().await;
+    test_deploy_only_directive_limits_jobs_and_services().await;
+    test_deploy_only_directive_limits_compose_commands().await;
+    test_approvers_are_authenticated_by_their_tokens();
+    test_run_waits_for_approval_of_its_branch().await;
+    test_rejected_or_expired_approval_skips_the_job().await;
+    test_schedules_fire_at_their_cron_times();
+    test_due_schedules_run_the_branch_tip_without_overlapping().await;
+    test_deploy_windows_open_on_their_days_and_hours();
+    test_runs_outside_their_windows_are_held_or_rejected().await;
+    test_freezes_hold_runs_until_they_are_removed().await;
+    test_health_checks_retry_until_the_service_answers().await;
+    test_failed_health_check_fails_the_run_and_rolls_back().await;
+    test_rollback_redeploys_a_deployed_commit_with_its_config().await;
+    test_deploy_history_is_kept_across_restarts();
+    test_inputs_are_checked_against_their_types();
+    test_manual_deploy_runs_a_branch_with_its_inputs().await;
+    test_commands_get_push_variables_and_env().await;
+    test_command_runner_passes_env_to_commands().await;
+    test_command_runner_keeps_its_own_environment_from_commands().await;
+    test_variables_are_not_parsed_as_part_of_commands().await;
+    test_secrets_are_encrypted_and_scoped_per_environment();
+    test_secrets_are_injected_as_env_and_files().await;
+    test_push_with_missing_secret_is_rejected().await;
+    test_secret_masker_masks_values_and_their_encodings();
+    test_secrets_are_masked_in_run_records().await;
+    test_git2_worktrees_are_isolated_per_branch();
+    test_git2_finds_the_commits_of_a_branch();
+    test_existing_clone_is_reused_and_invalid_clone_recloned();
+    test_clone_options_fall_back_to_server_defaults();
+    test_sparse_paths_limit_what_is_checked_out();
+    test_git2_changed_files_are_diffed_between_commits();
+    test_shallow_clone_is_deepened_for_older_commits();
+    test_submodules_are_checked_out_when_enabled();
+    test_lfs_files_are_downloaded_once_when_enabled().await;
+    test_repo_urls_are_parsed_into_host_owner_and_name();
+    test_git_credentials_follow_allowed_types_and_repo_overrides();
+    test_host_keys_are_checked_against_known_hosts();
+    test_deploy_keys_are_provisioned_once_per_repo().await;
+
+    std::fs::remove_dir_all(get_stores_dir()).unwrap();
+}
+
+async fn test_push_runs_branch_commands() {
+    let git_operations = Arc::new(FakeGitOperations::new());
+    let command_runner = Arc::new(FakeCommandRunner::new());
+    let (app_state, _) = create_app_state(
+        AppStateOptions::default(),
+        git_operations.clone(),
+        command_runner.clone(),
+    );
+
+    let status = post_push_event(app_state.clone(), SSH_URL, "refs/heads/dev").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert!(
+        app_state
+            .deploy_tracker
+            .wait_for_running(Duration::from_secs(5))
+            .await
+    );
+    assert_eq!(
+        *git_operations.checked_out_branches.lock().unwrap(),
+        vec![format!("{}:dev", REPO_PATH)]
+    );
+    assert_eq!(
+        *command_runner.commands.lock().unwrap(),
+        vec![
+            format!("{}:docker-compose build", DEV_WORKTREE_PATH),
+            format!("{}:docker-compose up -d api", DEV_WORKTREE_PATH),
+        ]
+    );
+}
+
+async fn test_pushes_to_same_repo_run_one_after_another() {
+    let command_runner = Arc::new(FakeCommandRunner::new());
+    let (app_state, _) = create_app_state(
+        AppStateOptions::default(),
+        Arc::new(FakeGitOperations::new()),
+        command_runner.clone(),
+    );
+
+    for _ in 0..3 {
+        let status = post_push_event(app_state.clone(), SSH_URL, "refs/heads/dev").await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    assert!(
+        app_state
+            .deploy_tracker
+            .wait_for_running(Duration::from_secs(5))
+            .await
+    );
+    assert_eq!(command_runner.commands.lock().unwrap().len(), 6);
+    assert_eq!(command_runner.max_running.load(Ordering::SeqCst), 1);
+}
+
+async fn test_push_for_unknown_repo_is_rejected() {
+    let command_runner = Arc::new(FakeCommandRunner::new());
+    let (app_state, _) = create_app_state(
+        AppStateOptions::default(),
+        Arc::new(FakeGitOperations::new()),
+        command_runner.clone(),
+    );
+
+    let status =
+        post_push_event(app_state, "git@github.com:romqu/unknown.git", "refs/heads/dev").await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(command_runner.commands.lock().unwrap().is_empty());
+}
+
+async fn test_push_after_shutdown_is_refused() {
+    let command_runner = Arc::new(FakeCommandRunner::new());
+    let (app_state, _) = create_app_state(
+        AppStateOptions::default(),
+        Arc::new(FakeGitOperations::new()),
+        command_runner.clone(),
+    );
+
+    app_state.deploy_tracker.stop_accepting();
+    let status = post_push_event(app_state, SSH_URL, "refs/heads/dev").await;
+
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert!(command_runner.commands.lock().unwrap().is_empty());
+}
+
+async fn test_timed_out_step_is_marked_and_stops_the_run() {
+    let command_runner = Arc::new(FakeCommandRunner::new());
+    command_runner
+        .timing_out_commands
+        .lock()
+        .unwrap()
+        .push("docker-compose build".to_string());
+    let (app_state, run_repository) = create_app_state(
+        AppStateOptions::default(),
+        Arc::new(FakeGitOperations::new()),
+        command_runner.clone(),
+    );
+
+    let status = post_push_event(app_state.clone(), SSH_URL, "refs/heads/dev").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert!(
+        app_state
+            .deploy_tracker
+            .wait_for_running(Duration::from_secs(5))
+            .await
+    );
+    let run_repository = run_repository.lock().unwrap();
+    let run = run_repository.get(1).unwrap();
+    assert_eq!(run.status, RunStatus::Failed);
+    assert_eq!(run.steps[0].status, StepStatus::TimedOut);
+    assert_eq!(run.steps[1].status, StepStatus::Skipped);
+    assert_eq!(
+        *command_runner.timeouts.lock().unwrap(),
+        vec![Duration::from_secs(600)]
+    );
+}
+
+async fn test_command_runner_kills_process_group_on_timeout() {
+    let pid_file = std::env::temp_dir().join(format!("mini-ci-test-{}.pid", std::process::id()));
+    let command = format!("sleep 30 & echo $! > {}; wait", pid_file.display());
+    let command_runner = TokioCommandRunner::new(Duration::from_secs(1));
+
+    let result = command_runner
+        .run(
+            command.as_str(),
+            "/tmp",
+            &HashMap::new(),
+            &SecretMasker::default(),
+            Duration::from_millis(500),
+            &CancellationToken::new(),
+        )
+        .await;
+
+    assert!(matches!(result, Err(CommandRunnerError::TimedOut)));
+    let pid = std::fs::read_to_string(&pid_file).unwrap();
+    let _ = std::fs::remove_file(&pid_file);
+    // a killed orphan can linger as a zombie until init reaps it, that counts as dead
+    let background_sleep_alive = std::fs::read_to_string(format!("/proc/{}/stat", pid.trim()))
+        .map(|stat| !stat.contains(") Z "))
+        .unwrap_or(false);
+    assert!(!background_sleep_alive);
+}
+
+async fn test_command_runner_kills_background_processes_holding_its_output() {
+    let pid_file =
+        std::env::temp_dir().join(format!("mini-ci-test-{}-background.pid", std::process::id()));
+    let command = format!("sleep 1000 & echo $! > {}", pid_file.display());
+    let command_runner = TokioCommandRunner::new(Duration::from_millis(300));
+    let started_at = std::time::Instant::now();
+
+    let result = tokio::time::timeout(
+        Duration::from_secs(10),
+        command_runner.run(
+            command.as_str(),
+            "/tmp",
+            &HashMap::new(),
+            &SecretMasker::default(),
+            Duration::from_secs(60),
+            &CancellationToken::new(),
+        ),
+    )
+    .await
+    .expect("the run waited for the background sleep");
+
+    assert!(result.is_ok());
+    assert!(started_at.elapsed() < Duration::from_secs(5));
+    let pid = std::fs::read_to_string(&pid_file).unwrap();
+    let _ = std::fs::remove_file(&pid_file);
+    // nothing waits for the killed sleep, it dies shortly after
+    let mut background_sleep_alive = true;
+    for _ in 0..50 {
+        background_sleep_alive = std::fs::read_to_string(format!("/proc/{}/stat", pid.trim()))
+            .map(|stat| !stat.contains(") Z "))
+            .unwrap_or(false);
+        if !background_sleep_alive {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert!(!background_sleep_alive);
+}
+
+async fn test_cancel_in_progress_supersedes_running_deploy() {
+    let command_runner = Arc::new(FakeCommandRunner::with_duration(Duration::from_millis(300)));
+    let branch = Branch {
+        concurrency: ConcurrencyPolicy::CancelInProgress,
+        ..create_dev_branch()
+    };
+    let (app_state, run_repository) = create_app_state(
+        AppStateOptions::with_branches(vec![branch]),
+        Arc::new(FakeGitOperations::new()),
+        command_runner.clone(),
+    );
+
+    post_push_event(app_state.clone(), SSH_URL, "refs/heads/dev").await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    post_push_event(app_state.clone(), SSH_URL, "refs/heads/dev").await;
+
+    assert!(
+        app_state
+            .deploy_tracker
+            .wait_for_running(Duration::from_secs(5))
+            .await
+    );
+    let run_repository = run_repository.lock().unwrap();
+    let first_run = run_repository.get(1).unwrap();
+    assert_eq!(first_run.status, RunStatus::Superseded);
+    assert_eq!(first_run.superseded_by, Some(2));
+    assert_eq!(first_run.steps[0].status, StepStatus::Cancelled);
+    assert_eq!(run_repository.get(2).unwrap().status, RunStatus::Succeeded);
+}
+
+async fn test_coalesce_runs_only_newest_push() {
+    let command_runner = Arc::new(FakeCommandRunner::new());
+    let branch = Branch {
+        concurrency: ConcurrencyPolicy::Coalesce,
+        debounce: Some(1),
+        ..create_dev_branch()
+    };
+    let (app_state, run_repository) = create_app_state(
+        AppStateOptions::with_branches(vec![branch]),
+        Arc::new(FakeGitOperations::new()),
+        command_runner.clone(),
+    );
+
+    for _ in 0..3 {
+        post_push_event(app_state.clone(), SSH_URL, "refs/heads/dev").await;
+    }
+
+    assert!(
+        app_state
+            .deploy_tracker
+            .wait_for_running(Duration::from_secs(5))
+            .await
+    );
+    let run_repository = run_repository.lock().unwrap();
+    assert_eq!(run_repository.get(1).unwrap().status, RunStatus::Superseded);
+    assert_eq!(run_repository.get(2).unwrap().status, RunStatus::Superseded);
+    assert_eq!(run_repository.get(3).unwrap().status, RunStatus::Succeeded);
+    assert_eq!(command_runner.commands.lock().unwrap().len(), 2);
+}
+
+async fn test_cancel_run_endpoint_cancels_running_deploy() {
+    let command_runner = Arc::new(FakeCommandRunner::with_duration(Duration::from_millis(500)));
+    let (app_state, run_repository) = create_app_state(
+        AppStateOptions::default(),
+        Arc::new(FakeGitOperations::new()),
+        command_runner.clone(),
+    );
+
+    let token = create_approver_store().add("heidi").unwrap();
+
+    post_push_event(app_state.clone(), SSH_URL, "refs/heads/dev").await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    assert_eq!(
+        post_cancel_run(app_state.clone(), 1, None).await,
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(
+        post_cancel_run(app_state.clone(), 1, Some("not-a-token")).await,
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(
+        post_cancel_run(app_state.clone(), 1, Some(&token)).await,
+        StatusCode::ACCEPTED
+    );
+    assert_eq!(
+        post_cancel_run(app_state.clone(), 42, Some(&token)).await,
+        StatusCode::NOT_FOUND
+    );
+    assert!(
+        app_state
+            .deploy_tracker
+            .wait_for_running(Duration::from_secs(5))
+            .await
+    );
+    let run = run_repository.lock().unwrap().get(1).unwrap().clone();
+    assert_eq!(run.status, RunStatus::Cancelled);
+    assert_eq!(run.cancelled_by.as_deref(), Some("heidi"));
+    assert_eq!(
+        post_cancel_run(app_state.clone(), 1, Some(&token)).await,
+        StatusCode::CONFLICT
+    );
+}
+
+async fn test_pushes_to_different_branches_run_in_parallel() {
+    let command_runner = Arc::new(FakeCommandRunner::with_duration(Duration::from_millis(200)));
+    let master_branch = Branch {
+        name: "master".to_string(),
+        ..create_dev_branch()
+    };
+    let (app_state, _) = create_app_state(
+        AppStateOptions::with_branches(vec![create_dev_branch(), master_branch]),
+        Arc::new(FakeGitOperations::new()),
+        command_runner.clone(),
+    );
+
+    for refs in ["refs/heads/dev", "refs/heads/master"] {
+        let status = post_push_event(app_state.clone(), SSH_URL, refs).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    assert!(
+        app_state
+            .deploy_tracker
+            .wait_for_running(Duration::from_secs(5))
+            .await
+    );
+    assert_eq!(command_runner.commands.lock().unwrap().len(), 4);
+    assert_eq!(command_runner.max_running.load(Ordering::SeqCst), 2);
+}
+
+async fn test_jobs_run_in_parallel_once_their_needs_succeeded() {
+    let command_runner = Arc::new(FakeCommandRunner::with_duration(Duration::from_millis(200)));
+    let deploy_info = DeployInfo::parse(JOBS_YAML).unwrap();
+    let (app_state, run_repository) = create_app_state(
+        AppStateOptions::with_deploy_info(deploy_info),
+        Arc::new(FakeGitOperations::new()),
+        command_runner.clone(),
+    );
+
+    let status = post_push_event(app_state.clone(), SSH_URL, "refs/heads/dev").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert!(
+        app_state
+            .deploy_tracker
+            .wait_for_running(Duration::from_secs(5))
+            .await
+    );
+    let commands = command_runner
+        .commands
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|command| command.trim_start_matches(&format!("{}:", DEV_WORKTREE_PATH)).to_string())
+        .collect::<Vec<String>>();
+    let position = |command: &str| commands.iter().position(|it| it == command).unwrap();
+    assert_eq!(commands.len(), 5);
+    assert!(position("make build") < position("make test"));
+    assert!(position("make test") < position("make deploy"));
+    assert!(position("make lint") < position("make deploy"));
+    assert_eq!(commands.last().unwrap(), "make deploy");
+    assert_eq!(command_runner.max_running.load(Ordering::SeqCst), 2);
+    assert_eq!(
+        command_runner.envs.lock().unwrap()[position("make deploy")]["TARGET"],
+        "production"
+    );
+
+    let run_repository = run_repository.lock().unwrap();
+    let run = run_repository.get(1).unwrap();
+    assert_eq!(run.status, RunStatus::Succeeded);
+    assert!(run.jobs.iter().all(|job| job.status == StepStatus::Succeeded));
+    assert_eq!(run.jobs[3].needs, vec!["test".to_string(), "lint".to_string()]);
+    assert_eq!(
+        run.steps
+            .iter()
+            .map(|step| step.job.as_str())
+            .collect::<Vec<&str>>(),
+        vec!["build", "test", "test", "lint", "deploy"]
+    );
+}
+
+async fn test_failed_job_skips_the_jobs_that_need_it() {
+    let command_runner = Arc::new(FakeCommandRunner::new());
+    command_runner
+        .timing_out_commands
+        .lock()
+        .unwrap()
+        .push("make build".to_string());
+    let deploy_info = DeployInfo::parse(JOBS_YAML).unwrap();
+    let (app_state, run_repository) = create_app_state(
+        AppStateOptions::with_deploy_info(deploy_info),
+        Arc::new(FakeGitOperations::new()),
+        command_runner.clone(),
+    );
+
+    let status = post_push_event(app_state.clone(), SSH_URL, "refs/heads/dev").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert!(
+        app_state
+            .deploy_tracker
+            .wait_for_running(Duration::from_secs(5))
+            .await
+    );
+    assert_eq!(command_runner.commands.lock().unwrap().len(), 2);
+
+    let run_repository = run_repository.lock().unwrap();
+    let run = run_repository.get(1).unwrap();
+    assert_eq!(run.status, RunStatus::Failed);
+    assert_eq!(
+        run.jobs
+            .iter()
+            .map(|job| (job.name.as_str(), job.status))
+            .collect::<Vec<(&str, StepStatus)>>(),
+        vec![
+            ("build", StepStatus::TimedOut),
+            ("test", StepStatus::Skipped),
+            ("lint", StepStatus::Succeeded),
+            ("deploy", StepStatus::Skipped),
+        ]
+    );
+    assert_eq!(run.steps[1].status, StepStatus::Skipped);
+    assert_eq!(run.steps[4].status, StepStatus::Skipped);
+}
+
+fn test_job_graphs_are_validated_when_parsed() {
+    let parse_jobs = |jobs: &str| {
+        DeployInfo::parse(format!("branches:\n  - name: dev\n    jobs:{}", jobs).as_str())
+    };
+
+    assert_eq!(
+        parse_jobs(
+            r#"
+      - { name: a, needs: [c], commands: [echo a] }
+      - { name: b, needs: [a], commands: [echo b] }
+      - { name: c, needs: [b], commands: [echo c] }
+      - { name: d, needs: [a], commands: [echo d] }
+      - { name: e, commands: [echo e] }
+"#
+        ),
+        Err(DeployInfoError::CyclicJobs(vec![
+            "a".to_string(),
+            "b".to_string(),
+            "c".to_string(),
+            "d".to_string(),
+        ]))
+    );
+    assert_eq!(
+        parse_jobs("\n      - { name: a, needs: [b], commands: [echo a] }"),
+        Err(DeployInfoError::UnknownJob("b".to_string()))
+    );
+    assert_eq!(
+        parse_jobs(
+            r#"
+      - { name: a, commands: [echo a] }
+      - { name: a, commands: [echo b] }
+"#
+        ),
+        Err(DeployInfoError::DuplicateJob("a".to_string()))
+    );
+    assert_eq!(
+        DeployInfo::parse(
+            r#"
+branches:
+  - name: dev
+    commands: [echo a]
+    jobs:
+      - { name: a, commands: [echo a] }
+"#
+        ),
+        Err(DeployInfoError::CommandsAndJobs("dev".to_string()))
+    );
+
+    let implicit_jobs = DeployInfo::parse("branches: [{ name: dev, commands: [echo a] }]")
+        .unwrap()
+        .branches[0]
+        .get_jobs();
+    assert_eq!(implicit_jobs.len(), 1);
+    assert_eq!(implicit_jobs[0].name, "deploy");
+}
+
+fn test_path_filters_match_changed_files() {
+    let to_strings = |values: &[&str]| {
+        values
+            .iter()
+            .map(|value| value.to_string())
+            .collect::<Vec<String>>()
+    };
+
+    assert!(PathFilter::glob_matches("api/*.rs", "api/main.rs"));
+    assert!(!PathFilter::glob_matches("api/*.rs", "api/src/main.rs"));
+    assert!(PathFilter::glob_matches("api/**", "api/src/main.rs"));
+    assert!(PathFilter::glob_matches("**/*.md", "README.md"));
+    assert!(PathFilter::glob_matches("**/*.md", "docs/guide/setup.md"));
+    assert!(PathFilter::glob_matches("docker-compose.?ml", "docker-compose.yml"));
+    assert!(!PathFilter::glob_matches("*.yml", "config/app.yml"));
+
+    let path_filter = PathFilter::new(&to_strings(&["api/**"]), &to_strings(&["**/*.md"]));
+    assert!(path_filter.matches(&to_strings(&["api/README.md", "api/main.rs"])));
+    assert!(!path_filter.matches(&to_strings(&["api/README.md", "web/index.html"])));
+    assert!(!path_filter.matches(&[]));
+
+    let ignore_filter = PathFilter::new(&[], &to_strings(&["docs/**"]));
+    assert!(!ignore_filter.matches(&to_strings(&["docs/setup.md"])));
+    assert!(ignore_filter.matches(&to_strings(&["docs/setup.md", "Dockerfile"])));
+    assert!(PathFilter::default().matches(&[]));
+}
+
+async fn test_runs_and_jobs_without_changed_paths_are_skipped() {
+    let deploy_info = DeployInfo::parse(
+        r#"
+branches:
+  - name: dev
+    paths: [api/**, web/**]
+    paths_ignore: ["**/*.md"]
+    jobs:
+      - { name: api, paths: [api/**], commands: [make api] }
+      - { name: web, paths: [web/**], commands: [make web] }
+      - { name: deploy, needs: [api, web], commands: [make deploy] }
+"#,
+    )
+    .unwrap();
+    let git_operations = Arc::new(FakeGitOperations::new());
+    let command_runner = Arc::new(FakeCommandRunner::new());
+    let (app_state, run_repository) = create_app_state(
+        AppStateOptions::with_deploy_info(deploy_info),
+        git_operations.clone(),
+        command_runner.clone(),
+    );
+
+    // only ignored files changed
+    *git_operations.changed_files.lock().unwrap() = Some(vec!["api/README.md".to_string()]);
+    assert_eq!(post_push_event(app_state.clone(), SSH_URL, "refs/heads/dev").await, StatusCode::OK);
+    assert!(
+        app_state
+            .deploy_tracker
+            .wait_for_running(Duration::from_secs(5))
+            .await
+    );
+    assert!(command_runner.commands.lock().unwrap().is_empty());
+    {
+        let run_repository = run_repository.lock().unwrap();
+        let run = run_repository.get(1).unwrap();
+        assert_eq!(run.status, RunStatus::Skipped);
+        assert_eq!(run.skip_reason.as_deref(), Some("no paths of the branch changed"));
+        assert!(run.jobs.iter().all(|job| job.status == StepStatus::Skipped));
+    }
+
+    // a job whose paths didn't change doesn't hold back the jobs that need it
+    *git_operations.changed_files.lock().unwrap() = Some(vec!["api/main.rs".to_string()]);
+    assert_eq!(post_push_event(app_state.clone(), SSH_URL, "refs/heads/dev").await, StatusCode::OK);
+    assert!(
+        app_state
+            .deploy_tracker
+            .wait_for_running(Duration::from_secs(5))
+            .await
+    );
+    assert_eq!(
+        *command_runner.commands.lock().unwrap(),
+        vec![
+            format!("{}:make api", DEV_WORKTREE_PATH),
+            format!("{}:make deploy", DEV_WORKTREE_PATH),
+        ]
+    );
+    {
+        let run_repository = run_repository.lock().unwrap();
+        let run = run_repository.get(2).unwrap();
+        assert_eq!(run.status, RunStatus::Succeeded);
+        assert_eq!(run.jobs[1].status, StepStatus::Skipped);
+    }
+
+    // without a diff the files of the payload's commits count
+    *git_operations.changed_files.lock().unwrap() = None;
+    command_runner.commands.lock().unwrap().clear();
+    let dto = GithubPushEventDto::default();
+    let post_dto = GithubPushEventDto {
+        ref_field: "refs/heads/dev".to_string(),
+        repository: Repository {
+            ssh_url: SSH_URL.to_string(),
+            ..dto.repository
+        },
+        commits: vec![Commit {
+            added: vec![serde_json::Value::String("web/index.html".to_string())],
+            ..Commit::default()
+        }],
+        ..dto
+    };
+    assert_eq!(post_push_event_dto(app_state.clone(), post_dto).await, StatusCode::OK);
+    assert!(
+        app_state
+            .deploy_tracker
+            .wait_for_running(Duration::from_secs(5))
+            .await
+    );
+    assert_eq!(
+        *command_runner.commands.lock().unwrap(),
+        vec![
+            format!("{}:make web", DEV_WORKTREE_PATH),
+            format!("{}:make deploy", DEV_WORKTREE_PATH),
+        ]
+    );
+}
+
+fn test_commit_directives_are_parsed_when_allowed() {
+    let all_directives = [
+        CommitDirective::SkipCi,
+        CommitDirective::SkipDeploy,
+        CommitDirective::DeployOnly,
+    ];
+
+    assert_eq!(
+        CommitDirectives::parse(&["Fix typo [Skip  CI]"], &all_directives).skip,
+        Some("[skip ci]".to_string())
+    );
+    assert_eq!(
+        CommitDirectives::parse(&["docs", "wip [skip deploy]"], &all_directives).skip,
+        Some("[skip deploy]".to_string())
+    );
+    assert_eq!(
+        CommitDirectives::parse(&["[skip deploy]"], &[CommitDirective::SkipCi]),
+        CommitDirectives::default()
+    );
+    assert_eq!(
+        CommitDirectives::parse(
+            &["Update [deploy only=api, worker]", "[[deploy only = api,web]"],
+            &all_directives
+        )
+        .deploy_only,
+        vec!["api".to_string(), "worker".to_string(), "web".to_string()]
+    );
+    assert!(CommitDirectives::parse(&["[deploy only=api]"], &[CommitDirective::SkipCi])
+        .deploy_only
+        .is_empty());
+    assert_eq!(
+        CommitDirectives::parse(&["[skip] [deploy] [only=api] unclosed [skip ci"], &all_directives),
+        CommitDirectives::default()
+    );
+}
+
+async fn test_skip_directive_skips_the_run_and_records_why() {
+    let command_runner = Arc::new(FakeCommandRunner::with_duration(Duration::from_millis(200)));
+    let (app_state, run_repository) = create_app_state(
+        AppStateOptions::with_deploy_info(DeployInfo::parse(JOBS_YAML).unwrap()),
+        Arc::new(FakeGitOperations::new()),
+        command_runner.clone(),
+    );
+
+    assert_eq!(
+        post_push_event_with_message(app_state.clone(), "Deploy").await,
+        StatusCode::OK
+    );
+    assert_eq!(
+        post_push_event_with_message(app_state.clone(), "Fix typo\n\n[skip ci]").await,
+        StatusCode::OK
+    );
+    // not allowed by the repo
+    assert_eq!(
+        post_push_event_with_message(app_state.clone(), "Fix typo [skip deploy]").await,
+        StatusCode::OK
+    );
+
+    {
+        let run_repository = run_repository.lock().unwrap();
+        let run = run_repository.get(2).unwrap();
+        assert_eq!(run.status, RunStatus::Skipped);
+        assert_eq!(run.skip_reason.as_deref(), Some("[skip ci] in a commit message"));
+        assert!(run.steps.iter().all(|step| step.status == StepStatus::Skipped));
+    }
+    assert!(
+        app_state
+            .deploy_tracker
+            .wait_for_running(Duration::from_secs(5))
+            .await
+    );
+
+    let run_repository = run_repository.lock().unwrap();
+    assert_eq!(run_repository.get(1).unwrap().status, RunStatus::Succeeded);
+    assert_eq!(run_repository.get(3).unwrap().status, RunStatus::Succeeded);
+    assert_eq!(command_runner.commands.lock().unwrap().len(), 10);
+}
+
+async fn test_deploy_only_directive_limits_jobs_and_services() {
+    let command_runner = Arc::new(FakeCommandRunner::new());
+    let (app_state, run_repository) = create_app_state(
+        AppStateOptions::with_deploy_info(DeployInfo::parse(JOBS_YAML).unwrap()),
+        Arc::new(FakeGitOperations::new()),
+        command_runner.clone(),
+    );
+
+    let status =
+        post_push_event_with_message(app_state.clone(), "Fix tests [deploy only=test,api]").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert!(
+        app_state
+            .deploy_tracker
+            .wait_for_running(Duration::from_secs(5))
+            .await
+    );
+    assert_eq!(
+        *command_runner.commands.lock().unwrap(),
+        vec![
+            format!("{}:make build", DEV_WORKTREE_PATH),
+            format!("{}:make test", DEV_WORKTREE_PATH),
+            format!("{}:make integration-test", DEV_WORKTREE_PATH),
+        ]
+    );
+    assert_eq!(command_runner.envs.lock().unwrap()[0]["MINI_CI_SERVICES"], "api");
+
+    let run_repository = run_repository.lock().unwrap();
+    let run = run_repository.get(1).unwrap();
+    assert_eq!(run.status, RunStatus::Succeeded);
+    assert_eq!(
+        run.jobs
+            .iter()
+            .map(|job| job.status)
+            .collect::<Vec<StepStatus>>(),
+        vec![
+            StepStatus::Succeeded,
+            StepStatus::Succeeded,
+            StepStatus::Skipped,
+            StepStatus::Skipped,
+        ]
+    );
+}
+
+async fn test_deploy_only_directive_limits_compose_commands() {
+    let deploy_yaml = r#"
+directives: [deploy_only]
+branches:
+  - name: dev
+    commands:
+      - docker-compose pull && docker-compose up -d
+      - docker compose build --pull
+      - docker-compose up -d --no-deps ${MINI_CI_SERVICES}
+      - docker-compose logs
+"#;
+    let command_runner = Arc::new(FakeCommandRunner::new());
+    let (app_state, _) = create_app_state(
+        AppStateOptions::with_deploy_info(DeployInfo::parse(deploy_yaml).unwrap()),
+        Arc::new(FakeGitOperations::new()),
+        command_runner.clone(),
+    );
+
+    let status = post_push_event_with_message(app_state.clone(), "Fix [deploy only=web,api]").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert!(
+        app_state
+            .deploy_tracker
+            .wait_for_running(Duration::from_secs(5))
+            .await
+    );
+
+    // the commands run in bash against a compose that records what it was asked to do
+    let compose_dir =
+        std::env::temp_dir().join(format!("mini-ci-test-{}-compose", std::process::id()));
+    let compose_log = compose_dir.join("calls");
+    std::fs::create_dir_all(&compose_dir).unwrap();
+    for name in ["docker-compose", "docker"] {
+        let script = compose_dir.join(name);
+        std::fs::write(&script, format!("#!/bin/sh\necho \"$*\" >> {}\n", compose_log.display()))
+            .unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+    let commands = command_runner.commands.lock().unwrap().clone();
+    let envs = command_runner.envs.lock().unwrap().clone();
+    for (command, mut env) in commands.iter().zip(envs) {
+        env.insert(
+            "PATH".to_string(),
+            format!("{}:{}", compose_dir.display(), std::env::var("PATH").unwrap()),
+        );
+        let command = command.split_once(':').unwrap().1;
+
+        let result = TokioCommandRunner::new(Duration::from_secs(1))
+            .run(
+                command,
+                "/tmp",
+                &env,
+                &SecretMasker::default(),
+                Duration::from_secs(5),
+                &CancellationToken::new(),
+            )
+            .await;
+
+        assert!(result.is_ok());
+    }
+    let compose_calls = std::fs::read_to_string(&compose_log).unwrap();
+    std::fs::remove_dir_all(&compose_dir).unwrap();
+    assert_eq!(
+        compose_calls.lines().collect::<Vec<&str>>(),
+        vec![
+            "pull web api",
+            "up -d web api",
+            "compose build --pull web api",
+            "up -d --no-deps web api",
+            "logs",
+        ]
+    );
+
+    assert_eq!(
+        CommitDirectives::restrict_compose_services(
+            "docker-compose -f prod.yml up -d; docker compose restart\n",
+            "MINI_CI_SERVICES"
+        ),
+        "docker-compose -f prod.yml up -d ${MINI_CI_SERVICES}; \
+         docker compose restart ${MINI_CI_SERVICES}\n"
+    );
+    assert_eq!(
+        CommitDirectives::restrict_compose_services(
+            "docker-compose up -d -t 10 api | tee log && docker compose build --pull api",
+            "MINI_CI_SERVICES"
+        ),
+        "docker-compose up -d -t 10 api | tee log && docker compose build --pull api"
+    );
+    assert_eq!(
+        CommitDirectives::restrict_compose_services(
+            "docker-compose ps && docker compose up --pull always -t 10",
+            "MINI_CI_SERVICES"
+        ),
+        "docker-compose ps && docker compose up --pull always -t 10 ${MINI_CI_SERVICES}"
+    );
+}
+
+fn test_approvers_are_authenticated_by_their_tokens() {
+    let approvers_path =
+        std::env::temp_dir().join(format!("mini-ci-approvers-test-{}.json", std::process::id()));
+    let approver_store = ApproverStore::new(approvers_path.to_str().unwrap().to_string());
+
+    let alice_token = approver_store.add("alice").unwrap();
+    let bob_token = approver_store.add("bob").unwrap();
+
+    assert_eq!(approver_store.authenticate(&alice_token), Ok(Some("alice".to_string())));
+    assert_eq!(approver_store.authenticate(&bob_token), Ok(Some("bob".to_string())));
+    assert_eq!(approver_store.authenticate("guess"), Ok(None));
+    assert!(!std::fs::read_to_string(&approvers_path)
+        .unwrap()
+        .contains(alice_token.as_str()));
+    assert_eq!(
+        std::fs::metadata(&approvers_path).unwrap().permissions().mode() & 0o777,
+        0o600
+    );
+
+    // a new token replaces the old one
+    let new_alice_token = approver_store.add("alice").unwrap();
+    assert_eq!(approver_store.authenticate(&alice_token), Ok(None));
+    assert_eq!(approver_store.authenticate(&new_alice_token), Ok(Some("alice".to_string())));
+
+    assert_eq!(approver_store.remove("bob"), Ok(true));
+    assert_eq!(approver_store.remove("bob"), Ok(false));
+    assert_eq!(approver_store.authenticate(&bob_token), Ok(None));
+    assert_eq!(approver_store.list(), Ok(vec!["alice".to_string()]));
+
+    // concurrent changes don't overwrite each other
+    std::thread::scope(|scope| {
+        for index in 0..8 {
+            let approver_store = &approver_store;
+            scope.spawn(move || approver_store.add(&format!("approver-{}", index)).unwrap());
+        }
+    });
+    assert_eq!(approver_store.list().unwrap().len(), 9);
+
+    std::fs::remove_file(&approvers_path).unwrap();
+}
+
+async fn test_run_waits_for_approval_of_its_branch() {
+    let command_runner = Arc::new(FakeCommandRunner::with_duration(Duration::from_millis(200)));
+    let master_branch = Branch {
+        name: "master".to_string(),
+        approval: ApprovalPolicy::Required,
+        ..create_dev_branch()
+    };
+    let staging_branch = Branch {
+        name: "staging".to_string(),
+        ..create_dev_branch()
+    };
+    let (app_state, run_repository) = create_app_state(
+        AppStateOptions::with_branches(vec![master_branch, create_dev_branch(), staging_branch]),
+        Arc::new(FakeGitOperations::new()),
+        command_runner.clone(),
+    );
+    let token = create_approver_store().add("carol").unwrap();
+
+    let status = post_push_event(app_state.clone(), SSH_URL, "refs/heads/master").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(wait_for_run_status(&run_repository, 1, RunStatus::AwaitingApproval).await);
+
+    // a run awaiting approval doesn't hold back the other branches
+    for refs in ["refs/heads/dev", "refs/heads/staging"] {
+        assert_eq!(post_push_event(app_state.clone(), SSH_URL, refs).await, StatusCode::OK);
+    }
+    assert!(wait_for_run_status(&run_repository, 2, RunStatus::Succeeded).await);
+    assert!(wait_for_run_status(&run_repository, 3, RunStatus::Succeeded).await);
+    assert_eq!(command_runner.max_running.load(Ordering::SeqCst), 2);
+    assert_eq!(command_runner.commands.lock().unwrap().len(), 4);
+
+    assert_eq!(
+        post_run_decision(app_state.clone(), 1, ApprovalDecision::Approved, None, None).await,
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(
+        post_run_decision(
+            app_state.clone(),
+            1,
+            ApprovalDecision::Approved,
+            Some("guess"),
+            None
+        )
+            .await,
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(
+        post_run_decision(
+            app_state.clone(),
+            1,
+            ApprovalDecision::Approved,
+            Some(&token),
+            None
+        )
+            .await,
+        StatusCode::OK
+    );
+    assert_eq!(
+        post_run_decision(
+            app_state.clone(),
+            1,
+            ApprovalDecision::Approved,
+            Some(&token),
+            None
+        )
+            .await,
+        StatusCode::CONFLICT
+    );
+
+    assert!(
+        app_state
+            .deploy_tracker
+            .wait_for_running(Duration::from_secs(5))
+            .await
+    );
+    let run_repository = run_repository.lock().unwrap();
+    let run = run_repository.get(1).unwrap();
+    assert_eq!(run.status, RunStatus::Succeeded);
+    assert_eq!(run.approvals.len(), 1);
+    assert_eq!(run.approvals[0].job, None);
+    assert_eq!(run.approvals[0].decision, Some(ApprovalDecision::Approved));
+    assert_eq!(run.approvals[0].approver.as_deref(), Some("carol"));
+    assert_eq!(command_runner.commands.lock().unwrap().len(), 6);
+}
+
+async fn test_rejected_or_expired_approval_skips_the_job() {
+    let command_runner = Arc::new(FakeCommandRunner::new());
+    let deploy_info = DeployInfo::parse(
+        r#"
+branches:
+  - name: dev
+    jobs:
+      - { name: build, commands: [make build] }
+      - name: deploy
+        needs: [build]
+        approval: required
+        approval_expiry: 1
+        commands: [docker-compose up -d api]
+"#,
+    )
+    .unwrap();
+    let (app_state, run_repository) = create_app_state(
+        AppStateOptions::with_deploy_info(deploy_info),
+        Arc::new(FakeGitOperations::new()),
+        command_runner.clone(),
+    );
+    let token = create_approver_store().add("dave").unwrap();
+
+    assert_eq!(post_push_event(app_state.clone(), SSH_URL, "refs/heads/dev").await, StatusCode::OK);
+    assert!(wait_for_run_status(&run_repository, 1, RunStatus::AwaitingApproval).await);
+    assert_eq!(
+        run_repository.lock().unwrap().get(1).unwrap().jobs[1].status,
+        StepStatus::AwaitingApproval
+    );
+    assert_eq!(
+        post_run_decision(
+            app_state.clone(),
+            1,
+            ApprovalDecision::Rejected,
+            Some(&token),
+            Some("build")
+        )
+            .await,
+        StatusCode::CONFLICT
+    );
+    assert_eq!(
+        post_run_decision(
+            app_state.clone(),
+            1,
+            ApprovalDecision::Rejected,
+            Some(&token),
+            Some("deploy")
+        )
+            .await,
+        StatusCode::OK
+    );
+    assert!(wait_for_run_status(&run_repository, 1, RunStatus::Rejected).await);
+
+    // nobody decides in time
+    assert_eq!(post_push_event(app_state.clone(), SSH_URL, "refs/heads/dev").await, StatusCode::OK);
+    assert!(
+        app_state
+            .deploy_tracker
+            .wait_for_running(Duration::from_secs(5))
+            .await
+    );
+
+    let run_repository = run_repository.lock().unwrap();
+    for (run_id, decision, approver) in [
+        (1, ApprovalDecision::Rejected, Some("dave")),
+        (2, ApprovalDecision::Expired, None),
+    ] {
+        let run = run_repository.get(run_id).unwrap();
+        assert_eq!(run.status, RunStatus::Rejected);
+        assert_eq!(run.jobs[0].status, StepStatus::Succeeded);
+        assert_eq!(run.jobs[1].status, StepStatus::Rejected);
+        assert_eq!(run.steps[1].status, StepStatus::Skipped);
+        assert_eq!(run.approvals[0].job.as_deref(), Some("deploy"));
+        assert_eq!(run.approvals[0].decision, Some(decision));
+        assert_eq!(run.approvals[0].approver.as_deref(), approver);
+    }
+    assert!(run_repository.get(2).unwrap().approvals[0].expires_at.is_some());
+    assert_eq!(
+        *command_runner.commands.lock().unwrap(),
+        vec![
+            format!("{}:make build", DEV_WORKTREE_PATH),
+            format!("{}:make build", DEV_WORKTREE_PATH),
+        ]
+    );
+}
+
+fn test_schedules_fire_at_their_cron_times() {
+    let utc = |value: &str| value.parse::<DateTime<Utc>>().unwrap();
+    let nightly = Schedule {
+        cron: "0 3 * * *".to_string(),
+        timezone: Some("Europe/Berlin".to_string()),
+        branch: "dev".to_string(),
+    };
+
+    // 03:00 in Berlin is 02:00 UTC in winter and 01:00 UTC in summer
+    assert_eq!(
+        nightly.next_after(utc("2026-01-10T02:30:00Z")),
+        Some(utc("2026-01-11T02:00:00Z"))
+    );
+    assert_eq!(
+        nightly.next_after(utc("2026-07-10T00:59:59.500Z")),
+        Some(utc("2026-07-10T01:00:00Z"))
+    );
+    // the time itself is not after it
+    assert_eq!(
+        nightly.next_after(utc("2026-07-10T01:00:00Z")),
+        Some(utc("2026-07-11T01:00:00Z"))
+    );
+
+    let weekdays = Schedule {
+        cron: "30 */6 * * 1-5".to_string(),
+        timezone: None,
+        branch: "dev".to_string(),
+    };
+    // 2026-01-10 is a saturday
+    assert_eq!(
+        weekdays.next_after(utc("2026-01-09T20:00:00Z")),
+        Some(utc("2026-01-12T00:30:00Z"))
+    );
+
+    for (cron, timezone, branch) in [
+        ("0 3 * *", "UTC", "dev"),
+        ("0 3 * * *", "Mars/Olympus", "dev"),
+        ("0 3 * * *", "UTC", "master"),
+    ] {
+        let yaml = format!(
+            r#"
+branches:
+  - name: dev
+    commands: [make build]
+schedules:
+  - {{ cron: "{}", timezone: {}, branch: {} }}
+"#,
+            cron, timezone, branch
+        );
+        assert_eq!(
+            DeployInfo::parse(&yaml),
+            Err(DeployInfoError::InvalidSchedule(cron.to_string()))
+        );
+    }
+}
+
+async fn test_due_schedules_run_the_branch_tip_without_overlapping() {
+    let utc = |value: &str| value.parse::<DateTime<Utc>>().unwrap();
+    let command_runner = Arc::new(FakeCommandRunner::with_duration(Duration::from_millis(200)));
+    let deploy_info = DeployInfo::parse(
+        r#"
+branches:
+  - name: dev
+    paths: [api/**]
+    commands: [docker-compose build --pull]
+schedules:
+  - { cron: "0 3 * * *", timezone: Europe/Berlin, branch: dev }
+"#,
+    )
+    .unwrap();
+    let (app_state, run_repository) = create_app_state(
+        AppStateOptions::with_deploy_info(deploy_info),
+        Arc::new(FakeGitOperations::new()),
+        command_runner.clone(),
+    );
+
+    let not_due =
+        trigger_due_schedules(&app_state, utc("2026-01-10T02:00:00Z"), utc("2026-01-10T02:15:00Z"))
+            .await;
+    assert_eq!(not_due, Some(vec![]));
+
+    let due =
+        trigger_due_schedules(&app_state, utc("2026-01-10T01:59:45Z"), utc("2026-01-10T02:00:00Z"))
+            .await;
+    assert_eq!(due, Some(vec![1]));
+
+    // the next tick comes while the first run is still going
+    let overlapping =
+        trigger_due_schedules(&app_state, utc("2026-01-11T01:59:45Z"), utc("2026-01-11T02:00:00Z"))
+            .await;
+    assert_eq!(overlapping, Some(vec![]));
+
+    assert!(
+        app_state
+            .deploy_tracker
+            .wait_for_running(Duration::from_secs(5))
+            .await
+    );
+    let next_day =
+        trigger_due_schedules(&app_state, utc("2026-01-11T01:59:45Z"), utc("2026-01-11T02:00:00Z"))
+            .await;
+    assert_eq!(next_day, Some(vec![2]));
+    assert!(
+        app_state
+            .deploy_tracker
+            .wait_for_running(Duration::from_secs(5))
+            .await
+    );
+
+    app_state.deploy_tracker.stop_accepting();
+    let stopped =
+        trigger_due_schedules(&app_state, utc("2026-01-12T01:59:45Z"), utc("2026-01-12T02:00:00Z"))
+            .await;
+    assert_eq!(stopped, None);
+
+    // no paths changed, the branch's path filter doesn't apply to scheduled runs
+    let run_repository = run_repository.lock().unwrap();
+    for run_id in [1, 2] {
+        let run = run_repository.get(run_id).unwrap();
+        assert_eq!(run.status, RunStatus::Succeeded);
+        assert_eq!(run.sha, BRANCH_TIP_SHA);
+        assert_eq!(
+            run.trigger,
+            RunTrigger::Schedule {
+                cron: "0 3 * * *".to_string()
+            }
+        );
+    }
+    assert_eq!(run_repository.get(3).map(|run| run.id), None);
+
+    let envs = command_runner.envs.lock().unwrap();
+    assert_eq!(envs[0]["MINI_CI_TRIGGER"], "schedule");
+    assert_eq!(envs[0]["MINI_CI_SHA"], BRANCH_TIP_SHA);
+    assert_eq!(envs[0]["MINI_CI_REPO"], "romqu/schimmelhof-api");
+
+}
+
+fn test_deploy_windows_open_on_their_days_and_hours() {
+    let utc = |value: &str| value.parse::<DateTime<Utc>>().unwrap();
+    // 2026-01-12 is a monday, Berlin is UTC+1 in winter
+    let office_hours = DeployWindow::parse("mon-thu 09:00-16:00 Europe/Berlin").unwrap();
+
+    assert!(office_hours.is_open(utc("2026-01-12T08:00:00Z")));
+    assert!(office_hours.is_open(utc("2026-01-15T14:59:00Z")));
+    assert!(!office_hours.is_open(utc("2026-01-12T07:59:00Z")));
+    assert!(!office_hours.is_open(utc("2026-01-12T15:00:00Z")));
+    assert!(!office_hours.is_open(utc("2026-01-16T10:00:00Z")));
+    assert_eq!(
+        office_hours.next_open(utc("2026-01-15T15:30:00Z")),
+        Some(utc("2026-01-19T08:00:00Z"))
+    );
+    assert_eq!(
+        office_hours.next_open(utc("2026-01-12T07:59:30Z")),
+        Some(utc("2026-01-12T08:00:00Z"))
+    );
+    assert_eq!(
+        office_hours.next_open(utc("2026-01-12T10:00:00Z")),
+        Some(utc("2026-01-12T10:00:00Z"))
+    );
+
+    // ranges wrap around the week, 24:00 ends the day
+    let weekend = DeployWindow::parse("fri-sun,tue 22:00-24:00").unwrap();
+    assert!(weekend.is_open(utc("2026-01-18T23:59:00Z")));
+    assert!(weekend.is_open(utc("2026-01-13T22:00:00Z")));
+    assert!(!weekend.is_open(utc("2026-01-12T23:00:00Z")));
+    assert!(DeployWindow::parse("* 00:00-24:00").unwrap().is_open(utc("2026-01-14T03:00:00Z")));
+
+    for spec in [
+        "mon-thu",
+        "mon-thu 16:00-09:00",
+        "mon-thu 9-16",
+        "mon-thu 09:00-16:00 Mars/Olympus",
+        "someday 09:00-16:00",
+        "mon 09:00-25:00",
+        "mon 09:75-16:00",
+        "mon 09:00-23:99",
+        "mon 09:00-24:30",
+        "mon 99999999:00-24:00",
+    ] {
+        assert_eq!(
+            DeployWindow::parse(spec),
+            Err(DeployInfoError::InvalidDeployWindow(spec.to_string()))
+        );
+    }
+
+    let deploy_info = DeployInfo::parse(
+        r#"
+branches:
+  - name: master
+    windows: ["mon-thu 09:00-16:00 Europe/Berlin"]
+    outside_window: reject
+    commands: [make deploy]
+"#,
+    )
+    .unwrap();
+    assert_eq!(
+        deploy_info.branches[0].windows,
+        Some(vec![office_hours.clone()])
+    );
+    assert_eq!(
+        serde_json::to_value(&office_hours).unwrap(),
+        serde_json::json!("mon-thu 09:00-16:00 Europe/Berlin")
+    );
+}
+
+async fn test_runs_outside_their_windows_are_held_or_rejected() {
+    let command_runner = Arc::new(FakeCommandRunner::new());
+    let closed_window = create_closed_window();
+    let dev_branch = Branch {
+        windows: Some(vec![closed_window.clone()]),
+        ..create_dev_branch()
+    };
+    let master_branch = Branch {
+        name: "master".to_string(),
+        windows: Some(vec![closed_window.clone()]),
+        outside_window: OutsideWindowPolicy::Reject,
+        ..create_dev_branch()
+    };
+    // without windows of its own the server's windows of its environment apply
+    let staging_branch = Branch {
+        name: "staging".to_string(),
+        environment: Some("prod".to_string()),
+        ..create_dev_branch()
+    };
+    let open_branch = Branch {
+        name: "feature".to_string(),
+        ..create_dev_branch()
+    };
+    let (app_state, run_repository) = create_app_state(
+        AppStateOptions {
+            deploy_info: DeployInfo {
+                branches: vec![dev_branch, master_branch, staging_branch, open_branch],
+                ..DeployInfo::default()
+            },
+            deploy_windows: HashMap::from([("prod".to_string(), vec![closed_window])]),
+        },
+        Arc::new(FakeGitOperations::new()),
+        command_runner.clone(),
+    );
+    let token = create_approver_store().add("erin").unwrap();
+
+    for branch_name in ["dev", "master", "staging", "feature"] {
+        let refs = format!("refs/heads/{}", branch_name);
+        assert_eq!(post_push_event(app_state.clone(), SSH_URL, &refs).await, StatusCode::OK);
+    }
+    assert!(wait_for_run_status(&run_repository, 1, RunStatus::Held).await);
+    assert!(wait_for_run_status(&run_repository, 2, RunStatus::Rejected).await);
+    assert!(wait_for_run_status(&run_repository, 3, RunStatus::Held).await);
+    assert!(wait_for_run_status(&run_repository, 4, RunStatus::Succeeded).await);
+
+    let override_request = |run_id: u64, reason: &str| {
+        test::TestRequest::post()
+            .uri(format!("/api/v1/runs/{}/override", run_id).as_str())
+            .set_json(OverrideDto {
+                reason: reason.to_string(),
+            })
+    };
+    let (status, _) =
+        send_approver_request(app_state.clone(), override_request(1, "hotfix"), None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) =
+        send_approver_request(app_state.clone(), override_request(1, " "), Some(&token)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) =
+        send_approver_request(app_state.clone(), override_request(2, "hotfix"), Some(&token))
+            .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    let (status, run) = send_approver_request(
+        app_state.clone(),
+        override_request(1, "payments are down"),
+        Some(&token),
+    )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(run["window_override"]["approver"], "erin");
+
+    assert!(wait_for_run_status(&run_repository, 1, RunStatus::Succeeded).await);
+    assert_eq!(
+        post_cancel_run(app_state.clone(), 3, Some(&token)).await,
+        StatusCode::ACCEPTED
+    );
+    assert!(
+        app_state
+            .deploy_tracker
+            .wait_for_running(Duration::from_secs(5))
+            .await
+    );
+
+    let audit_entries = create_audit_log().read().unwrap();
+    let override_entry = audit_entries
+        .iter()
+        .find(|entry| entry.details.contains("payments are down"))
+        .unwrap();
+    assert_eq!(override_entry.actor, "erin");
+    assert_eq!(override_entry.action, "override");
+
+    let run_repository = run_repository.lock().unwrap();
+    let dev_run = run_repository.get(1).unwrap();
+    assert_eq!(dev_run.hold_reason, None);
+    assert_eq!(dev_run.window_override.as_ref().unwrap().reason, "payments are down");
+    let master_run = run_repository.get(2).unwrap();
+    assert!(master_run
+        .hold_reason
+        .as_deref()
+        .unwrap()
+        .starts_with("outside the deploy windows of master"));
+    assert_eq!(master_run.steps[0].status, StepStatus::Skipped);
+    assert_eq!(run_repository.get(3).unwrap().status, RunStatus::Cancelled);
+    assert_eq!(
+        *command_runner.commands.lock().unwrap(),
+        vec![
+            "/tmp/schimmelhof-api.worktrees/feature:docker-compose build".to_string(),
+            "/tmp/schimmelhof-api.worktrees/feature:docker-compose up -d api".to_string(),
+            format!("{}:docker-compose build", DEV_WORKTREE_PATH),
+            format!("{}:docker-compose up -d api", DEV_WORKTREE_PATH),
+        ]
+    );
+}
+
+async fn test_freezes_hold_runs_until_they_are_removed() {
+    let command_runner = Arc::new(FakeCommandRunner::new());
+    let (app_state, run_repository) = create_app_state(
+        AppStateOptions::default(),
+        Arc::new(FakeGitOperations::new()),
+        command_runner.clone(),
+    );
+    let token = create_approver_store().add("frank").unwrap();
+    let freeze_request = |repo: &str, environment: &str, ends_in_secs: i64| {
+        test::TestRequest::post().uri(FREEZES_PATH).set_json(FreezeDto {
+            repo: Some(repo.to_string()),
+            environment: Some(environment.to_string()),
+            starts_at: None,
+            ends_at: Utc::now() + chrono::Duration::seconds(ends_in_secs),
+            reason: "release week".to_string(),
+        })
+    };
+
+    let (status, _) = send_approver_request(
+        app_state.clone(),
+        freeze_request("github.com/romqu/schimmelhof-api", "dev", 3600),
+        None,
+    )
+        .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = send_approver_request(
+        app_state.clone(),
+        freeze_request("github.com/romqu/schimmelhof-api", "dev", -60),
+        Some(&token),
+    )
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, freeze) = send_approver_request(
+        app_state.clone(),
+        freeze_request(SSH_URL, "dev", 3600),
+        Some(&token),
+    )
+        .await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(freeze["repo"], "github.com/romqu/schimmelhof-api");
+    assert_eq!(freeze["created_by"], "frank");
+    // a freeze of another environment doesn't matter
+    let (status, _) = send_approver_request(
+        app_state.clone(),
+        freeze_request(SSH_URL, "prod", 3600),
+        Some(&token),
+    )
+        .await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let (status, freezes) = send_approver_request(
+        app_state.clone(),
+        test::TestRequest::get().uri(FREEZES_PATH),
+        Some(&token),
+    )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(freezes.as_array().unwrap().len(), 2);
+
+    assert_eq!(post_push_event(app_state.clone(), SSH_URL, "refs/heads/dev").await, StatusCode::OK);
+    assert!(wait_for_run_status(&run_repository, 1, RunStatus::Held).await);
+    let hold_reason = run_repository.lock().unwrap().get(1).unwrap().hold_reason.clone();
+    assert!(hold_reason.unwrap().ends_with(": release week"));
+
+    let delete_request =
+        || test::TestRequest::delete().uri(format!("/api/v1/freezes/{}", freeze["id"]).as_str());
+    let (status, _) =
+        send_approver_request(app_state.clone(), delete_request(), Some(&token)).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) =
+        send_approver_request(app_state.clone(), delete_request(), Some(&token)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    assert!(
+        app_state
+            .deploy_tracker
+            .wait_for_running(Duration::from_secs(5))
+            .await
+    );
+    assert_eq!(run_repository.lock().unwrap().get(1).unwrap().status, RunStatus::Succeeded);
+    assert_eq!(command_runner.commands.lock().unwrap().len(), 2);
+
+    let audit_actions = create_audit_log()
+        .read()
+        .unwrap()
+        .into_iter()
+        .filter(|entry| entry.actor == "frank")
+        .map(|entry| entry.action)
+        .collect::<Vec<String>>();
+    assert_eq!(audit_actions, vec!["freeze", "freeze", "unfreeze"]);
+
+    // concurrent changes don't overwrite each other or reuse ids
+    let freezes_path =
+        std::env::temp_dir().join(format!("mini-ci-freezes-test-{}.json", std::process::id()));
+    let freeze_store = FreezeStore::new(freezes_path.to_str().unwrap().to_string());
+    std::thread::scope(|scope| {
+        for _ in 0..8 {
+            let freeze_store = &freeze_store;
+            scope.spawn(move || {
+                freeze_store
+                    .add(FreezeEntity {
+                        id: 0,
+                        repo: None,
+                        environment: None,
+                        starts_at: Utc::now(),
+                        ends_at: Utc::now() + chrono::Duration::seconds(3600),
+                        reason: "release week".to_string(),
+                        created_by: "frank".to_string(),
+                    })
+                    .unwrap()
+            });
+        }
+    });
+    let mut freeze_ids =
+        freeze_store.list().unwrap().iter().map(|freeze| freeze.id).collect::<Vec<u64>>();
+    freeze_ids.sort();
+    assert_eq!(freeze_ids, (1..=8).collect::<Vec<u64>>());
+    std::fs::remove_file(&freezes_path).unwrap();
+}
+
+async fn test_health_checks_retry_until_the_service_answers() {
+    let (url, requests) = serve_health_responses(vec![
+        (503, "down"),
+        (200, "starting"),
+        (200, "ready"),
+        (503, "down"),
+        (503, "down"),
+    ])
+    .await;
+    let health_check = HealthCheck {
+        url: url.clone(),
+        body_regex: Some("^ready$".to_string()),
+        retries: Some(3),
+        interval: Some(0),
+        ..HealthCheck::default()
+    };
+    let checker = HealthChecker::new();
+    let cancellation_token = CancellationToken::new();
+
+    let probe = HealthProbe::new(&health_check).unwrap();
+    assert_eq!(checker.check(&probe, &cancellation_token).await, Ok(3));
+
+    let probe = HealthProbe::new(&HealthCheck {
+        retries: Some(1),
+        body_regex: None,
+        ..health_check
+    })
+    .unwrap();
+    assert_eq!(
+        checker.check(&probe, &cancellation_token).await,
+        Err(HealthCheckError::Unhealthy {
+            attempts: 2,
+            reason: "status 503, expected 200".to_string(),
+        })
+    );
+    assert_eq!(requests.lock().unwrap().len(), 5);
+
+    assert_eq!(
+        DeployInfo::parse(
+            r#"
+branches:
+  - name: dev
+    commands: [make deploy]
+    health_check: { url: "http://localhost/health", body_regex: "(ready" }
+"#
+        ),
+        Err(DeployInfoError::InvalidHealthCheck("dev".to_string()))
+    );
+}
+
+async fn test_failed_health_check_fails_the_run_and_rolls_back() {
+    let (url, requests) = serve_health_responses(vec![(200, "ok"), (500, "down")]).await;
+    let git_operations = Arc::new(FakeGitOperations::new());
+    let command_runner = Arc::new(FakeCommandRunner::new());
+    let dev_branch = Branch {
+        health_check: Some(HealthCheck {
+            url: format!("{}?sha=${{MINI_CI_SHA}}", url),
+            retries: Some(1),
+            interval: Some(0),
+            ..HealthCheck::default()
+        }),
+        rollback: vec![Command::Plain("docker-compose up -d".to_string())],
+        ..create_dev_branch()
+    };
+    // the commit rolled back to brings its own rollback
+    git_operations.deploy_files.lock().unwrap().insert(
+        "1111111".to_string(),
+        "branches:\n  - name: dev\n    rollback: [docker-compose up -d --force-recreate]"
+            .to_string(),
+    );
+    let (app_state, run_repository) = create_app_state(
+        AppStateOptions::with_branches(vec![dev_branch]),
+        git_operations.clone(),
+        command_runner.clone(),
+    );
+
+    let push_event = create_push_event_dto("1111111");
+    assert_eq!(post_push_event_dto(app_state.clone(), push_event).await, StatusCode::OK);
+    assert!(wait_for_run_status(&run_repository, 1, RunStatus::Succeeded).await);
+    let push_event = create_push_event_dto("2222222");
+    assert_eq!(post_push_event_dto(app_state.clone(), push_event).await, StatusCode::OK);
+    assert!(wait_for_run_status(&run_repository, 2, RunStatus::Failed).await);
+
+    let run_repository = run_repository.lock().unwrap();
+    let first_run = run_repository.get(1).unwrap();
+    let health_check = first_run.health_check.as_ref().unwrap();
+    assert_eq!((health_check.status, health_check.attempts), (StepStatus::Succeeded, 1));
+    assert!(first_run.rollback.is_none());
+
+    let second_run = run_repository.get(2).unwrap();
+    let health_check = second_run.health_check.as_ref().unwrap();
+    assert_eq!((health_check.status, health_check.attempts), (StepStatus::Failed, 2));
+    assert_eq!(health_check.last_error.as_deref(), Some("status 500, expected 200"));
+    assert!(second_run.steps.iter().all(|step| step.status == StepStatus::Succeeded));
+
+    // the previous successful commit is checked out again and deployed
+    let rollback = second_run.rollback.as_ref().unwrap();
+    assert_eq!((rollback.sha.as_str(), rollback.status), ("1111111", StepStatus::Succeeded));
+    assert_eq!(rollback.steps.len(), 1);
+    assert_eq!(rollback.steps[0].job, "rollback");
+    assert_eq!(rollback.steps[0].status, StepStatus::Succeeded);
+    assert_eq!(
+        *git_operations.checked_out_shas.lock().unwrap(),
+        vec!["1111111", "2222222", "1111111"]
+    );
+    assert_eq!(
+        command_runner.commands.lock().unwrap().last().unwrap(),
+        &format!("{}:docker-compose up -d --force-recreate", DEV_WORKTREE_PATH)
+    );
+    assert_eq!(command_runner.envs.lock().unwrap().last().unwrap()["MINI_CI_SHA"], "1111111");
+    assert_eq!(
+        *requests.lock().unwrap(),
+        vec!["/health?sha=1111111", "/health?sha=2222222", "/health?sha=2222222"]
+    );
+}
+
+fn test_deploy_history_is_kept_across_restarts() {
+    let deploys_path =
+        std::env::temp_dir().join(format!("mini-ci-history-{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&deploys_path);
+    let create_repository = || {
+        DeployInfoRepository::new(
+            HashMap::new(),
+            DeployHistoryStore::new(deploys_path.to_str().unwrap().to_string()),
+        )
+    };
+    let deploy = |sha: &str, run_id: u64| DeployEntity {
+        sha: sha.to_string(),
+        run_id,
+        deployed_at: "2026-01-12T10:00:00Z".parse::<DateTime<Utc>>().unwrap(),
+    };
+
+    let mut repository = create_repository();
+    repository.load_deploys().unwrap();
+    repository.add_deploy(SSH_URL, "dev", deploy("1111111", 1)).unwrap();
+    repository.add_deploy(SSH_URL, "dev", deploy("2222222", 2)).unwrap();
+
+    assert_eq!(
+        std::fs::metadata(&deploys_path).unwrap().permissions().mode() & 0o777,
+        0o600
+    );
+
+    let mut restarted_repository = create_repository();
+    assert!(restarted_repository.get_deploys(SSH_URL, "dev").is_empty());
+    restarted_repository.load_deploys().unwrap();
+    assert_eq!(
+        restarted_repository.get_deploys(SSH_URL, "dev"),
+        vec![deploy("2222222", 2), deploy("1111111", 1)]
+    );
+
+    std::fs::write(&deploys_path, "not json").unwrap();
+    assert!(create_repository().load_deploys().is_err());
+    std::fs::remove_file(&deploys_path).unwrap();
+}
+
+async fn test_rollback_redeploys_a_deployed_commit_with_its_config() {
+    let git_operations = Arc::new(FakeGitOperations::new());
+    let command_runner = Arc::new(FakeCommandRunner::new());
+    git_operations.deploy_files.lock().unwrap().extend([
+        (
+            "1111111".to_string(),
+            "branches:\n  - name: dev\n    commands: [make deploy-v1]".to_string(),
+        ),
+        (
+            "2222222".to_string(),
+            "branches:\n  - name: dev\n    commands: [make deploy-v2]".to_string(),
+        ),
+        ("3333333".to_string(), "branches:\n  - name: master\n    commands: [make]".to_string()),
+    ]);
+    let (app_state, run_repository) = create_app_state(
+        AppStateOptions::default(),
+        git_operations.clone(),
+        command_runner.clone(),
+    );
+    let token = create_approver_store().add("frank").unwrap();
+
+    for (run_id, sha) in [(1, "1111111"), (2, "2222222"), (3, "3333333")] {
+        let push_event = create_push_event_dto(sha);
+        assert_eq!(post_push_event_dto(app_state.clone(), push_event).await, StatusCode::OK);
+        assert!(wait_for_run_status(&run_repository, run_id, RunStatus::Succeeded).await);
+    }
+
+    let deploys_uri = "/api/v1/repos/github.com/romqu/schimmelhof-api/branches/dev/deploys";
+    let deploys_request = || test::TestRequest::get().uri(deploys_uri);
+    let (status, _) = send_approver_request(app_state.clone(), deploys_request(), None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, deploys) =
+        send_approver_request(app_state.clone(), deploys_request(), Some(&token)).await;
+    assert_eq!(status, StatusCode::OK);
+    let deployed = |deploys: &serde_json::Value| {
+        deploys
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|deploy| (deploy["run_id"].as_u64().unwrap(), deploy["sha"].as_str().unwrap()))
+            .map(|(run_id, sha)| (run_id, sha.to_string()))
+            .collect::<Vec<(u64, String)>>()
+    };
+    assert_eq!(
+        deployed(&deploys),
+        vec![
+            (3, "3333333".to_string()),
+            (2, "2222222".to_string()),
+            (1, "1111111".to_string()),
+        ]
+    );
+
+    let rollback_request = |repo: &str, dto: Option<RollbackDto>| {
+        let request = test::TestRequest::post()
+            .uri(format!("/api/v1/repos/{}/branches/dev/rollback", repo).as_str());
+        match dto {
+            Some(dto) => request.set_json(dto),
+            None => request,
+        }
+    };
+    let repo = "github.com/romqu/schimmelhof-api";
+    let both = RollbackDto {
+        run_id: Some(1),
+        sha: Some("1111111".to_string()),
+    };
+    let unknown_sha = RollbackDto {
+        sha: Some("4444444".to_string()),
+        ..RollbackDto::default()
+    };
+    let (status, _) =
+        send_approver_request(app_state.clone(), rollback_request(repo, None), None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    for (repo, dto, expected_status) in [
+        (repo, Some(both), StatusCode::BAD_REQUEST),
+        (repo, Some(unknown_sha), StatusCode::NOT_FOUND),
+        ("github.com/romqu/unknown", None, StatusCode::NOT_FOUND),
+    ] {
+        let request = rollback_request(repo, dto);
+        let (status, _) = send_approver_request(app_state.clone(), request, Some(&token)).await;
+        assert_eq!(status, expected_status);
+    }
+
+    // the current commit's config has no dev branch anymore, the target's own config is used
+    let (status, run_created) =
+        send_approver_request(app_state.clone(), rollback_request(repo, None), Some(&token)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(run_created["run_id"], 4);
+    assert!(wait_for_run_status(&run_repository, 4, RunStatus::Succeeded).await);
+    {
+        let run_repository = run_repository.lock().unwrap();
+        let run = run_repository.get(4).unwrap();
+        assert_eq!(run.sha, "2222222");
+        assert_eq!(
+            run.trigger,
+            RunTrigger::Rollback {
+                approver: "frank".to_string(),
+                deploy_run_id: 2,
+            }
+        );
+    }
+    assert_eq!(
+        command_runner.commands.lock().unwrap().last().unwrap(),
+        &format!("{}:make deploy-v2", DEV_WORKTREE_PATH)
+    );
+    let env = command_runner.envs.lock().unwrap().last().unwrap().clone();
+    assert_eq!(env["MINI_CI_SHA"], "2222222");
+    assert_eq!(env["MINI_CI_TRIGGER"], "rollback");
+
+    let by_run = RollbackDto {
+        run_id: Some(1),
+        ..RollbackDto::default()
+    };
+    let (status, _) = send_approver_request(
+        app_state.clone(),
+        rollback_request(repo, Some(by_run)),
+        Some(&token),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(wait_for_run_status(&run_repository, 5, RunStatus::Succeeded).await);
+    assert_eq!(
+        command_runner.commands.lock().unwrap().last().unwrap(),
+        &format!("{}:make deploy-v1", DEV_WORKTREE_PATH)
+    );
+    assert_eq!(
+        *git_operations.checked_out_shas.lock().unwrap(),
+        vec!["1111111", "2222222", "3333333", "2222222", "1111111"]
+    );
+
+    let (_, deploys) =
+        send_approver_request(app_state.clone(), deploys_request(), Some(&token)).await;
+    assert_eq!(deployed(&deploys)[..2], [(5, "1111111".to_string()), (4, "2222222".to_string())]);
+    let audit_details = create_audit_log()
+        .read()
+        .unwrap()
+        .into_iter()
+        .filter(|entry| entry.action == "rollback")
+        .map(|entry| (entry.actor, entry.details))
+        .collect::<Vec<(String, String)>>();
+    assert_eq!(
+        audit_details,
+        vec![
+            ("frank".to_string(), format!("{} dev to 2222222 of run 2", SSH_URL)),
+            ("frank".to_string(), format!("{} dev to 1111111 of run 1", SSH_URL)),
+        ]
+    );
+}
+
+fn test_inputs_are_checked_against_their_types() {
+    let deploy_info = DeployInfo::parse(INPUTS_YAML).unwrap();
+    let resolve = |values: &[(&str, &str)]| {
+        deploy_info.resolve_inputs(
+            &values
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+        )
+    };
+
+    assert_eq!(deploy_info.inputs["verbose"].default.as_deref(), Some("false"));
+    assert_eq!(
+        resolve(&[("replicas", "3"), ("service", "web")]),
+        Ok(HashMap::from([
+            ("replicas".to_string(), "3".to_string()),
+            ("service".to_string(), "web".to_string()),
+            ("verbose".to_string(), "false".to_string()),
+        ]))
+    );
+    assert_eq!(resolve(&[]), Err(InputError::MissingInput("replicas".to_string())));
+    assert_eq!(
+        resolve(&[("replicas", "3"), ("service", "db")]),
+        Err(InputError::InvalidValue("service".to_string()))
+    );
+    assert_eq!(
+        resolve(&[("replicas", "three")]),
+        Err(InputError::InvalidValue("replicas".to_string()))
+    );
+    assert_eq!(
+        resolve(&[("replicas", "3"), ("verbose", "yes")]),
+        Err(InputError::InvalidValue("verbose".to_string()))
+    );
+    assert_eq!(
+        resolve(&[("replicas", "3"), ("region", "eu")]),
+        Err(InputError::UnknownInput("region".to_string()))
+    );
+    assert_eq!(Input::get_variable_name("log-level"), "MINI_CI_INPUT_LOG_LEVEL");
+
+    let parse_input = |input: &str| {
+        DeployInfo::parse(
+            format!("branches: []\ninputs:\n  service: {}", input).as_str(),
+        )
+    };
+    for input in ["{ type: choice }", "{ type: number, default: many }"] {
+        assert_eq!(parse_input(input), Err(DeployInfoError::InvalidInput("service".to_string())));
+    }
+}
+
+async fn test_manual_deploy_runs_a_branch_with_its_inputs() {
+    let git_operations = Arc::new(FakeGitOperations::new());
+    let command_runner = Arc::new(FakeCommandRunner::new());
+    let (app_state, run_repository) = create_app_state(
+        AppStateOptions::with_deploy_info(DeployInfo::parse(INPUTS_YAML).unwrap()),
+        git_operations.clone(),
+        command_runner.clone(),
+    );
+    let token = create_approver_store().add("grace").unwrap();
+    let deploy_request = |branch: &str, sha: Option<&str>, inputs: serde_json::Value| {
+        test::TestRequest::post()
+            .uri("/api/v1/repos/github.com/romqu/schimmelhof-api/deploy")
+            .set_json(DeployDto {
+                branch: branch.to_string(),
+                sha: sha.map(|sha| sha.to_string()),
+                inputs: serde_json::from_value(inputs).unwrap(),
+            })
+    };
+
+    let request = deploy_request("dev", None, serde_json::json!({ "replicas": 2 }));
+    let (status, _) = send_approver_request(app_state.clone(), request, None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    for (branch, inputs, expected_status) in [
+        ("master", serde_json::json!({ "replicas": 2 }), StatusCode::NOT_FOUND),
+        ("dev", serde_json::json!({}), StatusCode::BAD_REQUEST),
+        ("dev", serde_json::json!({ "replicas": 2, "service": "db" }), StatusCode::BAD_REQUEST),
+        ("dev", serde_json::json!({ "replicas": [2] }), StatusCode::BAD_REQUEST),
+    ] {
+        let request = deploy_request(branch, None, inputs);
+        let (status, _) = send_approver_request(app_state.clone(), request, Some(&token)).await;
+        assert_eq!(status, expected_status);
+    }
+    assert!(run_repository.lock().unwrap().get(1).is_none());
+
+    // without a sha the branch's latest commit runs, numbers and bools may be given as such
+    let inputs = serde_json::json!({ "replicas": 2, "service": "web", "verbose": true });
+    let request = deploy_request("dev", None, inputs);
+    let (status, run_created) =
+        send_approver_request(app_state.clone(), request, Some(&token)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(run_created["run_id"], 1);
+    assert!(wait_for_run_status(&run_repository, 1, RunStatus::Succeeded).await);
+    {
+        let run_repository = run_repository.lock().unwrap();
+        let run = run_repository.get(1).unwrap();
+        assert_eq!(run.sha, BRANCH_TIP_SHA);
+        assert_eq!(
+            run.trigger,
+            RunTrigger::Manual {
+                approver: "grace".to_string(),
+                inputs: HashMap::from([
+                    ("replicas".to_string(), "2".to_string()),
+                    ("service".to_string(), "web".to_string()),
+                    ("verbose".to_string(), "true".to_string()),
+                ]),
+            }
+        );
+    }
+    assert_eq!(
+        command_runner.commands.lock().unwrap().last().unwrap(),
+        &format!("{}:docker-compose up -d --scale {}", DEV_WORKTREE_PATH, SCALE_ARGUMENT)
+    );
+    let env = command_runner.envs.lock().unwrap().last().unwrap().clone();
+    assert_eq!(env["MINI_CI_TRIGGER"], "manual");
+    assert_eq!(env["MINI_CI_INPUT_SERVICE"], "web");
+    assert_eq!(env["MINI_CI_INPUT_REPLICAS"], "2");
+    assert_eq!(env["MINI_CI_INPUT_VERBOSE"], "true");
+
+    // only full shas of commits the branch has are run
+    let older_sha = "5555555555555555555555555555555555555555";
+    git_operations.branch_commits.lock().unwrap().push(older_sha.to_string());
+    for (sha, expected_status) in [
+        ("5555555", StatusCode::BAD_REQUEST),
+        ("zzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzz", StatusCode::BAD_REQUEST),
+        ("6666666666666666666666666666666666666666", StatusCode::NOT_FOUND),
+    ] {
+        let request = deploy_request("dev", Some(sha), serde_json::json!({ "replicas": "1" }));
+        let (status, _) = send_approver_request(app_state.clone(), request, Some(&token)).await;
+        assert_eq!(status, expected_status);
+    }
+    assert!(run_repository.lock().unwrap().get(2).is_none());
+
+    let request = deploy_request("dev", Some(older_sha), serde_json::json!({ "replicas": "1" }));
+    let (status, _) = send_approver_request(app_state.clone(), request, Some(&token)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(wait_for_run_status(&run_repository, 2, RunStatus::Succeeded).await);
+    assert_eq!(run_repository.lock().unwrap().get(2).unwrap().sha, older_sha);
+    let env = command_runner.envs.lock().unwrap().last().unwrap().clone();
+    assert_eq!(env["MINI_CI_INPUT_SERVICE"], "api");
+    assert_eq!(env["MINI_CI_INPUT_REPLICAS"], "1");
+    assert!(create_audit_log()
+        .read()
+        .unwrap()
+        .iter()
+        .any(|entry| entry.action == "deploy" && entry.details.contains("replicas=1")));
+}
+
+async fn test_commands_get_push_variables_and_env() {
+    let command_runner = Arc::new(FakeCommandRunner::new());
+    let deploy_info = serde_yaml::from_str::<DeployInfo>(
+        r#"
+env:
+  REGISTRY: registry.example.com
+  IMAGE: api
+branches:
+  - name: dev
+    env:
+      IMAGE: ${REGISTRY}/${IMAGE}-dev
+      TAG: ${MINI_CI_SHA}
+    commands:
+      - docker build -t ${IMAGE}:${TAG} .
+      - run: echo ${GREETING} from ${MINI_CI_PUSHER}, $HOME ${HOME} ${UNKNOWN}
+        env:
+          GREETING: hello
+"#,
+    )
+    .unwrap();
+    let (app_state, run_repository) = create_app_state(
+        AppStateOptions::with_deploy_info(deploy_info),
+        Arc::new(FakeGitOperations::new()),
+        command_runner.clone(),
+    );
+    let dto = GithubPushEventDto::default();
+    let post_dto = GithubPushEventDto {
+        ref_field: "refs/heads/dev".to_string(),
+        before: "1111111".to_string(),
+        after: "2222222".to_string(),
+        repository: Repository {
+            ssh_url: SSH_URL.to_string(),
+            full_name: "romqu/schimmelhof-api".to_string(),
+            ..dto.repository
+        },
+        pusher: Pusher {
+            name: "romqu".to_string(),
+            ..dto.pusher
+        },
+        head_commit: HeadCommit {
+            message: "Fix the build".to_string(),
+            ..dto.head_commit
+        },
+        ..dto
+    };
+
+    let status = post_push_event_dto(app_state.clone(), post_dto).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert!(
+        app_state
+            .deploy_tracker
+            .wait_for_running(Duration::from_secs(5))
+            .await
+    );
+    assert_eq!(
+        *command_runner.commands.lock().unwrap(),
+        vec![
+            format!("{}:docker build -t \"${{IMAGE}}\":\"${{TAG}}\" .", DEV_WORKTREE_PATH),
+            format!(
+                "{}:echo \"${{GREETING}}\" from \"${{MINI_CI_PUSHER}}\", {}",
+                DEV_WORKTREE_PATH, "$HOME ${HOME} ${UNKNOWN}"
+            ),
+        ]
+    );
+
+    let envs = command_runner.envs.lock().unwrap();
+    let expected_variables = [
+        ("MINI_CI_REPO", "romqu/schimmelhof-api"),
+        ("MINI_CI_BRANCH", "dev"),
+        ("MINI_CI_SHA", "2222222"),
+        ("MINI_CI_BEFORE_SHA", "1111111"),
+        ("MINI_CI_PUSHER", "romqu"),
+        ("MINI_CI_COMMIT_MESSAGE", "Fix the build"),
+        ("MINI_CI_RUN_ID", "1"),
+        ("MINI_CI_TRIGGER", "push"),
+        ("IMAGE", "registry.example.com/api-dev"),
+        ("TAG", "2222222"),
+    ];
+    for (name, value) in expected_variables {
+        assert_eq!(envs[0].get(name).map(|value| value.as_str()), Some(value));
+    }
+    assert_eq!(envs[0].get("GREETING"), None);
+    assert_eq!(envs[1].get("GREETING").map(|value| value.as_str()), Some("hello"));
+
+    // the run shows the commands as configured
+    let run_repository = run_repository.lock().unwrap();
+    assert_eq!(
+        run_repository.get(1).unwrap().steps[0].command,
+        "docker build -t ${IMAGE}:${TAG} ."
+    );
+}
+
+async fn test_command_runner_passes_env_to_commands() {
+    let output_file =
+        std::env::temp_dir().join(format!("mini-ci-test-{}.env", std::process::id()));
+    let command = format!("echo \"$MINI_CI_BRANCH\" > {}", output_file.display());
+    let command_runner = TokioCommandRunner::new(Duration::from_secs(1));
+
+    let result = command_runner
+        .run(
+            command.as_str(),
+            "/tmp",
+            &HashMap::from([("MINI_CI_BRANCH".to_string(), "dev branch".to_string())]),
+            &SecretMasker::default(),
+            Duration::from_secs(5),
+            &CancellationToken::new(),
+        )
+        .await;
+
+    assert!(result.is_ok());
+    assert_eq!(std::fs::read_to_string(&output_file).unwrap(), "dev branch\n");
+    std::fs::remove_file(&output_file).unwrap();
+}
+
+async fn test_command_runner_keeps_its_own_environment_from_commands() {
+    let output_file =
+        std::env::temp_dir().join(format!("mini-ci-test-{}.vars", std::process::id()));
+    let command = format!("env > {}", output_file.display());
+    std::env::set_var("MINI_CI_SECRETS_KEY", "server secrets key");
+    std::env::set_var("MINI_CI_SSH_PRIVATE_KEY", "server ssh key");
+    std::env::set_var("HTTPS_PROXY", "http://proxy.example.com:3128");
+
+    let result = TokioCommandRunner::new(Duration::from_secs(1))
+        .run(
+            command.as_str(),
+            "/tmp",
+            &HashMap::from([("MINI_CI_BRANCH".to_string(), "dev".to_string())]),
+            &SecretMasker::default(),
+            Duration::from_secs(5),
+            &CancellationToken::new(),
+        )
+        .await;
+    std::env::remove_var("MINI_CI_SECRETS_KEY");
+    std::env::remove_var("MINI_CI_SSH_PRIVATE_KEY");
+    std::env::remove_var("HTTPS_PROXY");
+
+    assert!(result.is_ok());
+    let variables = std::fs::read_to_string(&output_file).unwrap();
+    assert!(!variables.contains("server secrets key"));
+    assert!(!variables.contains("server ssh key"));
+    assert!(variables.contains("MINI_CI_BRANCH=dev"));
+    assert!(variables.lines().any(|line| line.starts_with("PATH=")));
+    // everything else of the server's environment is kept, like proxies and docker settings
+    assert!(variables.contains("HTTPS_PROXY=http://proxy.example.com:3128"));
+    std::fs::remove_file(&output_file).unwrap();
+}
+
+async fn test_variables_are_not_parsed_as_part_of_commands() {
+    let test_dir_path =
+        std::env::temp_dir().join(format!("mini-ci-injection-{}", std::process::id()));
+    std::fs::create_dir_all(&test_dir_path).unwrap();
+    let marker_path = test_dir_path.join("injected");
+    let output_path = test_dir_path.join("output");
+    let message = format!(
+        "Fix \"it\" $(touch {0}); touch {0} `touch {0}` 'done' \\",
+        marker_path.display()
+    );
+    let deploy_env = DeployEnv::new(
+        HashMap::from([("MINI_CI_COMMIT_MESSAGE".to_string(), message.clone())]),
+        HashMap::new(),
+    );
+    let command = format!(
+        "printf '%s|' ${{MINI_CI_COMMIT_MESSAGE}} \"${{MINI_CI_COMMIT_MESSAGE}}\" \
+         '${{MINI_CI_COMMIT_MESSAGE}}' > {}",
+        output_path.display()
+    );
+    let command = deploy_env.interpolate_command(command.as_str());
+
+    assert!(!command.contains("touch"));
+
+    let result = TokioCommandRunner::new(Duration::from_secs(1))
+        .run(
+            command.as_str(),
+            test_dir_path.to_str().unwrap(),
+            deploy_env.get_variables(),
+            &SecretMasker::default(),
+            Duration::from_secs(5),
+            &CancellationToken::new(),
+        )
+        .await;
+
+    assert!(result.is_ok());
+    assert!(!marker_path.exists());
+    assert_eq!(std::fs::read_to_string(&output_path).unwrap(), format!("{0}|{0}|{0}|", message));
+    std::fs::remove_dir_all(&test_dir_path).unwrap();
+}
+
+fn test_secrets_are_encrypted_and_scoped_per_environment() {
+    let secrets_path = std::env::temp_dir().join(format!("mini-ci-store-{}", std::process::id()));
+    let root_dir = secrets_path.to_str().unwrap().to_string();
+    let secret_store = SecretStore::new(root_dir.clone(), &[1; 32]).unwrap();
+    let repo_path = "github.com/romqu/schimmelhof-api";
+
+    secret_store.set(repo_path, None, "DB_PASSWORD", "shared password").unwrap();
+    secret_store.set(repo_path, Some("production"), "DB_PASSWORD", "prod password").unwrap();
+    secret_store.set(repo_path, Some("production"), "API_TOKEN", "prod token").unwrap();
+
+    let secrets_file_path = secrets_path.join(repo_path).join("secrets.json");
+    let secrets_file = std::fs::read_to_string(&secrets_file_path).unwrap();
+    assert!(!secrets_file.contains("password"));
+    assert_eq!(
+        std::fs::metadata(&secrets_file_path).unwrap().permissions().mode() & 0o777,
+        0o600
+    );
+    assert_eq!(
+        secret_store.list(repo_path).unwrap(),
+        vec![
+            ("*".to_string(), "DB_PASSWORD".to_string()),
+            ("production".to_string(), "API_TOKEN".to_string()),
+            ("production".to_string(), "DB_PASSWORD".to_string()),
+        ]
+    );
+    assert_eq!(
+        secret_store.get_secrets(repo_path, "production").unwrap(),
+        HashMap::from([
+            ("DB_PASSWORD".to_string(), "prod password".to_string()),
+            ("API_TOKEN".to_string(), "prod token".to_string()),
+        ])
+    );
+    assert_eq!(
+        secret_store.get_secrets(repo_path, "staging").unwrap(),
+        HashMap::from([("DB_PASSWORD".to_string(), "shared password".to_string())])
+    );
+    assert_eq!(
+        SecretStore::new(root_dir.clone(), &[2; 32])
+            .unwrap()
+            .get_secrets(repo_path, "staging"),
+        Err(SecretStoreError::CouldNotDecryptSecret)
+    );
+
+    // a value moved to another environment doesn't decrypt
+    let moved_secrets_file = serde_json::from_str::<serde_json::Value>(&secrets_file)
+        .map(|mut secrets_file| {
+            let production = secrets_file["environments"]["production"].clone();
+            secrets_file["environments"]["staging"] = production;
+            secrets_file.to_string()
+        })
+        .unwrap();
+    std::fs::write(&secrets_file_path, moved_secrets_file).unwrap();
+    assert_eq!(
+        secret_store.get_secrets(repo_path, "staging"),
+        Err(SecretStoreError::CouldNotDecryptSecret)
+    );
+
+    assert_eq!(secret_store.remove(repo_path, Some("staging"), "API_TOKEN"), Ok(true));
+    assert_eq!(secret_store.remove(repo_path, Some("staging"), "API_TOKEN"), Ok(false));
+    assert_eq!(SecretStore::new(root_dir, &[1; 16]).err(), Some(SecretStoreError::InvalidKey));
+
+    std::fs::remove_dir_all(&secrets_path).unwrap();
+}
+
+async fn test_secrets_are_injected_as_env_and_files() {
+    let repo_path = "github.com/romqu/schimmelhof-api";
+    let secret_store = create_secret_store();
+    secret_store.set(repo_path, None, "REGISTRY_PASSWORD", "registry password").unwrap();
+    secret_store.set(repo_path, Some("production"), "TLS_KEY", "prod key").unwrap();
+    secret_store.set(repo_path, Some("staging"), "TLS_KEY", "staging key").unwrap();
+
+    let command_runner = Arc::new(FakeCommandRunner::new());
+    let deploy_info = serde_yaml::from_str::<DeployInfo>(
+        r#"
+env:
+  REGISTRY_PASSWORD: ${{ secrets.REGISTRY_PASSWORD }}
+branches:
+  - name: dev
+    environment: production
+    commands:
+      - docker login -p "$REGISTRY_PASSWORD"
+      - run: docker-compose up -d api
+        files:
+          TLS_KEY_FILE: ${{secrets.TLS_KEY}}
+"#,
+    )
+    .unwrap();
+    let (app_state, _) = create_app_state(
+        AppStateOptions::with_deploy_info(deploy_info),
+        Arc::new(FakeGitOperations::new()),
+        command_runner.clone(),
+    );
+
+    let status = post_push_event(app_state.clone(), SSH_URL, "refs/heads/dev").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert!(
+        app_state
+            .deploy_tracker
+            .wait_for_running(Duration::from_secs(5))
+            .await
+    );
+    let envs = command_runner.envs.lock().unwrap();
+    assert_eq!(
+        envs[0].get("REGISTRY_PASSWORD").map(|value| value.as_str()),
+        Some("registry password")
+    );
+    assert_eq!(envs[0].get("TLS_KEY_FILE"), None);
+    assert_eq!(
+        *command_runner.files.lock().unwrap(),
+        HashMap::from([("TLS_KEY_FILE".to_string(), "prod key".to_string())])
+    );
+    // the files are gone once their step is done
+    assert!(!std::path::Path::new(envs[1].get("TLS_KEY_FILE").unwrap()).exists());
+
+    let secrets_path =
+        std::env::temp_dir().join(format!("mini-ci-secrets-{}", std::process::id()));
+    std::fs::remove_dir_all(secrets_path).unwrap();
+}
+
+async fn test_push_with_missing_secret_is_rejected() {
+    let command_runner = Arc::new(FakeCommandRunner::new());
+    let branch = Branch {
+        name: "dev".to_string(),
+        commands: vec![Command::Plain("echo ${{ secrets.MISSING }}".to_string())],
+        ..Branch::default()
+    };
+    let (app_state, run_repository) = create_app_state(
+        AppStateOptions::with_branches(vec![branch]),
+        Arc::new(FakeGitOperations::new()),
+        command_runner.clone(),
+    );
+
+    let status = post_push_event(app_state.clone(), SSH_URL, "refs/heads/dev").await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(run_repository.lock().unwrap().get(1).is_none());
+    assert!(command_runner.commands.lock().unwrap().is_empty());
+}
+
+fn test_secret_masker_masks_values_and_their_encodings() {
+    let secret_masker = SecretMasker::new(vec![
+        "hunter22".to_string(),
+        "p@ss word/1".to_string(),
+        "-----BEGIN KEY-----\nc2VjcmV0\n-----END KEY-----".to_string(),
+        "abc".to_string(),
+    ]);
+
+    assert_eq!(secret_masker.mask("password=hunter22;"), "password=***;");
+    assert_eq!(secret_masker.mask(STANDARD.encode("hunter22").as_str()), "***");
+    assert!(!secret_masker
+        .mask(format!("Authorization: Basic {}", STANDARD.encode("admin:hunter22")).as_str())
+        .contains(&STANDARD.encode("admin:hunter22")[4..12]));
+    assert_eq!(secret_masker.mask("?password=p%40ss%20word%2F1&"), "?password=***&");
+    assert_eq!(secret_masker.mask("password=p%40ss+word%2F1"), "password=***");
+    // short values are masked, only their encodings would mask too much
+    assert_eq!(secret_masker.mask("pin=abc"), "pin=***");
+    assert_eq!(SecretMasker::new(vec!["ab".to_string()]).mask("grab it"), "gr*** it");
+    assert_eq!(SecretMasker::new(vec!["".to_string()]).mask("text"), "text");
+
+    let mut masking_stream = secret_masker.create_stream();
+    let lines = [
+        "writing key",
+        "key: -----BEGIN KEY-----",
+        "c2VjcmV0",
+        "-----END KEY----- done",
+        "one",
+        "two",
+        "three",
+    ];
+    let mut masked_lines = lines
+        .iter()
+        .flat_map(|line| masking_stream.push_line(line))
+        .collect::<Vec<String>>();
+    masked_lines.extend(masking_stream.finish());
+
+    assert_eq!(masked_lines, vec!["writing key", "key: *** done", "one", "two", "three"]);
+}
 
-use untitled::entrypoint::github_push_event_dto::{GithubPushEventDto, Repository};
-use untitled::entrypoint::post_github_push_event_handler::handle_post_github_push_event;
-use untitled::init_app;
+async fn test_secrets_are_masked_in_run_records() {
+    create_secret_store()
+        .set("github.com/romqu/schimmelhof-api", None, "TOKEN", "deploy-token-123")
+        .unwrap();
+
+    let command_runner = Arc::new(FakeCommandRunner::new());
+    let branch = Branch {
+        name: "dev".to_string(),
+        commands: vec![
+            Command::Plain("export TOKEN=${{ secrets.TOKEN }}".to_string()),
+            // somebody pasted the value itself
+            Command::Plain("echo deploy-token-123".to_string()),
+        ],
+        ..Branch::default()
+    };
+    let (app_state, run_repository) = create_app_state(
+        AppStateOptions::with_branches(vec![branch]),
+        Arc::new(FakeGitOperations::new()),
+        command_runner.clone(),
+    );
+
+    let status = post_push_event(app_state.clone(), SSH_URL, "refs/heads/dev").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert!(
+        app_state
+            .deploy_tracker
+            .wait_for_running(Duration::from_secs(5))
+            .await
+    );
+    let run_repository = run_repository.lock().unwrap();
+    let steps = &run_repository.get(1).unwrap().steps;
+    assert_eq!(steps[0].command, "export TOKEN=${{ secrets.TOKEN }}");
+    assert_eq!(steps[1].command, "echo ***");
+    assert_eq!(
+        *command_runner.masked_commands.lock().unwrap(),
+        vec!["export TOKEN=\"${MINI_CI_SECRET_TOKEN}\"", "echo ***"]
+    );
+    // the secret is only in the env, never in the command bash gets as an argument
+    assert_eq!(
+        command_runner.commands.lock().unwrap()[0],
+        format!("{}:export TOKEN=\"${{MINI_CI_SECRET_TOKEN}}\"", DEV_WORKTREE_PATH)
+    );
+    assert_eq!(
+        command_runner.envs.lock().unwrap()[0]["MINI_CI_SECRET_TOKEN"],
+        "deploy-token-123"
+    );
+
+    let secrets_path =
+        std::env::temp_dir().join(format!("mini-ci-secrets-{}", std::process::id()));
+    std::fs::remove_dir_all(secrets_path).unwrap();
+}
+
+fn test_git2_finds_the_commits_of_a_branch() {
+    let test_dir_path =
+        std::env::temp_dir().join(format!("mini-ci-branch-commits-{}", std::process::id()));
+    let origin_path = test_dir_path.join("origin.git");
+    let origin = git2::Repository::init(&origin_path).unwrap();
+    let master_sha = commit_file(&origin, "master", "master 1");
+    let first_dev_sha = commit_file(&origin, "dev", "dev 1");
+    origin.set_head("refs/heads/master").unwrap();
+
+    let git_operations = create_git2_operations();
+    let repo_path = git_operations
+        .clone_repo(
+            format!("file://{}", origin_path.to_str().unwrap()).as_str(),
+            test_dir_path.to_str().unwrap(),
+            &CloneOptions::default(),
+        )
+        .unwrap();
+    let second_dev_sha = commit_file(&origin, "dev", "dev 2");
+    let is_on_dev = |sha: &str| {
+        git_operations
+            .is_on_branch(&repo_path, "dev", sha, &CloneOptions::default())
+            .unwrap()
+    };
+
+    // the newer commit is only known after the fetch
+    assert!(is_on_dev(&second_dev_sha));
+    assert!(is_on_dev(&first_dev_sha));
+    assert!(!is_on_dev(&master_sha));
+    assert!(!is_on_dev("6666666666666666666666666666666666666666"));
+
+    std::fs::remove_dir_all(&test_dir_path).unwrap();
+}
+
+fn test_git2_worktrees_are_isolated_per_branch() {
+    let test_dir_path =
+        std::env::temp_dir().join(format!("mini-ci-worktrees-{}", std::process::id()));
+    let origin_path = test_dir_path.join("origin.git");
+    let origin = git2::Repository::init(&origin_path).unwrap();
+    commit_file(&origin, "master", "master 1");
+    commit_file(&origin, "dev", "dev 1");
+    origin.set_head("refs/heads/master").unwrap();
+
+    let git_operations = create_git2_operations();
+    let repo_path = git_operations
+        .clone_repo(
+            format!("file://{}", origin_path.to_str().unwrap()).as_str(),
+            test_dir_path.to_str().unwrap(),
+            &CloneOptions::default(),
+        )
+        .unwrap();
+    let prepare_worktree = |branch_name: &str, sha: &str| {
+        git_operations
+            .prepare_worktree(&repo_path, branch_name, sha, &CloneOptions::default())
+            .unwrap()
+    };
+
+    let dev_path = prepare_worktree("dev", "");
+    let master_path = prepare_worktree("master", "");
+
+    assert_ne!(dev_path, master_path);
+    assert!(dev_path.ends_with("/origin"));
+    assert_eq!(read_branch_file(&dev_path), "dev 1");
+    assert_eq!(read_branch_file(&master_path), "master 1");
+
+    let sha = commit_file(&origin, "dev", "dev 2");
+    let reused_dev_path = prepare_worktree("dev", &sha);
+
+    assert_eq!(reused_dev_path, dev_path);
+    assert_eq!(read_branch_file(&dev_path), "dev 2");
+    assert_eq!(read_branch_file(&master_path), "master 1");
+
+    let removed_paths = git_operations
+        .remove_stale_worktrees(&repo_path, Duration::ZERO, &["master".to_string()])
+        .unwrap();
+
+    assert_eq!(removed_paths.len(), 1);
+    assert!(!std::path::Path::new(&dev_path).exists());
+    assert!(std::path::Path::new(&master_path).exists());
+
+    std::fs::remove_dir_all(&test_dir_path).unwrap();
+}
+
+fn test_existing_clone_is_reused_and_invalid_clone_recloned() {
+    let test_dir_path = std::env::temp_dir().join(format!("mini-ci-clones-{}", std::process::id()));
+    let workspace_root = test_dir_path.join("workspace");
+    let origin_path = test_dir_path.join("origin.git");
+    let origin = git2::Repository::init(&origin_path).unwrap();
+    commit_file(&origin, "master", "master 1");
+    origin.set_head("refs/heads/master").unwrap();
+
+    let git_operations = create_git2_operations();
+    let url = format!("file://{}", origin_path.to_str().unwrap());
+    let workspace_root = workspace_root.to_str().unwrap();
+    let clone_repo =
+        || git_operations.clone_repo(&url, workspace_root, &CloneOptions::default()).unwrap();
+
+    let repo_path = clone_repo();
+    let marker_path = std::path::Path::new(&repo_path).join("untracked.txt");
+    std::fs::write(&marker_path, "kept").unwrap();
+    std::fs::write(std::path::Path::new(&repo_path).join("branch.txt"), "changed").unwrap();
+    commit_file(&origin, "master", "master 2");
+
+    assert_eq!(clone_repo(), repo_path);
+    assert!(marker_path.exists());
+    assert_eq!(read_branch_file(&repo_path), "master 2");
+
+    git2::Repository::open(&repo_path)
+        .unwrap()
+        .remote_set_url("origin", "git@github.com:someone/else.git")
+        .unwrap();
+
+    assert_eq!(clone_repo(), repo_path);
+    assert!(!marker_path.exists());
+
+    std::fs::write(&marker_path, "kept").unwrap();
+    std::fs::remove_file(std::path::Path::new(&repo_path).join(".git").join("HEAD")).unwrap();
+
+    clone_repo();
+    assert!(!marker_path.exists());
+    assert_eq!(read_branch_file(&repo_path), "master 2");
+
+    std::fs::remove_dir_all(&test_dir_path).unwrap();
+}
+
+fn test_clone_options_fall_back_to_server_defaults() {
+    let deploy_info = serde_yaml::from_str::<DeployInfo>(
+        "clone:\n  depth: 5\n  sparse_paths: [api]\nbranches: []\n",
+    )
+    .unwrap();
+    let defaults = CloneOptions {
+        depth: Some(1),
+        sparse_paths: vec!["web".to_string()],
+        ..CloneOptions::default()
+    };
+    let clone_options = deploy_info.clone.with_defaults(&defaults);
+
+    assert_eq!(clone_options.depth, Some(5));
+    assert_eq!(clone_options.sparse_paths, vec!["api".to_string()]);
+    assert_eq!(
+        serde_yaml::from_str::<DeployInfo>("branches: []").unwrap().clone,
+        CloneOptions::default()
+    );
+    // a depth of 0 unshallows
+    let full = CloneOptions {
+        depth: Some(0),
+        ..CloneOptions::default()
+    };
+
+    assert_eq!(full.get_fetch_depth(true), i32::MAX);
+    assert_eq!(CloneOptions::default().get_fetch_depth(true), 0);
+    assert_eq!(clone_options.get_deepen_depths(), vec![40, 320, 2560, 4096, i32::MAX]);
+    assert_eq!(clone_options.get_bounded_deepen_depths(), vec![40, 320, 2560, 4096]);
+}
+
+fn test_sparse_paths_limit_what_is_checked_out() {
+    let test_dir_path = std::env::temp_dir().join(format!("mini-ci-sparse-{}", std::process::id()));
+    let origin_path = test_dir_path.join("origin");
+    let origin = git2::Repository::init(&origin_path).unwrap();
+    commit_files(&origin, &[("api/main.txt", "api 1"), ("assets/big.bin", "big")]);
+
+    let git_operations = create_git2_operations();
+    let sparse_options = CloneOptions {
+        sparse_paths: vec!["api".to_string()],
+        ..CloneOptions::default()
+    };
+    let repo_path = git_operations
+        .clone_repo(
+            format!("file://{}", origin_path.to_str().unwrap()).as_str(),
+            test_dir_path.join("workspace").to_str().unwrap(),
+            &sparse_options,
+        )
+        .unwrap();
+    let repo_path = std::path::Path::new(&repo_path);
+
+    assert!(repo_path.join("api/main.txt").exists());
+    assert!(!repo_path.join("assets").exists());
+
+    let sha = commit_files(&origin, &[("api/main.txt", "api 2"), ("assets/big.bin", "bigger")]);
+    let worktree_path = git_operations
+        .prepare_worktree(repo_path.to_str().unwrap(), "master", &sha, &sparse_options)
+        .unwrap();
+    let worktree_path = std::path::Path::new(&worktree_path);
+
+    assert_eq!(std::fs::read_to_string(worktree_path.join("api/main.txt")).unwrap(), "api 2");
+    assert!(!worktree_path.join("assets").exists());
+
+    // without sparse paths the worktree is recreated with everything in it
+    git_operations
+        .prepare_worktree(repo_path.to_str().unwrap(), "master", &sha, &CloneOptions::default())
+        .unwrap();
+
+    assert_eq!(std::fs::read_to_string(worktree_path.join("assets/big.bin")).unwrap(), "bigger");
+
+    std::fs::remove_dir_all(&test_dir_path).unwrap();
+}
+
+fn test_git2_changed_files_are_diffed_between_commits() {
+    let test_dir_path =
+        std::env::temp_dir().join(format!("mini-ci-changed-{}", std::process::id()));
+    let origin_path = test_dir_path.join("origin");
+    let origin = git2::Repository::init(&origin_path).unwrap();
+    let before_sha = commit_files(&origin, &[("api/main.rs", "1"), ("docs/setup.md", "1")]);
+
+    let git_operations = create_git2_operations();
+    let repo_path = git_operations
+        .clone_repo(
+            format!("file://{}", origin_path.to_str().unwrap()).as_str(),
+            test_dir_path.join("workspace").to_str().unwrap(),
+            &CloneOptions::default(),
+        )
+        .unwrap();
+
+    commit_files(&origin, &[("api/main.rs", "2")]);
+    let sha = commit_files(&origin, &[("web/index.html", "1")]);
+    git_operations
+        .prepare_worktree(&repo_path, "master", &sha, &CloneOptions::default())
+        .unwrap();
+
+    assert_eq!(
+        git_operations
+            .get_changed_files(&repo_path, &before_sha, &sha, &CloneOptions::default())
+            .unwrap(),
+        vec!["api/main.rs".to_string(), "web/index.html".to_string()]
+    );
+    // a push that created the branch has nothing to diff against
+    assert!(git_operations
+        .get_changed_files(&repo_path, &"0".repeat(40), &sha, &CloneOptions::default())
+        .is_err());
+
+    std::fs::remove_dir_all(&test_dir_path).unwrap();
+}
+
+// libgit2 can't make shallow clones of local repos, so origin is served by git daemon
+fn test_shallow_clone_is_deepened_for_older_commits() {
+    let test_dir_path =
+        std::env::temp_dir().join(format!("mini-ci-shallow-{}", std::process::id()));
+    let base_path = test_dir_path.join("daemon");
+    let origin = git2::Repository::init_bare(base_path.join("romqu/deep.git")).unwrap();
+    let first_sha = commit_file(&origin, "master", "master 1");
+
+    for index in 2..=10 {
+        commit_file(&origin, "master", format!("master {}", index).as_str());
+    }
+
+    origin.set_head("refs/heads/master").unwrap();
+
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let daemon = std::process::Command::new("git")
+        .arg("daemon")
+        .arg("--export-all")
+        .arg("--reuseaddr")
+        .arg("--listen=127.0.0.1")
+        .arg(format!("--port={}", port))
+        .arg(format!("--base-path={}", base_path.to_str().unwrap()))
+        .arg(base_path.to_str().unwrap())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn();
+    // killed on drop, a failed assertion would leave it running otherwise
+    let _daemon = match daemon {
+        Ok(daemon) => KillOnDrop(daemon),
+        Err(_) => {
+            println!("git daemon is not available, skipping the shallow clone test");
+            std::fs::remove_dir_all(&test_dir_path).unwrap();
+            return;
+        }
+    };
+
+    for _ in 0..50 {
+        if std::net::TcpStream::connect(("127.0.0.1", port)).is_ok() {
+            break;
+        }
+
+        std::thread::sleep(Duration::from_millis(100));
+    }
+
+    let git_operations = create_git2_operations();
+    let clone_options = CloneOptions {
+        depth: Some(1),
+        ..CloneOptions::default()
+    };
+    let repo_path = git_operations
+        .clone_repo(
+            format!("git://127.0.0.1:{}/romqu/deep.git", port).as_str(),
+            test_dir_path.join("workspace").to_str().unwrap(),
+            &clone_options,
+        )
+        .unwrap();
+    let repository = git2::Repository::open(&repo_path).unwrap();
+
+    assert!(repository.is_shallow());
+    assert!(repository.find_commit(git2::Oid::from_str(&first_sha).unwrap()).is_err());
+
+    // the before sha of a force push is gone from the remote, the diff gives up instead
+    let master_sha = repository.refname_to_id("refs/remotes/origin/master").unwrap().to_string();
+    assert!(git_operations
+        .get_changed_files(&repo_path, &"6".repeat(40), &master_sha, &clone_options)
+        .is_err());
+    // nor does a deploy of an unknown sha unshallow the clone to find it
+    assert!(!git_operations
+        .is_on_branch(&repo_path, "master", &"6".repeat(40), &clone_options)
+        .unwrap());
+
+    let worktree_path = git_operations
+        .prepare_worktree(&repo_path, "master", &first_sha, &clone_options)
+        .unwrap();
+
+    assert_eq!(read_branch_file(&worktree_path), "master 1");
+
+    std::fs::remove_dir_all(&test_dir_path).unwrap();
+}
+
+struct KillOnDrop(std::process::Child);
+
+impl Drop for KillOnDrop {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn test_submodules_are_checked_out_when_enabled() {
+    let test_dir_path =
+        std::env::temp_dir().join(format!("mini-ci-submodules-{}", std::process::id()));
+    let library = git2::Repository::init_bare(test_dir_path.join("origin/romqu/library.git"))
+        .unwrap();
+    let library_sha = commit_file(&library, "master", "library 1");
+    library.set_head("refs/heads/master").unwrap();
+
+    let app = git2::Repository::init_bare(test_dir_path.join("origin/romqu/app.git")).unwrap();
+    let signature = git2::Signature::now("mini-ci", "mini-ci@example.com").unwrap();
+    let gitmodules = format!(
+        "[submodule \"library\"]\n\tpath = library\n\turl = file://{}\n",
+        test_dir_path.join("origin/romqu/library.git").to_str().unwrap()
+    );
+    let mut tree_builder = app.treebuilder(None).unwrap();
+    tree_builder
+        .insert(".gitmodules", app.blob(gitmodules.as_bytes()).unwrap(), 0o100644)
+        .unwrap();
+    tree_builder
+        .insert("library", git2::Oid::from_str(&library_sha).unwrap(), 0o160000)
+        .unwrap();
+    let tree = app.find_tree(tree_builder.write().unwrap()).unwrap();
+    app.commit(Some("refs/heads/master"), &signature, &signature, "app", &tree, &[])
+        .unwrap();
+    app.set_head("refs/heads/master").unwrap();
+
+    let git_operations = create_git2_operations();
+    let repo_path = git_operations
+        .clone_repo(
+            format!("file://{}", test_dir_path.join("origin/romqu/app.git").to_str().unwrap())
+                .as_str(),
+            test_dir_path.join("workspace").to_str().unwrap(),
+            &CloneOptions::default(),
+        )
+        .unwrap();
+    let without_submodules = git_operations
+        .prepare_worktree(&repo_path, "master", "", &CloneOptions::default())
+        .unwrap();
+
+    assert!(!std::path::Path::new(&without_submodules).join("library/branch.txt").exists());
+
+    let with_submodules = CloneOptions {
+        submodules: true,
+        ..CloneOptions::default()
+    };
+    let worktree_path = git_operations
+        .prepare_worktree(&repo_path, "master", "", &with_submodules)
+        .unwrap();
+
+    assert_eq!(
+        read_branch_file(std::path::Path::new(&worktree_path).join("library").to_str().unwrap()),
+        "library 1"
+    );
+
+    std::fs::remove_dir_all(&test_dir_path).unwrap();
+}
+
+async fn test_lfs_files_are_downloaded_once_when_enabled() {
+    let test_dir_path = std::env::temp_dir().join(format!("mini-ci-lfs-{}", std::process::id()));
+    let content = "a big binary file";
+    let oid = format!("{:x}", Sha256::digest(content.as_bytes()));
+    let downloads = Arc::new(AtomicUsize::new(0));
+
+    let server_downloads = downloads.clone();
+    let server_oid = oid.clone();
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = actix_web::HttpServer::new(move || {
+        let downloads = server_downloads.clone();
+        let oid = server_oid.clone();
+
+        App::new()
+            .route(
+                "/lfs/objects/batch",
+                web::post().to(move || {
+                    let oid = oid.clone();
+
+                    async move {
+                        web::Json(serde_json::json!({
+                            "objects": [{
+                                "oid": oid,
+                                "size": content.len(),
+                                "actions": {
+                                    "download": {
+                                        "href": format!("http://127.0.0.1:{}/objects/{}", port, oid)
+                                    }
+                                }
+                            }]
+                        }))
+                    }
+                }),
+            )
+            .route(
+                "/objects/{oid}",
+                web::get().to(move || {
+                    downloads.fetch_add(1, Ordering::SeqCst);
+                    async move { content }
+                }),
+            )
+    })
+    .listen(listener)
+    .unwrap()
+    .run();
+    let server_handle = server.handle();
+    actix_web::rt::spawn(server);
+
+    let origin_path = test_dir_path.join("origin");
+    let origin = git2::Repository::init(&origin_path).unwrap();
+    commit_files(
+        &origin,
+        &[
+            (".lfsconfig", format!("[lfs]\n\turl = http://127.0.0.1:{}/lfs\n", port).as_str()),
+            (
+                "assets/big.bin",
+                format!(
+                    "version https://git-lfs.github.com/spec/v1\noid sha256:{}\nsize {}\n",
+                    oid,
+                    content.len()
+                )
+                .as_str(),
+            ),
+        ],
+    );
+
+    let git_operations = Arc::new(create_git2_operations());
+    let repo_path = git_operations
+        .clone_repo(
+            format!("file://{}", origin_path.to_str().unwrap()).as_str(),
+            test_dir_path.join("workspace").to_str().unwrap(),
+            &CloneOptions::default(),
+        )
+        .unwrap();
+    let with_lfs = CloneOptions {
+        lfs: true,
+        ..CloneOptions::default()
+    };
+
+    // the checkout with a reused worktree must not download the object again
+    for _ in 0..2 {
+        let git_operations = git_operations.clone();
+        let repo_path = repo_path.clone();
+        let with_lfs = with_lfs.clone();
+        let worktree_path = tokio::task::spawn_blocking(move || {
+            git_operations.prepare_worktree(&repo_path, "master", "", &with_lfs)
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(std::path::Path::new(&worktree_path).join("assets/big.bin"))
+                .unwrap(),
+            content
+        );
+    }
+
+    assert_eq!(downloads.load(Ordering::SeqCst), 1);
+
+    server_handle.stop(true).await;
+    std::fs::remove_dir_all(&test_dir_path).unwrap();
+}
+
+fn test_repo_urls_are_parsed_into_host_owner_and_name() {
+    let parse = |url: &str| RepoUrl::parse(url).map(|repo_url| repo_url.relative_path());
+
+    assert_eq!(
+        parse("git@github.com:romqu/my.repo.git"),
+        Ok("github.com/romqu/my.repo".to_string())
+    );
+    assert_eq!(
+        parse("ssh://git@github.com:22/alice/api.git"),
+        Ok("github.com/alice/api".to_string())
+    );
+    assert_eq!(
+        parse("https://token@GitHub.com/bob/api/"),
+        Ok("github.com/bob/api".to_string())
+    );
+    assert_eq!(
+        parse("https://gitlab.com/group/subgroup/api.git"),
+        Ok("gitlab.com/group/subgroup/api".to_string())
+    );
+    assert_eq!(
+        parse("file:///srv/git/api.git"),
+        Ok("local/srv/git/api".to_string())
+    );
+    assert_eq!(parse("git@github.com:api.git"), Err(RepoUrlError::MissingOwner));
+    assert_eq!(parse("git@github.com:alice/../api.git"), Err(RepoUrlError::InvalidPathSegment));
+    assert_eq!(parse("ftp://github.com/alice/api.git"), Err(RepoUrlError::UnsupportedScheme));
+    assert_eq!(parse("/srv/git/api.git"), Err(RepoUrlError::UnsupportedScheme));
+
+    assert_eq!(RepoUrl::get_ssh_port("ssh://git@git.example.com:2222/alice/api.git"), 2222);
+    assert_eq!(RepoUrl::get_ssh_port("ssh://git.example.com/alice/api.git"), 22);
+    assert_eq!(RepoUrl::get_ssh_port("git@git.example.com:alice/api.git"), 22);
+}
+
+fn test_git_credentials_follow_allowed_types_and_repo_overrides() {
+    let token = GitCredentials::HttpsToken {
+        token: "token".to_string(),
+    };
+    let mut repo_credentials = HashMap::new();
+    repo_credentials.insert("github.com/romqu/api".to_string(), token.clone());
+    let git_auth = GitAuth::new(GitCredentials::SshAgent, repo_credentials);
+    let repo_url = RepoUrl::parse(SSH_URL).unwrap();
+
+    assert_eq!(
+        git_auth.get_credentials("https://github.com/romqu/api.git").get_method(),
+        GitAuthMethod::HttpsToken
+    );
+    assert_eq!(
+        git_auth.get_credentials("git@github.com:romqu/api.git").get_method(),
+        GitAuthMethod::HttpsToken
+    );
+    assert_eq!(
+        git_auth.get_credentials(SSH_URL).get_method(),
+        GitAuthMethod::SshAgent
+    );
+    assert_eq!(
+        token.get_remote_url(&repo_url, SSH_URL),
+        "https://github.com/romqu/schimmelhof-api.git"
+    );
+    assert_eq!(GitCredentials::SshAgent.get_remote_url(&repo_url, SSH_URL), SSH_URL);
+
+    assert!(token
+        .create_cred(None, CredentialType::USER_PASS_PLAINTEXT)
+        .is_ok());
+    assert!(token.create_cred(Some("git"), CredentialType::SSH_KEY).is_err());
+    assert!(GitCredentials::SshAgent
+        .create_cred(None, CredentialType::USERNAME)
+        .is_ok());
+    assert!(GitCredentials::SshAgent
+        .create_cred(Some("git"), CredentialType::USER_PASS_PLAINTEXT)
+        .is_err());
+    assert_eq!(GitAuthMethod::from_str("github-app"), Ok(GitAuthMethod::GithubApp));
+}
+
+fn test_host_keys_are_checked_against_known_hosts() {
+    let test_dir_path =
+        std::env::temp_dir().join(format!("mini-ci-host-keys-{}", std::process::id()));
+    let known_hosts_path = test_dir_path.join("known_hosts").to_string_lossy().to_string();
+    let key = b"first key".to_vec();
+    let other_key = b"other key".to_vec();
+
+    let trusting_verifier = HostKeyVerifier::new(
+        known_hosts_path.clone(),
+        HostKeyPolicy::TrustOnFirstUse,
+        HashMap::new(),
+    );
+
+    assert_eq!(trusting_verifier.verify("git.example.com", 22, "ssh-ed25519", &key), Ok(()));
+    assert_eq!(trusting_verifier.verify("git.example.com", 22, "ssh-ed25519", &key), Ok(()));
+    assert_eq!(
+        trusting_verifier.verify("git.example.com", 22, "ssh-ed25519", &other_key),
+        Err(HostKeyError::HostKeyMismatch)
+    );
+    assert_eq!(
+        trusting_verifier.verify("github.com", 22, "ssh-ed25519", &key),
+        Err(HostKeyError::HostKeyMismatch)
+    );
+
+    let strict_verifier =
+        HostKeyVerifier::new(known_hosts_path.clone(), HostKeyPolicy::Strict, HashMap::new());
+
+    assert_eq!(strict_verifier.verify("git.example.com", 22, "ssh-ed25519", &key), Ok(()));
+    assert_eq!(
+        strict_verifier.verify("other.example.com", 22, "ssh-ed25519", &key),
+        Err(HostKeyError::UnknownHost)
+    );
+
+    // entries written with HashKnownHosts
+    let salt = b"0123456789abcdefghij";
+    let mut mac = Hmac::<Sha1>::new_from_slice(salt).unwrap();
+    mac.update(b"hashed.example.com");
+    let hashed_line = format!(
+        "|1|{}|{} ssh-ed25519 {}\n",
+        STANDARD.encode(salt),
+        STANDARD.encode(mac.finalize().into_bytes()),
+        STANDARD.encode(&other_key)
+    );
+    let mut known_hosts = std::fs::read_to_string(&known_hosts_path).unwrap();
+    known_hosts.push_str(hashed_line.as_str());
+    std::fs::write(&known_hosts_path, known_hosts).unwrap();
+
+    assert_eq!(
+        strict_verifier.verify("hashed.example.com", 22, "ssh-ed25519", &other_key),
+        Ok(())
+    );
+    assert_eq!(
+        strict_verifier.verify("hashed.example.com", 22, "ssh-ed25519", &key),
+        Err(HostKeyError::HostKeyMismatch)
+    );
+
+    // entries of other ports only match connections to their port, revoked keys never match and
+    // certificate authorities aren't host keys
+    let revoked_key = b"revoked key".to_vec();
+    let mut known_hosts = std::fs::read_to_string(&known_hosts_path).unwrap();
+    known_hosts.push_str(&format!(
+        "[ported.example.com]:2222 ssh-ed25519 {0}\n\
+         @revoked revoked.example.com ssh-ed25519 {1}\n\
+         @cert-authority ca.example.com ssh-ed25519 {0}\n",
+        STANDARD.encode(&key),
+        STANDARD.encode(&revoked_key)
+    ));
+    std::fs::write(&known_hosts_path, known_hosts).unwrap();
+
+    assert_eq!(strict_verifier.verify("ported.example.com", 2222, "ssh-ed25519", &key), Ok(()));
+    assert_eq!(
+        strict_verifier.verify("ported.example.com", 22, "ssh-ed25519", &key),
+        Err(HostKeyError::UnknownHost)
+    );
+    assert_eq!(
+        strict_verifier.verify("git.example.com", 2222, "ssh-ed25519", &key),
+        Err(HostKeyError::UnknownHost)
+    );
+    assert_eq!(
+        strict_verifier.verify("ca.example.com", 22, "ssh-ed25519", &key),
+        Err(HostKeyError::UnknownHost)
+    );
+    assert_eq!(
+        trusting_verifier.verify("revoked.example.com", 22, "ssh-ed25519", &revoked_key),
+        Err(HostKeyError::HostKeyMismatch)
+    );
+    assert_eq!(
+        trusting_verifier.verify("new.example.com", 2222, "ssh-ed25519", &key),
+        Ok(())
+    );
+    assert!(std::fs::read_to_string(&known_hosts_path)
+        .unwrap()
+        .contains("[new.example.com]:2222 ssh-ed25519"));
+    assert!(!std::fs::read_to_string(&known_hosts_path)
+        .unwrap()
+        .contains("\nrevoked.example.com"));
+
+    let mut pinned_fingerprints = HashMap::new();
+    pinned_fingerprints.insert(
+        "github.com".to_string(),
+        vec![HostKeyVerifier::get_fingerprint(&key)],
+    );
+    let pinned_verifier =
+        HostKeyVerifier::new(known_hosts_path, HostKeyPolicy::Strict, pinned_fingerprints);
+
+    assert_eq!(pinned_verifier.verify("github.com", 22, "ssh-ed25519", &key), Ok(()));
+
+    std::fs::remove_dir_all(&test_dir_path).unwrap();
+}
+
+async fn test_deploy_keys_are_provisioned_once_per_repo() {
+    let key_dir_path = std::env::temp_dir()
+        .join(format!("mini-ci-deploy-keys-{}", std::process::id()))
+        .to_string_lossy()
+        .to_string();
+    let _ = std::fs::remove_dir_all(&key_dir_path);
+    let mut repo_credentials = HashMap::new();
+    repo_credentials.insert(
+        "github.com/romqu/api".to_string(),
+        GitCredentials::HttpsToken {
+            token: "token".to_string(),
+        },
+    );
+    let git_auth = Arc::new(GitAuth::new(GitCredentials::SshAgent, repo_credentials));
+    let github_client = Arc::new(FakeGithubClient::new(vec![
+        create_deploy_key_dto(1, "mini-ci", "ssh-ed25519 AAAAold mini-ci@old"),
+        create_deploy_key_dto(2, "laptop", "ssh-ed25519 AAAAlaptop"),
+    ]));
+    let deploy_key_service = DeployKeyService::new(
+        github_client.clone(),
+        DeployKeyStore::new(key_dir_path.clone()),
+        git_auth.clone(),
+    );
+    let private_key_path = format!("{}/github.com/romqu/schimmelhof-api/id_ed25519", key_dir_path);
+
+    deploy_key_service
+        .provision("romqu", "schimmelhof-api", SSH_URL)
+        .await
+        .unwrap();
+
+    let created_keys = github_client.created_keys.lock().unwrap().clone();
+    assert_eq!(created_keys.len(), 1);
+    assert!(created_keys[0].read_only);
+    assert!(created_keys[0].key.starts_with("ssh-ed25519 "));
+    // the old mini-ci key is replaced, keys added by someone else stay
+    assert_eq!(*github_client.deleted_key_ids.lock().unwrap(), vec![1]);
+    assert_eq!(
+        std::fs::metadata(&private_key_path).unwrap().permissions().mode() & 0o777,
+        0o600
+    );
+    assert!(matches!(
+        git_auth.get_credentials(SSH_URL),
+        GitCredentials::SshKey { key_path, .. } if key_path == private_key_path
+    ));
+
+    // a restart reuses the stored key as long as it is still registered
+    deploy_key_service
+        .provision("romqu", "schimmelhof-api", SSH_URL)
+        .await
+        .unwrap();
+
+    assert_eq!(github_client.created_keys.lock().unwrap().len(), 1);
+
+    // a key removed on github is replaced by a new one
+    github_client.registered_keys.lock().unwrap().retain(|key| key.title != "mini-ci");
+    deploy_key_service
+        .provision("romqu", "schimmelhof-api", SSH_URL)
+        .await
+        .unwrap();
+
+    let created_keys = github_client.created_keys.lock().unwrap().clone();
+    assert_eq!(created_keys.len(), 2);
+    assert_ne!(created_keys[0].key, created_keys[1].key);
+
+    // explicitly configured credentials win
+    deploy_key_service
+        .provision("romqu", "api", "git@github.com:romqu/api.git")
+        .await
+        .unwrap();
+
+    assert_eq!(github_client.created_keys.lock().unwrap().len(), 2);
+    assert_eq!(
+        git_auth.get_credentials("git@github.com:romqu/api.git").get_method(),
+        GitAuthMethod::HttpsToken
+    );
 
-fn main() {
-    block_on(test());
+    std::fs::remove_dir_all(&key_dir_path).unwrap();
 }
 
-async fn test() {
-    init_app().await;
+fn create_deploy_key_dto(id: i64, title: &str, key: &str) -> GithubDeployKeyDto {
+    GithubDeployKeyDto {
+        id,
+        key: key.to_string(),
+        title: title.to_string(),
+        read_only: true,
+    }
+}
+
+fn create_git2_operations() -> Git2Operations {
+    let host_key_verifier = HostKeyVerifier::new(
+        std::env::temp_dir()
+            .join(format!("mini-ci-known-hosts-{}", std::process::id()))
+            .to_string_lossy()
+            .to_string(),
+        HostKeyPolicy::Strict,
+        HashMap::new(),
+    );
+
+    Git2Operations::new(
+        CloneRepoTask::new(Arc::new(host_key_verifier)),
+        Arc::new(GitAuth::new(GitCredentials::SshAgent, HashMap::new())),
+        LfsTask::new(LfsRepository::new(), None),
+    )
+}
+
+fn commit_file(repository: &git2::Repository, branch_name: &str, content: &str) -> String {
+    let signature = git2::Signature::now("mini-ci", "mini-ci@example.com").unwrap();
+    let blob = repository.blob(content.as_bytes()).unwrap();
+    let mut tree_builder = repository.treebuilder(None).unwrap();
+    tree_builder.insert("branch.txt", blob, 0o100644).unwrap();
+    let tree = repository.find_tree(tree_builder.write().unwrap()).unwrap();
+    let reference = format!("refs/heads/{}", branch_name);
+    let parent = repository
+        .find_reference(&reference)
+        .and_then(|reference| reference.peel_to_commit())
+        .ok();
+    let parents = parent.iter().collect::<Vec<&git2::Commit>>();
+
+    repository
+        .commit(Some(&reference), &signature, &signature, content, &tree, &parents)
+        .unwrap()
+        .to_string()
+}
+
+// commits the files into the working dir's HEAD branch, with directories
+fn commit_files(repository: &git2::Repository, files: &[(&str, &str)]) -> String {
+    let workdir = repository.workdir().unwrap();
+
+    for (path, content) in files {
+        let file_path = workdir.join(path);
+        std::fs::create_dir_all(file_path.parent().unwrap()).unwrap();
+        std::fs::write(file_path, content).unwrap();
+    }
+
+    let signature = git2::Signature::now("mini-ci", "mini-ci@example.com").unwrap();
+    let mut index = repository.index().unwrap();
+    index.add_all(["*"].iter(), git2::IndexAddOption::DEFAULT, None).unwrap();
+    index.write().unwrap();
+    let tree = repository.find_tree(index.write_tree().unwrap()).unwrap();
+    let parent = repository.head().and_then(|head| head.peel_to_commit()).ok();
+    let parents = parent.iter().collect::<Vec<&git2::Commit>>();
+
+    repository
+        .commit(Some("HEAD"), &signature, &signature, "files", &tree, &parents)
+        .unwrap()
+        .to_string()
+}
+
+fn read_branch_file(worktree_path: &str) -> String {
+    std::fs::read_to_string(std::path::Path::new(worktree_path).join("branch.txt")).unwrap()
+}
+
+fn create_dev_branch() -> Branch {
+    Branch {
+        name: "dev".to_string(),
+        timeout: Some(600),
+        commands: vec![
+            Command::Plain("docker-compose build".to_string()),
+            Command::Step(Step {
+                run: "docker-compose up -d api".to_string(),
+                timeout: Some(60),
+                ..Step::default()
+            }),
+        ],
+        ..Branch::default()
+    }
+}
+
+// what the app state of a test deploys, the dev branch unless set
+struct AppStateOptions {
+    deploy_info: DeployInfo,
+    deploy_windows: HashMap<String, Vec<DeployWindow>>,
+}
+
+impl AppStateOptions {
+    fn with_branches(branches: Vec<Branch>) -> AppStateOptions {
+        AppStateOptions::with_deploy_info(DeployInfo {
+            branches,
+            ..DeployInfo::default()
+        })
+    }
+
+    fn with_deploy_info(deploy_info: DeployInfo) -> AppStateOptions {
+        AppStateOptions {
+            deploy_info,
+            deploy_windows: HashMap::new(),
+        }
+    }
+}
+
+impl Default for AppStateOptions {
+    fn default() -> Self {
+        AppStateOptions::with_branches(vec![create_dev_branch()])
+    }
+}
+
+fn create_app_state(
+    options: AppStateOptions,
+    git_operations: Arc<FakeGitOperations>,
+    command_runner: Arc<FakeCommandRunner>,
+) -> (web::Data<AppState>, Arc<Mutex<RunRepository>>) {
+    let store_count = STORE_COUNT.fetch_add(1, Ordering::SeqCst);
+    let freezes_path = get_stores_dir().join(format!("freezes-{}.json", store_count));
+    let deploys_path = get_stores_dir().join(format!("deploys-{}.json", store_count));
+    let mut cache = HashMap::new();
+    cache.insert(
+        SSH_URL.to_string(),
+        DeployInfoEntity {
+            ssh_git_url: SSH_URL.to_string(),
+            repo_path: REPO_PATH.to_string(),
+            deploy_info: options.deploy_info,
+        },
+    );
+    let deploy_info_repository = Arc::new(Mutex::new(DeployInfoRepository::new(
+        cache,
+        DeployHistoryStore::new(deploys_path.to_str().unwrap().to_string()),
+    )));
+    let run_repository = Arc::new(Mutex::new(RunRepository::new()));
+    let deploy_service = DeployService::new(
+        deploy_info_repository,
+        run_repository.clone(),
+        git_operations,
+        command_runner,
+        Arc::new(BlockingPool::new(2)),
+        DeployExecutor::new(2, tokio::runtime::Handle::current()),
+        DeployServiceConfig {
+            default_step_timeout: Duration::from_secs(3600),
+            worktree_retention: Duration::from_secs(3600),
+            default_clone_options: CloneOptions::default(),
+            secret_store: Arc::new(create_secret_store()),
+            approver_store: Arc::new(create_approver_store()),
+            freeze_store: Arc::new(FreezeStore::new(freezes_path.to_str().unwrap().to_string())),
+            audit_log: Arc::new(create_audit_log()),
+            deploy_windows: options.deploy_windows,
+        },
+    );
+
+    (
+        web::Data::new(AppState::new(deploy_service, DeployTracker::new())),
+        run_repository,
+    )
+}
+
+// the approvers, audit log, freezes and deploys of the app states, removed once the tests ran
+fn get_stores_dir() -> std::path::PathBuf {
+    let stores_dir =
+        std::env::temp_dir().join(format!("mini-ci-stores-{}", std::process::id()));
+    std::fs::create_dir_all(&stores_dir).unwrap();
+
+    stores_dir
+}
+
+// like the secret store, stores on the same file see each other's approvers
+fn create_approver_store() -> ApproverStore {
+    let approvers_path = get_stores_dir().join("approvers.json");
 
+    ApproverStore::new(approvers_path.to_str().unwrap().to_string())
+}
+
+fn create_audit_log() -> AuditLog {
+    let audit_log_path = get_stores_dir().join("audit.log");
+
+    AuditLog::new(audit_log_path.to_str().unwrap().to_string())
+}
+
+// a window that's closed today and tomorrow, whatever day the tests run on
+fn create_closed_window() -> DeployWindow {
+    let day = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"]
+        [(Utc::now().weekday().num_days_from_monday() as usize + 3) % 7];
+
+    DeployWindow::parse(format!("{} 00:00-24:00", day).as_str()).unwrap()
+}
+
+// waits until the run has the status, returns false if it didn't get it within 5 seconds
+async fn wait_for_run_status(
+    run_repository: &Mutex<RunRepository>,
+    run_id: u64,
+    status: RunStatus,
+) -> bool {
+    for _ in 0..250 {
+        if run_repository.lock().unwrap().get(run_id).map(|run| run.status) == Some(status) {
+            return true;
+        }
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    false
+}
+
+// answers health checks with the responses in order, repeating the last one. Returns the url
+// of /health and the paths of the requests it got
+async fn serve_health_responses(
+    responses: Vec<(u16, &'static str)>,
+) -> (String, Arc<Mutex<Vec<String>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/health", listener.local_addr().unwrap());
+    let requests = Arc::new(Mutex::new(vec![]));
+    let served_requests = requests.clone();
+
+    tokio::spawn(async move {
+        for index in 0.. {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = vec![];
+            let mut buffer = [0; 1024];
+
+            while !request.ends_with(b"\r\n\r\n") {
+                match stream.read(&mut buffer).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(read) => request.extend_from_slice(&buffer[..read]),
+                }
+            }
+
+            let request = String::from_utf8_lossy(&request).to_string();
+            let path = request.split(' ').nth(1).unwrap_or_default().to_string();
+            served_requests.lock().unwrap().push(path);
+
+            let (status, body) = responses[index.min(responses.len() - 1)];
+            let response = format!(
+                "HTTP/1.1 {} X\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            );
+            let _ = stream.write_all(response.as_bytes()).await;
+        }
+    });
+
+    (url, requests)
+}
+
+// the store reads its file on every access, stores on the same dir see each other's secrets
+fn create_secret_store() -> SecretStore {
+    let secrets_path =
+        std::env::temp_dir().join(format!("mini-ci-secrets-{}", std::process::id()));
+
+    SecretStore::new(secrets_path.to_str().unwrap().to_string(), &[7; 32]).unwrap()
+}
+
+async fn post_push_event(app_state: web::Data<AppState>, ssh_url: &str, refs: &str) -> StatusCode {
     let dto = GithubPushEventDto::default();
     let post_dto = GithubPushEventDto {
-        ref_field: "refs/heads/mvp".to_string(),
+        ref_field: refs.to_string(),
         repository: Repository {
-            ssh_url: "git@github.com:romqu/schimmelhof-api.git".to_string(),
+            ssh_url: ssh_url.to_string(),
             ..dto.repository
         },
         ..dto
     };
 
-    let path = "/api/v1/events/push";
-    let mut app =
-        test::init_service(App::new().route(path, web::post().to(handle_post_github_push_event)))
-            .await;
+    post_push_event_dto(app_state, post_dto).await
+}
+
+// a push of the sha to dev
+fn create_push_event_dto(sha: &str) -> GithubPushEventDto {
+    let dto = GithubPushEventDto::default();
+
+    GithubPushEventDto {
+        ref_field: "refs/heads/dev".to_string(),
+        after: sha.to_string(),
+        repository: Repository {
+            ssh_url: SSH_URL.to_string(),
+            ..dto.repository
+        },
+        ..dto
+    }
+}
+
+async fn post_push_event_dto(
+    app_state: web::Data<AppState>,
+    post_dto: GithubPushEventDto,
+) -> StatusCode {
+    let app = test::init_service(
+        App::new()
+            .app_data(app_state)
+            .route(PUSH_EVENT_PATH, web::post().to(handle_post_github_push_event)),
+    )
+        .await;
     let req = test::TestRequest::post()
         .set_json(&post_dto)
-        .uri(path)
+        .uri(PUSH_EVENT_PATH)
         .to_request();
-    let resp = test::call_service(&mut app, req).await;
 
-    assert!(resp.status().is_success());
+    test::call_service(&app, req).await.status()
+}
+
+async fn post_push_event_with_message(app_state: web::Data<AppState>, message: &str) -> StatusCode {
+    let dto = GithubPushEventDto::default();
+    let post_dto = GithubPushEventDto {
+        ref_field: "refs/heads/dev".to_string(),
+        repository: Repository {
+            ssh_url: SSH_URL.to_string(),
+            ..dto.repository
+        },
+        head_commit: HeadCommit {
+            message: message.to_string(),
+            ..dto.head_commit
+        },
+        ..dto
+    };
+
+    post_push_event_dto(app_state, post_dto).await
+}
+
+async fn post_run_decision(
+    app_state: web::Data<AppState>,
+    run_id: u64,
+    decision: ApprovalDecision,
+    token: Option<&str>,
+    job: Option<&str>,
+) -> StatusCode {
+    let app = test::init_service(
+        App::new()
+            .app_data(app_state)
+            .route(APPROVE_RUN_PATH, web::post().to(handle_post_approve_run))
+            .route(REJECT_RUN_PATH, web::post().to(handle_post_reject_run)),
+    )
+        .await;
+    let action = match decision {
+        ApprovalDecision::Approved => "approve",
+        _ => "reject",
+    };
+    let mut req = test::TestRequest::post()
+        .uri(format!("/api/v1/runs/{}/{}", run_id, action).as_str())
+        .set_json(ApprovalDto {
+            job: job.map(|job| job.to_string()),
+        });
+
+    if let Some(token) = token {
+        req = req.insert_header(("Authorization", format!("Bearer {}", token)));
+    }
+
+    test::call_service(&app, req.to_request()).await.status()
+}
+
+// sends the json, if any, with the approver's token, if any
+async fn send_approver_request(
+    app_state: web::Data<AppState>,
+    req: test::TestRequest,
+    token: Option<&str>,
+) -> (StatusCode, serde_json::Value) {
+    let app = test::init_service(
+        App::new()
+            .app_data(app_state)
+            .route(OVERRIDE_RUN_PATH, web::post().to(handle_post_override_run))
+            .route(FREEZES_PATH, web::get().to(handle_get_freezes))
+            .route(FREEZES_PATH, web::post().to(handle_post_freeze))
+            .route(FREEZE_PATH, web::delete().to(handle_delete_freeze))
+            .route(DEPLOYS_PATH, web::get().to(handle_get_deploys))
+            .route(ROLLBACK_PATH, web::post().to(handle_post_rollback))
+            .route(DEPLOY_PATH, web::post().to(handle_post_deploy)),
+    )
+        .await;
+    let req = match token {
+        Some(token) => req.insert_header(("Authorization", format!("Bearer {}", token))),
+        None => req,
+    };
+
+    let response = test::call_service(&app, req.to_request()).await;
+    let status = response.status();
+    let body = test::read_body(response).await;
+
+    (status, serde_json::from_slice(&body).unwrap_or(serde_json::Value::Null))
+}
+
+async fn post_cancel_run(
+    app_state: web::Data<AppState>,
+    run_id: u64,
+    token: Option<&str>,
+) -> StatusCode {
+    let app = test::init_service(
+        App::new()
+            .app_data(app_state)
+            .route(CANCEL_RUN_PATH, web::post().to(handle_post_cancel_run)),
+    )
+        .await;
+    let req = test::TestRequest::post().uri(format!("/api/v1/runs/{}/cancel", run_id).as_str());
+    let req = match token {
+        Some(token) => req.insert_header(("Authorization", format!("Bearer {}", token))),
+        None => req,
+    };
+    let req = req.to_request();
+
+    test::call_service(&app, req).await.status()
+}
+
+struct FakeGitOperations {
+    checked_out_branches: Mutex<Vec<String>>,
+    checked_out_shas: Mutex<Vec<String>>,
+    // sha -> docker-deploy.yml
+    deploy_files: Mutex<HashMap<String, String>>,
+    // None fails the diff
+    changed_files: Mutex<Option<Vec<String>>>,
+    // the commits every branch has
+    branch_commits: Mutex<Vec<String>>,
+}
+
+impl FakeGitOperations {
+    fn new() -> FakeGitOperations {
+        FakeGitOperations {
+            checked_out_branches: Mutex::new(vec![]),
+            checked_out_shas: Mutex::new(vec![]),
+            deploy_files: Mutex::new(HashMap::new()),
+            changed_files: Mutex::new(None),
+            branch_commits: Mutex::new(vec![]),
+        }
+    }
+}
+
+impl GitOperations for FakeGitOperations {
+    fn clone_repo(
+        &self,
+        _url: &str,
+        into_dir_path: &str,
+        _clone_options: &CloneOptions,
+    ) -> Result<String, GitOperationsError> {
+        Ok(into_dir_path.to_string())
+    }
+
+    fn get_file_id(
+        &self,
+        _repo_path: &str,
+        _rev: &str,
+        _file_name: &str,
+    ) -> Result<String, GitOperationsError> {
+        Ok(String::new())
+    }
+
+    fn prepare_worktree(
+        &self,
+        repo_path: &str,
+        branch_name: &str,
+        sha: &str,
+        _clone_options: &CloneOptions,
+    ) -> Result<String, GitOperationsError> {
+        self.checked_out_branches
+            .lock()
+            .unwrap()
+            .push(format!("{}:{}", repo_path, branch_name));
+        self.checked_out_shas.lock().unwrap().push(sha.to_string());
+        Ok(format!("{}.worktrees/{}", repo_path, branch_name))
+    }
+
+    fn remove_stale_worktrees(
+        &self,
+        _repo_path: &str,
+        _max_idle: Duration,
+        _kept_branch_names: &[String],
+    ) -> Result<Vec<String>, GitOperationsError> {
+        Ok(vec![])
+    }
+
+    fn get_changed_files(
+        &self,
+        _repo_path: &str,
+        _from_sha: &str,
+        _to_sha: &str,
+        _clone_options: &CloneOptions,
+    ) -> Result<Vec<String>, GitOperationsError> {
+        self.changed_files
+            .lock()
+            .unwrap()
+            .clone()
+            .ok_or(GitOperationsError::CouldNotGetChangedFiles)
+    }
+
+    fn get_branch_sha(
+        &self,
+        _repo_path: &str,
+        _branch_name: &str,
+        _clone_options: &CloneOptions,
+    ) -> Result<String, GitOperationsError> {
+        Ok(BRANCH_TIP_SHA.to_string())
+    }
+
+    fn read_file(
+        &self,
+        _repo_path: &str,
+        sha: &str,
+        _file_name: &str,
+        _clone_options: &CloneOptions,
+    ) -> Result<String, GitOperationsError> {
+        self.deploy_files
+            .lock()
+            .unwrap()
+            .get(sha)
+            .cloned()
+            .ok_or(GitOperationsError::CouldNotReadFile)
+    }
+
+    fn is_on_branch(
+        &self,
+        _repo_path: &str,
+        _branch_name: &str,
+        sha: &str,
+        _clone_options: &CloneOptions,
+    ) -> Result<bool, GitOperationsError> {
+        Ok(self.branch_commits.lock().unwrap().iter().any(|commit| commit == sha))
+    }
+}
+
+struct FakeGithubClient {
+    registered_keys: Mutex<Vec<GithubDeployKeyDto>>,
+    created_keys: Mutex<Vec<GithubDeployKeyCreateDto>>,
+    deleted_key_ids: Mutex<Vec<i64>>,
+}
+
+impl FakeGithubClient {
+    fn new(registered_keys: Vec<GithubDeployKeyDto>) -> FakeGithubClient {
+        FakeGithubClient {
+            registered_keys: Mutex::new(registered_keys),
+            created_keys: Mutex::new(vec![]),
+            deleted_key_ids: Mutex::new(vec![]),
+        }
+    }
+}
+
+#[async_trait]
+impl GithubClient for FakeGithubClient {
+    async fn get_user_repos(
+        &self,
+        _page: u32,
+        _per_page: u32,
+    ) -> Result<DtoWithHeaders<Vec<GithubRepoDto>>, GithubClientError> {
+        Err(GithubClientError::CouldNotGetRepos)
+    }
+
+    async fn file_exists(&self, _url: &str) -> Result<bool, GithubClientError> {
+        Ok(true)
+    }
+
+    async fn get_webhooks(
+        &self,
+        _owner_name: String,
+        _repo_name: String,
+    ) -> Result<Vec<GithubWebhookDto>, GithubClientError> {
+        Ok(vec![])
+    }
+
+    async fn create_webhook(
+        &self,
+        _owner_name: String,
+        _repo_name: String,
+        _dto: GithubWebhookCreateDto,
+    ) -> Result<GithubWebhookDto, GithubClientError> {
+        Err(GithubClientError::CouldNotCreateWebhook)
+    }
+
+    async fn get_deploy_keys(
+        &self,
+        _owner_name: String,
+        _repo_name: String,
+    ) -> Result<Vec<GithubDeployKeyDto>, GithubClientError> {
+        Ok(self.registered_keys.lock().unwrap().clone())
+    }
+
+    async fn create_deploy_key(
+        &self,
+        _owner_name: String,
+        _repo_name: String,
+        dto: GithubDeployKeyCreateDto,
+    ) -> Result<GithubDeployKeyDto, GithubClientError> {
+        let mut registered_keys = self.registered_keys.lock().unwrap();
+        let key = create_deploy_key_dto(100 + registered_keys.len() as i64, &dto.title, &dto.key);
+
+        registered_keys.push(key.clone());
+        self.created_keys.lock().unwrap().push(dto);
+        Ok(key)
+    }
+
+    async fn delete_deploy_key(
+        &self,
+        _owner_name: String,
+        _repo_name: String,
+        key_id: i64,
+    ) -> Result<(), GithubClientError> {
+        self.registered_keys.lock().unwrap().retain(|key| key.id != key_id);
+        self.deleted_key_ids.lock().unwrap().push(key_id);
+        Ok(())
+    }
+}
+
+struct FakeCommandRunner {
+    commands: Mutex<Vec<String>>,
+    envs: Mutex<Vec<HashMap<String, String>>>,
+    // variable -> content of the files the steps got
+    files: Mutex<HashMap<String, String>>,
+    // the commands as their output would show them
+    masked_commands: Mutex<Vec<String>>,
+    timeouts: Mutex<Vec<Duration>>,
+    timing_out_commands: Mutex<Vec<String>>,
+    running: AtomicUsize,
+    max_running: AtomicUsize,
+    command_duration: Duration,
+}
+
+impl FakeCommandRunner {
+    fn new() -> FakeCommandRunner {
+        FakeCommandRunner::with_duration(Duration::from_millis(20))
+    }
+
+    fn with_duration(command_duration: Duration) -> FakeCommandRunner {
+        FakeCommandRunner {
+            commands: Mutex::new(vec![]),
+            envs: Mutex::new(vec![]),
+            files: Mutex::new(HashMap::new()),
+            masked_commands: Mutex::new(vec![]),
+            timeouts: Mutex::new(vec![]),
+            timing_out_commands: Mutex::new(vec![]),
+            running: AtomicUsize::new(0),
+            max_running: AtomicUsize::new(0),
+            command_duration,
+        }
+    }
+}
+
+#[async_trait]
+impl CommandRunner for FakeCommandRunner {
+    async fn run(
+        &self,
+        command: &str,
+        working_dir: &str,
+        env: &HashMap<String, String>,
+        secret_masker: &SecretMasker,
+        timeout: Duration,
+        cancellation_token: &CancellationToken,
+    ) -> Result<(), CommandRunnerError> {
+        let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+        self.max_running.fetch_max(running, Ordering::SeqCst);
+
+        let cancelled = tokio::select! {
+            _ = tokio::time::sleep(self.command_duration) => false,
+            _ = cancellation_token.cancelled() => true,
+        };
+        if cancelled {
+            self.running.fetch_sub(1, Ordering::SeqCst);
+            return Err(CommandRunnerError::Cancelled);
+        }
+
+        self.commands
+            .lock()
+            .unwrap()
+            .push(format!("{}:{}", working_dir, command));
+        self.envs.lock().unwrap().push(env.clone());
+        self.masked_commands.lock().unwrap().push(secret_masker.mask(command));
+
+        for (name, value) in env {
+            if value.contains("mini-ci-run-") {
+                if let Ok(content) = std::fs::read_to_string(value) {
+                    self.files.lock().unwrap().insert(name.clone(), content);
+                }
+            }
+        }
+        self.timeouts.lock().unwrap().push(timeout);
+
+        self.running.fetch_sub(1, Ordering::SeqCst);
+
+        if self
+            .timing_out_commands
+            .lock()
+            .unwrap()
+            .contains(&command.to_string())
+        {
+            Err(CommandRunnerError::TimedOut)
+        } else {
+            Ok(())
+        }
+    }
 }